@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS images(
+  path TEXT PRIMARY KEY,
+  mtime INTEGER NOT NULL,
+  nai INTEGER NOT NULL,
+  seed INTEGER,
+  prompt TEXT,
+  characters TEXT,
+  model TEXT
+);
+
+CREATE INDEX IF NOT EXISTS images_seed ON images(seed);
+CREATE INDEX IF NOT EXISTS images_mtime ON images(mtime);
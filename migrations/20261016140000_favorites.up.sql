@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS favorites(
+  path TEXT PRIMARY KEY,
+  ts INTEGER NOT NULL
+);
@@ -0,0 +1,3 @@
+ALTER TABLE base DROP COLUMN last_used;
+ALTER TABLE characters DROP COLUMN last_used;
+ALTER TABLE templates DROP COLUMN last_used;
@@ -0,0 +1,3 @@
+ALTER TABLE base ADD COLUMN last_used INTEGER;
+ALTER TABLE characters ADD COLUMN last_used INTEGER;
+ALTER TABLE templates ADD COLUMN last_used INTEGER;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS metadata_cache(
+  path TEXT PRIMARY KEY,
+  mtime INTEGER NOT NULL,
+  -- json of the metadata, NULL when the image has none
+  metadata TEXT
+);
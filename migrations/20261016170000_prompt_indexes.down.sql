@@ -0,0 +1,10 @@
+DROP INDEX IF EXISTS char_ts;
+DROP INDEX IF EXISTS templates_name;
+DROP INDEX IF EXISTS templates_ts;
+DROP INDEX IF EXISTS templates_prompts;
+DROP INDEX IF EXISTS templates_c1;
+DROP INDEX IF EXISTS templates_c2;
+DROP INDEX IF EXISTS templates_c3;
+DROP INDEX IF EXISTS templates_c4;
+DROP INDEX IF EXISTS templates_c5;
+DROP INDEX IF EXISTS templates_c6;
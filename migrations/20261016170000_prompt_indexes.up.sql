@@ -0,0 +1,12 @@
+CREATE INDEX IF NOT EXISTS char_ts ON characters(ts);
+
+CREATE INDEX IF NOT EXISTS templates_name ON templates(name);
+CREATE INDEX IF NOT EXISTS templates_ts ON templates(ts);
+-- finds an imported template by its prompts, and covers the foreign key on base
+CREATE INDEX IF NOT EXISTS templates_prompts ON templates(base, c1, c2, c3, c4, c5, c6);
+CREATE INDEX IF NOT EXISTS templates_c1 ON templates(c1);
+CREATE INDEX IF NOT EXISTS templates_c2 ON templates(c2);
+CREATE INDEX IF NOT EXISTS templates_c3 ON templates(c3);
+CREATE INDEX IF NOT EXISTS templates_c4 ON templates(c4);
+CREATE INDEX IF NOT EXISTS templates_c5 ON templates(c5);
+CREATE INDEX IF NOT EXISTS templates_c6 ON templates(c6);
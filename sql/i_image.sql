@@ -0,0 +1,9 @@
+INSERT INTO images(path, mtime, nai, seed, prompt, characters, model, dhash) VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+ON CONFLICT(path) DO UPDATE SET
+  mtime = excluded.mtime,
+  nai = excluded.nai,
+  seed = excluded.seed,
+  prompt = excluded.prompt,
+  characters = excluded.characters,
+  model = excluded.model,
+  dhash = excluded.dhash;
@@ -0,0 +1,4 @@
+INSERT INTO metadata_cache(path, mtime, metadata) VALUES(?1, ?2, ?3)
+ON CONFLICT(path) DO UPDATE SET
+  mtime = excluded.mtime,
+  metadata = excluded.metadata;
@@ -0,0 +1 @@
+SELECT path, dhash FROM images WHERE dhash IS NOT NULL ORDER BY mtime ASC;
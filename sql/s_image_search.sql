@@ -0,0 +1,3 @@
+SELECT path FROM images
+WHERE nai = 1 AND (prompt LIKE ?1 OR characters LIKE ?1)
+ORDER BY mtime DESC;
@@ -0,0 +1,492 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use image::DynamicImage;
+use serde_json::{Map, Value, json};
+
+use crate::{
+    highlight::{BRACE_FACTOR, format_weight, weight_opener},
+    image_metadata::read_text_chunks,
+};
+
+// A1111 strengthens each () by 10% and weakens each [] by the same amount
+const PAREN_FACTOR: f32 = 1.1;
+
+// the png text chunk A1111 keeps its generation info in
+const PARAMETERS: &str = "parameters";
+
+// separates the base prompt from the character prompts, A1111 splits the prompt there too
+const BREAK: &str = " BREAK ";
+
+// (NAI, A1111) sampler names
+const SAMPLERS: [(&str, &str); 7] = [
+    ("k_euler", "Euler"),
+    ("k_euler_ancestral", "Euler a"),
+    ("k_dpmpp_2s_ancestral", "DPM++ 2S a"),
+    ("k_dpmpp_2m", "DPM++ 2M"),
+    ("k_dpmpp_2m_sde", "DPM++ 2M SDE"),
+    ("k_dpmpp_sde", "DPM++ SDE"),
+    ("ddim_v3", "DDIM"),
+];
+
+/// text and the weight it ends up with, which both syntaxes flatten to
+type Segment = (String, f32);
+
+fn push(segments: &mut Vec<Segment>, text: &str, weight: f32) {
+    match segments.last_mut() {
+        Some((last, w)) if (*w - weight).abs() < 1e-4 => last.push_str(text),
+        _ => segments.push((text.to_string(), weight)),
+    }
+}
+
+/// `{}` / `[]` emphasis and `1.2::text::` weights, unbalanced closers are kept as text
+fn nai_segments(prompt: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    // open blocks as (opener, factor), `:` for weight blocks
+    let mut stack: Vec<(char, f32)> = Vec::new();
+
+    let mut i = 0;
+    while let Some(c) = prompt[i..].chars().next() {
+        let top = stack.last().map(|(opener, _)| *opener);
+        let mut len = c.len_utf8();
+        match c {
+            '{' => stack.push(('{', BRACE_FACTOR)),
+            '[' => stack.push(('[', 1.0 / BRACE_FACTOR)),
+            '}' if top == Some('{') => drop(stack.pop()),
+            ']' if top == Some('[') => drop(stack.pop()),
+            ':' if top == Some(':') && prompt[i..].starts_with("::") => {
+                stack.pop();
+                len = 2;
+            }
+            _ => match weight_opener(prompt, i) {
+                Some((w, opener)) => {
+                    stack.push((':', w));
+                    len = opener;
+                }
+                None => {
+                    let weight = stack.iter().map(|(_, f)| f).product();
+                    push(&mut segments, &prompt[i..i + len], weight);
+                }
+            },
+        }
+        i += len;
+    }
+    segments
+}
+
+/// `()` / `[]` emphasis and `(text:1.2)` weights, with `\` escaping the next char
+///
+/// groups left open at the end still apply, as in A1111
+fn a1111_segments(prompt: &str) -> Vec<Segment> {
+    // open groups with their opener and what is inside, weighted relative to the group
+    let mut stack: Vec<(char, Vec<Segment>)> = vec![(' ', Vec::new())];
+
+    // folds the innermost group into its parent
+    let close = |stack: &mut Vec<(char, Vec<Segment>)>| {
+        let Some((opener, mut inner)) = stack.pop() else {
+            return;
+        };
+        let factor = if opener == '[' {
+            1.0 / PAREN_FACTOR
+        } else {
+            explicit_weight(&mut inner).unwrap_or(PAREN_FACTOR)
+        };
+        let parent = &mut stack.last_mut().expect("the top level is never closed").1;
+        for (text, w) in inner {
+            push(parent, &text, w * factor);
+        }
+    };
+
+    let mut chars = prompt.chars();
+    while let Some(c) = chars.next() {
+        let top = stack.last().map(|(opener, _)| *opener);
+        match c {
+            '\\' => {
+                let escaped = chars.next().unwrap_or('\\');
+                push(
+                    &mut stack.last_mut().unwrap().1,
+                    escaped.encode_utf8(&mut [0; 4]),
+                    1.0,
+                );
+            }
+            '(' | '[' => stack.push((c, Vec::new())),
+            ')' if top == Some('(') => close(&mut stack),
+            ']' if top == Some('[') => close(&mut stack),
+            _ => push(
+                &mut stack.last_mut().unwrap().1,
+                c.encode_utf8(&mut [0; 4]),
+                1.0,
+            ),
+        }
+    }
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    stack
+        .pop()
+        .map(|(_, segments)| segments)
+        .unwrap_or_default()
+}
+
+/// removes the `:1.2` ending the unweighted text at the end of a group and returns the weight
+fn explicit_weight(inner: &mut Vec<Segment>) -> Option<f32> {
+    let (text, w) = inner.last_mut()?;
+    if (*w - 1.0).abs() > 1e-4 {
+        return None;
+    }
+    let colon = text.rfind(':')?;
+    let weight = text[colon + 1..].trim().parse::<f32>().ok()?;
+    text.truncate(colon);
+    if text.is_empty() {
+        inner.pop();
+    }
+    Some(weight)
+}
+
+/// writes weighted segments with `wrap(text, weight)`, keeping the commas and whitespace around
+/// a weighted part outside of it so the tags stay separate
+fn join(segments: Vec<Segment>, wrap: impl Fn(&str, &str) -> String) -> String {
+    let separator = |c: char| c == ',' || c.is_whitespace();
+    let mut out = String::new();
+    for (text, w) in segments {
+        let inner = text.trim_matches(separator);
+        if (w - 1.0).abs() < 1e-4 || inner.is_empty() {
+            out.push_str(&text);
+            continue;
+        }
+        let start = text.len() - text.trim_start_matches(separator).len();
+        out.push_str(&text[..start]);
+        out.push_str(&wrap(inner, &format_weight(w)));
+        out.push_str(&text[start + inner.len()..]);
+    }
+    out
+}
+
+/// a NovelAI prompt in A1111 syntax, every weighted part as an explicit `(text:1.2)`
+pub fn nai_to_a1111(prompt: &str) -> String {
+    let escaped = nai_segments(prompt)
+        .into_iter()
+        .map(|(text, w)| {
+            let text = text
+                .replace('\\', "\\\\")
+                .replace('(', "\\(")
+                .replace(')', "\\)")
+                .replace('[', "\\[")
+                .replace(']', "\\]");
+            (text, w)
+        })
+        .collect();
+    join(escaped, |text, w| format!("({}:{})", text, w))
+}
+
+/// an A1111 prompt in NovelAI syntax, every weighted part as a `1.2::text::` block
+pub fn a1111_to_nai(prompt: &str) -> String {
+    join(a1111_segments(prompt), |text, w| {
+        format!("{}::{}::", w, text)
+    })
+}
+
+/// the NAI metadata of an image as the text A1111 writes into its `parameters` chunk
+pub fn to_parameters(map: &Map<String, Value>) -> Result<String, String> {
+    let comment = map
+        .get("Comment")
+        .and_then(Value::as_object)
+        .ok_or("no Comment in the metadata")?;
+    let text = |key: &str| comment.get(key).and_then(Value::as_str).unwrap_or_default();
+
+    let mut prompts = vec![nai_to_a1111(text("prompt"))];
+    if let Some(chars) = map["Comment"]
+        .pointer("/v4_prompt/caption/char_captions")
+        .and_then(Value::as_array)
+    {
+        prompts.extend(
+            chars
+                .iter()
+                .filter_map(|c| c.get("char_caption").and_then(Value::as_str))
+                .map(nai_to_a1111),
+        );
+    }
+
+    let mut params = Vec::new();
+    let mut param = |name: &str, value: Option<String>| {
+        if let Some(value) = value {
+            params.push(format!("{}: {}", name, value));
+        }
+    };
+    let number = |key: &str| {
+        comment
+            .get(key)
+            .filter(|v| v.is_number())
+            .map(Value::to_string)
+    };
+    param("Steps", number("steps"));
+    param(
+        "Sampler",
+        comment.get("sampler").and_then(Value::as_str).map(|s| {
+            SAMPLERS
+                .iter()
+                .find(|(nai, _)| *nai == s)
+                .map_or(s, |(_, a1111)| a1111)
+                .to_string()
+        }),
+    );
+    param(
+        "Schedule type",
+        comment
+            .get("noise_schedule")
+            .and_then(Value::as_str)
+            .map(capitalize),
+    );
+    param("CFG scale", number("scale"));
+    param("Seed", number("seed"));
+    param(
+        "Size",
+        number("width")
+            .zip(number("height"))
+            .map(|(w, h)| format!("{}x{}", w, h)),
+    );
+    param(
+        "Model",
+        map.get("Source").and_then(Value::as_str).map(quote),
+    );
+
+    Ok(format!(
+        "{}\nNegative prompt: {}\n{}",
+        prompts.join(BREAK),
+        nai_to_a1111(text("uc")),
+        params.join(", ")
+    ))
+}
+
+/// NAI metadata, as `extract_image_metadata` returns it, from A1111 `parameters` text
+///
+/// the prompt is split into the base and character prompts at each ` BREAK `
+pub fn from_parameters(text: &str) -> Map<String, Value> {
+    let mut lines: Vec<&str> = text.lines().collect();
+    let negative_at = lines
+        .iter()
+        .position(|l| l.starts_with("Negative prompt: "));
+    // A1111 starts the settings with the steps, a converted NAI image may have none
+    let has_params = lines.last().is_some_and(|last| last.starts_with("Steps: "))
+        || negative_at.is_some_and(|at| at + 1 < lines.len());
+    let params = match lines.pop() {
+        Some(last) if has_params => parse_params(last),
+        Some(last) => {
+            lines.push(last);
+            Map::new()
+        }
+        None => Map::new(),
+    };
+    let negative_at = negative_at.unwrap_or(lines.len());
+    let prompt = lines[..negative_at].join("\n");
+    let negative = lines[negative_at..]
+        .join("\n")
+        .trim_start_matches("Negative prompt: ")
+        .to_string();
+
+    let mut prompts = prompt.split(BREAK).map(a1111_to_nai);
+    let base = prompts.next().unwrap_or_default();
+    let mut comment = nai_comment(base, prompts.collect(), a1111_to_nai(&negative));
+    let param = |name: &str| params.get(name).and_then(Value::as_str);
+    for (key, name) in [("steps", "Steps"), ("scale", "CFG scale"), ("seed", "Seed")] {
+        if let Some(n) = param(name).and_then(|v| serde_json::from_str::<Value>(v).ok()) {
+            comment.insert(key.into(), n);
+        }
+    }
+    if let Some(sampler) = param("Sampler") {
+        comment.insert("sampler".into(), nai_sampler(sampler).into());
+    }
+    if let Some(schedule) = param("Schedule type") {
+        comment.insert("noise_schedule".into(), schedule.to_lowercase().into());
+    }
+    let size = param("Size").and_then(|s| s.split_once('x'));
+    if let Some((Ok(w), Ok(h))) = size.map(|(w, h)| (w.parse::<u32>(), h.parse::<u32>())) {
+        comment.insert("width".into(), w.into());
+        comment.insert("height".into(), h.into());
+    }
+
+    let mut map = Map::new();
+    map.insert("Software".into(), "NovelAI".into());
+    if let Some(model) = param("Model") {
+        map.insert("Source".into(), model.into());
+    }
+    map.insert("Comment".into(), Value::Object(comment));
+    map
+}
+
+/// the Comment of NAI metadata with the prompts filled in, for metadata of other software
+pub fn nai_comment(base: String, characters: Vec<String>, uc: String) -> Map<String, Value> {
+    let chars: Vec<Value> = characters
+        .into_iter()
+        .map(|c| json!({"char_caption": c, "centers": [{"x": 0.5, "y": 0.5}]}))
+        .collect();
+    let Value::Object(comment) = json!({
+        "prompt": base,
+        "uc": uc,
+        "v4_prompt": {
+            "caption": {"base_caption": base, "char_captions": chars},
+            "use_coords": false,
+            "use_order": true,
+        },
+        "v4_negative_prompt": {"caption": {"base_caption": uc, "char_captions": []}},
+    }) else {
+        unreachable!()
+    };
+    comment
+}
+
+/// the NAI name of an A1111 or ComfyUI sampler, or the name itself if NAI has no such sampler
+pub fn nai_sampler(sampler: &str) -> &str {
+    SAMPLERS
+        .iter()
+        .find(|(nai, a1111)| *a1111 == sampler || nai.strip_prefix("k_") == Some(sampler))
+        .map_or(sampler, |(nai, _)| nai)
+}
+
+/// `Steps: 28, Sampler: "a, b"` as a map of strings, quoted values may contain commas
+fn parse_params(line: &str) -> Map<String, Value> {
+    let mut params = Map::new();
+    let mut rest = line;
+    while let Some((name, after)) = rest.split_once(": ") {
+        let (value, next) = if let Some(quoted) = after.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let next = quoted[end..].trim_start_matches('"');
+            (&quoted[..end], next.trim_start_matches(", "))
+        } else {
+            match after.split_once(", ") {
+                Some((value, next)) => (value, next),
+                None => (after, ""),
+            }
+        };
+        params.insert(name.trim().to_string(), value.into());
+        rest = next;
+    }
+    params
+}
+
+fn quote(s: &str) -> String {
+    if s.contains(',') {
+        format!("\"{}\"", s)
+    } else {
+        s.to_string()
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// the `parameters` text chunk of the png at `path`, if it has one
+pub fn read_parameters(path: &Path) -> Result<Option<String>, String> {
+    Ok(parameters(read_text_chunks(path)?))
+}
+
+/// the text of the `parameters` chunk among `chunks`
+pub fn parameters(chunks: Vec<(String, String)>) -> Option<String> {
+    chunks
+        .into_iter()
+        .find(|(keyword, _)| keyword == PARAMETERS)
+        .map(|(_, text)| text)
+}
+
+/// saves `im` as an 8 bit rgba png with `parameters` in a text chunk
+pub fn save_with_parameters(
+    im: &DynamicImage,
+    path: &Path,
+    parameters: &str,
+) -> Result<(), String> {
+    let rgba = im.to_rgba8();
+    let file = File::create(path).map_err(|e| format!("{:?}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), rgba.width(), rgba.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // tEXt only holds latin-1
+    let chunk = if parameters.chars().all(|c| (c as u32) < 0x100) {
+        encoder.add_text_chunk(PARAMETERS.into(), parameters.into())
+    } else {
+        encoder.add_itxt_chunk(PARAMETERS.into(), parameters.into())
+    };
+    chunk
+        .and_then(|()| encoder.write_header())
+        .and_then(|mut writer| writer.write_image_data(&rgba))
+        .map_err(|e| format!("{:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn weights_both_ways() {
+        assert_eq!(
+            nai_to_a1111("1girl, {{smile}}, [sky], 1.3::red dress, hat::, (text)"),
+            "1girl, (smile:1.1), (sky:0.95), (red dress, hat:1.3), \\(text\\)"
+        );
+        assert_eq!(
+            a1111_to_nai("1girl, ((smile)), [sky], (red dress, hat:1.3), \\(text\\)"),
+            "1girl, 1.21::smile::, 0.91::sky::, 1.3::red dress, hat::, (text)"
+        );
+        // nested weights multiply, unclosed groups still apply
+        assert_eq!(a1111_to_nai("((a:1.5), b:2"), "3::a::, 2::b::");
+        assert_eq!(a1111_to_nai("(a, (b:0.5):2)"), "2::a::, b");
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let map: Map<String, Value> = serde_json::from_value(json!({
+            "Software": "NovelAI",
+            "Source": "NovelAI Diffusion V4.5 4BDE2A90",
+            "Comment": {
+                "prompt": "1girl, {smile}",
+                "uc": "lowres, [blurry]",
+                "steps": 28,
+                "scale": 5.5,
+                "seed": 42,
+                "sampler": "k_euler_ancestral",
+                "noise_schedule": "karras",
+                "width": 832,
+                "height": 1216,
+                "v4_prompt": {"caption": {"char_captions": [{"char_caption": "girl, red hair"}]}},
+            }
+        }))
+        .unwrap();
+
+        let text = to_parameters(&map).unwrap();
+        assert_eq!(
+            text,
+            "1girl, (smile:1.05) BREAK girl, red hair\n\
+             Negative prompt: lowres, (blurry:0.95)\n\
+             Steps: 28, Sampler: Euler a, Schedule type: Karras, CFG scale: 5.5, Seed: 42, \
+             Size: 832x1216, Model: NovelAI Diffusion V4.5 4BDE2A90"
+        );
+
+        let back = from_parameters(&text);
+        assert_eq!(
+            from_parameters("a\nNegative prompt: b\nSeed: 1")["Comment"]["seed"],
+            1
+        );
+        assert_eq!(
+            from_parameters("a\nSeed: 1")["Comment"]["prompt"],
+            "a\nSeed: 1"
+        );
+        let comment = &back["Comment"];
+        assert_eq!(comment["prompt"], "1girl, 1.05::smile::");
+        assert_eq!(comment["uc"], "lowres, 0.95::blurry::");
+        assert_eq!(
+            comment["v4_prompt"]["caption"]["char_captions"][0]["char_caption"],
+            "girl, red hair"
+        );
+        assert_eq!(comment["seed"], 42);
+        assert_eq!(comment["scale"], 5.5);
+        assert_eq!(comment["sampler"], "k_euler_ancestral");
+        assert_eq!(comment["noise_schedule"], "karras");
+        assert_eq!(
+            (comment["width"].clone(), comment["height"].clone()),
+            (json!(832), json!(1216))
+        );
+        assert_eq!(back["Source"], map["Source"]);
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::PathBuf;
+
+use iced::widget::shader::wgpu::naga::FastHashMap;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+use tokio::task::spawn_blocking;
+
+use crate::db::SqliteError;
+
+pub const MAX_SUGGESTIONS: usize = 8;
+
+// shorter tokens match too much of the vocabulary to be useful
+const MIN_TOKEN_LEN: usize = 2;
+
+/// tag names known to the autocomplete, most used first
+#[derive(Debug, Clone, Default)]
+pub struct Vocabulary {
+    words: Vec<(String, u32)>,
+}
+
+impl Vocabulary {
+    pub fn new(usage: FastHashMap<String, u32>) -> Self {
+        let mut words: Vec<(String, u32)> = usage.into_iter().collect();
+        words.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Self { words }
+    }
+
+    /// up to `MAX_SUGGESTIONS` tags starting with `token`, ignoring case
+    pub fn suggest(&self, token: &str) -> Vec<String> {
+        let token = token.to_lowercase();
+        if token.len() < MIN_TOKEN_LEN {
+            return Vec::new();
+        }
+
+        self.words
+            .iter()
+            .filter(|(word, _)| word.len() > token.len() && word.to_lowercase().starts_with(&token))
+            .take(MAX_SUGGESTIONS)
+            .map(|(word, _)| word.clone())
+            .collect()
+    }
+
+    /// bumps `word` so it ranks higher the next time
+    pub fn used(&mut self, word: &str) {
+        let Some(i) = self.words.iter().position(|(w, _)| w == word) else {
+            return;
+        };
+        self.words[i].1 += 1;
+
+        let count = self.words[i].1;
+        let j = self.words[..i].partition_point(|(_, c)| *c >= count);
+        let entry = self.words.remove(i);
+        self.words.insert(j, entry);
+    }
+}
+
+/// the tag being typed: everything after the last comma before the cursor on `line`
+///
+/// `index` is the byte offset of the cursor, as reported by the text editor
+pub fn current_token(line: &str, index: usize) -> Option<&str> {
+    let before = line.get(..index)?;
+    let start = before.rfind(',').map_or(0, |i| i + 1);
+    let token = before[start..].trim_start();
+    (!token.is_empty()).then_some(token)
+}
+
+/// tags from the `tags`, `artists` and `characters` tables created by import.rs, ranked by how
+/// often they appear in the saved prompts
+///
+/// the import tables live in the database at `SQLITE_URL`; without it only tags from the saved
+/// prompts are suggested
+pub async fn load_vocabulary(
+    pool: Pool<SqliteConnectionManager>,
+) -> Result<Vocabulary, SqliteError> {
+    spawn_blocking(move || load_vocabulary_blocking(pool))
+        .await
+        .map_err(|e| SqliteError { err: e.to_string() })?
+}
+
+fn load_vocabulary_blocking(
+    pool: Pool<SqliteConnectionManager>,
+) -> Result<Vocabulary, SqliteError> {
+    let mut usage: FastHashMap<String, u32> = FastHashMap::default();
+
+    if let Some(path) = std::env::var_os("SQLITE_URL").map(PathBuf::from)
+        && path.exists()
+    {
+        let conn = Connection::open(path).map_err(SqliteError::new)?;
+        // any of these may be missing if the import was never run
+        for table in ["tags", "artists", "characters"] {
+            let Ok(mut stmt) = conn.prepare(&format!("SELECT name FROM {}", table)) else {
+                continue;
+            };
+            let rows = stmt
+                .query_map([], |r| r.get::<usize, String>(0))
+                .map_err(SqliteError::new)?;
+            for row in rows {
+                usage.entry(row.map_err(SqliteError::new)?).or_insert(0);
+            }
+        }
+    }
+
+    let conn = pool.get().unwrap();
+    let mut stmt = conn
+        .prepare("SELECT t FROM base UNION ALL SELECT t FROM characters")
+        .map_err(SqliteError::new)?;
+    let rows = stmt
+        .query_map([], |r| r.get::<usize, String>(0))
+        .map_err(SqliteError::new)?;
+    for row in rows {
+        let prompt = row.map_err(SqliteError::new)?;
+        for tag in prompt.split([',', '\n']).map(str::trim) {
+            if !tag.is_empty() {
+                *usage.entry(tag.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    Ok(Vocabulary::new(usage))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suggest_by_usage() {
+        let mut vocab = Vocabulary::new(FastHashMap::from_iter([
+            ("blue eyes".to_string(), 1),
+            ("blue hair".to_string(), 5),
+            ("blush".to_string(), 3),
+            ("red hair".to_string(), 9),
+        ]));
+
+        assert_eq!(vocab.suggest("bl"), vec!["blue hair", "blush", "blue eyes"]);
+        assert_eq!(vocab.suggest("Blue "), vec!["blue hair", "blue eyes"]);
+        assert!(vocab.suggest("b").is_empty());
+
+        for _ in 0..3 {
+            vocab.used("blue eyes");
+        }
+        assert_eq!(vocab.suggest("blue"), vec!["blue hair", "blue eyes"]);
+        vocab.used("blue eyes");
+        vocab.used("blue eyes");
+        assert_eq!(vocab.suggest("blue"), vec!["blue eyes", "blue hair"]);
+    }
+
+    #[test]
+    fn token_after_last_comma() {
+        assert_eq!(current_token("1girl, blue ha", 14), Some("blue ha"));
+        assert_eq!(current_token("1girl, blue ha", 5), Some("1girl"));
+        assert_eq!(current_token("1girl, ", 7), None);
+    }
+}
@@ -0,0 +1,1350 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use iced::futures::executor;
+use notify::{EventKind, RecursiveMode, Watcher};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rand::Rng;
+use rusqlite::{Connection, OpenFlags};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    PROJECT_DIRS, a1111,
+    db::{PromptKind, SqliteError, fetch_prompts, manager, run_migrations, save_prompt},
+    fuzzy::fuzzy_match,
+    history::{day_of, format_day},
+    image_metadata::{embed_metadata, extract_image_metadata, extract_many, strip_metadata},
+    library::{collect_pngs, dhash, group_duplicates},
+    logs::{debug, info, warning},
+    nai::{ImageGenRequest, Requester},
+    parallel::par_map,
+    prompt_diff::tags,
+    ui::get_prompt_metadata,
+};
+
+// chars of prompt text shown around a match
+const SNIPPET_CONTEXT: usize = 40;
+
+// chars of the bar drawn by `progress_bar`
+const PROGRESS_WIDTH: usize = 30;
+
+// longer pauses between two generations end a session, they are left out of the average
+const SESSION_GAP: Duration = Duration::from_secs(5 * 60);
+
+// quiet time after the last event for a file before `watch` reads it, as downloads are written
+// in several steps
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// the value at a dotted `path` like `Comment.seed`, where numbers index into arrays as in
+/// `Comment.v4_prompt.caption.char_captions.0.char_caption`
+pub fn metadata_field<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| match value {
+        Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        _ => value.get(key),
+    })
+}
+
+/// writes the NAI metadata of every png below `dir` to `out`, one json object per line as
+/// `{"path": ..., "metadata": {...}}`
+///
+/// files are read with `extract_many` and written as they finish, so the lines are in no
+/// particular order; returns the number of lines written and of pngs without metadata
+pub fn export_metadata(dir: &Path, out: &Path) -> io::Result<(usize, usize)> {
+    let mut pngs = Vec::new();
+    collect_pngs(dir, &mut pngs);
+    let paths: Vec<PathBuf> = pngs.into_iter().map(|(path, _)| path).collect();
+
+    let mut writer = BufWriter::new(File::create(out)?);
+    let (mut written, mut skipped) = (0, 0);
+    for (path, map) in executor::block_on_stream(extract_many(paths)) {
+        let Ok(map) = map else {
+            skipped += 1;
+            continue;
+        };
+        let line = json!({"path": path.to_string_lossy(), "metadata": map});
+        writeln!(writer, "{}", line)?;
+        written += 1;
+    }
+    writer.flush()?;
+    Ok((written, skipped))
+}
+
+/// groups of near-identical pngs below `dir`, newest file first in each group
+///
+/// pngs that fail to decode are left out
+pub fn find_duplicates_in(dir: &Path, max_distance: u32) -> Vec<Vec<PathBuf>> {
+    let mut pngs = Vec::new();
+    collect_pngs(dir, &mut pngs);
+    let paths: Vec<PathBuf> = pngs.iter().map(|(path, _)| path.clone()).collect();
+    let mtimes: HashMap<PathBuf, i64> = pngs.into_iter().collect();
+
+    let hashes: Vec<(PathBuf, u64)> =
+        par_map(&paths, |path| image::open(path).ok().map(|im| dhash(&im)))
+            .into_iter()
+            .zip(paths)
+            .filter_map(|(hash, path)| Some((path, hash?)))
+            .collect();
+
+    let mut groups = group_duplicates(&hashes, max_distance);
+    for group in &mut groups {
+        group.sort_by_key(|path| Reverse(mtimes[path]));
+    }
+    groups
+}
+
+/// what `verify` found in a folder of the library
+#[derive(Debug, Default)]
+pub struct Verification {
+    pub checked: usize,
+    // pngs that fail to decode, with the error
+    pub corrupt: Vec<(PathBuf, String)>,
+    // pngs that decode but carry no NAI metadata
+    pub foreign: Vec<PathBuf>,
+    // indexed below the folder but gone from disk
+    pub missing: Vec<PathBuf>,
+    // not in the index, or modified since they were indexed
+    pub unindexed: Vec<PathBuf>,
+}
+
+/// decodes every png below `dir` and reads its metadata, and compares the files with the rows of
+/// the images table below `dir`
+pub fn verify(conn: &Connection, dir: &Path) -> Result<Verification, SqliteError> {
+    let dir = std::path::absolute(dir).map_err(|e| SqliteError { err: e.to_string() })?;
+    let mut pngs = Vec::new();
+    collect_pngs(&dir, &mut pngs);
+
+    let mut indexed = HashMap::new();
+    let mut stmt = conn
+        .prepare("SELECT path, mtime FROM images")
+        .map_err(SqliteError::new)?;
+    let rows = stmt
+        .query_map([], |r| {
+            Ok((
+                PathBuf::from(r.get::<usize, String>(0)?),
+                r.get::<usize, i64>(1)?,
+            ))
+        })
+        .map_err(SqliteError::new)?;
+    for row in rows {
+        let (path, mtime) = row.map_err(SqliteError::new)?;
+        if path.starts_with(&dir) {
+            indexed.insert(path, mtime);
+        }
+    }
+
+    let decoded = par_map(&pngs, |(path, _)| {
+        image::open(path)
+            .map(|im| extract_image_metadata(im).is_ok())
+            .map_err(|e| e.to_string())
+    });
+
+    let mut report = Verification {
+        checked: pngs.len(),
+        ..Default::default()
+    };
+    for ((path, mtime), decoded) in pngs.into_iter().zip(decoded) {
+        match decoded {
+            Ok(true) => {}
+            Ok(false) => report.foreign.push(path.clone()),
+            Err(e) => report.corrupt.push((path.clone(), e)),
+        }
+        if indexed.remove(&path) != Some(mtime) {
+            report.unindexed.push(path);
+        }
+    }
+    report.missing = indexed.into_keys().collect();
+    report.missing.sort();
+    Ok(report)
+}
+
+/// writes a copy of the png at `path`, or of every png below it, to `out` without NAI metadata,
+/// keeping the folder structure; existing files are not overwritten
+///
+/// returns the number of copies written, files that fail are logged and skipped
+pub fn strip_metadata_to(path: &Path, out: &Path) -> Result<usize, String> {
+    let copies: Vec<(PathBuf, PathBuf)> = if path.is_dir() {
+        let mut pngs = Vec::new();
+        collect_pngs(path, &mut pngs);
+        pngs.into_iter()
+            .filter_map(|(source, _)| {
+                let dest = out.join(source.strip_prefix(path).ok()?);
+                Some((source, dest))
+            })
+            .collect()
+    } else {
+        let name = path
+            .file_name()
+            .ok_or_else(|| format!("{:?} is not a file", path))?;
+        vec![(path.to_path_buf(), out.join(name))]
+    };
+
+    let results = par_map(&copies, |(source, dest)| {
+        if dest.exists() {
+            return Err(format!("{:?} already exists", dest));
+        }
+        let im = image::open(source).map_err(|e| format!("{:?}: {}", source, e))?;
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{:?}: {}", parent, e))?;
+        }
+        // the png encoder writes no text chunks, so only the pixels carry over
+        strip_metadata(im)
+            .save_with_format(dest, image::ImageFormat::Png)
+            .map_err(|e| format!("{:?}: {}", dest, e))
+    });
+
+    let mut written = 0;
+    for result in results {
+        match result {
+            Ok(()) => written += 1,
+            Err(e) => warning!("not stripped: {}", e),
+        }
+    }
+    Ok(written)
+}
+
+/// embeds the NAI metadata of `from` into the png at `image`, replacing the file
+///
+/// `from` is another png or a json object, either the metadata itself or a line written by
+/// `export_metadata`
+pub fn write_metadata(image: &Path, from: &Path) -> Result<(), String> {
+    let map = if from.extension().is_some_and(|ext| ext == "png") {
+        let im = image::open(from).map_err(|e| format!("{:?}: {}", from, e))?;
+        extract_image_metadata(im).map_err(|e| format!("{:?}: {}", from, e))?
+    } else {
+        let text = fs::read_to_string(from).map_err(|e| format!("{:?}: {}", from, e))?;
+        let mut map: Map<String, Value> =
+            serde_json::from_str(&text).map_err(|e| format!("{:?}: {}", from, e))?;
+        match map.remove("metadata") {
+            Some(Value::Object(metadata)) if map.contains_key("path") => metadata,
+            Some(other) => {
+                map.insert("metadata".into(), other);
+                map
+            }
+            None => map,
+        }
+    };
+
+    let im = image::open(image).map_err(|e| format!("{:?}: {}", image, e))?;
+    let embedded = embed_metadata(im, &map).map_err(|e| format!("{:?}: {}", image, e))?;
+    // written next to it first, so a failed write leaves the original
+    let tmp = image.with_extension("png.writing");
+    embedded
+        .save_with_format(&tmp, image::ImageFormat::Png)
+        .map_err(|e| format!("{:?}: {}", tmp, e))?;
+    fs::rename(&tmp, image).map_err(|e| format!("{:?}: {}", image, e))
+}
+
+/// adds the NAI metadata of the png at `image` as an A1111 `parameters` chunk, replacing the
+/// file; the pixels, and so the NAI metadata, stay as they are
+pub fn convert_to_a1111(image: &Path) -> Result<(), String> {
+    let im = image::open(image).map_err(|e| format!("{:?}: {}", image, e))?;
+    let map = extract_image_metadata(im.clone()).map_err(|e| format!("{:?}: {}", image, e))?;
+    let parameters = a1111::to_parameters(&map)?;
+    let tmp = image.with_extension("png.writing");
+    a1111::save_with_parameters(&im, &tmp, &parameters)?;
+    fs::rename(&tmp, image).map_err(|e| format!("{:?}: {}", image, e))
+}
+
+/// embeds the A1111 `parameters` chunk of the png at `image` as NAI metadata, replacing the file
+pub fn convert_to_nai(image: &Path) -> Result<(), String> {
+    let parameters = a1111::read_parameters(image)?
+        .ok_or_else(|| format!("{:?} has no A1111 parameters", image))?;
+    let map = a1111::from_parameters(&parameters);
+    let im = image::open(image).map_err(|e| format!("{:?}: {}", image, e))?;
+    let embedded = embed_metadata(im, &map).map_err(|e| format!("{:?}: {}", image, e))?;
+    let tmp = image.with_extension("png.writing");
+    embedded
+        .save_with_format(&tmp, image::ImageFormat::Png)
+        .map_err(|e| format!("{:?}: {}", tmp, e))?;
+    fs::rename(&tmp, image).map_err(|e| format!("{:?}: {}", image, e))
+}
+
+/// generates `count` images with the request that made `image`, one after the other with
+/// `cooldown` in between, with its seed or with new ones
+///
+/// returns the paths of the new images in `output_dir`
+pub async fn regen(
+    image: &Path,
+    same_seed: bool,
+    count: usize,
+    output_dir: PathBuf,
+    cooldown: Duration,
+) -> Result<Vec<PathBuf>, String> {
+    let im = image::open(image).map_err(|e| format!("{:?}: {}", image, e))?;
+    let map = extract_image_metadata(im).map_err(|e| format!("{:?}: {}", image, e))?;
+    let mut req = ImageGenRequest::from_metadata(&map)?;
+    if std::env::var("NOVELAI_API_KEY").is_err() {
+        return Err("NOVELAI_API_KEY is not set".into());
+    }
+
+    let client = Requester::default();
+    let mut paths = Vec::with_capacity(count);
+    for i in 0..count {
+        if i > 0 {
+            tokio::time::sleep(cooldown).await;
+        }
+        if !same_seed {
+            req.seed(rand::rng().random_range(1e9..9e9) as u64);
+        }
+        let (_, path) = client
+            .generate_image(req.clone(), output_dir.clone(), || {})
+            .await
+            .map_err(|e| e.to_string())?;
+        info!("seed {} saved to {:?}", req.get_seed(), path);
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// `template` filled in for an output saved at `epoch`: `{date}` as yyyy-mm-dd, `{time}` as
+/// hhmmss (both UTC), `{epoch}`, `{seed}` and `{n}`
+fn render_name(template: &str, epoch: u64, seed: Option<u64>, n: usize) -> String {
+    let secs = epoch % 86400;
+    template
+        .replace("{date}", &format_day(epoch / 86400))
+        .replace(
+            "{time}",
+            &format!("{:02}{:02}{:02}", secs / 3600, secs % 3600 / 60, secs % 60),
+        )
+        .replace("{epoch}", &epoch.to_string())
+        .replace("{seed}", &seed.map_or("noseed".into(), |s| s.to_string()))
+        .replace("{n}", &n.to_string())
+}
+
+/// new names from `template` for the epoch named pngs in `dir`, as (old, new) in the order they
+/// were saved
+///
+/// `{n}` counts up from 1 until the name is free, without it a taken name is logged and skipped
+pub fn plan_renames(dir: &Path, template: &str) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed {{ in {:?}", template))?;
+        let key = &rest[start..start + end + 1];
+        if !["{date}", "{time}", "{epoch}", "{seed}", "{n}"].contains(&key) {
+            return Err(format!("unknown placeholder {}", key));
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    let mut outputs: Vec<(u64, PathBuf)> = fs::read_dir(dir)
+        .map_err(|e| format!("{:?}: {}", dir, e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+        .filter_map(|path| {
+            let epoch = path.file_stem()?.to_str()?.parse().ok()?;
+            Some((epoch, path))
+        })
+        .collect();
+    outputs.sort();
+
+    let seeds = par_map(&outputs, |(_, path)| {
+        let map = extract_image_metadata(image::open(path).ok()?).ok()?;
+        map.get("Comment")?.get("seed")?.as_u64()
+    });
+
+    let mut taken = HashSet::new();
+    let mut renames = Vec::new();
+    for ((epoch, path), seed) in outputs.into_iter().zip(seeds) {
+        let mut n = 1;
+        let dest = loop {
+            let dest = dir.join(format!("{}.png", render_name(template, epoch, seed, n)));
+            if dest == path || (!taken.contains(&dest) && !dest.exists()) {
+                break Some(dest);
+            }
+            if !template.contains("{n}") {
+                break None;
+            }
+            n += 1;
+        };
+        match dest {
+            Some(dest) if dest != path => {
+                taken.insert(dest.clone());
+                renames.push((path, dest));
+            }
+            Some(_) => {}
+            None => warning!("not renaming {:?}, the name is taken", path),
+        }
+    }
+    Ok(renames)
+}
+
+/// points the index and favorites at the renamed files
+pub fn move_db_paths(conn: &Connection, renames: &[(PathBuf, PathBuf)]) -> Result<(), SqliteError> {
+    for (old, new) in renames {
+        let (old, new) = (old.to_string_lossy(), new.to_string_lossy());
+        for sql in [
+            "UPDATE images SET path = ?2 WHERE path = ?1",
+            "UPDATE favorites SET path = ?2 WHERE path = ?1",
+        ] {
+            conn.execute(sql, [&old, &new]).map_err(SqliteError::new)?;
+        }
+    }
+    Ok(())
+}
+
+/// the files matching `pattern`, sorted; `*` and `?` match within a path component and a `**`
+/// component any number of folders
+pub fn glob(pattern: &str) -> Vec<PathBuf> {
+    // the leading components without wildcards are where the search starts
+    let mut root = PathBuf::new();
+    let mut parts = Vec::new();
+    for component in Path::new(pattern).components() {
+        let s = component.as_os_str().to_string_lossy();
+        if parts.is_empty() && !s.contains(['*', '?']) {
+            root.push(component);
+        } else {
+            parts.push(s.into_owned());
+        }
+    }
+    if root.as_os_str().is_empty() {
+        root.push(".");
+    }
+
+    let mut found = Vec::new();
+    glob_walk(&root, &parts, &mut found);
+    found.sort();
+    // `**/**` reaches files more than once
+    found.dedup();
+    found
+}
+
+fn glob_walk(dir: &Path, parts: &[String], found: &mut Vec<PathBuf>) {
+    let Some((first, rest)) = parts.split_first() else {
+        if dir.is_file() {
+            found.push(dir.to_path_buf());
+        }
+        return;
+    };
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+
+    if first == "**" && !rest.is_empty() {
+        glob_walk(dir, rest, found);
+    }
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        // linked folders can point back up the tree, so only real folders are walked
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        if first == "**" {
+            if is_dir {
+                glob_walk(&path, parts, found);
+            } else if rest.is_empty() {
+                found.push(path);
+            }
+        } else if wildcard_match(first, &entry.file_name().to_string_lossy()) {
+            if rest.is_empty() {
+                if !is_dir {
+                    found.push(path);
+                }
+            } else if is_dir {
+                glob_walk(&path, rest, found);
+            }
+        }
+    }
+}
+
+/// whether `name` matches `pattern`, where `*` is any text and `?` any one char
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // the last `*` and where in `name` it started matching, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    p = sp + 1;
+                    n = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// draws `[#####     ] 12/40` over the current line of stderr, ending it once `done` reaches
+/// `total`
+pub fn progress_bar(done: usize, total: usize) {
+    let filled = (done * PROGRESS_WIDTH)
+        .checked_div(total)
+        .unwrap_or(PROGRESS_WIDTH);
+    eprint!(
+        "\r[{}{}] {}/{}",
+        "#".repeat(filled),
+        " ".repeat(PROGRESS_WIDTH - filled),
+        done,
+        total
+    );
+    if done >= total {
+        eprintln!();
+    }
+}
+
+/// the database the ui uses
+pub fn db_path() -> PathBuf {
+    PROJECT_DIRS.data_dir().join("prompts.db")
+}
+
+/// writes a consistent copy of the database at `db` to `dest` with VACUUM INTO, which is safe
+/// while the ui has it open; an existing `dest` is not overwritten
+pub fn backup_db(db: &Path, dest: &Path) -> Result<(), SqliteError> {
+    if dest.exists() {
+        return Err(SqliteError {
+            err: format!("{:?} already exists", dest),
+        });
+    }
+    let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(SqliteError::new)?;
+    conn.execute("VACUUM INTO ?1", [dest.to_string_lossy()])
+        .map_err(SqliteError::new)?;
+    Ok(())
+}
+
+/// replaces the database at `db` with the backup at `src` once it passes an integrity check
+///
+/// the replaced database is kept next to it with a .bak extension, which is returned; the ui
+/// should be closed, it keeps using the old file otherwise
+pub fn restore_db(src: &Path, db: &Path) -> Result<Option<PathBuf>, SqliteError> {
+    let err = |e: io::Error| SqliteError { err: e.to_string() };
+    {
+        let conn = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(SqliteError::new)?;
+        let check: String = conn
+            .query_row("PRAGMA integrity_check", [], |r| r.get(0))
+            .map_err(SqliteError::new)?;
+        if check != "ok" {
+            return Err(SqliteError {
+                err: format!("{:?} failed the integrity check: {}", src, check),
+            });
+        }
+    }
+
+    // copied next to the database first, so the final rename does not cross file systems
+    let restoring = db.with_extension("db.restoring");
+    std::fs::copy(src, &restoring).map_err(err)?;
+    let kept = if db.exists() {
+        let bak = db.with_extension("db.bak");
+        std::fs::rename(db, &bak).map_err(err)?;
+        Some(bak)
+    } else {
+        None
+    };
+    std::fs::rename(&restoring, db).map_err(err)?;
+    Ok(kept)
+}
+
+// template columns and the table they point into
+const TEMPLATE_REFERENCES: [(&str, &str); 7] = [
+    ("base", "base"),
+    ("c1", "characters"),
+    ("c2", "characters"),
+    ("c3", "characters"),
+    ("c4", "characters"),
+    ("c5", "characters"),
+    ("c6", "characters"),
+];
+
+/// what `maintain_db` found and did
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    // "ok" or the problems sqlite reports
+    pub integrity: String,
+    // (table, rows, bytes if sqlite was built with dbstat)
+    pub tables: Vec<(String, i64, Option<i64>)>,
+    // (template, column) pointing at a missing prompt
+    pub orphans: Vec<(String, &'static str)>,
+    // references cleared plus templates deleted
+    pub fixed: usize,
+}
+
+/// checks the integrity, finds templates referring to deleted prompts, then runs ANALYZE and
+/// VACUUM and counts the rows of every table
+///
+/// with `fix_orphans` a dangling character is cleared and a template without a base prompt,
+/// which could not be loaded, is deleted
+pub fn maintain_db(conn: &Connection, fix_orphans: bool) -> Result<Maintenance, SqliteError> {
+    let mut report = Maintenance {
+        integrity: conn
+            .query_row("PRAGMA integrity_check", [], |r| r.get(0))
+            .map_err(SqliteError::new)?,
+        ..Maintenance::default()
+    };
+
+    let mut fixes = Vec::new();
+    for (column, table) in TEMPLATE_REFERENCES {
+        let mut condition = format!(
+            "{c} IS NOT NULL AND {c} NOT IN (SELECT id FROM {t})",
+            c = column,
+            t = table
+        );
+        if column == "base" {
+            condition = format!("base IS NULL OR ({})", condition);
+        }
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT name FROM templates WHERE {}", condition))
+            .map_err(SqliteError::new)?;
+        let names = stmt
+            .query_map([], |r| r.get::<usize, String>(0))
+            .map_err(SqliteError::new)?;
+        for name in names {
+            report
+                .orphans
+                .push((name.map_err(SqliteError::new)?, column));
+        }
+
+        fixes.push(if column == "base" {
+            format!("DELETE FROM templates WHERE {}", condition)
+        } else {
+            format!("UPDATE templates SET {} = NULL WHERE {}", column, condition)
+        });
+    }
+    if fix_orphans {
+        // base first, so deleted templates are not counted again for their characters
+        for sql in &fixes {
+            report.fixed += conn.execute(sql, []).map_err(SqliteError::new)?;
+        }
+    }
+
+    conn.execute_batch("ANALYZE; VACUUM;")
+        .map_err(SqliteError::new)?;
+
+    let mut stmt = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")
+        .map_err(SqliteError::new)?;
+    let tables = stmt
+        .query_map([], |r| r.get::<usize, String>(0))
+        .map_err(SqliteError::new)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SqliteError::new)?;
+    for table in tables {
+        let rows = conn
+            .query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |r| {
+                r.get(0)
+            })
+            .map_err(SqliteError::new)?;
+        // dbstat is an optional part of sqlite
+        let bytes = conn
+            .query_row(
+                "SELECT SUM(pgsize) FROM dbstat WHERE name = ?1",
+                [&table],
+                |r| r.get(0),
+            )
+            .ok();
+        report.tables.push((table, rows, bytes));
+    }
+    Ok(report)
+}
+
+/// imports the prompts of NAI pngs appearing in `dir` into the database at `db`, returning
+/// only if the watcher fails
+///
+/// a file is read once no event came for it for `SETTLE` and imported at most once per run
+pub fn watch(dir: &Path, db: &Path) -> Result<(), String> {
+    let conn = Connection::open(db).map_err(|e| e.to_string())?;
+    run_migrations(&conn).map_err(|e| e.to_string())?;
+    drop(conn);
+    let pool = Pool::new(manager(db)).map_err(|e| e.to_string())?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(|e| e.to_string())?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|e| format!("{:?}: {}", dir, e))?;
+    info!("watching {:?}", dir);
+
+    let mut pending = HashMap::new();
+    let mut imported = HashSet::new();
+    loop {
+        match rx.recv_timeout(SETTLE / 4) {
+            Ok(Ok(event)) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths {
+                    if path.extension().is_some_and(|e| e == "png") && !imported.contains(&path) {
+                        pending.insert(path, Instant::now());
+                    }
+                }
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => warning!("watcher: {}", e),
+            Err(RecvTimeoutError::Disconnected) => return Err("the watcher stopped".into()),
+        }
+
+        for path in settled(&mut pending, Instant::now()) {
+            match import_png(&pool, &path) {
+                Ok(Some((prompt, characters))) => {
+                    info!(
+                        "imported {:?}: {} with {} characters",
+                        path,
+                        snippet(&prompt, None),
+                        characters
+                    );
+                    imported.insert(path);
+                }
+                // also a png still being written, its next event queues it again
+                Ok(None) => debug!("no NAI metadata in {:?}", path),
+                Err(e) => warning!("failed to import {:?}: {}", path, e.err),
+            }
+        }
+    }
+}
+
+/// removes the paths without an event for `SETTLE` before `now` from `pending`, sorted
+fn settled(pending: &mut HashMap<PathBuf, Instant>, now: Instant) -> Vec<PathBuf> {
+    let mut ready = Vec::new();
+    pending.retain(|path, last| {
+        let waiting = now.duration_since(*last) < SETTLE;
+        if !waiting {
+            ready.push(path.clone());
+        }
+        waiting
+    });
+    ready.sort();
+    ready
+}
+
+/// saves the prompt of the png at `path` like `import_files`, returning it and the number of
+/// characters; None if the file has no NAI metadata
+fn import_png(
+    pool: &Pool<SqliteConnectionManager>,
+    path: &Path,
+) -> Result<Option<(String, usize)>, SqliteError> {
+    let Ok(meta) = get_prompt_metadata(pool, path) else {
+        return Ok(None);
+    };
+    let (prompt, characters) = (meta.prompt.clone(), meta.character_prompts());
+    let ts = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64);
+    let count = characters.len();
+    executor::block_on(save_prompt(
+        pool.clone(),
+        vec![(ts, prompt.clone(), characters)],
+        true,
+    ))?;
+    Ok(Some((prompt, count)))
+}
+
+/// what `stats` reports about the generated images
+#[derive(Debug, Default)]
+pub struct Stats {
+    // (yyyy-mm-dd, generations) of the latest days with any, newest first
+    pub per_day: Vec<(String, usize)>,
+    pub prompts: Vec<(String, usize)>,
+    pub tags: Vec<(String, usize)>,
+    // average time between consecutive generations of a session
+    pub average: Option<Duration>,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// counts the NAI images of the index per day, their most used prompts and tags and how long
+/// a generation takes, plus the files and bytes below `output_dir`
+///
+/// durations are not stored, so the average is taken over the gaps between the mtimes of
+/// consecutive images that are closer than `SESSION_GAP`
+pub fn stats(
+    conn: &Connection,
+    output_dir: &Path,
+    days: usize,
+    top: usize,
+) -> Result<Stats, SqliteError> {
+    let mut stmt = conn
+        .prepare("SELECT mtime, prompt FROM images WHERE nai = 1 ORDER BY mtime")
+        .map_err(SqliteError::new)?;
+    let rows = stmt
+        .query_map([], |r| {
+            Ok((r.get::<usize, i64>(0)?, r.get::<usize, Option<String>>(1)?))
+        })
+        .map_err(SqliteError::new)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SqliteError::new)?;
+
+    let mut per_day: HashMap<u64, usize> = HashMap::new();
+    let mut prompts: HashMap<&str, usize> = HashMap::new();
+    let mut tag_counts: HashMap<&str, usize> = HashMap::new();
+    for (mtime, prompt) in &rows {
+        let generated = UNIX_EPOCH + Duration::from_secs(u64::try_from(*mtime).unwrap_or(0));
+        *per_day.entry(day_of(generated)).or_default() += 1;
+        if let Some(prompt) = prompt {
+            *prompts.entry(prompt).or_default() += 1;
+            for tag in tags(prompt) {
+                *tag_counts.entry(tag).or_default() += 1;
+            }
+        }
+    }
+
+    let gaps: Vec<u64> = rows
+        .windows(2)
+        .filter_map(|w| u64::try_from(w[1].0 - w[0].0).ok())
+        .filter(|gap| *gap < SESSION_GAP.as_secs())
+        .collect();
+    let average = u64::try_from(gaps.len())
+        .ok()
+        .filter(|n| *n > 0)
+        .map(|n| Duration::from_secs(gaps.iter().sum::<u64>() / n));
+
+    let mut per_day: Vec<(u64, usize)> = per_day.into_iter().collect();
+    per_day.sort_by_key(|(day, _)| Reverse(*day));
+    per_day.truncate(days);
+
+    let mut files = Vec::new();
+    collect_files(output_dir, &mut files);
+
+    Ok(Stats {
+        per_day: per_day
+            .into_iter()
+            .map(|(day, n)| (format_day(day), n))
+            .collect(),
+        prompts: most_used(prompts, top),
+        tags: most_used(tag_counts, top),
+        average,
+        files: files.len(),
+        bytes: files.iter().sum(),
+    })
+}
+
+/// the `top` most counted, ties by name
+fn most_used(counts: HashMap<&str, usize>, top: usize) -> Vec<(String, usize)> {
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts
+        .into_iter()
+        .take(top)
+        .map(|(s, n)| (s.to_string(), n))
+        .collect()
+}
+
+/// the size of every file below `dir`
+fn collect_files(dir: &Path, sizes: &mut Vec<u64>) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in read_dir.flatten() {
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.is_dir() && !entry.file_type().is_ok_and(|t| t.is_symlink()) {
+            collect_files(&entry.path(), sizes);
+        } else if meta.is_file() {
+            sizes.push(meta.len());
+        }
+    }
+}
+
+/// a saved prompt found by `search_prompts`
+#[derive(Debug)]
+pub struct Hit {
+    pub kind: PromptKind,
+    pub name: String,
+    pub snippet: String,
+    score: i32,
+}
+
+/// every saved prompt as (kind, name, text), a template's text being its base and characters
+pub fn load_prompts() -> Result<Vec<(PromptKind, String, String)>, SqliteError> {
+    let pool =
+        r2d2::Pool::new(manager(db_path())).map_err(|e| SqliteError { err: e.to_string() })?;
+    let (base, base_map, characters, character_map, templates, template_map) =
+        fetch_prompts(pool).map_err(SqliteError::new)?;
+
+    let mut prompts = Vec::with_capacity(base.len() + characters.len() + templates.len());
+    for name in base {
+        let text = base_map.get(&name).cloned().unwrap_or_default();
+        prompts.push((PromptKind::Base, name, text));
+    }
+    for name in characters {
+        let text = character_map.get(&name).cloned().unwrap_or_default();
+        prompts.push((PromptKind::Character, name, text));
+    }
+    for name in templates {
+        let text = template_map.get(&name).map_or(String::new(), |t| {
+            let mut parts = vec![t.base.as_str()];
+            parts.extend(t.characters.iter().flatten().map(String::as_str));
+            parts.join("\n")
+        });
+        prompts.push((PromptKind::Template, name, text));
+    }
+    Ok(prompts)
+}
+
+/// prompts whose name fuzzy matches `query` or whose text contains all of its terms, best first
+///
+/// name matches rank above text matches; every one of `required` has to be a tag of the text,
+/// ignoring case. an empty query lists everything with the tags by name
+pub fn search_prompts(
+    prompts: Vec<(PromptKind, String, String)>,
+    query: &str,
+    required: &[String],
+    limit: usize,
+) -> Vec<Hit> {
+    let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    let required: Vec<String> = required.iter().map(|t| t.trim().to_lowercase()).collect();
+
+    let mut hits: Vec<Hit> = prompts
+        .into_iter()
+        .filter(|(_, _, text)| {
+            let tags: Vec<String> = tags(text).into_iter().map(str::to_lowercase).collect();
+            required.iter().all(|t| tags.contains(t))
+        })
+        .filter_map(|(kind, name, text)| {
+            let lower = text.to_lowercase();
+            let in_text = terms
+                .iter()
+                .map(|t| lower.find(t.as_str()))
+                .collect::<Option<Vec<_>>>();
+            let (score, at) = if terms.is_empty() {
+                (0, None)
+            } else if let Some((score, _)) = fuzzy_match(query, &name) {
+                (1000 + score, in_text.and_then(|at| at.into_iter().min()))
+            } else {
+                let at = in_text?;
+                (at.len() as i32, at.into_iter().min())
+            };
+            Some(Hit {
+                kind,
+                snippet: snippet(&text, at),
+                name,
+                score,
+            })
+        })
+        .collect();
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then(a.name.cmp(&b.name)));
+    hits.truncate(limit);
+    hits
+}
+
+// the text around byte offset `at`, or its start, on one line
+fn snippet(text: &str, at: Option<usize>) -> String {
+    // offsets found in the lowercased text can be off if lowercasing changed a char's length
+    let at = at.filter(|&at| text.is_char_boundary(at)).unwrap_or(0);
+    let before: Vec<char> = text[..at].chars().rev().take(SNIPPET_CONTEXT).collect();
+    let after: String = text[at..].chars().take(SNIPPET_CONTEXT).collect();
+    let mut snippet: String = before.into_iter().rev().collect();
+    if snippet.len() < at {
+        snippet.insert_str(0, "...");
+    }
+    snippet.push_str(&after);
+    if at + after.len() < text.len() {
+        snippet.push_str("...");
+    }
+    snippet.replace('\n', " ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn export_skips_images_without_metadata() {
+        let dir = std::env::temp_dir().join("prompts-export-metadata");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        image::RgbaImage::new(8, 8)
+            .save(dir.join("nested/plain.png"))
+            .unwrap();
+        std::fs::write(dir.join("notes.txt"), "not an image").unwrap();
+
+        let out = dir.join("meta.jsonl");
+        assert_eq!(export_metadata(&dir, &out).unwrap(), (0, 1));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let dir = std::env::temp_dir().join("prompts-backup");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (db, backup) = (dir.join("prompts.db"), dir.join("backup.db"));
+
+        let conn = Connection::open(&db).unwrap();
+        conn.execute_batch("CREATE TABLE base(name TEXT); INSERT INTO base VALUES ('kept');")
+            .unwrap();
+        backup_db(&db, &backup).unwrap();
+        assert!(backup_db(&db, &backup).is_err());
+
+        conn.execute("DELETE FROM base", []).unwrap();
+        drop(conn);
+        assert_eq!(
+            restore_db(&backup, &db).unwrap(),
+            Some(dir.join("prompts.db.bak"))
+        );
+        let name: String = Connection::open(&db)
+            .unwrap()
+            .query_row("SELECT name FROM base", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(name, "kept");
+
+        std::fs::write(dir.join("garbage.db"), "not a database").unwrap();
+        assert!(restore_db(&dir.join("garbage.db"), &db).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn maintenance_fixes_orphans() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::run_migrations(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO base(id, name, t) VALUES (1, 'b', 'x');
+             INSERT INTO characters(id, name, t) VALUES (1, 'c', 'y');
+             INSERT INTO templates(name, base, c1, c2) VALUES ('fine', 1, 1, NULL);
+             INSERT INTO templates(name, base, c1, c2) VALUES ('lost char', 1, 1, 7);
+             INSERT INTO templates(name, base, c1) VALUES ('lost base', 9, 7);",
+        )
+        .unwrap();
+
+        let report = maintain_db(&conn, false).unwrap();
+        assert_eq!(report.integrity, "ok");
+        assert_eq!(
+            report.orphans,
+            [
+                ("lost base".to_string(), "base"),
+                ("lost base".to_string(), "c1"),
+                ("lost char".to_string(), "c2"),
+            ]
+        );
+        assert_eq!(report.fixed, 0);
+        assert!(
+            report
+                .tables
+                .iter()
+                .any(|(t, rows, _)| t == "templates" && *rows == 3)
+        );
+
+        let report = maintain_db(&conn, true).unwrap();
+        assert_eq!(report.fixed, 2);
+        let report = maintain_db(&conn, false).unwrap();
+        assert!(report.orphans.is_empty());
+        assert!(
+            report
+                .tables
+                .iter()
+                .any(|(t, rows, _)| t == "templates" && *rows == 2)
+        );
+    }
+
+    #[test]
+    fn watch_waits_for_settled_files() {
+        let now = Instant::now();
+        let mut pending = HashMap::from([
+            (PathBuf::from("/dl/b.png"), now - SETTLE),
+            (PathBuf::from("/dl/a.png"), now - SETTLE * 2),
+            (PathBuf::from("/dl/c.png"), now - SETTLE / 2),
+        ]);
+        assert_eq!(
+            settled(&mut pending, now),
+            [PathBuf::from("/dl/a.png"), PathBuf::from("/dl/b.png")]
+        );
+        assert_eq!(pending.len(), 1);
+        assert!(settled(&mut pending, now).is_empty());
+        assert_eq!(settled(&mut pending, now + SETTLE).len(), 1);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn duplicates_newest_first() {
+        let dir = std::env::temp_dir().join("prompts-dedupe");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+
+        let gradient = image::RgbImage::from_fn(64, 64, |x, _| image::Rgb([x as u8 * 4; 3]));
+        let flipped = image::imageops::flip_horizontal(&gradient);
+        let save = |im: &image::RgbImage, name: &str, mtime: u64| {
+            let path = dir.join(name);
+            im.save(&path).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(mtime))
+                .unwrap();
+            path
+        };
+        let old = save(&gradient, "old.png", 100);
+        let new = save(&gradient, "nested/new.png", 300);
+        let newer = save(&gradient, "newer.png", 200);
+        save(&flipped, "other.png", 400);
+        std::fs::write(dir.join("broken.png"), b"not a png").unwrap();
+
+        assert_eq!(find_duplicates_in(&dir, 4), [vec![new, newer, old]]);
+    }
+
+    #[test]
+    fn stats_of_the_index() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::run_migrations(&conn).unwrap();
+        // 2026-10-14 and 2026-10-15, 12:00 UTC
+        let (day1, day2) = (1_791_979_200, 1_792_065_600);
+        for (path, mtime, nai, prompt) in [
+            ("a.png", day1, 1, "1girl, smile"),
+            ("b.png", day1 + 20, 1, "1girl, smile"),
+            ("c.png", day1 + 60, 1, "1girl, sky"),
+            ("d.png", day2, 1, "cat, sky"),
+            ("e.png", day2 + 10, 0, "not nai"),
+        ] {
+            conn.execute(
+                "INSERT INTO images(path, mtime, nai, prompt) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![path, mtime, nai, prompt],
+            )
+            .unwrap();
+        }
+
+        let dir = std::env::temp_dir().join("prompts-stats");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("x.png"), [0; 10]).unwrap();
+        std::fs::write(dir.join("nested/y.png"), [0; 5]).unwrap();
+
+        let stats = stats(&conn, &dir, 7, 2).unwrap();
+        assert_eq!(
+            stats.per_day,
+            [("2026-10-15".to_string(), 1), ("2026-10-14".to_string(), 3)]
+        );
+        assert_eq!(
+            stats.prompts,
+            [
+                ("1girl, smile".to_string(), 2),
+                ("1girl, sky".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            stats.tags,
+            [("1girl".to_string(), 3), ("sky".to_string(), 2)]
+        );
+        // the day between is not a session
+        assert_eq!(stats.average, Some(Duration::from_secs(30)));
+        assert_eq!((stats.files, stats.bytes), (2, 15));
+    }
+
+    #[test]
+    fn watch_saves_a_repeated_prompt_once() {
+        let dir = std::env::temp_dir().join("prompts-watch-import");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let im = image::DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+        let map = json!({"Comment": {"prompt": "1girl"}});
+        let png = dir.join("nai.png");
+        embed_metadata(im, map.as_object().unwrap())
+            .unwrap()
+            .save(&png)
+            .unwrap();
+
+        let pool = Pool::new(manager(dir.join("prompts.db"))).unwrap();
+        run_migrations(&pool.get().unwrap()).unwrap();
+        for _ in 0..2 {
+            let imported = import_png(&pool, &png).unwrap();
+            assert_eq!(imported, Some(("1girl".to_string(), 0)));
+        }
+
+        let templates: i64 = pool
+            .get()
+            .unwrap()
+            .query_row("SELECT COUNT(1) FROM templates", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(templates, 1);
+    }
+
+    #[test]
+    fn verify_against_the_index() {
+        let dir = std::env::temp_dir().join("prompts-verify");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let im = image::DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+        let map = json!({"Comment": {"prompt": "1girl"}});
+        embed_metadata(im.clone(), map.as_object().unwrap())
+            .unwrap()
+            .save(dir.join("nai.png"))
+            .unwrap();
+        im.save(dir.join("plain.png")).unwrap();
+        std::fs::write(dir.join("broken.png"), b"not a png").unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let mut pngs = Vec::new();
+        collect_pngs(&dir, &mut pngs);
+        for (path, mtime) in pngs {
+            if !path.ends_with("plain.png") {
+                conn.execute(
+                    "INSERT INTO images(path, mtime, nai) VALUES (?1, ?2, 1)",
+                    rusqlite::params![path.to_string_lossy(), mtime],
+                )
+                .unwrap();
+            }
+        }
+        for path in [dir.join("gone.png"), PathBuf::from("/elsewhere/a.png")] {
+            conn.execute(
+                "INSERT INTO images(path, mtime, nai) VALUES (?1, 0, 1)",
+                [path.to_string_lossy()],
+            )
+            .unwrap();
+        }
+
+        let report = verify(&conn, &dir).unwrap();
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.corrupt.len(), 1);
+        assert!(report.corrupt[0].0.ends_with("broken.png"));
+        assert_eq!(report.foreign, [dir.join("plain.png")]);
+        assert_eq!(report.missing, [dir.join("gone.png")]);
+        assert_eq!(report.unindexed, [dir.join("plain.png")]);
+    }
+
+    #[test]
+    fn metadata_fields() {
+        let map = json!({
+            "Source": "NovelAI Diffusion V4.5",
+            "Comment": {
+                "seed": 42,
+                "v4_prompt": {"caption": {"char_captions": [{"char_caption": "alice"}]}}
+            }
+        });
+        assert_eq!(metadata_field(&map, "Comment.seed"), Some(&json!(42)));
+        assert_eq!(
+            metadata_field(
+                &map,
+                "Comment.v4_prompt.caption.char_captions.0.char_caption"
+            ),
+            Some(&json!("alice"))
+        );
+        assert_eq!(metadata_field(&map, "Source.name"), None);
+        assert_eq!(
+            metadata_field(&map, "Comment.v4_prompt.caption.char_captions.1"),
+            None
+        );
+        assert_eq!(metadata_field(&map, "Comment.missing"), None);
+    }
+
+    #[test]
+    fn renames_epoch_outputs() {
+        assert_eq!(
+            render_name("{date}_{time}_{seed}_{n}", 1_792_065_601, Some(7), 2),
+            "2026-10-15_120001_7_2"
+        );
+
+        let dir = std::env::temp_dir().join("prompts-rename");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "1792065600.png",
+            "1792065601.png",
+            "1792152000.png",
+            "kept.png",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        // already taken by an earlier run
+        std::fs::write(dir.join("2026-10-16_1.png"), b"").unwrap();
+
+        let renames = plan_renames(&dir, "{date}_{n}").unwrap();
+        let names: Vec<(String, String)> = renames
+            .iter()
+            .map(|(old, new)| {
+                let name = |p: &PathBuf| p.file_name().unwrap().to_string_lossy().to_string();
+                (name(old), name(new))
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("1792065600.png".into(), "2026-10-15_1.png".into()),
+                ("1792065601.png".into(), "2026-10-15_2.png".into()),
+                ("1792152000.png".into(), "2026-10-16_2.png".into()),
+            ]
+        );
+
+        // the second image of 2026-10-15 has nowhere to go without {n}
+        assert_eq!(plan_renames(&dir, "{date}").unwrap().len(), 2);
+        assert!(plan_renames(&dir, "{day}").is_err());
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(wildcard_match("*.png", "123.png"));
+        assert!(wildcard_match("a?c*", "abc"));
+        assert!(wildcard_match("*b*b", "abbab"));
+        assert!(!wildcard_match("*.png", "123.jpg"));
+        assert!(!wildcard_match("a?c", "ac"));
+
+        let dir = std::env::temp_dir().join("prompts-glob");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        for name in ["1.png", "2.jpg", "a/3.png", "a/b/4.png", "a/b/5.txt"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let root = dir.to_string_lossy();
+        let found = |pattern: &str| -> Vec<String> {
+            glob(&format!("{}/{}", root, pattern))
+                .iter()
+                .map(|p| p.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(found("**/*.png"), ["1.png", "a/3.png", "a/b/4.png"]);
+        assert_eq!(found("a/**"), ["a/3.png", "a/b/4.png", "a/b/5.txt"]);
+        assert_eq!(found("*/?.png"), ["a/3.png"]);
+        assert_eq!(found("a/b/4.png"), ["a/b/4.png"]);
+        assert!(found("*.gif").is_empty());
+    }
+
+    #[test]
+    fn search_ranks_names_first() {
+        let prompts = || {
+            vec![
+                (
+                    PromptKind::Base,
+                    "beach day".to_string(),
+                    "1girl, swimsuit, beach".to_string(),
+                ),
+                (
+                    PromptKind::Character,
+                    "surfer".to_string(),
+                    "1boy, surfboard,\nbeach, tan".to_string(),
+                ),
+                (
+                    PromptKind::Template,
+                    "night".to_string(),
+                    "city, night".to_string(),
+                ),
+            ]
+        };
+        let names = |hits: Vec<Hit>| -> Vec<String> { hits.into_iter().map(|h| h.name).collect() };
+
+        assert_eq!(
+            names(search_prompts(prompts(), "beach", &[], 10)),
+            ["beach day", "surfer"]
+        );
+        assert_eq!(
+            names(search_prompts(prompts(), "beach", &["TAN".into()], 10)),
+            ["surfer"]
+        );
+        assert_eq!(names(search_prompts(prompts(), "", &[], 2)).len(), 2);
+        assert!(search_prompts(prompts(), "forest", &[], 10).is_empty());
+
+        let hit = &search_prompts(prompts(), "tan", &[], 10)[0];
+        assert_eq!(hit.snippet, "1boy, surfboard, beach, tan");
+    }
+}
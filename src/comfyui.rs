@@ -0,0 +1,269 @@
+use serde_json::{Map, Value, json};
+
+use crate::a1111::{a1111_to_nai, nai_comment, nai_sampler};
+
+// the graph ComfyUI ran, node id -> {"class_type", "inputs"}, and the one its editor shows
+const PROMPT: &str = "prompt";
+const WORKFLOW: &str = "workflow";
+
+// links followed from a sampler before giving up on finding its text or seed
+const MAX_DEPTH: usize = 8;
+
+// inputs holding the text of the common prompt and string nodes, in order of preference
+const TEXT_INPUTS: [&str; 5] = ["text", "text_g", "string", "value", "prompt"];
+
+// inputs that lead to models rather than to the prompt
+const MODEL_INPUTS: [&str; 3] = ["clip", "model", "vae"];
+
+// names of the widgets of editor nodes, as the inputs of the same node in the prompt graph
+const WIDGETS: [(&str, &[&str]); 7] = [
+    ("CLIPTextEncode", &["text"]),
+    (
+        "KSampler",
+        &[
+            "seed",
+            "",
+            "steps",
+            "cfg",
+            "sampler_name",
+            "scheduler",
+            "denoise",
+        ],
+    ),
+    (
+        "KSamplerAdvanced",
+        &[
+            "add_noise",
+            "noise_seed",
+            "",
+            "steps",
+            "cfg",
+            "sampler_name",
+            "scheduler",
+        ],
+    ),
+    ("EmptyLatentImage", &["width", "height", "batch_size"]),
+    ("CheckpointLoaderSimple", &["ckpt_name"]),
+    ("PrimitiveNode", &["value"]),
+    ("UNETLoader", &["unet_name"]),
+];
+
+/// NAI metadata, as `extract_image_metadata` returns it, from the ComfyUI graph among the text
+/// `chunks` of a png
+///
+/// the prompts are those linked to the positive and negative inputs of the first sampler; the
+/// editor graph is only read when the prompt graph is missing
+pub fn from_chunks(chunks: &[(String, String)]) -> Option<Map<String, Value>> {
+    let chunk = |keyword: &str| {
+        chunks
+            .iter()
+            .find(|(k, _)| k == keyword)
+            .and_then(|(_, text)| serde_json::from_str::<Value>(text).ok())
+    };
+    let graph = match chunk(PROMPT) {
+        Some(Value::Object(graph)) => graph,
+        _ => from_workflow(&chunk(WORKFLOW)?)?,
+    };
+    from_graph(&graph)
+}
+
+fn from_graph(graph: &Map<String, Value>) -> Option<Map<String, Value>> {
+    // ids are numbers, the first sampler is the one that ran first
+    let mut nodes: Vec<(&String, &Value)> = graph.iter().collect();
+    nodes.sort_by_key(|(id, _)| (id.parse::<u64>().unwrap_or(u64::MAX), *id));
+    let inputs = nodes.into_iter().find_map(|(_, node)| {
+        let inputs = node.get("inputs")?.as_object()?;
+        (inputs.contains_key("positive") && inputs.contains_key("negative")).then_some(inputs)
+    })?;
+
+    let positive = text_of(graph, &inputs["positive"], 0)?;
+    let negative = text_of(graph, &inputs["negative"], 0).unwrap_or_default();
+    let mut comment = nai_comment(a1111_to_nai(&positive), Vec::new(), a1111_to_nai(&negative));
+
+    let seed = inputs.get("seed").or_else(|| inputs.get("noise_seed"));
+    for (key, value) in [
+        ("seed", seed),
+        ("steps", inputs.get("steps")),
+        ("scale", inputs.get("cfg")),
+    ] {
+        if let Some(n) = value.and_then(|v| number_of(graph, v, 0)) {
+            comment.insert(key.into(), n);
+        }
+    }
+    if let Some(sampler) = inputs.get("sampler_name").and_then(Value::as_str) {
+        comment.insert("sampler".into(), nai_sampler(sampler).into());
+    }
+    if let Some(scheduler) = inputs.get("scheduler").and_then(Value::as_str) {
+        comment.insert("noise_schedule".into(), scheduler.into());
+    }
+    if let Some(latent) = inputs.get("latent_image").and_then(|v| node_of(graph, v)) {
+        for key in ["width", "height"] {
+            if let Some(n) = latent.get(key).and_then(|v| number_of(graph, v, 0)) {
+                comment.insert(key.into(), n);
+            }
+        }
+    }
+
+    let mut map = Map::new();
+    map.insert("Software".into(), "ComfyUI".into());
+    let model = graph.values().find_map(|node| {
+        let inputs = node.get("inputs")?;
+        inputs
+            .get("ckpt_name")
+            .or_else(|| inputs.get("unet_name"))?
+            .as_str()
+    });
+    if let Some(model) = model {
+        map.insert("Source".into(), model.into());
+    }
+    map.insert("Comment".into(), Value::Object(comment));
+    Some(map)
+}
+
+/// the inputs of the node a `[id, slot]` link points to
+fn node_of<'a>(graph: &'a Map<String, Value>, link: &Value) -> Option<&'a Map<String, Value>> {
+    let id = link.as_array()?.first()?;
+    let id = id.as_str().map_or_else(|| id.to_string(), String::from);
+    graph.get(&id)?.get("inputs")?.as_object()
+}
+
+/// the text at `value`, following links through string and conditioning nodes
+fn text_of(graph: &Map<String, Value>, value: &Value, depth: usize) -> Option<String> {
+    if let Some(text) = value.as_str() {
+        return Some(text.to_string());
+    }
+    if depth == MAX_DEPTH {
+        return None;
+    }
+    let inputs = node_of(graph, value)?;
+    TEXT_INPUTS
+        .iter()
+        .filter_map(|key| inputs.get(*key))
+        .chain(
+            inputs
+                .iter()
+                .filter(|(key, _)| !MODEL_INPUTS.contains(&key.as_str()))
+                .map(|(_, v)| v)
+                .filter(|v| v.is_array()),
+        )
+        .find_map(|v| text_of(graph, v, depth + 1))
+}
+
+/// the number at `value`, following links through primitive and seed nodes
+fn number_of(graph: &Map<String, Value>, value: &Value, depth: usize) -> Option<Value> {
+    if value.is_number() {
+        return Some(value.clone());
+    }
+    if depth == MAX_DEPTH {
+        return None;
+    }
+    let inputs = node_of(graph, value)?;
+    ["seed", "noise_seed", "value", "int"]
+        .iter()
+        .filter_map(|key| inputs.get(*key))
+        .find_map(|v| number_of(graph, v, depth + 1))
+}
+
+/// the prompt graph of an editor graph, with the widgets of known nodes named and the links
+/// as `[id, slot]` inputs
+fn from_workflow(workflow: &Value) -> Option<Map<String, Value>> {
+    // [id, from node, from slot, to node, to slot, type]
+    let links: Vec<&Vec<Value>> = workflow
+        .get("links")?
+        .as_array()?
+        .iter()
+        .filter_map(Value::as_array)
+        .collect();
+
+    let mut graph = Map::new();
+    for node in workflow.get("nodes")?.as_array()? {
+        let (Some(id), Some(kind)) = (node.get("id"), node.get("type").and_then(Value::as_str))
+        else {
+            continue;
+        };
+        let mut inputs = Map::new();
+        let widgets = node.get("widgets_values").and_then(Value::as_array);
+        if let (Some((_, names)), Some(widgets)) =
+            (WIDGETS.iter().find(|(k, _)| *k == kind), widgets)
+        {
+            for (name, value) in names.iter().zip(widgets) {
+                if !name.is_empty() {
+                    inputs.insert(name.to_string(), value.clone());
+                }
+            }
+        }
+        // a linked input replaces the widget it was converted from
+        for input in node
+            .get("inputs")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let (Some(name), Some(link)) = (
+                input.get("name").and_then(Value::as_str),
+                input.get("link").filter(|l| !l.is_null()),
+            ) else {
+                continue;
+            };
+            if let Some(l) = links.iter().find(|l| l.first() == Some(link)) {
+                inputs.insert(name.into(), json!([l.get(1)?, l.get(2)?]));
+            }
+        }
+        graph.insert(
+            id.as_str().map_or_else(|| id.to_string(), String::from),
+            json!({"class_type": kind, "inputs": inputs}),
+        );
+    }
+    Some(graph)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prompts_from_graphs() {
+        let prompt = json!({
+            "3": {"class_type": "KSampler", "inputs": {
+                "seed": ["10", 0], "steps": 28, "cfg": 5.5, "sampler_name": "euler_ancestral",
+                "scheduler": "karras", "positive": ["6", 0], "negative": ["7", 0],
+                "model": ["4", 0], "latent_image": ["5", 0]
+            }},
+            "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "noob.safetensors"}},
+            "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 832, "height": 1216}},
+            "6": {"class_type": "CLIPTextEncode", "inputs": {"text": ["9", 0], "clip": ["4", 1]}},
+            "7": {"class_type": "CLIPTextEncode", "inputs": {"text": "lowres", "clip": ["4", 1]}},
+            "9": {"class_type": "String Literal", "inputs": {"string": "1girl, (smile:1.1)"}},
+            "10": {"class_type": "Seed", "inputs": {"seed": 42}}
+        });
+        let chunks = [(PROMPT.to_string(), prompt.to_string())];
+        let map = from_chunks(&chunks).unwrap();
+        let comment = &map["Comment"];
+        assert_eq!(map["Source"], "noob.safetensors");
+        assert_eq!(comment["prompt"], "1girl, 1.1::smile::");
+        assert_eq!(comment["uc"], "lowres");
+        assert_eq!(comment["seed"], 42);
+        assert_eq!(comment["scale"], 5.5);
+        assert_eq!(comment["sampler"], "k_euler_ancestral");
+        assert_eq!(comment["width"], 832);
+
+        let workflow = json!({
+            "nodes": [
+                {"id": 3, "type": "KSampler",
+                 "inputs": [{"name": "positive", "link": 1}, {"name": "negative", "link": 2}],
+                 "widgets_values": [7, "randomize", 20, 7, "euler", "normal", 1]},
+                {"id": 6, "type": "CLIPTextEncode", "widgets_values": ["a cat"]},
+                {"id": 7, "type": "CLIPTextEncode", "widgets_values": ["blurry"]}
+            ],
+            "links": [[1, 6, 0, 3, 1, "CONDITIONING"], [2, 7, 0, 3, 2, "CONDITIONING"]]
+        });
+        let chunks = [(WORKFLOW.to_string(), workflow.to_string())];
+        let comment = from_chunks(&chunks).unwrap()["Comment"].clone();
+        assert_eq!(comment["prompt"], "a cat");
+        assert_eq!(comment["uc"], "blurry");
+        assert_eq!(comment["seed"], 7);
+        assert_eq!(comment["steps"], 20);
+
+        assert!(from_chunks(&[("parameters".into(), "1girl".into())]).is_none());
+    }
+}
@@ -0,0 +1,118 @@
+use std::ops::Deref;
+
+/// NAI stops reading the prompt after this many tokens
+pub const TOKEN_LIMIT: usize = 512;
+
+// counts above this share of the limit are shown as a warning
+const WARN_RATIO: f32 = 0.9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Ok,
+    Warning,
+    Over,
+}
+
+impl Level {
+    pub fn of(tokens: usize) -> Self {
+        if tokens > TOKEN_LIMIT {
+            Self::Over
+        } else if tokens as f32 > TOKEN_LIMIT as f32 * WARN_RATIO {
+            Self::Warning
+        } else {
+            Self::Ok
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct LineStats {
+    tags: usize,
+    tokens: usize,
+}
+
+impl LineStats {
+    fn count(line: &str) -> Self {
+        Self {
+            tags: line.split(',').filter(|t| !t.trim().is_empty()).count(),
+            tokens: approx_tokens(line),
+        }
+    }
+}
+
+/// tag and token counts of one prompt editor, kept per line so an edit only recounts the lines
+/// it changed
+#[derive(Debug, Clone, Default)]
+pub struct PromptStats {
+    lines: Vec<(String, LineStats)>,
+    tags: usize,
+    tokens: usize,
+}
+
+impl PromptStats {
+    pub fn update<L: Deref<Target = str>>(&mut self, lines: impl IntoIterator<Item = L>) {
+        let mut n = 0;
+        for line in lines {
+            match self.lines.get_mut(n) {
+                Some((cached, _)) if **cached == *line => (),
+                Some(entry) => *entry = (line.to_string(), LineStats::count(&line)),
+                None => self.lines.push((line.to_string(), LineStats::count(&line))),
+            }
+            n += 1;
+        }
+        self.lines.truncate(n);
+
+        self.tags = self.lines.iter().map(|(_, s)| s.tags).sum();
+        self.tokens = self.lines.iter().map(|(_, s)| s.tokens).sum();
+    }
+
+    pub fn tags(&self) -> usize {
+        self.tags
+    }
+
+    pub fn tokens(&self) -> usize {
+        self.tokens
+    }
+}
+
+/// a rough count of T5 tokens: about one per four characters of a word, one per punctuation mark
+///
+/// NAI does not expose its tokenizer, so this only needs to be close enough to warn in time
+pub fn approx_tokens(s: &str) -> usize {
+    let mut tokens = 0;
+    let mut word: usize = 0;
+    for c in s.chars() {
+        if c.is_alphanumeric() {
+            word += 1;
+            continue;
+        }
+        tokens += word.div_ceil(4);
+        word = 0;
+        if !c.is_whitespace() {
+            tokens += 1;
+        }
+    }
+    tokens + word.div_ceil(4)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_follow_edits() {
+        assert_eq!(approx_tokens("1girl, blue hair"), 5);
+        assert_eq!(approx_tokens("1.2::smile::"), 9);
+
+        let mut stats = PromptStats::default();
+        stats.update(["1girl, blue hair", "smile"]);
+        assert_eq!((stats.tags(), stats.tokens()), (3, 7));
+
+        stats.update(["1girl, blue hair,"]);
+        assert_eq!((stats.tags(), stats.tokens()), (2, 6));
+
+        assert_eq!(Level::of(100), Level::Ok);
+        assert_eq!(Level::of(480), Level::Warning);
+        assert_eq!(Level::of(TOKEN_LIMIT + 1), Level::Over);
+    }
+}
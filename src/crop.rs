@@ -0,0 +1,289 @@
+use std::path::{Path, PathBuf};
+
+use iced::{
+    Color, Element, Event, Length, Point, Rectangle, Size, Theme,
+    advanced::{
+        Clipboard, Layout, Shell, Widget, image, layout, mouse, renderer,
+        widget::{Tree, tree},
+    },
+    event,
+    widget::image::Handle,
+};
+
+use crate::{swipe::fit, ui::Message};
+
+const BORDER_WIDTH: f32 = 1.0;
+
+/// a rectangle of an image in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// the region spanned by two corners in pixels, clamped to an image of `size`
+    ///
+    /// None if it is empty
+    pub fn between(a: (f32, f32), b: (f32, f32), size: (u32, u32)) -> Option<Self> {
+        let clamp = |v: f32, max: u32| v.round().clamp(0.0, max as f32) as u32;
+        let (x0, x1) = (clamp(a.0.min(b.0), size.0), clamp(a.0.max(b.0), size.0));
+        let (y0, y1) = (clamp(a.1.min(b.1), size.1), clamp(a.1.max(b.1), size.1));
+        (x1 > x0 && y1 > y0).then_some(Self {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        })
+    }
+}
+
+/// where the crop of `source` is exported, next to it as `<stem>-crop-<x>-<y>-<w>x<h>.png`
+pub fn crop_path(source: &Path, r: Region) -> PathBuf {
+    let stem = source
+        .file_stem()
+        .map_or("image".into(), |s| s.to_string_lossy());
+    source.with_file_name(format!(
+        "{}-crop-{}-{}-{}x{}.png",
+        stem, r.x, r.y, r.width, r.height
+    ))
+}
+
+/// writes the `region` of the image at `source` as a png next to it
+pub fn export(source: &Path, region: Region) -> Result<PathBuf, String> {
+    let im = ::image::open(source).map_err(|e| format!("{:?}: {}", source, e))?;
+    let cropped = im.crop_imm(region.x, region.y, region.width, region.height);
+    let path = crop_path(source, region);
+    cropped
+        .save_with_format(&path, ::image::ImageFormat::Png)
+        .map_err(|e| format!("{:?}: {}", path, e))?;
+    Ok(path)
+}
+
+/// an image fitted into its bounds with the selected region outlined and the rest dimmed
+///
+/// dragging anywhere selects a new region, which is sent as `Message::CropSelect`
+pub struct Crop {
+    handle: Handle,
+    size: (u32, u32),
+    region: Option<Region>,
+}
+
+impl Crop {
+    pub fn new(handle: Handle, size: (u32, u32), region: Option<Region>) -> Self {
+        Self {
+            handle,
+            size,
+            region,
+        }
+    }
+
+    // the image pixel under `pos`, which may be outside of the image
+    fn pixel_at(&self, pos: Point, bounds: Rectangle) -> (f32, f32) {
+        let fit = fit(self.size, bounds);
+        (
+            (pos.x - fit.x) / fit.width * self.size.0 as f32,
+            (pos.y - fit.y) / fit.height * self.size.1 as f32,
+        )
+    }
+}
+
+// the pixel the drag started at
+#[derive(Default)]
+struct Dragging(Option<(f32, f32)>);
+
+impl<Renderer> Widget<Message, Theme, Renderer> for Crop
+where
+    Renderer: image::Renderer<Handle = Handle>,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<Dragging>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(Dragging::default())
+    }
+
+    fn size(&self) -> Size<Length> {
+        Size::new(Length::Fill, Length::Fill)
+    }
+
+    fn layout(
+        &self,
+        _tree: &mut Tree,
+        _renderer: &Renderer,
+        limits: &layout::Limits,
+    ) -> layout::Node {
+        layout::Node::new(limits.max())
+    }
+
+    fn draw(
+        &self,
+        _tree: &Tree,
+        renderer: &mut Renderer,
+        theme: &Theme,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+    ) {
+        let fit = fit(self.size, layout.bounds());
+        renderer.draw_image(image::Image::new(self.handle.clone()), fit);
+
+        let Some(r) = self.region else {
+            return;
+        };
+        let scale = fit.width / self.size.0.max(1) as f32;
+        let selected = Rectangle::new(
+            Point::new(fit.x + r.x as f32 * scale, fit.y + r.y as f32 * scale),
+            Size::new(r.width as f32 * scale, r.height as f32 * scale),
+        );
+
+        // the four sides around the selection
+        let dim = Color {
+            a: 0.6,
+            ..Color::BLACK
+        };
+        let (right, bottom) = (selected.x + selected.width, selected.y + selected.height);
+        for side in [
+            Rectangle::new(fit.position(), Size::new(fit.width, selected.y - fit.y)),
+            Rectangle::new(
+                Point::new(fit.x, bottom),
+                Size::new(fit.width, fit.y + fit.height - bottom),
+            ),
+            Rectangle::new(
+                Point::new(fit.x, selected.y),
+                Size::new(selected.x - fit.x, selected.height),
+            ),
+            Rectangle::new(
+                Point::new(right, selected.y),
+                Size::new(fit.x + fit.width - right, selected.height),
+            ),
+        ] {
+            renderer.fill_quad(
+                renderer::Quad {
+                    bounds: side,
+                    ..renderer::Quad::default()
+                },
+                dim,
+            );
+        }
+
+        renderer.fill_quad(
+            renderer::Quad {
+                bounds: selected,
+                border: iced::Border {
+                    color: theme.extended_palette().primary.strong.color,
+                    width: BORDER_WIDTH,
+                    radius: 0.0.into(),
+                },
+                ..renderer::Quad::default()
+            },
+            Color::TRANSPARENT,
+        );
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        _viewport: &Rectangle,
+    ) -> event::Status {
+        let Event::Mouse(event) = event else {
+            return event::Status::Ignored;
+        };
+        let dragging = &mut tree.state.downcast_mut::<Dragging>().0;
+        let bounds = layout.bounds();
+
+        match event {
+            mouse::Event::ButtonPressed(mouse::Button::Left) => {
+                let Some(pos) = cursor.position_over(bounds) else {
+                    return event::Status::Ignored;
+                };
+                *dragging = Some(self.pixel_at(pos, bounds));
+                shell.publish(Message::CropSelect(None));
+                event::Status::Captured
+            }
+            mouse::Event::CursorMoved { position } => {
+                let Some(start) = *dragging else {
+                    return event::Status::Ignored;
+                };
+                let end = self.pixel_at(position, bounds);
+                shell.publish(Message::CropSelect(Region::between(start, end, self.size)));
+                event::Status::Captured
+            }
+            mouse::Event::ButtonReleased(mouse::Button::Left) if dragging.is_some() => {
+                *dragging = None;
+                event::Status::Captured
+            }
+            _ => event::Status::Ignored,
+        }
+    }
+
+    fn mouse_interaction(
+        &self,
+        _tree: &Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+        _renderer: &Renderer,
+    ) -> mouse::Interaction {
+        if cursor.is_over(layout.bounds()) {
+            mouse::Interaction::Crosshair
+        } else {
+            mouse::Interaction::default()
+        }
+    }
+}
+
+impl<'a, Renderer> From<Crop> for Element<'a, Message, Theme, Renderer>
+where
+    Renderer: image::Renderer<Handle = Handle> + 'a,
+{
+    fn from(crop: Crop) -> Self {
+        Element::new(crop)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn region_between_corners() {
+        let size = (100, 50);
+        let r = Region {
+            x: 10,
+            y: 5,
+            width: 20,
+            height: 15,
+        };
+        assert_eq!(Region::between((10.0, 5.0), (30.0, 20.0), size), Some(r));
+        // dragged up and left
+        assert_eq!(Region::between((30.2, 19.8), (9.6, 5.0), size), Some(r));
+        // clamped to the image
+        assert_eq!(
+            Region::between((-20.0, -5.0), (150.0, 60.0), size),
+            Some(Region {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 50
+            })
+        );
+        assert_eq!(Region::between((10.0, 5.0), (10.2, 40.0), size), None);
+        assert_eq!(Region::between((120.0, 5.0), (150.0, 40.0), size), None);
+
+        assert_eq!(
+            crop_path(Path::new("/out/1234.png"), r),
+            PathBuf::from("/out/1234-crop-10-5-20x15.png")
+        );
+    }
+}
@@ -1,12 +1,22 @@
-use std::{fs, path::Path, time::UNIX_EPOCH};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    pin::pin,
+};
 
+use iced::futures::StreamExt;
 use iced::widget::shader::wgpu::naga::FastHashMap;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rand::distr::{Alphanumeric, SampleString};
-use rusqlite::{Error, OptionalExtension, params};
+use rusqlite::{Connection, Error, OptionalExtension, Transaction, params, params_from_iter};
 
-use crate::ui::get_prompt_metadata;
+use crate::{
+    image_metadata::extract_many,
+    library::{IndexedMetadata, collect_images},
+    logs::debug,
+    metadata_cache,
+};
 
 #[derive(Debug, Clone)]
 pub struct SqliteError {
@@ -19,6 +29,23 @@ impl SqliteError {
     }
 }
 
+/// entries in the recently used list
+pub const RECENT_PROMPTS: usize = 10;
+
+/// statements each connection keeps compiled for `prepare_cached`
+const STATEMENT_CACHE: usize = 64;
+
+/// prompt texts looked up per query, below sqlite's limit on parameters
+const TEXT_LOOKUP_CHUNK: usize = 500;
+
+/// a manager for the database at `path` whose connections keep their prepared statements
+pub fn manager<P: AsRef<Path>>(path: P) -> SqliteConnectionManager {
+    SqliteConnectionManager::file(path).with_init(|conn| {
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE);
+        Ok(())
+    })
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PromptKind {
     Template,
@@ -42,18 +69,57 @@ pub struct PromptDb {
     pub prompt: String,
 }
 
+/// creates any missing tables; every up migration run here must be idempotent
+pub fn run_migrations(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(include_str!(
+        "../migrations/20250724234734_create_tables.up.sql"
+    ))?;
+    conn.execute_batch(include_str!("../migrations/20250730202229_snippets.up.sql"))?;
+    conn.execute_batch(include_str!(
+        "../migrations/20261016120000_image_index.up.sql"
+    ))?;
+    conn.execute_batch(include_str!(
+        "../migrations/20261016140000_favorites.up.sql"
+    ))?;
+    conn.execute_batch(include_str!(
+        "../migrations/20261016160000_metadata_cache.up.sql"
+    ))?;
+    conn.execute_batch(include_str!(
+        "../migrations/20261016170000_prompt_indexes.up.sql"
+    ))?;
+
+    // sqlite has no ADD COLUMN IF NOT EXISTS
+    let has_dhash = conn.query_row(
+        "SELECT COUNT(1) FROM pragma_table_info('images') WHERE name = 'dhash'",
+        [],
+        |r| r.get::<usize, bool>(0),
+    )?;
+    if !has_dhash {
+        conn.execute_batch(include_str!(
+            "../migrations/20261016130000_image_dhash.up.sql"
+        ))?;
+    }
+    let has_last_used = conn.query_row(
+        "SELECT COUNT(1) FROM pragma_table_info('base') WHERE name = 'last_used'",
+        [],
+        |r| r.get::<usize, bool>(0),
+    )?;
+    if !has_last_used {
+        conn.execute_batch(include_str!(
+            "../migrations/20261016150000_last_used.up.sql"
+        ))?;
+    }
+
+    Ok(())
+}
+
 pub fn update_prompt_name(
     pool: Pool<SqliteConnectionManager>,
     table: PromptKind,
     old_name: String,
     new_name: String,
 ) -> Result<usize, Error> {
-    let table_name = match table {
-        PromptKind::Character => "characters",
-        PromptKind::Base => "base",
-        PromptKind::Template => "templates",
-    };
-    let update = format!("UPDATE {} SET name = ?1 WHERE name = ?2", table_name);
+    let update = format!("UPDATE {} SET name = ?1 WHERE name = ?2", table_name(table));
 
     let conn = pool.get().unwrap();
     conn.execute(&update, [&new_name, &old_name])
@@ -146,77 +212,132 @@ pub fn fetch_prompts(
     ))
 }
 
+/// saves each (ts, base, characters), see `insert_prompts`; imports dedupe the templates, while
+/// Save New in the prompt pane always adds one
 pub async fn save_prompt(
     pool: Pool<SqliteConnectionManager>,
     metadata: Vec<(i64, String, Vec<String>)>,
+    dedupe_templates: bool,
 ) -> Result<(), SqliteError> {
     let mut conn = pool.get().unwrap();
-    let tx = conn.transaction().map_err(|e| SqliteError::new(e))?;
-    let insert_base = include_str!("../sql/i_base.sql");
-    let insert_char = include_str!("../sql/i_char.sql");
-    let insert_template = include_str!("../sql/i_template.sql");
-    let select_base = "SELECT id FROM base WHERE t = ?1";
-    let select_char = "SELECT id FROM characters WHERE t = ?1";
+    let tx = conn.transaction().map_err(SqliteError::new)?;
+    insert_prompts(&tx, metadata, dedupe_templates).map_err(SqliteError::new)?;
+    tx.commit().map_err(SqliteError::new)?;
+
+    Ok(())
+}
+
+/// prompts of one kind an import adds, and the ones it finds already saved
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Created {
+    pub new: usize,
+    pub existing: usize,
+}
+
+/// saves each (ts, base, characters) as a template, reusing the base prompts and characters
+/// whose text is already saved, and with `dedupe_templates` the templates as well
+///
+/// returns what was created for bases, characters and templates; nothing is committed so a
+/// dry run can roll it back
+fn insert_prompts(
+    tx: &Transaction,
+    metadata: Vec<(i64, String, Vec<String>)>,
+    dedupe_templates: bool,
+) -> Result<[Created; 3], Error> {
+    let mut base = tx.prepare_cached(include_str!("../sql/i_base.sql"))?;
+    let mut char = tx.prepare_cached(include_str!("../sql/i_char.sql"))?;
+    let mut template = tx.prepare_cached(include_str!("../sql/i_template.sql"))?;
+    let mut select_template = tx.prepare_cached(
+        "SELECT id FROM templates WHERE base IS ?1 AND c1 IS ?2 AND c2 IS ?3 AND c3 IS ?4
+         AND c4 IS ?5 AND c5 IS ?6 AND c6 IS ?7",
+    )?;
+    // the saved texts are looked up at once, and the ones added here join them
+    let mut base_ids = ids_by_text(tx, "base", metadata.iter().map(|(_, b, _)| b))?;
+    let mut char_ids = ids_by_text(tx, "characters", metadata.iter().flat_map(|(_, _, c)| c))?;
 
     let mut rng = rand::rng();
+    let [mut bases, mut chars, mut templates] = [Created::default(); 3];
 
-    {
-        let mut base = tx.prepare(insert_base).map_err(|e| SqliteError::new(e))?;
-        let mut char = tx.prepare(insert_char).map_err(|e| SqliteError::new(e))?;
-        let mut template = tx
-            .prepare(insert_template)
-            .map_err(|e| SqliteError::new(e))?;
-        let mut select_base = tx.prepare(select_base).map_err(|e| SqliteError::new(e))?;
-        let mut select_char = tx.prepare(select_char).map_err(|e| SqliteError::new(e))?;
-
-        for (ts, prompt, characters) in metadata {
-            let name = Alphanumeric.sample_string(&mut rng, 8);
-
-            let b = match base
-                .query_row(params![ts, name, prompt], |r| r.get::<usize, i64>(0))
-                .optional()
-            {
-                Ok(Some(id)) => Ok(id),
-                Ok(None) => Ok(select_base
-                    .query_row(params![prompt], |r| r.get::<usize, i64>(0))
-                    .map_err(|e| SqliteError::new(e)))?,
-                Err(e) => Err(SqliteError::new(e)),
-            }?;
-
-            let mut c: Vec<Option<i64>> = characters
-                .iter()
-                .try_fold(Vec::new(), |mut acc, s| {
-                    let name = Alphanumeric.sample_string(&mut rng, 8);
+    for (ts, prompt, characters) in metadata {
+        let name = Alphanumeric.sample_string(&mut rng, 8);
 
-                    match char
-                        .query_row(params![ts, name, s], |r| r.get::<usize, i64>(0))
-                        .optional()?
-                    {
-                        Some(id) => acc.push(Some(id)),
-                        None => acc.push(Some(
-                            select_char.query_row(params![s], |r| r.get::<usize, i64>(0))?,
-                        )),
-                    }
-                    Ok::<Vec<Option<i64>>, Error>(acc)
-                })
-                .map_err(|e| SqliteError::new(e))?;
-
-            let mut count = 6 - c.len();
-            while count > 0 {
-                c.push(None);
-                count -= 1;
+        let b = match base_ids.get(&prompt) {
+            Some(&id) => {
+                bases.existing += 1;
+                id
             }
-
-            template
-                .execute(params![ts, name, b, c[0], c[1], c[2], c[3], c[4], c[5]])
-                .map_err(|e| SqliteError::new(e))?;
-            eprintln!("inserted {}", name);
+            None => {
+                base.execute(params![ts, name, prompt])?;
+                bases.new += 1;
+                let id = tx.last_insert_rowid();
+                base_ids.insert(prompt, id);
+                id
+            }
+        };
+
+        let mut c: Vec<Option<i64>> = Vec::with_capacity(6);
+        for s in characters {
+            let id = match char_ids.get(&s) {
+                Some(&id) => {
+                    chars.existing += 1;
+                    id
+                }
+                None => {
+                    let name = Alphanumeric.sample_string(&mut rng, 8);
+                    char.execute(params![ts, name, s])?;
+                    chars.new += 1;
+                    let id = tx.last_insert_rowid();
+                    char_ids.insert(s, id);
+                    id
+                }
+            };
+            c.push(Some(id));
         }
+        c.resize(6, None);
+
+        if dedupe_templates {
+            let saved = select_template
+                .query_row(params![b, c[0], c[1], c[2], c[3], c[4], c[5]], |_| Ok(()))
+                .optional()?;
+            if saved.is_some() {
+                templates.existing += 1;
+                continue;
+            }
+        }
+        template.execute(params![ts, name, b, c[0], c[1], c[2], c[3], c[4], c[5]])?;
+        templates.new += 1;
+        debug!("inserted {}", name);
     }
 
-    tx.commit().map_err(|e| SqliteError::new(e))?;
+    Ok([bases, chars, templates])
+}
 
-    Ok(())
+/// text -> id of the prompts in `table` whose text is one of `texts`
+fn ids_by_text<'a>(
+    tx: &Transaction,
+    table: &str,
+    texts: impl Iterator<Item = &'a String>,
+) -> Result<FastHashMap<String, i64>, Error> {
+    let texts: Vec<&String> = texts.collect::<HashSet<_>>().into_iter().collect();
+    let mut ids = FastHashMap::default();
+    for chunk in texts.chunks(TEXT_LOOKUP_CHUNK) {
+        let sql = format!(
+            "SELECT t, id FROM {} WHERE t IN ({}) ORDER BY id",
+            table,
+            vec!["?"; chunk.len()].join(", ")
+        );
+        // not cached: each chunk size is its own statement and would push out the hot ones
+        let mut stmt = tx.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(chunk), |r| {
+            Ok((r.get::<usize, String>(0)?, r.get::<usize, i64>(1)?))
+        })?;
+        for row in rows {
+            let (t, id) = row?;
+            // the oldest of the same text, like a lookup of one text found
+            ids.entry(t).or_insert(id);
+        }
+    }
+    Ok(ids)
 }
 
 pub async fn update_prompt(
@@ -237,6 +358,52 @@ pub async fn update_prompt(
     }
 }
 
+fn table_name(kind: PromptKind) -> &'static str {
+    match kind {
+        PromptKind::Character => "characters",
+        PromptKind::Base => "base",
+        PromptKind::Template => "templates",
+    }
+}
+
+/// sets last_used of the named prompts to `ts` and returns the recently used ones
+pub async fn mark_used(
+    pool: Pool<SqliteConnectionManager>,
+    used: Vec<(PromptKind, String)>,
+    ts: i64,
+) -> Result<Vec<(PromptKind, String)>, SqliteError> {
+    let conn = pool.get().unwrap();
+    for (kind, name) in &used {
+        let update = format!(
+            "UPDATE {} SET last_used = ?1 WHERE name = ?2",
+            table_name(*kind)
+        );
+        conn.prepare_cached(&update)
+            .and_then(|mut stmt| stmt.execute(params![ts, name]))
+            .map_err(SqliteError::new)?;
+    }
+    recent_prompts(&conn, RECENT_PROMPTS).map_err(SqliteError::new)
+}
+
+/// the `limit` base prompts, characters and templates most recently used for generation
+pub fn recent_prompts(conn: &Connection, limit: usize) -> Result<Vec<(PromptKind, String)>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT 0, name, last_used FROM base WHERE last_used IS NOT NULL
+         UNION ALL SELECT 1, name, last_used FROM characters WHERE last_used IS NOT NULL
+         UNION ALL SELECT 2, name, last_used FROM templates WHERE last_used IS NOT NULL
+         ORDER BY last_used DESC LIMIT ?1",
+    )?;
+    let rows = stmt.query_map([limit], |r| {
+        let kind = match r.get::<usize, u8>(0)? {
+            0 => PromptKind::Base,
+            1 => PromptKind::Character,
+            _ => PromptKind::Template,
+        };
+        Ok((kind, r.get::<usize, String>(1)?))
+    })?;
+    rows.collect()
+}
+
 pub async fn delete_prompt(
     pool: Pool<SqliteConnectionManager>,
     kind: PromptKind,
@@ -254,28 +421,253 @@ pub async fn delete_prompt(
     }
 }
 
-pub async fn import_from_dir<P: AsRef<Path>>(dir: P) -> Result<usize, SqliteError> {
-    let mut metadata: Vec<(i64, String, Vec<String>)> = vec![];
-    let len = metadata.len();
-    let mut read_dir = fs::read_dir(dir).expect("read_dir");
-    while let Some(Ok(entry)) = read_dir.next() {
-        if let Ok(meta) = entry.metadata() {
-            let ts = meta
-                .modified()
-                .expect("modified")
-                .duration_since(UNIX_EPOCH)
-                .expect("duration_since")
-                .as_secs() as i64;
-            if let Some((_seed, prompt, characters)) = get_prompt_metadata(entry.path()) {
-                metadata.push((ts, prompt, characters));
+/// what `import_files` did, or would do on a dry run
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    // images without NAI metadata
+    pub skipped: usize,
+    pub failed: Vec<(PathBuf, String)>,
+    pub bases: Created,
+    pub characters: Created,
+    pub templates: Created,
+}
+
+/// imports every png, jpeg and webp below `dir` into the database at `db`, see `import_files`
+pub async fn import_from_dir<P: AsRef<Path>>(
+    db: &Path,
+    dir: P,
+    dry_run: bool,
+    progress: impl Fn(usize, usize),
+) -> Result<ImportReport, SqliteError> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        return Err(SqliteError {
+            err: format!("{:?} is not a folder", dir),
+        });
+    }
+    let mut images = vec![];
+    collect_images(dir, &mut images);
+    let paths: Vec<PathBuf> = images.into_iter().map(|(path, _)| path).collect();
+    import_files(db, &paths, dry_run, progress).await
+}
+
+/// saves the prompts of the given NAI images to the database at `db`, reading them on all cores
+/// and calling `progress` with the number of files read and the total after each one
+///
+/// a dry run rolls the changes back, so only the report says what would have been saved
+pub async fn import_files(
+    db: &Path,
+    paths: &[PathBuf],
+    dry_run: bool,
+    progress: impl Fn(usize, usize),
+) -> Result<ImportReport, SqliteError> {
+    let mut conn = Connection::open(db).map_err(SqliteError::new)?;
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE);
+    run_migrations(&conn).map_err(SqliteError::new)?;
+    let cache = Pool::new(manager(db)).ok();
+
+    let (mut metadata, mut report) = read_prompts(paths, cache.as_ref(), progress).await;
+    // in the order they were made, not in the order they were read
+    metadata.sort_by_key(|(ts, _, _)| *ts);
+    report.imported = metadata.len();
+
+    let tx = conn.transaction().map_err(SqliteError::new)?;
+    [report.bases, report.characters, report.templates] =
+        insert_prompts(&tx, metadata, true).map_err(SqliteError::new)?;
+    if dry_run { tx.rollback() } else { tx.commit() }.map_err(SqliteError::new)?;
+
+    Ok(report)
+}
+
+/// the prompts of `paths` with their mtimes, looking each file up in `cache` first; the rest
+/// are read with `extract_many` and cached afterwards in one go
+async fn read_prompts(
+    paths: &[PathBuf],
+    cache: Option<&Pool<SqliteConnectionManager>>,
+    progress: impl Fn(usize, usize),
+) -> (Vec<(i64, String, Vec<String>)>, ImportReport) {
+    let mut report = ImportReport::default();
+    let mut entries = vec![];
+    // path -> mtime of the files the cache has nothing for
+    let mut unread = FastHashMap::default();
+    {
+        let conn = cache.and_then(|pool| pool.get().ok());
+        for path in paths {
+            match metadata_cache::mtime_of(path) {
+                Ok(mtime) => match conn
+                    .as_deref()
+                    .and_then(|conn| metadata_cache::lookup(conn, path, mtime))
+                {
+                    Some(entry) => entries.push((mtime, entry)),
+                    None => {
+                        unread.insert(path.clone(), mtime);
+                    }
+                },
+                Err(e) => report.failed.push((path.clone(), e.to_string())),
             }
         }
     }
 
-    let manager = SqliteConnectionManager::file(std::env::var("SQLITE_URL").unwrap());
-    let pool = r2d2::Pool::new(manager).unwrap();
+    let mut done = paths.len() - unread.len();
+    progress(done, paths.len());
+    let mut read = vec![];
+    let mut extracted = pin!(extract_many(unread.keys().cloned().collect()));
+    while let Some((path, metadata)) = extracted.next().await {
+        match metadata_cache::entry_of(metadata) {
+            Ok(entry) => {
+                let mtime = unread[&path];
+                entries.push((mtime, entry.clone()));
+                read.push((path, mtime, entry));
+            }
+            Err(e) => report.failed.push((path, e.to_string())),
+        }
+        done += 1;
+        progress(done, paths.len());
+    }
+    if let Some(conn) = cache.and_then(|pool| pool.get().ok())
+        && let Err(e) = metadata_cache::store(&conn, &read)
+    {
+        debug!("metadata cache: {}", e);
+    }
+
+    let mut metadata = vec![];
+    for (mtime, entry) in entries {
+        match entry.as_ref().map(IndexedMetadata::from_map) {
+            Some(IndexedMetadata {
+                prompt: Some(prompt),
+                characters,
+                ..
+            }) => metadata.push((mtime, prompt, characters)),
+            _ => report.skipped += 1,
+        }
+    }
+    report.failed.sort();
+    (metadata, report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_prompts_reports_each_file() {
+        let dir = std::env::temp_dir().join("prompts-import");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let map = serde_json::json!({"Comment": {"prompt": "1girl", "seed": 1}});
+        let im = image::DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+        crate::image_metadata::embed_metadata(im.clone(), map.as_object().unwrap())
+            .unwrap()
+            .save(dir.join("nai.png"))
+            .unwrap();
+        im.save(dir.join("plain.png")).unwrap();
+        std::fs::write(dir.join("broken.png"), b"not a png").unwrap();
+        let paths: Vec<PathBuf> = ["nai.png", "plain.png", "broken.png", "missing.png"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+
+        let calls = std::sync::Mutex::new(vec![]);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (metadata, report) = runtime.block_on(read_prompts(&paths, None, |done, total| {
+            calls.lock().unwrap().push((done, total))
+        }));
+
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[0].1, "1girl");
+        assert_eq!(report.skipped, 1);
+        let failed: Vec<&PathBuf> = report.failed.iter().map(|(path, _)| path).collect();
+        assert_eq!(failed, [&paths[2], &paths[3]]);
+        assert_eq!(calls.into_inner().unwrap().last(), Some(&(4, 4)));
+    }
 
-    save_prompt(pool, metadata).await?;
+    #[test]
+    fn insert_prompts_reuses_saved_text() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        let tx = conn.transaction().unwrap();
+        let metadata = |base: &str, chars: &[&str]| {
+            (
+                0,
+                base.to_string(),
+                chars.iter().map(|c| c.to_string()).collect(),
+            )
+        };
+
+        let created = insert_prompts(
+            &tx,
+            vec![
+                metadata("1girl", &["alice"]),
+                metadata("1girl", &["alice"]),
+                metadata("1girl", &["alice", "bob"]),
+                metadata("cat", &[]),
+            ],
+            true,
+        )
+        .unwrap();
+        let new_existing = created.map(|c| (c.new, c.existing));
+        assert_eq!(new_existing, [(2, 2), (2, 2), (3, 1)]);
+
+        // saving from the prompt pane always adds a template
+        let created = insert_prompts(&tx, vec![metadata("cat", &[])], false).unwrap();
+        assert_eq!((created[2].new, created[2].existing), (1, 0));
+
+        // a rolled back dry run leaves nothing behind
+        tx.rollback().unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(1) FROM templates", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+
+        // saved texts are found past one lookup's worth
+        let many: Vec<_> = (0..TEXT_LOOKUP_CHUNK + 10)
+            .map(|i| metadata(&i.to_string(), &["alice"]))
+            .collect();
+        let tx = conn.transaction().unwrap();
+        insert_prompts(&tx, many.clone(), true).unwrap();
+        let created = insert_prompts(&tx, many, true).unwrap();
+        assert_eq!(created[0].new, 0);
+        assert_eq!(created[2].existing, TEXT_LOOKUP_CHUNK + 10);
+        tx.commit().unwrap();
+
+        let plan: String = conn
+            .query_row(
+                "EXPLAIN QUERY PLAN SELECT id FROM templates WHERE base IS 1 AND c1 IS 2
+                 AND c2 IS NULL AND c3 IS NULL AND c4 IS NULL AND c5 IS NULL AND c6 IS NULL",
+                [],
+                |r| r.get(3),
+            )
+            .unwrap();
+        assert!(plan.contains("templates_prompts"), "{}", plan);
+    }
 
-    Ok(len)
+    #[test]
+    fn recent_prompts_by_last_used() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO base(name, t) VALUES('never', 'a'), ('old', 'b'), ('new', 'c');
+             INSERT INTO characters(name, t) VALUES('girl', 'd');
+             UPDATE base SET last_used = 10 WHERE name = 'old';
+             UPDATE base SET last_used = 30 WHERE name = 'new';
+             UPDATE characters SET last_used = 20 WHERE name = 'girl';",
+        )
+        .unwrap();
+        // running the migrations again keeps the column
+        run_migrations(&conn).unwrap();
+
+        assert_eq!(
+            recent_prompts(&conn, 10).unwrap(),
+            [
+                (PromptKind::Base, "new".to_string()),
+                (PromptKind::Character, "girl".to_string()),
+                (PromptKind::Base, "old".to_string()),
+            ]
+        );
+        assert_eq!(recent_prompts(&conn, 1).unwrap().len(), 1);
+    }
 }
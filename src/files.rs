@@ -8,8 +8,10 @@ use std::{
 use iced::widget::{image::Handle, shader::wgpu::naga::FastHashMap};
 use slotmap::{SlotMap, new_key_type};
 
+use crate::logs::{debug, info};
+
 pub const MAX_VISIBLE: usize = 40;
-const FILE_EXTENSIONS: [&'static str; 3] = ["jpeg", "jpg", "png"];
+const FILE_EXTENSIONS: [&str; 4] = ["jpeg", "jpg", "png", "webp"];
 
 new_key_type! { pub struct EntryId; }
 
@@ -23,12 +25,17 @@ pub enum CreateEntryKind {
 pub enum EntryKind {
     Folder,
     File,
+    Symlink,
 }
 
 #[derive(Debug)]
 pub struct Entry {
     pub path: PathBuf,
     pub kind: EntryKind,
+    // where a symlink points, as stored in the link
+    pub target: Option<PathBuf>,
+    // the path with every link resolved, for folders and links to folders
+    pub canonical: Option<PathBuf>,
     pub parent: Option<EntryId>,
     pub children: Vec<EntryId>,
     pub expanded: bool,
@@ -43,6 +50,143 @@ impl Entry {
     }
 }
 
+/// a single file system operation of a batch, run off the UI thread
+#[derive(Debug, Clone)]
+///
+/// entries are named by path, since the tree may be rebuilt with new ids while a batch runs
+pub enum FileOp {
+    Delete { path: PathBuf, kind: EntryKind },
+    Move { from: PathBuf, to: PathBuf },
+}
+
+impl FileOp {
+    pub fn run(&self) -> Result<(), io::Error> {
+        match self {
+            Self::Delete {
+                path,
+                kind: EntryKind::Folder,
+                ..
+            } => fs::remove_dir_all(path),
+            // removes the link itself, never the target
+            Self::Delete {
+                path,
+                kind: EntryKind::File | EntryKind::Symlink,
+                ..
+            } => fs::remove_file(path),
+            Self::Move { from, to, .. } => fs::rename(from, to),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Delete { path, .. } => path,
+            Self::Move { from, .. } => from,
+        }
+    }
+}
+
+/// images opened from the tree, dropped least recently opened first once they take more than
+/// `max_bytes`
+///
+/// an image counts as its decoded size, which is what showing it takes
+pub struct ImageCache {
+    images: FastHashMap<PathBuf, CachedImage>,
+    bytes: u64,
+    max_bytes: u64,
+    // bumped on every open, orders the images for eviction
+    clock: u64,
+}
+
+struct CachedImage {
+    handle: Handle,
+    bytes: u64,
+    used: u64,
+}
+
+impl ImageCache {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            images: FastHashMap::default(),
+            bytes: 0,
+            max_bytes,
+            clock: 0,
+        }
+    }
+
+    pub fn contains_key(&self, path: &Path) -> bool {
+        self.images.contains_key(path)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Handle> {
+        self.images.get(path).map(|image| &image.handle)
+    }
+
+    /// opens the image at `path`, or marks it as just opened if it already is
+    pub fn open(&mut self, path: &Path) {
+        self.clock += 1;
+        if let Some(image) = self.images.get_mut(path) {
+            image.used = self.clock;
+            return;
+        }
+        // an image that cannot be read shows nothing and costs nothing
+        let bytes = image::image_dimensions(path).map_or(0, |(w, h)| w as u64 * h as u64 * 4);
+        self.bytes += bytes;
+        self.images.insert(
+            path.to_path_buf(),
+            CachedImage {
+                handle: Handle::from_path(path),
+                bytes,
+                used: self.clock,
+            },
+        );
+        self.evict();
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        if let Some(image) = self.images.remove(path) {
+            self.bytes -= image.bytes;
+        }
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path) -> bool) {
+        let paths: Vec<PathBuf> = self.images.keys().filter(|p| !keep(p)).cloned().collect();
+        for path in paths {
+            self.remove(&path);
+        }
+    }
+
+    /// the decoded size of the open images
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = max_bytes;
+        self.evict();
+    }
+
+    /// drops the least recently opened images until the rest fit, except the last one opened,
+    /// which is the one shown
+    fn evict(&mut self) {
+        while self.bytes > self.max_bytes && self.images.len() > 1 {
+            let Some(oldest) = self
+                .images
+                .iter()
+                .min_by_key(|(_, image)| image.used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            debug!("image cache: dropped {:?}", &oldest);
+            self.remove(&oldest);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct VisibleEntry {
     pub id: EntryId,
@@ -52,6 +196,8 @@ pub struct VisibleEntry {
 pub struct FileTree {
     pub entries: SlotMap<EntryId, Entry>,
     pub root: EntryId,
+    // additional watched directories, shown as top-level folders after the root
+    pub extra_roots: Vec<EntryId>,
 
     pub selected: EntryId,
     pub visible: Vec<VisibleEntry>,
@@ -59,11 +205,20 @@ pub struct FileTree {
 
     pub temp: Vec<EntryId>,
 
-    pub cache: FastHashMap<PathBuf, Handle>,
+    pub cache: ImageCache,
 
     // if the user is currently typing a new file/folder to create in the UI
     pub create_flag: bool,
 
+    // dotfiles are skipped when reading directories unless this is set
+    pub show_hidden: bool,
+
+    // when set, only files in this set (indexed NAI images) are shown
+    pub nai_only: Option<HashSet<PathBuf>>,
+
+    // compare runs of digits in filenames by value, so img2 comes before img10
+    pub natural_sort: bool,
+
     // file rename events come in pairs: old path then new path
     // track which path we are receiving
     pub notify_modify: bool,
@@ -72,11 +227,17 @@ pub struct FileTree {
 
 impl FileTree {
     pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self::with_hidden(dir, false)
+    }
+
+    pub fn with_hidden<P: AsRef<Path>>(dir: P, show_hidden: bool) -> Self {
         let mut entries = SlotMap::with_key();
 
         let root = entries.insert(Entry {
             path: dir.as_ref().to_path_buf(),
             kind: EntryKind::Folder,
+            target: None,
+            canonical: fs::canonicalize(dir.as_ref()).ok(),
             parent: None,
             children: Vec::new(),
             expanded: true,
@@ -87,6 +248,7 @@ impl FileTree {
         let mut ret = Self {
             entries,
             root,
+            extra_roots: Vec::new(),
 
             selected: root,
             visible: Vec::new(),
@@ -94,79 +256,213 @@ impl FileTree {
 
             temp: Vec::new(),
 
-            cache: FastHashMap::default(),
+            // limited by the settings once the ui has them
+            cache: ImageCache::new(u64::MAX),
 
             create_flag: false,
 
+            show_hidden,
+
+            nai_only: None,
+
+            natural_sort: true,
+
             notify_modify: false,
             modify_from: None,
         };
 
-        if let Ok(mut read_dir) = fs::read_dir(dir.as_ref()) {
+        ret.visit(root);
+
+        ret.visible = ret.visible_entries();
+        debug!("{:?}", &ret.visible);
+
+        ret
+    }
+
+    pub fn enter(&mut self) {
+        let id = self.selected;
+        let is_folder = self.is_folder(id);
+        let entry = &mut self.entries[id];
+        debug!("entered {:?}", entry);
+        if is_folder {
+            entry.expanded = !entry.expanded;
+            self.visit(id);
+
+            self.visible = self.visible_entries();
+            debug!("{:?}", &self.visible);
+        } else if is_image(&entry.path) {
+            if !self.cache.contains_key(&entry.path) {
+                self.cache.open(&entry.path);
+            } else {
+                self.cache.remove(&entry.path);
+            }
+        }
+    }
+
+    /// reads the children of a folder from disk the first time it is opened
+    fn visit(&mut self, id: EntryId) {
+        if self.entries[id].visited || !self.is_folder(id) {
+            return;
+        }
+        let entry = &mut self.entries[id];
+        entry.visited = true;
+
+        if let Ok(mut read_dir) = fs::read_dir(&entry.path) {
             while let Some(Ok(entry)) = read_dir.next() {
-                if let Ok(meta) = entry.metadata() {
-                    let kind = if meta.is_dir() {
+                if !self.show_hidden && is_hidden(&entry.path()) {
+                    continue;
+                }
+
+                // DirEntry::file_type does not follow symlinks
+                if let Ok(file_type) = entry.file_type() {
+                    let kind = if file_type.is_symlink() {
+                        EntryKind::Symlink
+                    } else if file_type.is_dir() {
                         EntryKind::Folder
                     } else {
                         EntryKind::File
                     };
 
-                    ret.add(root, entry.path(), kind);
+                    self.add(id, entry.path(), kind);
                 }
             }
         }
+    }
 
-        ret.visible = ret.visible_entries();
-        println!("{:?}", &ret.visible);
+    /// expands every ancestor of `path` and moves the cursor onto it
+    pub fn reveal<P: AsRef<Path>>(&mut self, path: P) -> Option<EntryId> {
+        let id = self.find_path(path)?;
 
-        ret
+        let mut parent = self.entries[id].parent;
+        while let Some(pid) = parent {
+            self.entries[pid].expanded = true;
+            parent = self.entries[pid].parent;
+        }
+
+        self.selected = id;
+        self.visible = self.visible_entries();
+        self.scroll_to_selected();
+
+        Some(id)
     }
 
-    pub fn enter(&mut self) {
-        let id = self.selected;
-        let entry = &mut self.entries[id];
-        println!("entered {:?}", entry);
-        match entry.kind {
-            EntryKind::Folder => {
-                entry.expanded = !entry.expanded;
+    /// looks up the entry for `path`, reading folders from disk along the way without expanding
+    /// them
+    fn find_path<P: AsRef<Path>>(&mut self, path: P) -> Option<EntryId> {
+        let (mut curr, relative) = self.tops().find_map(|id| {
+            path.as_ref()
+                .strip_prefix(&self.entries[id].path)
+                .ok()
+                .map(|relative| (id, relative.to_path_buf()))
+        })?;
+
+        for component in relative.components() {
+            self.visit(curr);
+
+            curr =
+                self.entries[curr].children.iter().copied().find(|&cid| {
+                    self.entries[cid].path.file_name() == Some(component.as_os_str())
+                })?;
+        }
+        Some(curr)
+    }
 
-                if !entry.visited {
-                    entry.visited = true;
+    /// moves the cursor to the next visible entry whose name starts with `prefix`, wrapping
+    /// around to the top, so repeating the same prefix cycles through the matches
+    pub fn jump_to(&mut self, prefix: &str) -> Option<EntryId> {
+        let prefix = prefix.to_lowercase();
+        let current = self
+            .visible
+            .iter()
+            .position(|ve| ve.id == self.selected)
+            .unwrap_or(0);
+
+        let n = self.visible.len();
+        let id = (1..=n)
+            .map(|offset| self.visible[(current + offset) % n].id)
+            .find(|&id| {
+                self.entries[id]
+                    .path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().to_lowercase().starts_with(&prefix))
+            })?;
 
-                    if let Ok(mut read_dir) = fs::read_dir(&entry.path) {
-                        while let Some(Ok(entry)) = read_dir.next() {
-                            if let Ok(meta) = entry.metadata() {
-                                let kind = if meta.is_dir() {
-                                    EntryKind::Folder
-                                } else {
-                                    EntryKind::File
-                                };
+        self.selected = id;
+        self.scroll_to_selected();
+        Some(id)
+    }
 
-                                self.add(id, entry.path(), kind);
-                            }
-                        }
-                    }
-                }
+    // keep the cursor inside the rendered window
+    fn scroll_to_selected(&mut self) {
+        if let Some(i) = self.visible.iter().position(|ve| ve.id == self.selected)
+            && (i < self.view_offset || i >= self.view_offset + MAX_VISIBLE)
+        {
+            self.view_offset = i.saturating_sub(MAX_VISIBLE / 2);
+        }
+    }
 
-                self.visible = self.visible_entries();
-                println!("{:?}", &self.visible);
-            }
-            EntryKind::File => {
-                if let Some(_) = entry
-                    .path
-                    .extension()
-                    .and_then(|ext| ext.to_str())
-                    .filter(|ext| FILE_EXTENSIONS.contains(&ext))
-                {
-                    if !self.cache.contains_key(&entry.path) {
-                        let handle = Handle::from_path(&entry.path);
-                        self.cache.insert(entry.path.clone(), handle);
-                    } else {
-                        self.cache.remove(&entry.path);
-                    }
-                }
+    /// re-reads the tree from disk, reopening previously expanded folders and keeping the cursor
+    /// on the selected entry, or its closest ancestor that still exists
+    pub fn refresh(&mut self) {
+        let mut expanded: Vec<PathBuf> = self
+            .entries
+            .values()
+            .filter(|e| e.kind != EntryKind::File && e.expanded)
+            .map(|e| e.path.clone())
+            .collect();
+        let selected = self.entries[self.selected].path.clone();
+        let view_offset = self.view_offset;
+        let mut cache = std::mem::replace(&mut self.cache, ImageCache::new(u64::MAX));
+
+        self.rebuild(self.root_path().to_path_buf(), self.show_hidden);
+
+        // parents first, so children are read from disk only once
+        expanded.sort_by_key(|p| p.components().count());
+        for path in expanded {
+            if let Some(id) = self.find_path(&path) {
+                self.visit(id);
+                self.entries[id].expanded = true;
             }
         }
+
+        if let Some(id) = selected.ancestors().find_map(|p| self.find_path(p)) {
+            self.selected = id;
+        }
+
+        cache.retain(|path| path.exists());
+        self.cache = cache;
+
+        self.visible = self.visible_entries();
+        self.view_offset = view_offset.min(self.visible.len().saturating_sub(1));
+        self.scroll_to_selected();
+    }
+
+    /// image files in the selected folder, or in the parent folder of the selected file
+    pub fn folder_images(&mut self) -> Vec<EntryId> {
+        let id = self.selected;
+        let folder = if self.is_folder(id) {
+            id
+        } else {
+            self.entries[id].parent.unwrap_or(self.root)
+        };
+        self.visit(folder);
+
+        self.entries[folder]
+            .children
+            .iter()
+            .copied()
+            .filter(|&cid| is_image(&self.entries[cid].path) && !self.is_filtered(cid))
+            .collect()
+    }
+
+    /// selects a file and loads it into the cache so the image pane shows it
+    pub fn preview(&mut self, id: EntryId) {
+        if !self.entries.contains_key(id) {
+            return;
+        }
+        self.selected = id;
+
+        self.cache.open(&self.entries[id].path);
     }
 
     pub fn add(&mut self, parent: EntryId, path: PathBuf, kind: EntryKind) -> Option<EntryId> {
@@ -174,10 +470,17 @@ impl FileTree {
             return None;
         }
 
-        println!("add {:?} to {:?}", &path, &parent);
+        debug!("add {:?} to {:?}", &path, &parent);
+        let target = match kind {
+            EntryKind::Symlink => fs::read_link(&path).ok(),
+            _ => None,
+        };
+        let canonical = self.canonical_of(parent, &path, kind);
         let id = self.entries.insert(Entry {
             path,
             kind,
+            target,
+            canonical,
             parent: Some(parent),
             children: Vec::new(),
             expanded: false,
@@ -191,7 +494,9 @@ impl FileTree {
 
     pub fn visible_entries(&self) -> Vec<VisibleEntry> {
         let mut result = Vec::new();
-        self.collect_visible(self.root, &mut result);
+        for id in self.tops() {
+            self.collect_visible(id, &mut result);
+        }
         result
     }
 
@@ -199,28 +504,25 @@ impl FileTree {
         let id = self.selected;
         let entry = &self.entries[id];
 
-        let (entry_id, parent_path) = match entry.kind {
-            EntryKind::Folder => (id, &entry.path),
-            EntryKind::File => {
-                if let Some(pid) = entry.parent {
-                    (pid, &self.entries[pid].path)
-                } else {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        "selected entry is a file without a parent",
-                    ));
-                }
-            }
+        let (entry_id, parent_path) = if self.is_folder(id) {
+            (id, &entry.path)
+        } else if let Some(pid) = entry.parent {
+            (pid, &self.entries[pid].path)
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "selected entry is a file without a parent",
+            ));
         };
 
         let (newpath, entry_kind) = match kind {
             CreateEntryKind::Folder(path) => {
-                println!("create dir: {:?}", &parent_path.join(&path));
+                info!("create dir: {:?}", &parent_path.join(&path));
                 let _ = fs::create_dir_all(parent_path.join(&path))?;
                 (path, EntryKind::Folder)
             }
             CreateEntryKind::File(path) => {
-                println!("create file: {:?}", &parent_path.join(&path));
+                info!("create file: {:?}", &parent_path.join(&path));
                 let _ = fs::File::create_new(parent_path.join(&path))?;
                 (path, EntryKind::File)
             }
@@ -235,7 +537,7 @@ impl FileTree {
     }
 
     pub fn delete(&mut self, id: EntryId) -> Result<EntryId, io::Error> {
-        if id == self.root {
+        if self.is_root(id) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
                 "cannot delete root",
@@ -243,12 +545,13 @@ impl FileTree {
         }
 
         let entry = &self.entries[id];
-        println!("deleting {:?}", &entry.path);
+        info!("deleting {:?}", &entry.path);
         match entry.kind {
             EntryKind::Folder => {
                 let _ = fs::remove_dir_all(&entry.path)?;
             }
-            EntryKind::File => {
+            // for symlinks this removes the link, not the target
+            EntryKind::File | EntryKind::Symlink => {
                 let _ = fs::remove_file(&entry.path)?;
             }
         }
@@ -283,107 +586,132 @@ impl FileTree {
         self.entries.remove(id);
     }
 
-    pub fn batch_delete(&mut self) -> Result<(), io::Error> {
-        let mut parents = HashSet::new();
-        for id in self.temp.clone() {
-            if id == self.root {
-                return Ok(());
-            }
-
-            let entry = &self.entries[id];
-            let r = match entry.kind {
-                EntryKind::Folder => fs::remove_dir_all(&entry.path),
-                EntryKind::File => fs::remove_file(&entry.path),
-            };
-            if let Err(e) = r {
-                if e.kind() == io::ErrorKind::NotFound {
-                    continue;
-                } else {
-                    return Err(e);
-                }
-            }
-
-            assert!(self.entries[id].parent.is_some());
-            let parent_id = self.entries[id].parent.unwrap();
-
-            self.entries[parent_id].children.retain(|cid| *cid != id);
-            parents.insert(parent_id);
-
-            self._delete_recursive(id);
-        }
-
-        // when finalizing batch delete, the selected entry may be an entry that will be deleted,
-        // so we need use the EntryId of the position of the earliest marked entry - 1
-        // however, looking this up is stupid
-        let m_id = self
-            .temp
+    /// one delete per marked entry, to be run with `FileOp::run` and applied with `apply`
+    pub fn batch_delete_ops(&self) -> Vec<FileOp> {
+        self.temp
             .iter()
-            .min_by(|a, b| {
-                let a_pos = self.visible.iter().position(|i| i.id == **a);
-                let b_pos = self.visible.iter().position(|i| i.id == **b);
-                a_pos.cmp(&b_pos)
+            .filter(|&&id| !self.is_root(id) && self.entries.contains_key(id))
+            .map(|&id| FileOp::Delete {
+                path: self.entries[id].path.clone(),
+                kind: self.entries[id].kind,
             })
-            .expect("empty visible");
-        let idx = self.visible.iter().position(|i| i.id == *m_id).unwrap_or(1);
-        self.selected = self.visible[idx - 1].id;
-
-        self.temp.clear();
-        self.visible = self.visible_entries();
-
-        Ok(())
+            .collect()
     }
 
-    pub fn batch_move(&mut self) -> Result<(), io::Error> {
-        let new_parent = self.selected;
-
-        let ids = &self.temp.clone();
+    /// one move per marked entry into the selected folder (or the folder of the selected file)
+    pub fn batch_move_ops(&self) -> Vec<FileOp> {
+        let selected = self.selected;
+        let new_parent = if self.is_folder(selected) {
+            selected
+        } else {
+            self.entries[selected].parent.unwrap_or(self.root)
+        };
 
-        if !self.entries.contains_key(new_parent) {
-            return Ok(());
-        }
+        self.temp
+            .iter()
+            .filter(|&&id| !self.is_root(id) && self.entries.contains_key(id))
+            .filter_map(|&id| {
+                let from = self.entries[id].path.clone();
+                let to = self.entries[new_parent].path.join(from.file_name()?);
+                Some(FileOp::Move { from, to })
+            })
+            .collect()
+    }
 
-        for &id in ids {
-            if id == self.root {
-                continue;
+    /// updates the tree after `op` succeeded on disk
+    ///
+    /// the watcher may have already removed the entry, so paths missing from the tree are ignored
+    pub fn apply(&mut self, op: &FileOp) {
+        match op {
+            FileOp::Delete { path, .. } => {
+                let Some(id) = self.entry_at(path).filter(|&id| !self.is_root(id)) else {
+                    return;
+                };
+                self.remove_entry(id);
             }
+            FileOp::Move { from, to } => {
+                let Some(id) = self.entry_at(from).filter(|&id| !self.is_root(id)) else {
+                    return;
+                };
+                // moved into a folder the tree has not read
+                let Some(new_parent) = to.parent().and_then(|parent| self.entry_at(parent)) else {
+                    self.remove_entry(id);
+                    self.visible = self.visible_entries();
+                    return;
+                };
 
-            let filename = self.entries[id].path.file_name().expect("invalid filename");
-            let newpath = self.entries[new_parent].path.join(filename);
+                if let Some(old_parent) = self.entries[id].parent {
+                    self.entries[old_parent].children.retain(|cid| *cid != id);
+                }
 
-            if let Err(e) = fs::rename(&self.entries[id].path, &newpath) {
-                return Err(e);
-            }
+                let canonical = self.canonical_of(new_parent, to, self.entries[id].kind);
+                let entry = &mut self.entries[id];
+                entry.path = to.clone();
+                entry.canonical = canonical;
+                entry.parent = Some(new_parent);
+                entry.marked = false;
+
+                // paths below a moved folder are stale, read them again on the next expand
+                entry.expanded = false;
+                let children = std::mem::take(&mut entry.children);
+                entry.visited = false;
+                for c in children {
+                    self._delete_recursive(c);
+                }
 
-            if let Some(old_parent) = self.entries[id].parent {
-                self.entries[old_parent].children.retain(|cid| *cid != id);
+                if self.entries[new_parent].visited {
+                    self.insert_sorted_child(new_parent, id);
+                }
+            }
+        }
 
-                // need to revisit the old parent
-                self.entries[old_parent].visited = false;
+        self.visible = self.visible_entries();
+    }
 
-                // we need to explicitly set expanded to false because the children have changed,
-                // and if we were to only clear the children, the entry is still in an expanded
-                // state
-                // so visually it would have no children but still be in the expanded state
-                // thus pressing enter would collapse it but have no visual effect
-                // therefore we do this to prevent needing to "double enter"
-                self.entries[old_parent].expanded = false;
-                self.entries[old_parent].children.clear();
-            }
+    /// drops `id` and everything below it from the tree, moving the cursor off it
+    fn remove_entry(&mut self, id: EntryId) {
+        let idx = self.visible.iter().position(|ve| ve.id == id);
+        if let Some(pid) = self.entries[id].parent {
+            self.entries[pid].children.retain(|cid| *cid != id);
+        }
+        self._delete_recursive(id);
 
-            self.entries[id].path = newpath;
-            self.entries[id].marked = false;
-            self.entries[id].parent = Some(new_parent);
-            self.entries[new_parent].visited = false;
-            self.entries[new_parent].expanded = false;
-            self.entries[new_parent].children.clear();
+        // the cursor may have been on (or inside) the deleted entry, fall back to the
+        // closest surviving entry above it
+        if !self.entries.contains_key(self.selected) {
+            self.selected = idx
+                .and_then(|i| {
+                    self.visible[..i]
+                        .iter()
+                        .rev()
+                        .find(|ve| self.entries.contains_key(ve.id))
+                })
+                .map_or(self.root, |ve| ve.id);
         }
+    }
 
-        self.sort_children(new_parent);
+    /// the entry of `path` among the ones already read, without touching the disk
+    pub fn entry_at(&self, path: &Path) -> Option<EntryId> {
+        let (mut curr, relative) = self.tops().find_map(|id| {
+            path.strip_prefix(&self.entries[id].path)
+                .ok()
+                .map(|relative| (id, relative))
+        })?;
+
+        for component in relative.components() {
+            curr =
+                self.entries[curr].children.iter().copied().find(|&cid| {
+                    self.entries[cid].path.file_name() == Some(component.as_os_str())
+                })?;
+        }
+        Some(curr)
+    }
 
-        self.temp.clear();
+    /// clears the batch once all of its operations have finished or were cancelled
+    pub fn finish_batch(&mut self) {
+        self.clear_marked();
         self.visible = self.visible_entries();
-
-        Ok(())
+        self.scroll_to_selected();
     }
 
     fn insert_sorted_child(&mut self, parent: EntryId, child: EntryId) {
@@ -394,25 +722,17 @@ impl FileTree {
         self.entries[parent].children.insert(pos, child);
     }
 
-    fn sort_children(&mut self, parent_id: EntryId) {
-        let mut ids = self.entries[parent_id]
-            .children
-            .iter()
-            .copied()
-            .collect::<Vec<EntryId>>();
-        ids.sort_by(|&a, &b| self.cmp_entries(a, b));
-        self.entries[parent_id].children = ids;
-    }
-
     fn collect_visible(&self, id: EntryId, out: &mut Vec<VisibleEntry>) {
         out.push(VisibleEntry {
             id,
             depth: self.depth(id),
         });
         let entry = &self.entries[id];
-        if entry.kind == EntryKind::Folder && entry.expanded {
+        if entry.kind != EntryKind::File && entry.expanded {
             for &child_id in &entry.children {
-                self.collect_visible(child_id, out);
+                if !self.is_filtered(child_id) {
+                    self.collect_visible(child_id, out);
+                }
             }
         }
     }
@@ -428,36 +748,175 @@ impl FileTree {
     pub fn mark(&mut self) {
         let id = self.selected;
         self.entries[id].marked = !self.entries[id].marked;
-        self.temp.push(id);
+        if self.entries[id].marked {
+            self.temp.push(id);
+        } else {
+            self.temp.retain(|&i| i != id);
+        }
+    }
+
+    /// existing paths that the batch move into the selected folder would overwrite
+    pub fn move_conflicts(&self) -> Vec<PathBuf> {
+        self.batch_move_ops()
+            .into_iter()
+            .filter_map(|op| match op {
+                FileOp::Move { from, to, .. } => (to != from && to.exists()).then_some(to),
+                FileOp::Delete { .. } => None,
+            })
+            .collect()
+    }
+
+    /// makes the selected entry the only marked one, so pasting it is a single-entry batch move
+    pub fn cut(&mut self) -> bool {
+        self.clear_marked();
+        if self.is_root(self.selected) {
+            return false;
+        }
+        self.mark();
+        true
     }
 
     pub fn clear_marked(&mut self) {
         if !self.temp.is_empty() {
+            // deleted entries are no longer in the slotmap
             for &i in &self.temp {
-                self.entries[i].marked = false;
+                if let Some(entry) = self.entries.get_mut(i) {
+                    entry.marked = false;
+                }
             }
 
             self.temp.clear();
         }
     }
 
+    /// re-roots the tree at `dir`
+    pub fn cd<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), io::Error> {
+        std::env::set_current_dir(dir.as_ref())?;
+        self.rebuild(dir.as_ref().to_path_buf(), self.show_hidden);
+        Ok(())
+    }
+
+    /// replaces the tree with a fresh one rooted at `dir`, carrying over the extra roots and the
+    /// NAI filter
+    fn rebuild(&mut self, dir: PathBuf, show_hidden: bool) {
+        let extra_roots: Vec<PathBuf> = self
+            .extra_roots
+            .iter()
+            .map(|&id| self.entries[id].path.clone())
+            .collect();
+        let nai_only = self.nai_only.take();
+        let natural_sort = self.natural_sort;
+
+        *self = Self::with_hidden(dir, show_hidden);
+        for path in extra_roots {
+            self.add_root(path);
+        }
+        self.set_nai_only(nai_only);
+        if natural_sort != self.natural_sort {
+            self.set_natural_sort(natural_sort);
+        }
+    }
+
+    /// switches between natural and plain path ordering and re-sorts every loaded folder
+    pub fn set_natural_sort(&mut self, natural_sort: bool) {
+        self.natural_sort = natural_sort;
+
+        let ids: Vec<EntryId> = self.entries.keys().collect();
+        for id in ids {
+            let mut children = std::mem::take(&mut self.entries[id].children);
+            children.sort_by(|&a, &b| self.cmp_entries(a, b));
+            self.entries[id].children = children;
+        }
+        self.visible = self.visible_entries();
+    }
+
+    /// adds a watched directory as another top-level folder
+    ///
+    /// directories inside the current root are skipped since they are already in the tree
+    pub fn add_root<P: AsRef<Path>>(&mut self, dir: P) -> Option<EntryId> {
+        let path = dir.as_ref().to_path_buf();
+        if !path.is_dir()
+            || self
+                .tops()
+                .any(|id| path.starts_with(&self.entries[id].path))
+        {
+            return None;
+        }
+
+        let canonical = fs::canonicalize(&path).ok();
+        let id = self.entries.insert(Entry {
+            path,
+            kind: EntryKind::Folder,
+            target: None,
+            canonical,
+            parent: None,
+            children: Vec::new(),
+            expanded: false,
+            visited: false,
+            marked: false,
+        });
+        self.extra_roots.push(id);
+        self.visible = self.visible_entries();
+
+        Some(id)
+    }
+
+    /// the root followed by the extra roots
+    fn tops(&self) -> impl Iterator<Item = EntryId> + '_ {
+        std::iter::once(self.root).chain(self.extra_roots.iter().copied())
+    }
+
+    pub fn is_root(&self, id: EntryId) -> bool {
+        self.tops().any(|top| top == id)
+    }
+
+    /// shows only the given files, or every file when `None`
+    pub fn set_nai_only(&mut self, paths: Option<HashSet<PathBuf>>) {
+        self.nai_only = paths;
+        self.visible = self.visible_entries();
+
+        if !self.visible.iter().any(|ve| ve.id == self.selected) {
+            self.selected = self.root;
+            self.view_offset = 0;
+        }
+    }
+
+    fn is_filtered(&self, id: EntryId) -> bool {
+        let entry = &self.entries[id];
+        entry.kind == EntryKind::File
+            && self
+                .nai_only
+                .as_ref()
+                .is_some_and(|paths| !paths.contains(&entry.path))
+    }
+
+    /// rebuilds the tree with dotfiles shown or hidden, keeping the cursor if it is still visible
+    pub fn toggle_hidden(&mut self) {
+        let selected = self.entries[self.selected].path.clone();
+        self.rebuild(self.root_path().to_path_buf(), !self.show_hidden);
+        self.reveal(selected);
+    }
+
     pub fn cd_parent(&mut self) {
         if let Ok(dir) = std::env::current_dir() {
             if let Some(parent) = dir.parent() {
-                std::env::set_current_dir(parent).expect("failed to cd into parent");
-                *self = Self::new(parent);
+                self.cd(parent).expect("failed to cd into parent");
             }
         }
     }
 
     pub fn cd_selected(&mut self) {
         let id = self.selected;
-        if self.entries[id].kind == EntryKind::Folder {
-            std::env::set_current_dir(&self.entries[id].path).expect("failed to cd");
-            *self = Self::new(&self.entries[id].path);
+        if self.is_folder(id) {
+            let path = self.entries[id].path.clone();
+            self.cd(path).expect("failed to cd");
         }
     }
 
+    pub fn root_path(&self) -> &Path {
+        &self.entries[self.root].path
+    }
+
     pub fn select_start(&mut self) {
         assert!(self.visible.len() > 0);
         self.selected = self.visible[0].id;
@@ -505,18 +964,126 @@ impl FileTree {
         depth
     }
 
+    /// folders, and symlinks to folders that do not lead back into one of their own ancestors
+    pub fn is_folder(&self, id: EntryId) -> bool {
+        let entry = &self.entries[id];
+        match entry.kind {
+            EntryKind::Folder => true,
+            EntryKind::File => false,
+            // a link to a folder the entry is already inside of would expand without end, also
+            // through other links like a/l -> b and b/l2 -> a
+            EntryKind::Symlink => entry.canonical.as_ref().is_some_and(|target| {
+                !self.ancestors(id).any(|aid| {
+                    self.entries[aid]
+                        .canonical
+                        .as_ref()
+                        .is_some_and(|c| c.starts_with(target))
+                })
+            }),
+        }
+    }
+
+    fn ancestors(&self, id: EntryId) -> impl Iterator<Item = EntryId> + '_ {
+        std::iter::successors(self.entries[id].parent, |&pid| self.entries[pid].parent)
+    }
+
+    /// the canonical path of an entry of `kind` at `path` below `parent`; None for files and for
+    /// broken links, link loops and links to files
+    fn canonical_of(&self, parent: EntryId, path: &Path, kind: EntryKind) -> Option<PathBuf> {
+        match kind {
+            EntryKind::File => None,
+            // a real folder in a resolved one needs no lookup
+            EntryKind::Folder => match &self.entries[parent].canonical {
+                Some(parent) => path.file_name().map(|name| parent.join(name)),
+                None => fs::canonicalize(path).ok(),
+            },
+            EntryKind::Symlink => fs::canonicalize(path).ok().filter(|target| target.is_dir()),
+        }
+    }
+
     fn cmp_entries(&self, a: EntryId, b: EntryId) -> Ordering {
+        // links to folders sort with the folders
+        match (self.is_folder(a), self.is_folder(b)) {
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            _ => (),
+        }
         let a = &self.entries[a];
         let b = &self.entries[b];
+        if self.natural_sort {
+            natural_cmp(&a.path.to_string_lossy(), &b.path.to_string_lossy())
+        } else {
+            a.path.cmp(&b.path)
+        }
+    }
+}
+
+/// compares strings with runs of ascii digits ordered by their numeric value
+///
+/// "img2.png" < "img10.png"; equal values with different zero padding fall back to the
+/// shorter run first so the ordering stays total
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (a_num, b_num) = (&a[..a_len], &b[..b_len]);
+
+                let a_trim = trim_zeros(a_num);
+                let b_trim = trim_zeros(b_num);
+                let ord = a_trim
+                    .len()
+                    .cmp(&b_trim.len())
+                    .then_with(|| a_trim.cmp(b_trim))
+                    .then_with(|| a_len.cmp(&b_len));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
 
-        match (&a.kind, &b.kind) {
-            (EntryKind::Folder, EntryKind::File) => Ordering::Less,
-            (EntryKind::File, EntryKind::Folder) => Ordering::Greater,
-            _ => a.path.cmp(&b.path),
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
         }
     }
 }
 
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|&&c| c == b'0').count();
+    &digits[zeros..]
+}
+
+/// extra directories to show and watch next to the data dir, from `PROMPTS_WATCH_DIRS`
+/// (separated like `PATH`)
+pub fn watch_dirs() -> Vec<PathBuf> {
+    std::env::var_os("PROMPTS_WATCH_DIRS")
+        .map(|dirs| std::env::split_paths(&dirs).collect())
+        .unwrap_or_default()
+}
+
+pub fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| FILE_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
 impl FileTree {
     pub fn handle_notify(&mut self, ev: notify::Event) -> Result<(), io::Error> {
         use io::{Error, ErrorKind};
@@ -540,14 +1107,19 @@ impl FileTree {
         match ev.kind {
             EventKind::Create(kind) => {
                 let entry_kind = match kind {
+                    _ if ev_path.is_symlink() => EntryKind::Symlink,
                     CreateKind::File => EntryKind::File,
                     CreateKind::Folder => EntryKind::Folder,
                     _ => {
-                        println!("skipping create: {:?}", &ev);
+                        debug!("skipping create: {:?}", &ev);
                         return Err(Error::new(ErrorKind::Unsupported, "unknown create kind"));
                     }
                 };
 
+                if !self.show_hidden && is_hidden(ev_path) {
+                    return Ok(());
+                }
+
                 // the create event gives us the new filename; we need to check if the parent path
                 // exists in the slotmap
                 if let Some(parent_path) = ev_path.parent() {
@@ -574,7 +1146,7 @@ impl FileTree {
                     RemoveKind::File => EntryKind::File,
                     RemoveKind::Folder => EntryKind::Folder,
                     _ => {
-                        println!("skipping remove: {:?}", &ev);
+                        debug!("skipping remove: {:?}", &ev);
                         return Err(Error::new(ErrorKind::Unsupported, "unknown remove kind"));
                     }
                 };
@@ -615,11 +1187,11 @@ impl FileTree {
             // BUG: visible entries don't update when moving from higher to lower depth
             EventKind::Modify(ModifyKind::Name(RenameMode::Any)) => {
                 if !self.notify_modify {
-                    println!("modify1: {:?}", &ev.paths);
+                    debug!("modify1: {:?}", &ev.paths);
                     self.notify_modify = true;
                     self.modify_from = Some(ev_path.clone());
                 } else {
-                    println!("modify2: {:?}", &ev.paths);
+                    debug!("modify2: {:?}", &ev.paths);
                     self.notify_modify = false;
                     let modify_to = ev_path;
 
@@ -637,7 +1209,7 @@ impl FileTree {
 
                         (old_entry, new_parent)
                     } else {
-                        println!("skipping modify: {:?}", &ev);
+                        debug!("skipping modify: {:?}", &ev);
                         return Err(Error::new(
                             ErrorKind::InvalidInput,
                             "invalid old or new parent",
@@ -654,6 +1226,9 @@ impl FileTree {
                                 }
 
                                 self.entries[old_pid].children.retain(|cid| *cid != old_id);
+                                let kind = self.entries[old_id].kind;
+                                self.entries[old_id].canonical =
+                                    self.canonical_of(new_pid, modify_to, kind);
                                 self.entries[old_id].parent = Some(new_pid);
                                 self.entries[old_id].path = modify_to.clone();
 
@@ -665,7 +1240,7 @@ impl FileTree {
                         // as notify only watches a directory (recursively if specified)
                         // unless we set up multiple watchers
                         _ => {
-                            println!("skip modify: {:?}", &ev);
+                            debug!("skip modify: {:?}", &ev);
                         }
                     }
 
@@ -697,6 +1272,36 @@ mod test {
             .expect("not found")
     }
 
+    #[test]
+    fn image_cache_drops_least_recently_opened() {
+        let dir = std::env::temp_dir().join("prompts-image-cache");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = ["a.png", "b.png", "c.png"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        for path in &paths {
+            image::RgbImage::new(16, 16).save(path).unwrap();
+        }
+        let size = 16 * 16 * 4;
+
+        let mut cache = ImageCache::new(2 * size);
+        cache.open(&paths[0]);
+        cache.open(&paths[1]);
+        cache.open(&paths[0]);
+        cache.open(&paths[2]);
+        assert!(cache.contains_key(&paths[0]) && !cache.contains_key(&paths[1]));
+        assert_eq!((cache.len(), cache.bytes()), (2, 2 * size));
+
+        // the image shown stays even when it alone is over the limit
+        cache.set_max_bytes(0);
+        assert!(cache.contains_key(&paths[2]));
+        assert_eq!(cache.bytes(), size);
+        cache.retain(|_| false);
+        assert_eq!((cache.len(), cache.bytes()), (0, 0));
+    }
+
     #[test]
     fn v4_t_delete() {
         setup();
@@ -730,4 +1335,115 @@ mod test {
         // println!("after delete: {:#?}\n\n", &tree.entries);
         // println!("after visible: {:#?}\n\n", &tree.visible);
     }
+
+    #[test]
+    fn refresh_keeps_expanded_and_selected() {
+        setup();
+        let _ = fs::create_dir_all("temp/refresh");
+        let _ = fs::File::create_new("temp/refresh/banana");
+
+        let mut tree = FileTree::new(PROJECT_DIRS.data_dir());
+        let banana = PROJECT_DIRS.data_dir().join("temp/refresh/banana");
+        tree.reveal(&banana).expect("reveal");
+
+        tree.refresh();
+
+        let (_, temp) = find_expect(&tree, "temp");
+        let (_, refresh) = find_expect(&tree, "temp/refresh");
+        assert!(temp.expanded);
+        assert!(refresh.expanded);
+        assert_eq!(tree.entries[tree.selected].path, banana);
+
+        // the cursor falls back to the closest surviving ancestor
+        fs::remove_file(&banana).unwrap();
+        tree.refresh();
+        assert!(tree.entries[tree.selected].path.ends_with("temp/refresh"));
+    }
+
+    #[test]
+    fn apply_finds_entries_by_path_after_a_rebuild() {
+        setup();
+        let _ = fs::create_dir_all("temp/ops");
+        let _ = fs::File::create_new("temp/ops/cherry");
+        let _ = fs::File::create_new("temp/ops/damson");
+        let cherry = PROJECT_DIRS.data_dir().join("temp/ops/cherry");
+        let damson = PROJECT_DIRS.data_dir().join("temp/ops/damson");
+
+        let mut tree = FileTree::new(PROJECT_DIRS.data_dir());
+        tree.reveal(&cherry).expect("reveal");
+        tree.mark();
+        let ops = tree.batch_delete_ops();
+
+        // a refresh while the batch runs hands out the same ids to other entries
+        tree.refresh();
+        for op in &ops {
+            op.run().unwrap();
+            tree.apply(op);
+        }
+
+        assert_eq!(tree.entry_at(&cherry), None);
+        assert!(tree.entry_at(&damson).is_some());
+        let _ = fs::remove_file(&damson);
+    }
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec!["img10.png", "img2.png", "img1.png", "img02.png", "a", "img"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec!["a", "img", "img1.png", "img2.png", "img02.png", "img10.png"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_cycle_is_not_followed() {
+        setup();
+        let _ = fs::create_dir_all("temp/links/inner");
+        let _ = std::os::unix::fs::symlink("..", "temp/links/inner/up");
+        let _ = std::os::unix::fs::symlink("inner", "temp/links/side");
+        let _ = fs::create_dir_all("temp/links/a");
+        let _ = fs::create_dir_all("temp/links/b");
+        let _ = std::os::unix::fs::symlink("../b", "temp/links/a/l");
+        let _ = std::os::unix::fs::symlink("../a", "temp/links/b/l2");
+        let _ = fs::File::create_new("temp/links/0.png");
+
+        let mut tree = FileTree::new(PROJECT_DIRS.data_dir());
+        let up = tree
+            .reveal(PROJECT_DIRS.data_dir().join("temp/links/inner/up"))
+            .expect("reveal up");
+        let side = tree
+            .reveal(PROJECT_DIRS.data_dir().join("temp/links/side"))
+            .expect("reveal side");
+
+        assert_eq!(tree.entries[up].kind, EntryKind::Symlink);
+        assert_eq!(tree.entries[up].target, Some(PathBuf::from("..")));
+        assert!(!tree.is_folder(up));
+        assert!(tree.is_folder(side));
+
+        // links pointing at each other's folders stop after one step
+        let l = tree
+            .reveal(PROJECT_DIRS.data_dir().join("temp/links/a/l"))
+            .expect("reveal l");
+        let l2 = tree
+            .reveal(PROJECT_DIRS.data_dir().join("temp/links/a/l/l2"))
+            .expect("reveal l2");
+        assert!(tree.is_folder(l));
+        assert!(!tree.is_folder(l2));
+
+        // links to folders sort with the folders
+        let (links, _) = find_expect(&tree, "temp/links");
+        let names: Vec<_> = tree.entries[links]
+            .children
+            .iter()
+            .map(|&cid| tree.entries[cid].path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["a", "b", "inner", "side", "0.png"]);
+
+        // deleting the link leaves the target alone
+        tree.delete(side).unwrap();
+        assert!(!PROJECT_DIRS.data_dir().join("temp/links/side").exists());
+        assert!(PROJECT_DIRS.data_dir().join("temp/links/inner").is_dir());
+    }
 }
@@ -0,0 +1,105 @@
+/// scores how well `query` matches `candidate`, ignoring case
+///
+/// every whitespace separated term has to appear in order, either as a substring or as a
+/// subsequence; substrings and matches at the start of a word score higher. returns the score
+/// and the char indices of `candidate` that matched, None if a term can't be found
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<(i32, Vec<usize>)> {
+    let chars: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+    // to_lowercase can expand a char, then the highlight would be off
+    if chars.len() != candidate.chars().count() {
+        return None;
+    }
+
+    let mut score = 0;
+    let mut matched = Vec::new();
+    let mut from = 0;
+    for term in query.split_whitespace() {
+        let term: Vec<char> = term.chars().flat_map(char::to_lowercase).collect();
+
+        let found = find_substring(&chars, &term, from)
+            .map(|start| {
+                score += 8 * term.len() as i32;
+                (start..start + term.len()).collect::<Vec<_>>()
+            })
+            .or_else(|| find_subsequence(&chars, &term, from))?;
+
+        for pair in found.windows(2) {
+            score -= (pair[1] - pair[0] - 1).min(4) as i32;
+        }
+        if found[0] == 0 || !chars[found[0] - 1].is_alphanumeric() {
+            score += 4;
+        }
+        score += term.len() as i32;
+        from = found[found.len() - 1] + 1;
+        matched.extend(found);
+    }
+    // shorter names win ties
+    Some((score - chars.len() as i32 / 8, matched))
+}
+
+/// options matching `query`, best first, with the matched char indices for highlighting
+pub fn rank<'a>(query: &str, options: &'a [String], limit: usize) -> Vec<(&'a str, Vec<usize>)> {
+    let mut ranked: Vec<_> = options
+        .iter()
+        .filter_map(|o| fuzzy_match(query, o).map(|(score, m)| (score, o.as_str(), m)))
+        .collect();
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    ranked
+        .into_iter()
+        .take(limit)
+        .map(|(_, o, m)| (o, m))
+        .collect()
+}
+
+fn find_substring(chars: &[char], term: &[char], from: usize) -> Option<usize> {
+    if term.is_empty() || from >= chars.len() {
+        return None;
+    }
+    let starts = from..=chars.len().checked_sub(term.len())?;
+    // a match at the start of a word is preferred over the first one
+    let mut first = None;
+    for start in starts {
+        if chars[start..start + term.len()] == *term {
+            if start == 0 || !chars[start - 1].is_alphanumeric() {
+                return Some(start);
+            }
+            first.get_or_insert(start);
+        }
+    }
+    first
+}
+
+fn find_subsequence(chars: &[char], term: &[char], from: usize) -> Option<Vec<usize>> {
+    let mut found = Vec::with_capacity(term.len());
+    let mut i = from;
+    for c in term {
+        i += chars.get(i..)?.iter().position(|x| x == c)?;
+        found.push(i);
+        i += 1;
+    }
+    (!found.is_empty()).then_some(found)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn terms_match_in_order() {
+        let (_, matched) = fuzzy_match("red dr", "girl_red_dress_v2").unwrap();
+        assert_eq!(matched, [5, 6, 7, 9, 10]);
+
+        assert_eq!(fuzzy_match("grd", "girl_red").unwrap().1, [0, 2, 7]);
+        assert!(fuzzy_match("dress red", "girl_red_dress").is_none());
+        assert!(fuzzy_match("blue", "girl_red_dress").is_none());
+
+        let options = vec![
+            "girl_red_dress_v2".to_string(),
+            "reading".to_string(),
+            "red".to_string(),
+        ];
+        let ranked: Vec<&str> = rank("red", &options, 10).into_iter().map(|r| r.0).collect();
+        // a substring beats a scattered match
+        assert_eq!(ranked, ["red", "girl_red_dress_v2", "reading"]);
+    }
+}
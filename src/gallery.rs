@@ -0,0 +1,233 @@
+use std::{
+    fs,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::ImageFormat;
+use serde_json::{Map, Value};
+
+use crate::{
+    history::{day_of, format_day},
+    image_metadata::extract_image_metadata,
+    library::collect_pngs,
+    parallel::par_map,
+};
+
+// longest side of the thumbnails embedded in the html
+const THUMBNAIL_SIZE: u32 = 384;
+
+const STYLE: &str = "body{font-family:sans-serif;background:#1e2030;color:#cad3f5;margin:2em}\
+figure{display:flex;gap:1.5em;margin:0 0 2em}\
+img{max-width:384px;max-height:384px;border-radius:6px}\
+dl{display:grid;grid-template-columns:auto 1fr;gap:.3em 1em;margin:0}\
+dt{color:#8aadf4}dd{margin:0;white-space:pre-wrap;overflow-wrap:anywhere}";
+
+/// what the gallery shows of one image
+struct Entry {
+    path: PathBuf,
+    mtime: i64,
+    // jpeg, only for html
+    thumbnail: Option<Vec<u8>>,
+    // (label, value) of the metadata that is set
+    fields: Vec<(&'static str, String)>,
+}
+
+/// writes a static page of every png below `dir` to `out`, oldest first, with the prompts, seed
+/// and parameters of each
+///
+/// an `out` ending in .md is markdown linking the images, anything else html with the thumbnails
+/// embedded so the file can be shared on its own; returns the number of images
+pub fn export_gallery(dir: &Path, out: &Path) -> io::Result<usize> {
+    let markdown = out.extension().is_some_and(|ext| ext == "md");
+    let mut pngs = Vec::new();
+    collect_pngs(dir, &mut pngs);
+    pngs.sort_by_key(|(path, mtime)| (*mtime, path.clone()));
+
+    let entries = par_map(&pngs, |(path, mtime)| {
+        let im = image::open(path).ok();
+        let thumbnail = im.as_ref().filter(|_| !markdown).and_then(|im| {
+            let mut jpeg = Vec::new();
+            im.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+                .to_rgb8()
+                .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+                .ok()
+                .map(|()| jpeg)
+        });
+        let fields = im
+            .and_then(|im| extract_image_metadata(im).ok())
+            .map(|map| fields(&map))
+            .unwrap_or_default();
+        Entry {
+            path: path.clone(),
+            mtime: *mtime,
+            thumbnail,
+            fields,
+        }
+    });
+
+    let title = format!("{}", dir.display());
+    let page = if markdown {
+        let base = out.parent().unwrap_or(Path::new(""));
+        to_markdown(&title, &entries, base)
+    } else {
+        to_html(&title, &entries)
+    };
+    fs::write(out, page)?;
+    Ok(entries.len())
+}
+
+/// the labelled metadata shown for an image
+fn fields(map: &Map<String, Value>) -> Vec<(&'static str, String)> {
+    let comment = map.get("Comment");
+    let get = |pointer: &str| comment.and_then(|c| c.pointer(pointer));
+    let text = |pointer: &str| get(pointer).and_then(Value::as_str).map(String::from);
+    let number = |pointer: &str| get(pointer).filter(|v| v.is_number()).map(Value::to_string);
+
+    let mut fields = vec![("prompt", text("/prompt"))];
+    if let Some(chars) = get("/v4_prompt/caption/char_captions").and_then(Value::as_array) {
+        for c in chars {
+            fields.push((
+                "character",
+                c.get("char_caption")
+                    .and_then(Value::as_str)
+                    .map(String::from),
+            ));
+        }
+    }
+    fields.extend([
+        ("negative", text("/uc")),
+        ("seed", number("/seed")),
+        (
+            "size",
+            number("/width")
+                .zip(number("/height"))
+                .map(|(w, h)| format!("{}x{}", w, h)),
+        ),
+        ("steps", number("/steps")),
+        ("scale", number("/scale")),
+        ("sampler", text("/sampler")),
+        (
+            "model",
+            map.get("Source").and_then(Value::as_str).map(String::from),
+        ),
+    ]);
+    fields
+        .into_iter()
+        .filter_map(|(label, value)| Some((label, value.filter(|v| !v.is_empty())?)))
+        .collect()
+}
+
+fn date(mtime: i64) -> String {
+    let generated = UNIX_EPOCH + Duration::from_secs(u64::try_from(mtime).unwrap_or(0));
+    let secs = mtime.rem_euclid(86400);
+    format!(
+        "{} {:02}:{:02}",
+        format_day(day_of(generated)),
+        secs / 3600,
+        secs % 3600 / 60
+    )
+}
+
+fn name(path: &Path) -> String {
+    path.file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().into_owned())
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn to_html(title: &str, entries: &[Entry]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title>\
+         <style>{1}</style></head>\n<body><h1>{0}</h1>\n",
+        escape_html(title),
+        STYLE
+    );
+    for entry in entries {
+        html.push_str("<figure>");
+        if let Some(jpeg) = &entry.thumbnail {
+            html.push_str(&format!(
+                "<img src=\"data:image/jpeg;base64,{}\" alt=\"{}\">",
+                STANDARD.encode(jpeg),
+                escape_html(&name(&entry.path))
+            ));
+        }
+        html.push_str(&format!(
+            "<dl><dt>file</dt><dd>{}</dd><dt>date</dt><dd>{}</dd>",
+            escape_html(&name(&entry.path)),
+            date(entry.mtime)
+        ));
+        for (label, value) in &entry.fields {
+            html.push_str(&format!(
+                "<dt>{}</dt><dd>{}</dd>",
+                label,
+                escape_html(value)
+            ));
+        }
+        html.push_str("</dl></figure>\n");
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn to_markdown(title: &str, entries: &[Entry], base: &Path) -> String {
+    let mut md = format!("# {}\n", title);
+    for entry in entries {
+        let link = entry.path.strip_prefix(base).unwrap_or(&entry.path);
+        let link = link.to_string_lossy().replace(' ', "%20");
+        md.push_str(&format!(
+            "\n## {}\n\n![{0}]({})\n\n- date: {}\n",
+            name(&entry.path),
+            link,
+            date(entry.mtime)
+        ));
+        for (label, value) in &entry.fields {
+            md.push_str(&format!("- {}: {}\n", label, value.replace('\n', " ")));
+        }
+    }
+    md
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gallery_of_a_folder() {
+        let dir = std::env::temp_dir().join("prompts-gallery");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("images")).unwrap();
+
+        let map = serde_json::json!({
+            "Comment": {"prompt": "1girl, <b>", "seed": 42, "width": 64, "height": 64}
+        });
+        let im = image::DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+        crate::image_metadata::embed_metadata(im.clone(), map.as_object().unwrap())
+            .unwrap()
+            .save(dir.join("images/nai.png"))
+            .unwrap();
+        im.save(dir.join("images/plain.png")).unwrap();
+
+        let html = dir.join("gallery.html");
+        assert_eq!(export_gallery(&dir.join("images"), &html).unwrap(), 2);
+        let html = fs::read_to_string(html).unwrap();
+        assert_eq!(html.matches("<figure>").count(), 2);
+        assert_eq!(html.matches("data:image/jpeg;base64,").count(), 2);
+        assert!(html.contains("<dd>1girl, &lt;b&gt;</dd>"));
+        assert!(html.contains("<dt>seed</dt><dd>42</dd>"));
+        assert!(html.contains("<dt>size</dt><dd>64x64</dd>"));
+
+        let md = dir.join("gallery.md");
+        export_gallery(&dir.join("images"), &md).unwrap();
+        let md = fs::read_to_string(md).unwrap();
+        assert!(md.contains("![nai.png](images/nai.png)"));
+        assert!(md.contains("- seed: 42\n"));
+    }
+}
@@ -0,0 +1,299 @@
+use std::ops::Range;
+
+use iced::{
+    Font, Theme,
+    advanced::text::{Highlighter, highlighter::Format},
+};
+
+// NAI strengthens each {} by 5% and weakens each [] by the same amount
+pub const BRACE_FACTOR: f32 = 1.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Highlight {
+    // braces, brackets, `1.2::` and the closing `::`
+    Syntax,
+    Emphasis,
+    Deemphasis,
+    // unbalanced openers and closers
+    Error,
+}
+
+impl Highlight {
+    pub fn to_format(highlight: &Self, theme: &Theme) -> Format<Font> {
+        let palette = theme.extended_palette();
+        let color = match highlight {
+            Self::Syntax => palette.primary.strong.color,
+            Self::Emphasis => palette.success.base.color,
+            Self::Deemphasis => palette.secondary.strong.color,
+            Self::Error => palette.danger.base.color,
+        };
+        Format {
+            color: Some(color),
+            font: None,
+        }
+    }
+}
+
+/// colors NovelAI weight syntax in the prompt editors: `{}` / `[]` emphasis and `1.2::text::`
+/// weights
+///
+/// blocks are matched per line, so a block left open at the end of a line shows as an error
+pub struct WeightHighlighter {
+    current_line: usize,
+}
+
+impl Highlighter for WeightHighlighter {
+    type Settings = ();
+    type Highlight = Highlight;
+    type Iterator<'a> = std::vec::IntoIter<(Range<usize>, Highlight)>;
+
+    fn new(_settings: &Self::Settings) -> Self {
+        Self { current_line: 0 }
+    }
+
+    fn update(&mut self, _new_settings: &Self::Settings) {
+        self.current_line = 0;
+    }
+
+    fn change_line(&mut self, line: usize) {
+        self.current_line = self.current_line.min(line);
+    }
+
+    fn highlight_line(&mut self, line: &str) -> Self::Iterator<'_> {
+        self.current_line += 1;
+        highlight_weights(line).into_iter()
+    }
+
+    fn current_line(&self) -> usize {
+        self.current_line
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Block {
+    Brace,
+    Bracket,
+    Weight(f32),
+}
+
+impl Block {
+    fn factor(self) -> f32 {
+        match self {
+            Self::Brace => BRACE_FACTOR,
+            Self::Bracket => 1.0 / BRACE_FACTOR,
+            Self::Weight(w) => w,
+        }
+    }
+}
+
+/// the highlighted ranges of one line, in order
+pub fn highlight_weights(line: &str) -> Vec<(Range<usize>, Highlight)> {
+    let bytes = line.as_bytes();
+    let mut marks: Vec<Option<Highlight>> = vec![None; bytes.len()];
+    // open blocks with the range of their opener
+    let mut stack: Vec<(Block, Range<usize>)> = Vec::new();
+
+    let emphasis = |stack: &[(Block, Range<usize>)]| {
+        let factor: f32 = stack.iter().map(|(b, _)| b.factor()).product();
+        if (factor - 1.0).abs() < f32::EPSILON {
+            None
+        } else if factor > 1.0 {
+            Some(Highlight::Emphasis)
+        } else {
+            Some(Highlight::Deemphasis)
+        }
+    };
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let (opened, closed, len) = match bytes[i] {
+            b'{' => (Some(Block::Brace), None, 1),
+            b'[' => (Some(Block::Bracket), None, 1),
+            b'}' => (
+                None,
+                Some(matches!(stack.last(), Some((Block::Brace, _)))),
+                1,
+            ),
+            b']' => (
+                None,
+                Some(matches!(stack.last(), Some((Block::Bracket, _)))),
+                1,
+            ),
+            b':' if bytes.get(i + 1) == Some(&b':') => (
+                None,
+                Some(matches!(stack.last(), Some((Block::Weight(_), _)))),
+                2,
+            ),
+            _ => match weight_opener(line, i) {
+                Some((w, len)) => (Some(Block::Weight(w)), None, len),
+                None => {
+                    marks[i] = emphasis(&stack);
+                    i += 1;
+                    continue;
+                }
+            },
+        };
+
+        let range = i..i + len;
+        if let Some(block) = opened {
+            stack.push((block, range.clone()));
+            marks[range].fill(Some(Highlight::Syntax));
+        } else if closed == Some(true) {
+            stack.pop();
+            marks[range].fill(Some(Highlight::Syntax));
+        } else {
+            marks[range].fill(Some(Highlight::Error));
+        }
+        i += len;
+    }
+
+    for (_, range) in stack {
+        marks[range].fill(Some(Highlight::Error));
+    }
+
+    let mut ranges: Vec<(Range<usize>, Highlight)> = Vec::new();
+    for (i, mark) in marks.into_iter().enumerate() {
+        let Some(mark) = mark else {
+            continue;
+        };
+        match ranges.last_mut() {
+            Some((range, last)) if range.end == i && *last == mark => range.end = i + 1,
+            _ => ranges.push((i..i + 1, mark)),
+        }
+    }
+    ranges
+}
+
+/// `1.2::` or `-1::` starting at byte `i`, as (weight, length including the colons)
+pub fn weight_opener(line: &str, i: usize) -> Option<(f32, usize)> {
+    let bytes = line.as_bytes();
+    if !(bytes[i].is_ascii_digit() || bytes[i] == b'-') {
+        return None;
+    }
+    // the number must start a token, not continue one like `x2::`
+    if i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'.') {
+        return None;
+    }
+
+    let rest = &line[i..];
+    let end = rest
+        .char_indices()
+        .find(|&(j, c)| !(c.is_ascii_digit() || c == '.' || (j == 0 && c == '-')))
+        .map_or(rest.len(), |(j, _)| j);
+    if !rest[end..].starts_with("::") {
+        return None;
+    }
+    let weight = rest[..end].parse::<f32>().ok()?;
+    Some((weight, end + 2))
+}
+
+/// the `w::text::` block around byte `index` of `line`, or else the comma separated tag under it,
+/// without surrounding whitespace
+pub fn weight_target(line: &str, index: usize) -> Option<Range<usize>> {
+    let index = index.min(line.len());
+
+    // weight blocks may contain commas, so look for one enclosing the cursor first
+    let mut i = 0;
+    while i < line.len() {
+        let Some((_, len)) = weight_opener(line, i) else {
+            i += 1;
+            continue;
+        };
+        let Some(close) = line[i + len..].find("::") else {
+            break;
+        };
+        let end = i + len + close + 2;
+        if (i..=end).contains(&index) {
+            return Some(i..end);
+        }
+        i = end;
+    }
+
+    let start = line.get(..index)?.rfind(',').map_or(0, |i| i + 1);
+    let end = line[index..].find(',').map_or(line.len(), |i| index + i);
+    let tag = &line[start..end];
+    let trimmed_start = start + (tag.len() - tag.trim_start().len());
+    let trimmed_end = end - (tag.len() - tag.trim_end().len());
+    (trimmed_start < trimmed_end).then_some(trimmed_start..trimmed_end)
+}
+
+/// wraps `text` in a weight block or changes the weight of the block it already is, by `delta`
+///
+/// a block that ends up at weight 1 is unwrapped
+pub fn adjust_weight(text: &str, delta: f32) -> String {
+    let (weight, inner) = match weight_opener(text, 0) {
+        Some((w, len)) if text.len() >= len + 2 && text.ends_with("::") => {
+            (w, &text[len..text.len() - 2])
+        }
+        _ => (1.0, text),
+    };
+
+    let weight = ((weight + delta) * 100.0).round() / 100.0;
+    if (weight - 1.0).abs() < f32::EPSILON {
+        return inner.to_string();
+    }
+
+    format!("{}::{}::", format_weight(weight), inner)
+}
+
+/// `weight` with at most two decimals and no trailing zeros
+pub fn format_weight(weight: f32) -> String {
+    let weight = format!("{:.2}", weight);
+    weight
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn weights_and_braces() {
+        use Highlight::*;
+
+        assert_eq!(
+            highlight_weights("a, 1.2::b::, {c}, [d]"),
+            vec![
+                (3..8, Syntax),
+                (8..9, Emphasis),
+                (9..11, Syntax),
+                (13..14, Syntax),
+                (14..15, Emphasis),
+                (15..16, Syntax),
+                (18..19, Syntax),
+                (19..20, Deemphasis),
+                (20..21, Syntax),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjust() {
+        assert_eq!(adjust_weight("blue hair", 0.05), "1.05::blue hair::");
+        assert_eq!(adjust_weight("1.05::blue hair::", 0.05), "1.1::blue hair::");
+        assert_eq!(adjust_weight("1.05::blue hair::", -0.05), "blue hair");
+        assert_eq!(adjust_weight("blue hair", -0.05), "0.95::blue hair::");
+
+        let line = "1girl, 1.2::a, b::, blue hair ";
+        assert_eq!(weight_target(line, 2), Some(0..5));
+        assert_eq!(weight_target(line, 13), Some(7..18));
+        assert_eq!(weight_target(line, 23), Some(20..29));
+        assert_eq!(weight_target(", ", 1), None);
+    }
+
+    #[test]
+    fn unbalanced_blocks() {
+        use Highlight::*;
+
+        assert_eq!(
+            highlight_weights("{a]"),
+            vec![(0..1, Error), (1..2, Emphasis), (2..3, Error)]
+        );
+        assert_eq!(
+            highlight_weights("0.5::a"),
+            vec![(0..5, Error), (5..6, Deemphasis)]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value};
+
+use crate::library::IndexedMetadata;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// what the image history filter matches against, read from the metadata of a generated image
+#[derive(Debug, Clone, Default)]
+pub struct HistoryInfo {
+    seed: Option<i64>,
+    // base and character prompts, lowercased
+    prompt: String,
+    // days since the unix epoch (UTC) when the image was generated
+    day: u64,
+}
+
+impl HistoryInfo {
+    pub fn new(metadata: Option<&Map<String, Value>>, generated: SystemTime) -> Self {
+        let meta = metadata.map(IndexedMetadata::from_map).unwrap_or_default();
+        let mut prompt = meta.prompt.unwrap_or_default();
+        for c in meta.characters {
+            prompt.push('\n');
+            prompt.push_str(&c);
+        }
+
+        Self {
+            seed: meta.seed,
+            prompt: prompt.to_lowercase(),
+            day: day_of(generated),
+        }
+    }
+
+    pub fn seed(&self) -> Option<i64> {
+        self.seed
+    }
+
+    /// whether every whitespace separated term of `query` matches
+    ///
+    /// terms are "today", "yesterday", a date like 2026-10-15 (UTC), digits that are part of
+    /// the seed, or text that is part of a prompt
+    pub fn matches(&self, query: &str, today: u64) -> bool {
+        query.split_whitespace().all(|term| {
+            let term = term.to_lowercase();
+            match term.as_str() {
+                "today" => self.day == today,
+                "yesterday" => self.day + 1 == today,
+                _ => match parse_date(&term) {
+                    Some(day) => self.day == day,
+                    None => {
+                        self.prompt.contains(&term)
+                            || (term.bytes().all(|b| b.is_ascii_digit())
+                                && self.seed.is_some_and(|s| s.to_string().contains(&term)))
+                    }
+                },
+            }
+        })
+    }
+}
+
+pub fn day_of(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / SECS_PER_DAY)
+}
+
+/// days since the unix epoch for a yyyy-mm-dd date
+fn parse_date(s: &str) -> Option<u64> {
+    let mut parts = s.splitn(3, '-');
+    let y: i64 = parts.next()?.parse().ok()?;
+    let m: i64 = parts.next()?.parse().ok()?;
+    let d: i64 = parts.next()?.parse().ok()?;
+    if y < 1970 || !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+
+    // days_from_civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    u64::try_from(era * 146097 + doe - 719468).ok()
+}
+
+/// a day since the unix epoch as yyyy-mm-dd, the inverse of `parse_date`
+pub fn format_day(day: u64) -> String {
+    // civil_from_days, from the same page
+    let z = day as i64 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn filter_terms() {
+        let meta = json!({
+            "Comment": {
+                "seed": 1234567,
+                "prompt": "1girl, Red Dress",
+                "v4_prompt": {"caption": {"char_captions": [{"char_caption": "smile"}]}}
+            }
+        });
+        // 2026-10-15 12:00 UTC
+        let generated = UNIX_EPOCH + Duration::from_secs(1_792_065_600);
+        let info = HistoryInfo::new(meta.as_object(), generated);
+        let today = day_of(generated) + 1;
+
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2026-10-15"), Some(day_of(generated)));
+        assert_eq!(format_day(0), "1970-01-01");
+        assert_eq!(format_day(day_of(generated)), "2026-10-15");
+        assert_eq!(parse_date(&format_day(20_000)), Some(20_000));
+        assert!(info.matches("", today));
+        assert!(info.matches("red dress", today));
+        assert!(info.matches("yesterday SMILE", today));
+        assert!(info.matches("2026-10-15 4567", today));
+        assert!(!info.matches("today", today));
+        assert!(!info.matches("blue", today));
+        assert!(!info.matches("2026-10-16", today));
+    }
+}
@@ -1,97 +1,1040 @@
-use std::{error::Error, io::Read};
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
 
-use flate2::read::GzDecoder;
-use image::DynamicImage;
-use ndarray::Array2;
+use exif::{In, Tag};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use iced::futures::Stream;
+use image::{DynamicImage, ImageFormat, ImageReader, RgbaImage};
 use serde_json::{Map, Value};
 
+use crate::{a1111, comfyui, parallel::par_stream};
+
 const MAGIC: &str = "stealth_pngcomp";
 
+// the start of the jpeg segments holding exif and xmp
+const JPEG_EXIF: &[u8] = b"Exif\0\0";
+const JPEG_XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+// xmp properties prompts are copied into, in order of preference
+const XMP_FIELDS: [&str; 2] = ["exif:UserComment", "dc:description"];
+
+/// why no metadata could be read from an image
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataError {
+    // the file could not be opened or decoded
+    Read(String),
+    // no stealth payload in the alpha lsbs
+    NotNaiImage,
+    // the payload is longer than the image holds
+    TruncatedPayload,
+    Compression(String),
+    BadJson(String),
+    // neither a stealth payload nor NAI, A1111 or ComfyUI text
+    NoMetadata,
+    // metadata without a prompt in it
+    NoPrompt,
+}
+
+impl Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use MetadataError::*;
+        match self {
+            Read(err) => write!(f, "{}", err),
+            NotNaiImage => write!(f, "image does not contain NovelAI metadata"),
+            TruncatedPayload => write!(f, "the NovelAI metadata is cut off"),
+            Compression(err) => write!(f, "decompress the NovelAI metadata: {}", err),
+            BadJson(err) => write!(f, "metadata is not valid json: {}", err),
+            NoMetadata => write!(
+                f,
+                "image does not contain NovelAI, A1111 or ComfyUI metadata"
+            ),
+            NoPrompt => write!(f, "metadata has no prompt"),
+        }
+    }
+}
+
+impl Error for MetadataError {}
+
+impl From<io::Error> for MetadataError {
+    fn from(e: io::Error) -> Self {
+        MetadataError::Read(e.to_string())
+    }
+}
+
+impl From<image::ImageError> for MetadataError {
+    fn from(e: image::ImageError) -> Self {
+        MetadataError::Read(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for MetadataError {
+    fn from(e: serde_json::Error) -> Self {
+        MetadataError::BadJson(e.to_string())
+    }
+}
+
+/// how sure `check_integrity` is of what it says about the pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+/// what `check_integrity` found in an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Integrity {
+    // Ok when the stealth payload decodes, else the step that failed
+    pub payload: Result<(), MetadataError>,
+    // signs that the pixels were saved again since NAI made them, surest first
+    pub reencoded: Vec<(String, Confidence)>,
+    pub confidence: Confidence,
+}
+
+impl Display for Integrity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.payload {
+            Ok(()) => writeln!(f, "payload: intact")?,
+            Err(e) => writeln!(f, "payload: {}", e)?,
+        }
+        if self.reencoded.is_empty() {
+            writeln!(f, "pixels: no sign of re-encoding")?;
+        }
+        for (sign, confidence) in &self.reencoded {
+            writeln!(f, "pixels: {} ({:?})", sign, confidence)?;
+        }
+        write!(f, "confidence: {:?}", self.confidence)
+    }
+}
+
+/// the bytes hidden in the alpha lsbs of an image, column by column, read as they are asked for
+/// so only the payload is ever walked
 struct LSBExtractor {
-    data: Vec<u8>,
+    rgba: RgbaImage,
+    // bits read so far
     pos: usize,
 }
 
 impl LSBExtractor {
     fn new(im: DynamicImage) -> Self {
-        let data = byteize(im).expect("something went wrong");
-
-        Self { data, pos: 0 }
+        // no copy when the image already is 8 bit rgba, as NAI's are
+        Self {
+            rgba: im.into_rgba8(),
+            pos: 0,
+        }
     }
 
-    fn read_n(&mut self, n: usize) -> &[u8] {
-        let bytes = &self.data[self.pos..self.pos + n];
-        self.pos += n;
-        &bytes
+    /// the next `n` bytes, fewer at the end of the image
+    fn read_n(&mut self, n: usize) -> Vec<u8> {
+        let (width, height) = self.rgba.dimensions();
+        let (width, height) = (width as usize, height as usize);
+        let left = (width * height - self.pos) / 8;
+        let alpha = self.rgba.as_raw();
+
+        let mut bytes = Vec::with_capacity(n.min(left));
+        for _ in 0..n.min(left) {
+            let mut byte = 0;
+            for _ in 0..8 {
+                let (x, y) = (self.pos / height, self.pos % height);
+                byte = byte << 1 | alpha[(y * width + x) * 4 + 3] & 1;
+                self.pos += 1;
+            }
+            bytes.push(byte);
+        }
+        bytes
     }
 
     fn read_u32(&mut self) -> Option<u32> {
         let bytes = self.read_n(4);
-        if bytes.len() == 4 {
-            Some(u32::from_be_bytes(bytes.try_into().unwrap()))
-        } else {
-            None
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    }
+}
+
+pub fn extract_image_metadata(im: DynamicImage) -> Result<Map<String, Value>, MetadataError> {
+    let mut reader = LSBExtractor::new(im);
+    if reader.read_n(MAGIC.len()) != MAGIC.as_bytes() {
+        return Err(MetadataError::NotNaiImage);
+    }
+
+    // the length is in bits
+    let len = reader.read_u32().ok_or(MetadataError::TruncatedPayload)? as usize / 8;
+    let gzipped = reader.read_n(len);
+    if gzipped.len() < len {
+        return Err(MetadataError::TruncatedPayload);
+    }
+    let mut json = String::with_capacity(len);
+    GzDecoder::new(gzipped.as_slice())
+        .read_to_string(&mut json)
+        .map_err(|e| MetadataError::Compression(e.to_string()))?;
+
+    let Value::Object(mut map) = serde_json::from_str(&json)? else {
+        return Err(MetadataError::BadJson("not an object".into()));
+    };
+    // NAI stores the comment as a string of json
+    if let Some(Value::String(comment)) = map.get("Comment") {
+        let comment = serde_json::from_str(comment)?;
+        map.insert("Comment".into(), comment);
+    }
+    normalize_legacy(&mut map);
+    Ok(map)
+}
+
+/// moves the prompt of images from before NAI v3, which is only in `Description`, into the
+/// `Comment` where newer images have it, so every reader of the metadata finds it there
+fn normalize_legacy(map: &mut Map<String, Value>) {
+    let Some(Value::String(description)) = map.get("Description") else {
+        return;
+    };
+    let description = description.clone();
+    if let Value::Object(comment) = map
+        .entry("Comment")
+        .or_insert_with(|| Value::Object(Map::new()))
+        && !comment.contains_key("prompt")
+    {
+        comment.insert("prompt".into(), description.into());
+    }
+}
+
+/// checks that the stealth payload of the image at `path` decodes, its length, gzip and json in
+/// turn, and looks for signs that the pixels were saved again, which is how images lose it
+///
+/// the confidence is that of the surest sign; without any, a lost payload may never have been
+/// there and the confidence is low
+pub fn check_integrity(path: &Path) -> Result<Integrity, MetadataError> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let format = reader.format();
+    let im = reader.decode()?;
+
+    let mut reencoded = vec![];
+    match format {
+        Some(ImageFormat::Jpeg) => reencoded.push((
+            "saved as jpeg, which changes every pixel".into(),
+            Confidence::High,
+        )),
+        Some(ImageFormat::WebP) => reencoded.push(("saved as webp".into(), Confidence::Medium)),
+        _ => (),
+    }
+    if !im.color().has_alpha() {
+        reencoded.push(("the alpha channel was dropped".into(), Confidence::High));
+    } else {
+        let rgba = im.to_rgba8();
+        // NAI leaves every pixel opaque but for the lsb
+        if rgba.pixels().any(|p| p[3] < u8::MAX - 1) {
+            reencoded.push(("the alpha was painted over".into(), Confidence::Medium));
+        } else if rgba.pixels().all(|p| p[3] == u8::MAX) {
+            reencoded.push((
+                "every alpha lsb is set, as by a strip".into(),
+                Confidence::Medium,
+            ));
+        }
+    }
+    // NAI only makes multiples of 64
+    if im.width() % 64 != 0 || im.height() % 64 != 0 {
+        reencoded.push((
+            format!("{}x{} was cropped or resized", im.width(), im.height()),
+            Confidence::Medium,
+        ));
+    }
+
+    let payload = extract_image_metadata(im).map(|_| ());
+    if payload.is_err()
+        && format == Some(ImageFormat::Png)
+        && read_text_chunks(path).is_ok_and(|chunks| from_nai_chunks(&chunks).is_some())
+    {
+        reencoded.push((
+            "NAI's text chunks were kept but not the alpha lsbs".into(),
+            Confidence::High,
+        ));
+    }
+
+    reencoded.sort_by_key(|(_, confidence)| std::cmp::Reverse(*confidence));
+    let confidence = match reencoded.first() {
+        Some((_, confidence)) => *confidence,
+        None if payload.is_ok() => Confidence::High,
+        None => Confidence::Low,
+    };
+    Ok(Integrity {
+        payload,
+        reencoded,
+        confidence,
+    })
+}
+
+/// whether the alpha lsbs of the png at `path` start with the stealth magic, decoding only the
+/// rows the magic is in and not reading the payload
+pub fn is_nai_image(path: &Path) -> bool {
+    magic_of(path).is_some_and(|magic| magic == MAGIC.as_bytes())
+}
+
+/// the first `MAGIC.len()` bytes hidden in the alpha lsbs of the png at `path`
+fn magic_of(path: &Path) -> Option<Vec<u8>> {
+    let file = File::open(path).ok()?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let channels = match reader.output_color_type().0 {
+        png::ColorType::Rgba => 4,
+        png::ColorType::GrayscaleAlpha => 2,
+        // opaque, every lsb is set
+        _ => return None,
+    };
+    let info = reader.info();
+    let (width, height) = (info.width as usize, info.height as usize);
+    // rows of an interlaced png come in passes, so decode it whole
+    if info.interlaced {
+        let mut reader = LSBExtractor::new(image::open(path).ok()?);
+        return Some(reader.read_n(MAGIC.len()));
+    }
+
+    // the bits run down the columns, so a tall image only needs the top of the first one
+    let bits = MAGIC.len() * 8;
+    let columns = bits.div_ceil(height).min(width);
+    let rows = height.min(bits);
+    let mut lsbs = vec![0u8; columns * rows];
+    for y in 0..rows {
+        let row = reader.next_row().ok()??;
+        for x in 0..columns {
+            lsbs[x * rows + y] = row.data()[(x + 1) * channels - 1] & 1;
         }
     }
+    Some(
+        lsbs.chunks_exact(8)
+            .take(MAGIC.len())
+            .map(|bits| bits.iter().fold(0, |byte, bit| byte << 1 | bit))
+            .collect(),
+    )
 }
 
-fn byteize(im: DynamicImage) -> Result<Vec<u8>, Box<dyn Error>> {
-    let img = im.to_rgba8();
+/// `read_metadata` of each of `paths` on all cores, yielded as each file is done and so in no
+/// particular order
+///
+/// at most one image per core is decoded at a time and finished files wait in a channel of as
+/// many, so memory stays bounded however many paths there are; dropping the stream stops the
+/// workers after the file they are on
+pub fn extract_many(
+    paths: Vec<PathBuf>,
+) -> impl Stream<Item = (PathBuf, Result<Map<String, Value>, MetadataError>)> {
+    read_many(paths, read_metadata)
+}
+
+/// like `extract_many` with `read` in place of `read_metadata`, for callers that want more of
+/// the image than its metadata
+pub fn read_many<T: Send + 'static>(
+    paths: Vec<PathBuf>,
+    read: impl Fn(&Path) -> T + Send + Sync + 'static,
+) -> impl Stream<Item = (PathBuf, T)> {
+    par_stream(paths, move |path| (path.clone(), read(path)))
+}
 
-    let (width, height) = img.dimensions();
-    let mut alpha = Array2::<u8>::zeros((height as usize, width as usize));
+/// the metadata of the image at `path` in the layout of NAI's: the stealth payload, or else the
+/// text chunks NAI writes next to it, which survive edits that lose the alpha lsbs, or the graph
+/// ComfyUI or the `parameters` A1111 keep in text chunks
+///
+/// jpegs and webps are read from their exif UserComment or xmp instead of text chunks
+pub fn read_metadata(path: &Path) -> Result<Map<String, Value>, MetadataError> {
+    extract_metadata(image::open(path)?, path)
+}
 
-    for (x, y, pixel) in img.enumerate_pixels() {
-        alpha[[y as usize, x as usize]] = pixel[3];
+/// like `read_metadata`, for an image already decoded from `path`
+pub fn extract_metadata(
+    im: DynamicImage,
+    path: &Path,
+) -> Result<Map<String, Value>, MetadataError> {
+    match extract_image_metadata(im) {
+        Ok(map) => return Ok(map),
+        // a payload that is there but broken is reported, not covered up by the text chunks
+        Err(MetadataError::NotNaiImage) => {}
+        Err(e) => return Err(e),
+    }
+    let format = ImageReader::open(path)?.with_guessed_format()?.format();
+    let chunks = match format {
+        Some(ImageFormat::Jpeg | ImageFormat::WebP) => read_exif_chunks(path),
+        _ => read_text_chunks(path),
+    }
+    .map_err(MetadataError::Read)?;
+    if let Some(map) = from_nai_chunks(&chunks) {
+        return Ok(map);
     }
+    if let Some(map) = comfyui::from_chunks(&chunks) {
+        return Ok(map);
+    }
+    match a1111::parameters(chunks) {
+        Some(text) => Ok(a1111::from_parameters(&text)),
+        None => Err(MetadataError::NoMetadata),
+    }
+}
+
+/// the metadata NAI also writes as plain text chunks, shaped like the stealth payload; None
+/// without a `Comment` chunk holding a json object, unless NovelAI wrote a `Description` as the
+/// earliest versions did
+fn from_nai_chunks(chunks: &[(String, String)]) -> Option<Map<String, Value>> {
+    let text = |keyword: &str| {
+        chunks
+            .iter()
+            .find(|(k, _)| k == keyword)
+            .map(|(_, text)| text.as_str())
+    };
+    let comment = match text("Comment") {
+        Some(comment) => serde_json::from_str::<Value>(comment)
+            .ok()
+            .filter(Value::is_object)?,
+        None if text("Software") == Some("NovelAI") && text("Description").is_some() => {
+            Value::Object(Map::new())
+        }
+        None => return None,
+    };
+
+    let mut map: Map<String, Value> = chunks
+        .iter()
+        .map(|(k, text)| (k.clone(), Value::String(text.clone())))
+        .collect();
+    map.insert("Comment".into(), comment);
+    normalize_legacy(&mut map);
+    Some(map)
+}
+
+/// (keyword, text) of every tEXt, zTXt and iTXt chunk of the png at `path`
+pub fn read_text_chunks(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let file = File::open(path).map_err(|e| format!("{:?}: {}", path, e))?;
+    let reader = png::Decoder::new(BufReader::new(file))
+        .read_info()
+        .map_err(|e| format!("{:?}: {}", path, e))?;
+    let info = reader.info();
 
-    let transposed = alpha.t();
-    let mut flat: Vec<u8> = transposed.iter().copied().collect();
+    let mut chunks: Vec<(String, String)> = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|c| (c.keyword.clone(), c.text.clone()))
+        .collect();
+    chunks.extend(
+        info.compressed_latin1_text
+            .iter()
+            .filter_map(|c| Some((c.keyword.clone(), c.get_text().ok()?))),
+    );
+    chunks.extend(
+        info.utf8_text
+            .iter()
+            .filter_map(|c| Some((c.keyword.clone(), c.get_text().ok()?))),
+    );
+    Ok(chunks)
+}
 
-    let trunc_len = (flat.len() / 8) * 8;
-    flat.truncate(trunc_len);
+/// the exif UserComment and xmp descriptions of the jpeg or webp at `path` as the text chunks
+/// a png would hold them in: NAI json as its own chunks, any other text as A1111 `parameters`
+pub fn read_exif_chunks(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{:?}: {}", path, e))?;
+    let (exif, xmp) = if bytes.starts_with(&[0xff, 0xd8]) {
+        jpeg_segments(&bytes)
+    } else if bytes.get(..4) == Some(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        webp_chunks(&bytes)
+    } else {
+        return Err(format!("{:?} is not a jpeg or webp", path));
+    };
 
-    for v in &mut flat {
-        *v &= 1;
+    let mut texts = Vec::new();
+    if let Some(comment) = exif.and_then(user_comment) {
+        texts.push(comment);
+    }
+    if let Some(xmp) = xmp.map(String::from_utf8_lossy) {
+        texts.extend(XMP_FIELDS.iter().filter_map(|field| xmp_field(&xmp, field)));
     }
+    Ok(texts
+        .into_iter()
+        .filter(|text| !text.trim().is_empty())
+        .flat_map(text_chunks)
+        .collect())
+}
 
-    let mut packed = Vec::with_capacity(flat.len() / 8);
-    for chunk in flat.chunks(8) {
-        let mut byte = 0u8;
-        for (i, &bit) in chunk.iter().enumerate() {
-            byte |= bit << (7 - i);
+/// the exif (from its tiff header on) and the xmp packet of a jpeg
+fn jpeg_segments(bytes: &[u8]) -> (Option<&[u8]>, Option<&[u8]>) {
+    let (mut exif, mut xmp) = (None, None);
+    let mut at = 2;
+    while at + 4 <= bytes.len() && bytes[at] == 0xff {
+        let marker = bytes[at + 1];
+        // padding before a marker
+        if marker == 0xff {
+            at += 1;
+            continue;
+        }
+        // the image data starts, no more metadata
+        if marker == 0xda || marker == 0xd9 {
+            break;
         }
-        packed.push(byte);
+        let len = u16::from_be_bytes([bytes[at + 2], bytes[at + 3]]) as usize;
+        let Some(segment) = bytes.get(at + 4..at + 2 + len) else {
+            break;
+        };
+        if marker == 0xe1 {
+            if let Some(tiff) = segment.strip_prefix(JPEG_EXIF) {
+                exif = Some(tiff);
+            } else if let Some(packet) = segment.strip_prefix(JPEG_XMP) {
+                xmp = Some(packet);
+            }
+        }
+        at += 2 + len;
     }
+    (exif, xmp)
+}
 
-    Ok(packed)
+/// the `EXIF` and `XMP ` chunks of a webp
+fn webp_chunks(bytes: &[u8]) -> (Option<&[u8]>, Option<&[u8]>) {
+    let (mut exif, mut xmp) = (None, None);
+    let mut at = 12;
+    while let Some(header) = bytes.get(at..at + 8) {
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        let Some(chunk) = bytes.get(at + 8..at + 8 + len) else {
+            break;
+        };
+        match &header[..4] {
+            // some writers keep the jpeg header
+            b"EXIF" => exif = Some(chunk.strip_prefix(JPEG_EXIF).unwrap_or(chunk)),
+            b"XMP " => xmp = Some(chunk),
+            _ => {}
+        }
+        // chunks are padded to an even length
+        at += 8 + len + len % 2;
+    }
+    (exif, xmp)
 }
 
-pub fn extract_image_metadata(im: DynamicImage) -> Result<Map<String, Value>, Box<dyn Error>> {
-    let mut reader = LSBExtractor::new(im);
-    let magic = String::from_utf8(reader.read_n(MAGIC.len()).to_vec()).expect("invalid utf-8");
-    assert_eq!(magic, MAGIC);
-
-    if let Some(read_len) = reader.read_u32() {
-        let json_data = reader.read_n(read_len as usize);
-        let mut s = String::with_capacity(json_data.len());
-
-        let mut gz = GzDecoder::new(json_data);
-        gz.read_to_string(&mut s)?;
-
-        let mut val: Value = serde_json::from_str(&s)?;
-        if let Some(map) = val.as_object_mut() {
-            if let Some(comment) = map.get("Comment") {
-                map.insert(
-                    "Comment".into(),
-                    serde_json::from_str(comment.as_str().unwrap())?,
-                );
+/// the text of the UserComment in `tiff`, after its 8 byte character code
+fn user_comment(tiff: &[u8]) -> Option<String> {
+    let exif = exif::Reader::new().read_raw(tiff.to_vec()).ok()?;
+    let field = exif.get_field(Tag::UserComment, In::PRIMARY)?;
+    let exif::Value::Undefined(bytes, _) = &field.value else {
+        return None;
+    };
+    let (code, text) = bytes.split_at_checked(8)?;
+    let text = if code == b"UNICODE\0" {
+        // in the byte order of the tiff, which is what A1111 writes
+        let units: Vec<u16> = text
+            .chunks_exact(2)
+            .map(|pair| {
+                let pair = [pair[0], pair[1]];
+                if exif.little_endian() {
+                    u16::from_le_bytes(pair)
+                } else {
+                    u16::from_be_bytes(pair)
+                }
+            })
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(text).into_owned()
+    };
+    Some(text.trim_end_matches('\0').to_string())
+}
+
+/// the value of the xmp property `name`, written as an attribute or as an element, of which
+/// the first `rdf:li` is taken for lists and alternatives
+fn xmp_field(xmp: &str, name: &str) -> Option<String> {
+    let attribute = format!("{}=\"", name);
+    let value = if let Some((_, rest)) = xmp.split_once(&attribute) {
+        rest.split_once('"')?.0
+    } else {
+        let (_, rest) = xmp.split_once(&format!("<{}", name))?;
+        let (_, rest) = rest.split_once('>')?;
+        let (mut value, _) = rest.split_once(&format!("</{}>", name))?;
+        if let Some((_, li)) = value.split_once("<rdf:li") {
+            value = li.split_once('>')?.1.split_once("</rdf:li>")?.0;
+        }
+        value
+    };
+    Some(unescape_xml(value))
+}
+
+fn unescape_xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('&') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let decoded = match &rest[1..end] {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
             }
-            Ok(map.clone())
-        } else {
-            panic!("map");
         }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// a copied prompt as text chunks: the whole NAI metadata, only its comment, or A1111 text
+fn text_chunks(text: String) -> Vec<(String, String)> {
+    match serde_json::from_str::<Value>(&text) {
+        Ok(Value::Object(map))
+            if map.contains_key("Comment") || map.contains_key("Description") =>
+        {
+            map.into_iter()
+                .map(|(k, v)| {
+                    let text = v.as_str().map_or_else(|| v.to_string(), String::from);
+                    (k, text)
+                })
+                .collect()
+        }
+        Ok(Value::Object(map)) if map.contains_key("prompt") => vec![("Comment".into(), text)],
+        _ => vec![("parameters".into(), text)],
+    }
+}
+
+/// `im` without the stealth payload, as every alpha lsb is set; an alpha channel left fully
+/// opaque is dropped
+pub fn strip_metadata(im: DynamicImage) -> DynamicImage {
+    let mut rgba = im.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        pixel[3] |= 1;
+    }
+    if rgba.pixels().all(|p| p[3] == u8::MAX) {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8())
     } else {
-        panic!("read_len");
+        DynamicImage::ImageRgba8(rgba)
+    }
+}
+
+/// `im` with `map` hidden in its alpha lsbs the way NAI does it: the magic, the length of the
+/// gzipped json in bits and the json, column by column
+pub fn embed_metadata(
+    im: DynamicImage,
+    map: &Map<String, Value>,
+) -> Result<RgbaImage, Box<dyn Error>> {
+    let mut map = map.clone();
+    // NAI stores the comment as a string of json
+    if let Some(comment) = map.get("Comment").filter(|c| !c.is_string()) {
+        let comment = serde_json::to_string(comment)?;
+        map.insert("Comment".into(), Value::String(comment));
+    }
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(serde_json::to_string(&map)?.as_bytes())?;
+    let json = gz.finish()?;
+
+    let mut payload = MAGIC.as_bytes().to_vec();
+    payload.extend((json.len() as u32 * 8).to_be_bytes());
+    payload.extend(json);
+
+    let mut rgba = im.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    if payload.len() * 8 > width as usize * height as usize {
+        return Err(format!(
+            "{}x{} is too small for {} bytes of metadata",
+            width,
+            height,
+            payload.len()
+        )
+        .into());
+    }
+
+    let bits = payload
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+    let columns = (0..width).flat_map(|x| (0..height).map(move |y| (x, y)));
+    for ((x, y), bit) in columns.zip(bits) {
+        let alpha = &mut rgba.get_pixel_mut(x, y)[3];
+        *alpha = (*alpha & !1) | bit;
+    }
+    Ok(rgba)
+}
+
+#[cfg(test)]
+mod test {
+    use iced::futures::executor;
+
+    use super::*;
+    use crate::nai::GenerationMetadata;
+
+    #[test]
+    fn extracts_many_files() {
+        let dir = std::env::temp_dir().join("prompts-extract-many");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let im = DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+        let mut paths = vec![];
+        for seed in 0..10 {
+            let map = serde_json::json!({"Comment": {"prompt": "1girl", "seed": seed}});
+            let path = dir.join(format!("{}.png", seed));
+            embed_metadata(im.clone(), map.as_object().unwrap())
+                .unwrap()
+                .save(&path)
+                .unwrap();
+            paths.push(path);
+        }
+        im.save(dir.join("plain.png")).unwrap();
+        paths.push(dir.join("plain.png"));
+
+        let mut read: Vec<_> = executor::block_on_stream(extract_many(paths.clone())).collect();
+        read.sort_by_key(|(path, _)| paths.iter().position(|p| p == path));
+        assert_eq!(read.len(), paths.len());
+        for (seed, (path, map)) in read.iter().take(10).enumerate() {
+            assert_eq!(*path, paths[seed]);
+            assert_eq!(map.as_ref().unwrap()["Comment"]["seed"], seed);
+        }
+        assert_eq!(read[10].1, Err(MetadataError::NoMetadata));
+
+        // the workers stop once nobody is reading
+        let first = executor::block_on_stream(extract_many(paths)).next();
+        assert!(first.is_some());
+        assert_eq!(executor::block_on_stream(extract_many(vec![])).count(), 0);
+    }
+
+    #[test]
+    fn reads_nai_text_chunks() {
+        let path = std::env::temp_dir().join("prompts-nai-chunks.png");
+        let file = File::create(&path).unwrap();
+        let mut encoder = png::Encoder::new(file, 8, 8);
+        encoder.set_color(png::ColorType::Rgb);
+        for (keyword, text) in [
+            ("Software", "NovelAI"),
+            ("Source", "NovelAI Diffusion V4.5 4BDE2A90"),
+            ("Comment", r#"{"prompt": "1girl, smile", "seed": 42}"#),
+        ] {
+            encoder.add_text_chunk(keyword.into(), text.into()).unwrap();
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0; 8 * 8 * 3]).unwrap();
+        writer.finish().unwrap();
+
+        // no alpha channel, so nothing for the stealth reader
+        let map = read_metadata(&path).unwrap();
+        assert_eq!(map["Software"], "NovelAI");
+        assert_eq!(map["Source"], "NovelAI Diffusion V4.5 4BDE2A90");
+        assert_eq!(map["Comment"]["prompt"], "1girl, smile");
+        assert_eq!(map["Comment"]["seed"], 42);
+
+        assert!(from_nai_chunks(&[("Comment".into(), "edited in gimp".into())]).is_none());
+    }
+
+    #[test]
+    fn reads_legacy_nai_layouts() {
+        let dir = std::env::temp_dir().join("prompts-legacy-nai");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // the first versions kept the prompt out of the comment
+        let path = dir.join("v1.png");
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 8, 8);
+        encoder.set_color(png::ColorType::Rgb);
+        for (keyword, text) in [
+            ("Title", "AI generated image"),
+            ("Description", "masterpiece, 1girl"),
+            ("Software", "NovelAI"),
+            ("Source", "Stable Diffusion 1D44365E"),
+            (
+                "Comment",
+                r#"{"steps": 28, "sampler": "k_euler_ancestral", "seed": 42, "strength": 0.7, "noise": 0.2, "scale": 11, "uc": "lowres"}"#,
+            ),
+        ] {
+            encoder.add_text_chunk(keyword.into(), text.into()).unwrap();
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0; 8 * 8 * 3]).unwrap();
+        writer.finish().unwrap();
+        let meta = GenerationMetadata::from_map(&read_metadata(&path).unwrap()).unwrap();
+        assert_eq!(meta.prompt, "masterpiece, 1girl");
+        assert_eq!(meta.negative.as_deref(), Some("lowres"));
+        assert_eq!((meta.seed, meta.steps), (Some(42), Some(28)));
+        assert_eq!(meta.model.as_deref(), Some("Stable Diffusion 1D44365E"));
+
+        // or had no comment at all
+        let map = serde_json::json!({"Description": "1girl", "Software": "NovelAI"});
+        let im = DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+        let im = embed_metadata(im, map.as_object().unwrap()).unwrap();
+        let map = extract_image_metadata(DynamicImage::ImageRgba8(im)).unwrap();
+        assert_eq!(map["Comment"]["prompt"], "1girl");
+        let meta = GenerationMetadata::from_map(&map).unwrap();
+        assert_eq!((meta.prompt.as_str(), meta.seed), ("1girl", None));
+
+        // a newer comment keeps its prompt over the description
+        let mut map = serde_json::json!({"Description": "short", "Comment": {"prompt": "long"}});
+        normalize_legacy(map.as_object_mut().unwrap());
+        assert_eq!(map["Comment"]["prompt"], "long");
+
+        assert!(from_nai_chunks(&[("Description".into(), "a photo".into())]).is_none());
+    }
+
+    #[test]
+    fn reads_a1111_parameters() {
+        let dir = std::env::temp_dir().join("prompts-read-metadata");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let im = DynamicImage::ImageRgb8(image::RgbImage::new(8, 8));
+
+        let webui = dir.join("webui.png");
+        a1111::save_with_parameters(
+            &im,
+            &webui,
+            "1girl, (smile:1.1)\nNegative prompt: lowres\nSteps: 28, Sampler: Euler a, Seed: 42, Size: 832x1216",
+        )
+        .unwrap();
+        let map = read_metadata(&webui).unwrap();
+        let comment = &map["Comment"];
+        assert_eq!(comment["prompt"], "1girl, 1.1::smile::");
+        assert_eq!(comment["uc"], "lowres");
+        assert_eq!(comment["seed"], 42);
+        assert_eq!(comment["steps"], 28);
+        assert_eq!(comment["sampler"], "k_euler_ancestral");
+        assert_eq!(
+            (&comment["width"], &comment["height"]),
+            (&832.into(), &1216.into())
+        );
+
+        im.save(dir.join("plain.png")).unwrap();
+        assert!(read_metadata(&dir.join("plain.png")).is_err());
+    }
+
+    #[test]
+    fn reads_exif_and_xmp() {
+        let dir = std::env::temp_dir().join("prompts-exif");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let im = DynamicImage::ImageRgb8(image::RgbImage::new(16, 16));
+
+        // A1111 parameters in the UserComment of a jpeg, as utf-16 in the order of the tiff
+        let parameters = "1girl, smile\nNegative prompt: lowres\nSteps: 28, Seed: 42";
+        let mut comment = b"UNICODE\0".to_vec();
+        comment.extend(parameters.encode_utf16().flat_map(u16::to_be_bytes));
+        let field = exif::Field {
+            tag: Tag::UserComment,
+            ifd_num: In::PRIMARY,
+            value: exif::Value::Undefined(comment, 0),
+        };
+        let mut writer = exif::experimental::Writer::new();
+        writer.push_field(&field);
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let segment = [JPEG_EXIF, tiff.get_ref()].concat();
+
+        let mut jpeg = std::io::Cursor::new(Vec::new());
+        im.write_to(&mut jpeg, ImageFormat::Jpeg).unwrap();
+        let jpeg = jpeg.into_inner();
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend([0xff, 0xe1]);
+        bytes.extend((segment.len() as u16 + 2).to_be_bytes());
+        bytes.extend(segment);
+        bytes.extend(&jpeg[2..]);
+        std::fs::write(dir.join("a.jpg"), bytes).unwrap();
+
+        let comment = &read_metadata(&dir.join("a.jpg")).unwrap()["Comment"];
+        assert_eq!(comment["prompt"], "1girl, smile");
+        assert_eq!(comment["uc"], "lowres");
+        assert_eq!(comment["seed"], 42);
+
+        // NAI metadata in the xmp of a webp
+        let nai = r#"{"Software": "NovelAI", "Comment": "{\"prompt\": \"1girl\", \"seed\": 7}"}"#;
+        let xmp = format!(
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description><dc:description><rdf:Alt><rdf:li xml:lang="x-default">{}</rdf:li></rdf:Alt></dc:description></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+            nai.replace('&', "&amp;").replace('"', "&quot;")
+        );
+        let mut webp = std::io::Cursor::new(Vec::new());
+        im.write_to(&mut webp, ImageFormat::WebP).unwrap();
+        let mut bytes = webp.into_inner();
+        bytes.extend(b"XMP ");
+        bytes.extend((xmp.len() as u32).to_le_bytes());
+        bytes.extend(xmp.as_bytes());
+        if xmp.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        let riff = (bytes.len() as u32 - 8).to_le_bytes();
+        bytes[4..8].copy_from_slice(&riff);
+        std::fs::write(dir.join("b.webp"), bytes).unwrap();
+
+        let map = read_metadata(&dir.join("b.webp")).unwrap();
+        assert_eq!(map["Software"], "NovelAI");
+        assert_eq!(map["Comment"]["prompt"], "1girl");
+        assert_eq!(map["Comment"]["seed"], 7);
+
+        im.save(dir.join("plain.jpg")).unwrap();
+        assert!(read_metadata(&dir.join("plain.jpg")).is_err());
+    }
+
+    #[test]
+    fn checks_payload_integrity() {
+        let dir = std::env::temp_dir().join("prompts-integrity");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let map = serde_json::json!({"Comment": {"prompt": "1girl"}});
+        let im = DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+        let nai = embed_metadata(im, map.as_object().unwrap()).unwrap();
+
+        nai.save(dir.join("nai.png")).unwrap();
+        let integrity = check_integrity(&dir.join("nai.png")).unwrap();
+        assert_eq!(integrity.payload, Ok(()));
+        assert!(integrity.reencoded.is_empty());
+        assert_eq!(integrity.confidence, Confidence::High);
+
+        // flattened and cropped by an editor
+        let flat = DynamicImage::ImageRgba8(nai.clone()).crop_imm(0, 0, 60, 64);
+        flat.to_rgb8().save(dir.join("flat.png")).unwrap();
+        let integrity = check_integrity(&dir.join("flat.png")).unwrap();
+        assert_eq!(integrity.payload, Err(MetadataError::NotNaiImage));
+        assert_eq!(integrity.confidence, Confidence::High);
+        assert_eq!(integrity.reencoded.len(), 2);
+        assert_eq!(integrity.reencoded[1].1, Confidence::Medium);
+
+        // the lsbs cut off by painting over the alpha
+        let mut painted = nai;
+        for y in 0..64 {
+            painted.get_pixel_mut(0, y)[3] = 0;
+        }
+        painted.save(dir.join("painted.png")).unwrap();
+        let integrity = check_integrity(&dir.join("painted.png")).unwrap();
+        assert!(integrity.payload.is_err());
+        assert_eq!(integrity.confidence, Confidence::Medium);
+
+        // a plain image gives no reason to think it ever had a payload
+        let plain = image::RgbaImage::from_pixel(64, 64, image::Rgba([0, 0, 0, 254]));
+        plain.save(dir.join("plain.png")).unwrap();
+        let integrity = check_integrity(&dir.join("plain.png")).unwrap();
+        assert_eq!(integrity.confidence, Confidence::Low);
+        assert!(check_integrity(&dir.join("missing.png")).is_err());
+    }
+
+    #[test]
+    fn malformed_payloads_are_errors() {
+        // `payload` in the alpha lsbs after the magic, column by column like NAI
+        let hide = |payload: &[u8]| {
+            let mut rgba = image::RgbaImage::from_pixel(32, 32, image::Rgba([0, 0, 0, 254]));
+            let bytes = [MAGIC.as_bytes(), payload].concat();
+            let bits = bytes
+                .iter()
+                .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+            let columns = (0..32).flat_map(|x| (0..32).map(move |y| (x, y)));
+            for ((x, y), bit) in columns.zip(bits) {
+                rgba.get_pixel_mut(x, y)[3] |= bit;
+            }
+            extract_image_metadata(DynamicImage::ImageRgba8(rgba))
+        };
+        let gzip = |text: &str| {
+            let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+            gz.write_all(text.as_bytes()).unwrap();
+            let gzipped = gz.finish().unwrap();
+            [(gzipped.len() as u32 * 8).to_be_bytes().to_vec(), gzipped].concat()
+        };
+
+        assert_eq!(
+            hide(&u32::MAX.to_be_bytes()),
+            Err(MetadataError::TruncatedPayload)
+        );
+        assert!(matches!(
+            hide(&[0, 0, 0, 32, 1, 2, 3, 4]),
+            Err(MetadataError::Compression(_))
+        ));
+        assert!(matches!(hide(&gzip("[1]")), Err(MetadataError::BadJson(_))));
+        assert!(matches!(
+            hide(&gzip(r#"{"Comment": "not json"}"#)),
+            Err(MetadataError::BadJson(_))
+        ));
+        assert!(hide(&gzip(r#"{"Comment": "{}"}"#)).is_ok());
+
+        let plain = DynamicImage::ImageRgb8(image::RgbImage::new(8, 8));
+        assert_eq!(
+            extract_image_metadata(plain),
+            Err(MetadataError::NotNaiImage)
+        );
+    }
+
+    #[test]
+    fn broken_payloads_are_not_covered_by_text_chunks() {
+        let dir = std::env::temp_dir().join("prompts-broken-payload");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // the magic, then a length far past the end of the image
+        let mut rgba = image::RgbaImage::from_pixel(32, 32, image::Rgba([0, 0, 0, 254]));
+        let bytes = [MAGIC.as_bytes(), &u32::MAX.to_be_bytes()].concat();
+        let bits = bytes
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+        let columns = (0..32).flat_map(|x| (0..32).map(move |y| (x, y)));
+        for ((x, y), bit) in columns.zip(bits) {
+            rgba.get_pixel_mut(x, y)[3] |= bit;
+        }
+        let path = dir.join("truncated.png");
+        rgba.save(&path).unwrap();
+
+        assert_eq!(read_metadata(&path), Err(MetadataError::TruncatedPayload));
+    }
+
+    #[test]
+    fn detects_nai_images_from_the_magic() {
+        let dir = std::env::temp_dir().join("prompts-is-nai");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let map: Map<String, Value> =
+            serde_json::from_str(r#"{"Comment": {"prompt": "1girl"}}"#).unwrap();
+
+        // the magic fills one column of the tall image and several of the square one
+        for (name, width, height) in [("tall.png", 16, 512), ("square.png", 64, 64)] {
+            let im = DynamicImage::ImageRgb8(image::RgbImage::new(width, height));
+            let path = dir.join(name);
+            embed_metadata(im.clone(), &map)
+                .unwrap()
+                .save(&path)
+                .unwrap();
+            assert!(is_nai_image(&path), "{}", name);
+
+            let plain = dir.join(format!("plain-{}", name));
+            im.to_rgba8().save(&plain).unwrap();
+            assert!(!is_nai_image(&plain));
+        }
+        assert!(!is_nai_image(&dir.join("missing.png")));
+    }
+
+    #[test]
+    fn embedded_metadata_round_trips() {
+        let map: Map<String, Value> = serde_json::from_str(
+            r#"{"Software": "NovelAI", "Comment": {"prompt": "1girl, smile", "seed": 42}}"#,
+        )
+        .unwrap();
+        let im = DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+
+        let embedded = embed_metadata(im.clone(), &map).unwrap();
+        let read = extract_image_metadata(DynamicImage::ImageRgba8(embedded.clone())).unwrap();
+        assert_eq!(read, map);
+
+        let stripped = strip_metadata(DynamicImage::ImageRgba8(embedded));
+        assert!(extract_image_metadata(stripped).is_err());
+
+        let tiny = DynamicImage::ImageRgb8(image::RgbImage::new(8, 8));
+        assert!(embed_metadata(tiny, &map).is_err());
+    }
+
+    #[test]
+    fn strip_clears_alpha_lsbs() {
+        // payload bits in an opaque image, the alpha channel goes away
+        let mut im = image::RgbaImage::from_pixel(4, 4, image::Rgba([10, 20, 30, 255]));
+        im.get_pixel_mut(1, 2)[3] = 254;
+        let stripped = strip_metadata(DynamicImage::ImageRgba8(im));
+        assert_eq!(stripped.color(), image::ColorType::Rgb8);
+        assert_eq!(stripped.to_rgb8().get_pixel(1, 2).0, [10, 20, 30]);
+
+        // real transparency is kept, off by at most one
+        let im = image::RgbaImage::from_pixel(4, 4, image::Rgba([10, 20, 30, 128]));
+        let stripped = strip_metadata(DynamicImage::ImageRgba8(im));
+        assert_eq!(stripped.to_rgba8().get_pixel(0, 0).0, [10, 20, 30, 129]);
     }
 }
@@ -0,0 +1,609 @@
+use std::{fmt, fs, io, path::Path};
+
+use iced::{
+    keyboard::{
+        Modifiers,
+        key::{Key, Named},
+    },
+    widget::shader::wgpu::naga::FastHashMap,
+};
+use serde::{
+    Deserialize,
+    de::{DeserializeOwned, IntoDeserializer, value::Error as ValueError},
+};
+
+use crate::PROJECT_DIRS;
+
+/// what a key in the files pane can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilesAction {
+    // tree navigation, available in every mode that shows the tree
+    Up,
+    Down,
+    Start,
+    End,
+    Expand,
+    Refresh,
+    RefreshSelected,
+
+    // normal mode
+    NavigateUp,
+    SetRoot,
+    Import,
+    Delete,
+    Cut,
+    Paste,
+    Batch,
+    Create,
+    Search,
+    Duplicates,
+    Gallery,
+    NaiOnly,
+    Hidden,
+    NaturalSort,
+    CopyPath,
+    CopyFileName,
+    CopyMetadata,
+    Jump,
+    Reindex,
+    Review,
+
+    // batch mode
+    Mark,
+    MoveBatch,
+    DeleteBatch,
+}
+
+/// what a key in the image pane can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageAction {
+    // through the history entries the filter shows, wrapping around
+    Previous,
+    Next,
+    First,
+    Last,
+    DeleteHistory,
+    Pin,
+    Unpin,
+    Compare,
+    // only while comparing
+    ZoomIn,
+    ZoomOut,
+    ZoomFit,
+    // toggles the swipe view, also only while comparing
+    Swipe,
+    Review,
+    Inspect,
+    CopyImage,
+    CopyMetadata,
+    Favorite,
+    // the shown image alone on the whole screen, escape returns
+    Fullscreen,
+    // select a region of the shown image to export
+    Crop,
+    // copy the shown image to a chosen path
+    SaveAs,
+    // write a copy of the shown image without any metadata, for sharing
+    ExportStripped,
+}
+
+/// keys that work in every pane
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GlobalAction {
+    FocusUp,
+    FocusDown,
+    FocusLeft,
+    FocusRight,
+    ReloadKeymap,
+    Settings,
+    // inserts a snippet into the prompt editor last typed in
+    Palette,
+    Help,
+}
+
+// named keys that can be bound, by their name in keymap.toml
+const NAMED_KEYS: [(&str, Named); 13] = [
+    ("enter", Named::Enter),
+    ("backspace", Named::Backspace),
+    ("delete", Named::Delete),
+    ("space", Named::Space),
+    ("tab", Named::Tab),
+    ("up", Named::ArrowUp),
+    ("down", Named::ArrowDown),
+    ("left", Named::ArrowLeft),
+    ("right", Named::ArrowRight),
+    ("home", Named::Home),
+    ("end", Named::End),
+    ("pageup", Named::PageUp),
+    ("pagedown", Named::PageDown),
+];
+
+/// a key name ("j", "enter", "up", ...) and the modifiers held, written as "shift+g" or
+/// "ctrl+shift+r"
+///
+/// ctrl is the platform command key, so cmd on macOS
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Binding {
+    key: String,
+    shift: bool,
+    ctrl: bool,
+}
+
+impl Binding {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim().to_lowercase();
+        // the plus key is written as "+" or "ctrl++"
+        let (modifiers, key) = if s == "+" {
+            ("", "+")
+        } else if let Some(modifiers) = s.strip_suffix("++") {
+            (modifiers, "+")
+        } else {
+            s.rsplit_once('+').unwrap_or(("", &s))
+        };
+        if key.is_empty() {
+            return Err(format!("missing key in `{}`", s));
+        }
+
+        let mut binding = Self {
+            key: key.to_string(),
+            shift: false,
+            ctrl: false,
+        };
+        for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+            match modifier {
+                "shift" => binding.shift = true,
+                "ctrl" | "cmd" => binding.ctrl = true,
+                other => return Err(format!("unknown modifier `{}` in `{}`", other, s)),
+            }
+        }
+
+        let is_named = NAMED_KEYS.iter().any(|(name, _)| *name == binding.key);
+        if !is_named && binding.key.chars().count() != 1 {
+            return Err(format!("unknown key `{}` in `{}`", binding.key, s));
+        }
+        if binding.shift && !is_named && !is_letter(&binding.key) {
+            return Err(format!(
+                "shift with `{}` in `{}`, bind the shifted character instead",
+                binding.key, s
+            ));
+        }
+        Ok(binding)
+    }
+
+    pub fn from_key(key: &Key, modifiers: Modifiers) -> Option<Self> {
+        let key = match key.as_ref() {
+            Key::Character(c) => c.to_lowercase(),
+            Key::Named(named) => NAMED_KEYS
+                .iter()
+                .find(|(_, n)| *n == named)
+                .map(|(name, _)| name.to_string())?,
+            _ => return None,
+        };
+
+        // other characters already come shifted, "+" rather than shift + "="
+        let shift = modifiers.shift() && (key.chars().count() > 1 || is_letter(&key));
+        Some(Self {
+            key,
+            shift,
+            ctrl: modifiers.command(),
+        })
+    }
+}
+
+fn is_letter(key: &str) -> bool {
+    key.chars().all(char::is_alphabetic)
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "ctrl+")?;
+        }
+        if self.shift {
+            write!(f, "shift+")?;
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+/// keybindings, split by the pane and mode they apply in
+///
+/// `global` bindings are checked first in every pane; in the files pane `normal` and `batch` only
+/// apply in their mode and take precedence over `tree`, which applies whenever the tree is shown
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    pub global: FastHashMap<Binding, GlobalAction>,
+    pub tree: FastHashMap<Binding, FilesAction>,
+    pub normal: FastHashMap<Binding, FilesAction>,
+    pub batch: FastHashMap<Binding, FilesAction>,
+    pub image: FastHashMap<Binding, ImageAction>,
+}
+
+/// keymap.toml: every section is optional, a binding set to "none" removes the default
+///
+/// ```toml
+/// [normal]
+/// "shift+x" = "delete"
+/// d = "none"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct KeymapFile {
+    global: FastHashMap<String, String>,
+    tree: FastHashMap<String, String>,
+    normal: FastHashMap<String, String>,
+    batch: FastHashMap<String, String>,
+    image: FastHashMap<String, String>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        use FilesAction::*;
+
+        fn table<A: Copy>(bindings: &[(&str, A)]) -> FastHashMap<Binding, A> {
+            bindings
+                .iter()
+                .map(|&(key, action)| (Binding::parse(key).unwrap(), action))
+                .collect()
+        }
+
+        Self {
+            global: table(&[
+                ("ctrl+up", GlobalAction::FocusUp),
+                ("ctrl+down", GlobalAction::FocusDown),
+                ("ctrl+left", GlobalAction::FocusLeft),
+                ("ctrl+right", GlobalAction::FocusRight),
+                ("ctrl+shift+r", GlobalAction::ReloadKeymap),
+                ("ctrl+,", GlobalAction::Settings),
+                ("ctrl+j", GlobalAction::Palette),
+                ("?", GlobalAction::Help),
+            ]),
+            tree: table(&[
+                ("up", Up),
+                ("k", Up),
+                ("down", Down),
+                ("j", Down),
+                ("g", Start),
+                ("shift+g", End),
+                ("enter", Expand),
+                ("r", RefreshSelected),
+                ("shift+r", Refresh),
+            ]),
+            normal: table(&[
+                ("backspace", NavigateUp),
+                (".", SetRoot),
+                ("i", Import),
+                ("shift+i", Reindex),
+                ("d", Delete),
+                ("x", Cut),
+                ("p", Paste),
+                ("b", Batch),
+                ("a", Create),
+                ("/", Search),
+                ("u", Duplicates),
+                ("v", Gallery),
+                ("shift+v", Review),
+                ("n", NaiOnly),
+                ("shift+h", Hidden),
+                ("o", NaturalSort),
+                ("y", CopyPath),
+                ("shift+y", CopyFileName),
+                ("m", CopyMetadata),
+                ("f", Jump),
+            ]),
+            batch: table(&[("s", Mark), ("m", MoveBatch), ("shift+d", DeleteBatch)]),
+            image: table(&[
+                ("up", ImageAction::Previous),
+                ("k", ImageAction::Previous),
+                ("down", ImageAction::Next),
+                ("j", ImageAction::Next),
+                ("home", ImageAction::First),
+                ("end", ImageAction::Last),
+                ("shift+d", ImageAction::DeleteHistory),
+                ("p", ImageAction::Pin),
+                ("shift+p", ImageAction::Unpin),
+                ("c", ImageAction::Compare),
+                ("+", ImageAction::ZoomIn),
+                ("=", ImageAction::ZoomIn),
+                ("-", ImageAction::ZoomOut),
+                ("0", ImageAction::ZoomFit),
+                ("r", ImageAction::Review),
+                ("m", ImageAction::Inspect),
+                ("y", ImageAction::CopyImage),
+                ("ctrl+c", ImageAction::CopyImage),
+                ("shift+m", ImageAction::CopyMetadata),
+                ("f", ImageAction::Favorite),
+                ("shift+f", ImageAction::Fullscreen),
+                ("x", ImageAction::Crop),
+                ("ctrl+s", ImageAction::SaveAs),
+                ("ctrl+shift+s", ImageAction::ExportStripped),
+                ("s", ImageAction::Swipe),
+            ]),
+        }
+    }
+}
+
+impl Keymap {
+    /// the default bindings with the overrides from keymap.toml in the config dir applied
+    ///
+    /// a missing file is not an error
+    pub fn load() -> Result<Self, io::Error> {
+        let path = PROJECT_DIRS.config_dir().join("keymap.toml");
+        match Self::from_file(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            result => result,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, io::Error> {
+        let s = fs::read_to_string(path)?;
+        Self::from_toml(&s)
+    }
+
+    /// every invalid key or action is reported, one per line
+    pub fn from_toml(s: &str) -> Result<Self, io::Error> {
+        let file: KeymapFile =
+            toml::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut keymap = Self::default();
+        let mut errors = Vec::new();
+        apply_overrides("global", &mut keymap.global, file.global, &mut errors);
+        apply_overrides("tree", &mut keymap.tree, file.tree, &mut errors);
+        apply_overrides("normal", &mut keymap.normal, file.normal, &mut errors);
+        apply_overrides("batch", &mut keymap.batch, file.batch, &mut errors);
+        apply_overrides("image", &mut keymap.image, file.image, &mut errors);
+
+        if !errors.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                errors.join("\n"),
+            ));
+        }
+        Ok(keymap)
+    }
+
+    /// bindings that can never fire because a table checked earlier has the same key, sorted
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+
+        let shadowed = |conflicts: &mut Vec<String>,
+                        first: &str,
+                        first_table: &FastHashMap<Binding, String>,
+                        second: &str,
+                        second_table: &FastHashMap<Binding, String>| {
+            for (binding, action) in second_table {
+                if let Some(winner) = first_table.get(binding) {
+                    conflicts.push(format!(
+                        "{} in [{}] ({}) hides [{}] ({})",
+                        binding, first, winner, second, action
+                    ));
+                }
+            }
+        };
+
+        let global = names_of(&self.global);
+        let tree = names_of(&self.tree);
+        let normal = names_of(&self.normal);
+        let batch = names_of(&self.batch);
+        let image = names_of(&self.image);
+
+        for (name, table) in [
+            ("tree", &tree),
+            ("normal", &normal),
+            ("batch", &batch),
+            ("image", &image),
+        ] {
+            shadowed(&mut conflicts, "global", &global, name, table);
+        }
+        shadowed(&mut conflicts, "normal", &normal, "tree", &tree);
+        shadowed(&mut conflicts, "batch", &batch, "tree", &tree);
+
+        conflicts.sort();
+        conflicts
+    }
+
+    /// the bindings of every table as (section, [(keys, action)]), in the order the actions are
+    /// declared, for the help overlay
+    pub fn help(&self) -> Vec<(&'static str, Vec<(String, String)>)> {
+        vec![
+            ("global", help_of(&self.global)),
+            ("files: tree", help_of(&self.tree)),
+            ("files: normal mode", help_of(&self.normal)),
+            ("files: batch mode", help_of(&self.batch)),
+            ("image", help_of(&self.image)),
+        ]
+    }
+
+    pub fn global(&self, key: &Key, modifiers: Modifiers) -> Option<GlobalAction> {
+        self.global
+            .get(&Binding::from_key(key, modifiers)?)
+            .copied()
+    }
+
+    pub fn tree(&self, key: &Key, modifiers: Modifiers) -> Option<FilesAction> {
+        self.tree.get(&Binding::from_key(key, modifiers)?).copied()
+    }
+
+    pub fn normal(&self, key: &Key, modifiers: Modifiers) -> Option<FilesAction> {
+        self.normal
+            .get(&Binding::from_key(key, modifiers)?)
+            .copied()
+    }
+
+    pub fn batch(&self, key: &Key, modifiers: Modifiers) -> Option<FilesAction> {
+        self.batch.get(&Binding::from_key(key, modifiers)?).copied()
+    }
+
+    pub fn image(&self, key: &Key, modifiers: Modifiers) -> Option<ImageAction> {
+        self.image.get(&Binding::from_key(key, modifiers)?).copied()
+    }
+}
+
+fn apply_overrides<A: DeserializeOwned>(
+    section: &str,
+    table: &mut FastHashMap<Binding, A>,
+    overrides: FastHashMap<String, String>,
+    errors: &mut Vec<String>,
+) {
+    for (key, action) in overrides {
+        let binding = match Binding::parse(&key) {
+            Ok(binding) => binding,
+            Err(e) => {
+                errors.push(format!("[{}] {}", section, e));
+                continue;
+            }
+        };
+        if action == "none" {
+            table.remove(&binding);
+            continue;
+        }
+        match A::deserialize(action.as_str().into_deserializer()) {
+            Ok(action) => {
+                table.insert(binding, action);
+            }
+            Err(e) => {
+                let e: ValueError = e;
+                errors.push(format!("[{}] {}: {}", section, key, e));
+            }
+        }
+    }
+}
+
+// keys bound to the same action are joined, "down, j"
+fn help_of<A: fmt::Debug + Ord + Copy>(table: &FastHashMap<Binding, A>) -> Vec<(String, String)> {
+    let mut bindings: Vec<(A, String)> = table.iter().map(|(b, a)| (*a, b.to_string())).collect();
+    bindings.sort();
+
+    let mut help: Vec<(A, Vec<String>)> = Vec::new();
+    for (action, key) in bindings {
+        match help.last_mut() {
+            Some((last, keys)) if *last == action => keys.push(key),
+            _ => help.push((action, vec![key])),
+        }
+    }
+    help.into_iter()
+        .map(|(action, keys)| (keys.join(", "), words(&format!("{:?}", action))))
+        .collect()
+}
+
+// "NavigateUp" as "navigate up"
+fn words(name: &str) -> String {
+    let mut words = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            words.push(' ');
+        }
+        words.extend(c.to_lowercase());
+    }
+    words
+}
+
+fn names_of<A: fmt::Debug>(table: &FastHashMap<Binding, A>) -> FastHashMap<Binding, String> {
+    table
+        .iter()
+        .map(|(b, a)| (b.clone(), format!("{:?}", a)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overrides_and_disables() {
+        let keymap = Keymap::from_toml(
+            r#"
+            [tree]
+            j = "none"
+
+            [normal]
+            "shift+x" = "delete"
+            "#,
+        )
+        .unwrap();
+
+        let j = Key::Character("j".into());
+        assert_eq!(keymap.tree(&j, Modifiers::empty()), None);
+        assert_eq!(
+            keymap.tree(&Key::Named(Named::ArrowDown), Modifiers::empty()),
+            Some(FilesAction::Down)
+        );
+
+        let x = Key::Character("x".into());
+        assert_eq!(
+            keymap.normal(&x, Modifiers::SHIFT),
+            Some(FilesAction::Delete)
+        );
+        assert_eq!(
+            keymap.normal(&x, Modifiers::empty()),
+            Some(FilesAction::Cut)
+        );
+        assert_eq!(keymap.normal(&x, Modifiers::COMMAND), None);
+    }
+
+    #[test]
+    fn invalid_entries_are_all_reported() {
+        let err = Keymap::from_toml(
+            r#"
+            [normal]
+            d = "explode"
+            "alt+q" = "cut"
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(err.lines().count(), 2);
+        assert!(err.contains("explode"));
+        assert!(err.contains("alt"));
+
+        assert_eq!(Binding::parse("ctrl++").unwrap().to_string(), "ctrl++");
+        assert!(Binding::parse("shift+").is_err());
+        assert!(Binding::parse("jk").is_err());
+        assert!(Binding::parse("shift+1").is_err());
+
+        let plus = Binding::from_key(&Key::Character("+".into()), Modifiers::SHIFT);
+        assert_eq!(plus, Binding::parse("+").ok());
+    }
+
+    #[test]
+    fn shadowed_bindings() {
+        assert!(Keymap::default().conflicts().is_empty());
+
+        let keymap = Keymap::from_toml(
+            r#"
+            [normal]
+            j = "cut"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            keymap.conflicts(),
+            vec!["j in [normal] (Cut) hides [tree] (Down)"]
+        );
+    }
+    #[test]
+    fn help_follows_overrides() {
+        let keymap = Keymap::from_toml(
+            r#"
+            [tree]
+            k = "none"
+            "shift+u" = "up"
+            "#,
+        )
+        .unwrap();
+        let help = keymap.help();
+        let (section, tree) = &help[1];
+        assert_eq!(*section, "files: tree");
+        assert_eq!(tree[0], ("shift+u, up".to_string(), "up".to_string()));
+        assert_eq!(tree[1], ("down, j".to_string(), "down".to_string()));
+        assert!(
+            help[2]
+                .1
+                .contains(&("shift+i".to_string(), "reindex".to_string()))
+        );
+        assert_eq!(words("NavigateUp"), "navigate up");
+    }
+}
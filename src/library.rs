@@ -0,0 +1,500 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use iced::{futures::executor, widget::shader::wgpu::naga::FastHashMap};
+use image::{DynamicImage, imageops::FilterType};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, params};
+use serde_json::{Map, Value};
+use tokio::task::spawn_blocking;
+
+use crate::{
+    db::SqliteError,
+    files::is_image,
+    image_metadata::{extract_image_metadata, is_nai_image, read_many},
+    parallel::par_map,
+};
+
+/// max hamming distance between dhashes for two images to count as duplicates
+pub const DUPLICATE_MAX_DISTANCE: u32 = 4;
+
+// decoded images written per transaction, so the ui's writes wait for one batch at most
+const INDEX_BATCH: usize = 100;
+
+// path, mtime, dhash and metadata of a decoded image
+type Indexed = (PathBuf, i64, Option<i64>, Option<Map<String, Value>>);
+
+/// the subset of NAI metadata stored per file in the images table
+#[derive(Debug, Clone, Default)]
+pub struct IndexedMetadata {
+    pub seed: Option<i64>,
+    pub prompt: Option<String>,
+    pub characters: Vec<String>,
+    pub model: Option<String>,
+}
+
+impl IndexedMetadata {
+    pub fn from_map(map: &Map<String, Value>) -> Self {
+        let comment = map.get("Comment");
+
+        let seed = comment.and_then(|c| c.get("seed")).and_then(Value::as_i64);
+        let prompt = comment
+            .and_then(|c| c.get("prompt"))
+            .and_then(Value::as_str)
+            .map(String::from);
+        let characters = comment
+            .and_then(|c| c.pointer("/v4_prompt/caption/char_captions"))
+            .and_then(Value::as_array)
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|c| c.get("char_caption").and_then(Value::as_str))
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let model = map.get("Source").and_then(Value::as_str).map(String::from);
+
+        Self {
+            seed,
+            prompt,
+            characters,
+            model,
+        }
+    }
+}
+
+/// walks `dir` recursively and records the metadata of every png in the images table
+///
+/// files whose mtime matches the stored row are skipped, so only new or modified images are
+/// decoded, on all cores; rows for files that no longer exist are removed
+/// returns the number of files that were (re)indexed
+pub async fn index_library(
+    pool: Pool<SqliteConnectionManager>,
+    dir: PathBuf,
+) -> Result<usize, SqliteError> {
+    spawn_blocking(move || index_library_blocking(pool, &dir))
+        .await
+        .map_err(|e| SqliteError { err: e.to_string() })?
+}
+
+fn index_library_blocking(
+    pool: Pool<SqliteConnectionManager>,
+    dir: &Path,
+) -> Result<usize, SqliteError> {
+    let mut conn = pool.get().unwrap();
+
+    // path -> (mtime, has dhash)
+    let mut known: FastHashMap<String, (i64, bool)> = FastHashMap::default();
+    {
+        let mut stmt = conn
+            .prepare("SELECT path, mtime, dhash IS NOT NULL FROM images")
+            .map_err(SqliteError::new)?;
+        let rows = stmt
+            .query_map([], |r| {
+                Ok((
+                    r.get::<usize, String>(0)?,
+                    r.get::<usize, i64>(1)?,
+                    r.get::<usize, bool>(2)?,
+                ))
+            })
+            .map_err(SqliteError::new)?;
+        for row in rows {
+            let (path, mtime, hashed) = row.map_err(SqliteError::new)?;
+            known.insert(path, (mtime, hashed));
+        }
+    }
+
+    let mut paths = Vec::new();
+    collect_pngs(dir, &mut paths);
+
+    let seen: HashSet<String> = paths
+        .iter()
+        .map(|(path, _)| path.to_string_lossy().to_string())
+        .collect();
+    // path -> mtime of the files that are new, modified or were never hashed
+    let stale: FastHashMap<PathBuf, i64> = paths
+        .into_iter()
+        .filter(|(path, mtime)| known.get(&*path.to_string_lossy()) != Some(&(*mtime, true)))
+        .collect();
+    let read = read_many(stale.keys().cloned().collect(), |path| {
+        let im = image::open(path).ok();
+        let hash = im.as_ref().map(|im| dhash(im) as i64);
+        // most images of a mixed folder have no payload, skip unpacking their alpha
+        let map = im
+            .filter(|_| is_nai_image(path))
+            .and_then(|im| extract_image_metadata(im).ok());
+        (hash, map)
+    });
+    let mut count = 0;
+
+    // the images are decoded outside of any transaction and written in short ones
+    let mut batch: Vec<Indexed> = Vec::with_capacity(INDEX_BATCH);
+    for (path, (hash, map)) in executor::block_on_stream(read) {
+        let mtime = stale[&path];
+        batch.push((path, mtime, hash, map));
+        if batch.len() == INDEX_BATCH {
+            count += write_indexed(&mut conn, &mut batch)?;
+        }
+    }
+    count += write_indexed(&mut conn, &mut batch)?;
+
+    let tx = conn.transaction().map_err(SqliteError::new)?;
+    {
+        let mut delete = tx
+            .prepare_cached("DELETE FROM images WHERE path = ?1")
+            .map_err(SqliteError::new)?;
+        let mut uncache = tx
+            .prepare_cached("DELETE FROM metadata_cache WHERE path = ?1")
+            .map_err(SqliteError::new)?;
+        for path in known.keys().filter(|p| !seen.contains(*p)) {
+            delete.execute([path]).map_err(SqliteError::new)?;
+            uncache.execute([path]).map_err(SqliteError::new)?;
+        }
+    }
+    tx.commit().map_err(SqliteError::new)?;
+
+    Ok(count)
+}
+
+/// saves and empties `batch` in one transaction; returns the number of images written
+fn write_indexed(conn: &mut Connection, batch: &mut Vec<Indexed>) -> Result<usize, SqliteError> {
+    if batch.is_empty() {
+        return Ok(0);
+    }
+    let tx = conn.transaction().map_err(SqliteError::new)?;
+    {
+        let mut insert = tx
+            .prepare_cached(include_str!("../sql/i_image.sql"))
+            .map_err(SqliteError::new)?;
+        let mut cache = tx
+            .prepare_cached(include_str!("../sql/i_metadata_cache.sql"))
+            .map_err(SqliteError::new)?;
+
+        for (path, mtime, hash, map) in batch.iter() {
+            let key = path.to_string_lossy().to_string();
+            // saves the UI decoding the image again when it is opened
+            if let Some(map) = map {
+                cache
+                    .execute(params![key, mtime, Value::Object(map.clone()).to_string()])
+                    .map_err(SqliteError::new)?;
+            }
+            let meta = map.as_ref().map(IndexedMetadata::from_map);
+
+            let nai = meta.is_some();
+            let meta = meta.unwrap_or_default();
+            insert
+                .execute(params![
+                    key,
+                    mtime,
+                    nai,
+                    meta.seed,
+                    meta.prompt,
+                    meta.characters.join("\n"),
+                    meta.model,
+                    hash
+                ])
+                .map_err(SqliteError::new)?;
+        }
+    }
+    tx.commit().map_err(SqliteError::new)?;
+
+    let written = batch.len();
+    batch.clear();
+    Ok(written)
+}
+
+/// every png below `dir` with its mtime in seconds
+pub fn collect_pngs(dir: &Path, out: &mut Vec<(PathBuf, i64)>) {
+    collect_files(
+        dir,
+        &|path| path.extension().is_some_and(|ext| ext == "png"),
+        out,
+    );
+}
+
+/// like `collect_pngs`, with the jpegs and webps metadata can be read from as well
+pub fn collect_images(dir: &Path, out: &mut Vec<(PathBuf, i64)>) {
+    collect_files(dir, &is_image, out);
+}
+
+fn collect_files(dir: &Path, wanted: &dyn Fn(&Path) -> bool, out: &mut Vec<(PathBuf, i64)>) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in read_dir.flatten() {
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        let path = entry.path();
+
+        // linked folders can point back up the tree, so only real folders are walked
+        if meta.is_dir() && !entry.file_type().is_ok_and(|t| t.is_symlink()) {
+            collect_files(&path, wanted, out);
+        } else if wanted(&path) {
+            let mtime = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs() as i64);
+            out.push((path, mtime));
+        }
+    }
+}
+
+/// returns paths of indexed images whose base or character prompts contain `query`
+pub async fn search_images(
+    pool: Pool<SqliteConnectionManager>,
+    query: String,
+) -> Result<Vec<PathBuf>, SqliteError> {
+    let conn = pool.get().unwrap();
+    let mut stmt = conn
+        .prepare(include_str!("../sql/s_image_search.sql"))
+        .map_err(SqliteError::new)?;
+
+    let pattern = format!("%{}%", query.trim());
+    let rows = stmt
+        .query_map([pattern], |r| r.get::<usize, String>(0))
+        .map_err(SqliteError::new)?;
+
+    let mut paths = Vec::new();
+    for row in rows {
+        paths.push(PathBuf::from(row.map_err(SqliteError::new)?));
+    }
+    Ok(paths)
+}
+
+/// paths of every indexed image that carries NAI metadata
+pub async fn nai_paths(
+    pool: Pool<SqliteConnectionManager>,
+) -> Result<HashSet<PathBuf>, SqliteError> {
+    let conn = pool.get().unwrap();
+    let mut stmt = conn
+        .prepare("SELECT path FROM images WHERE nai = 1")
+        .map_err(SqliteError::new)?;
+    let rows = stmt
+        .query_map([], |r| r.get::<usize, String>(0))
+        .map_err(SqliteError::new)?;
+
+    let mut paths = HashSet::new();
+    for row in rows {
+        paths.insert(PathBuf::from(row.map_err(SqliteError::new)?));
+    }
+    Ok(paths)
+}
+
+/// `nai_paths` and those of `files` it lacks that are NAI images, probed with `is_nai_image` on
+/// all cores, so images added since the last index are found too
+pub async fn nai_paths_among(
+    pool: Pool<SqliteConnectionManager>,
+    files: Vec<PathBuf>,
+) -> Result<HashSet<PathBuf>, SqliteError> {
+    let mut paths = nai_paths(pool).await?;
+    let unknown: Vec<PathBuf> = files.into_iter().filter(|p| !paths.contains(p)).collect();
+    let probed = spawn_blocking(move || {
+        let nai = par_map(&unknown, |path| is_nai_image(path));
+        unknown
+            .into_iter()
+            .zip(nai)
+            .filter_map(|(path, nai)| nai.then_some(path))
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| SqliteError { err: e.to_string() })?;
+    paths.extend(probed);
+    Ok(paths)
+}
+
+/// paths of every image marked as favorite
+pub fn favorite_paths(conn: &Connection) -> Result<HashSet<PathBuf>, SqliteError> {
+    let mut stmt = conn
+        .prepare("SELECT path FROM favorites")
+        .map_err(SqliteError::new)?;
+    let rows = stmt
+        .query_map([], |r| r.get::<usize, String>(0))
+        .map_err(SqliteError::new)?;
+
+    let mut paths = HashSet::new();
+    for row in rows {
+        paths.insert(PathBuf::from(row.map_err(SqliteError::new)?));
+    }
+    Ok(paths)
+}
+
+pub async fn set_favorite(
+    pool: Pool<SqliteConnectionManager>,
+    path: PathBuf,
+    favorite: bool,
+) -> Result<(), SqliteError> {
+    let conn = pool.get().unwrap();
+    let path = path.to_string_lossy().to_string();
+    if favorite {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        conn.execute(
+            "INSERT OR REPLACE INTO favorites(path, ts) VALUES(?1, ?2)",
+            params![path, ts],
+        )
+        .map_err(SqliteError::new)?;
+    } else {
+        conn.execute("DELETE FROM favorites WHERE path = ?1", [path])
+            .map_err(SqliteError::new)?;
+    }
+    Ok(())
+}
+
+/// 64-bit difference hash of a 9x8 grayscale thumbnail
+///
+/// each bit records whether a pixel is brighter than its right neighbour, so re-encodes and
+/// near-identical generations end up a few bits apart
+pub fn dhash(im: &DynamicImage) -> u64 {
+    let small = im.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+/// groups hashes whose hamming distance to the first member of a group is at most
+/// `max_distance`; only groups with more than one member are returned
+pub fn group_duplicates(hashes: &[(PathBuf, u64)], max_distance: u32) -> Vec<Vec<PathBuf>> {
+    let mut grouped = vec![false; hashes.len()];
+    let mut groups = Vec::new();
+
+    for i in 0..hashes.len() {
+        if grouped[i] {
+            continue;
+        }
+
+        let mut group = vec![hashes[i].0.clone()];
+        for j in i + 1..hashes.len() {
+            if !grouped[j] && (hashes[i].1 ^ hashes[j].1).count_ones() <= max_distance {
+                grouped[j] = true;
+                group.push(hashes[j].0.clone());
+            }
+        }
+
+        if group.len() > 1 {
+            groups.push(group);
+        }
+    }
+    groups
+}
+
+/// groups of near-identical indexed images, oldest file first in each group
+pub async fn find_duplicates(
+    pool: Pool<SqliteConnectionManager>,
+    max_distance: u32,
+) -> Result<Vec<Vec<PathBuf>>, SqliteError> {
+    let hashes = {
+        let conn = pool.get().unwrap();
+        let mut stmt = conn
+            .prepare(include_str!("../sql/s_image_dhash.sql"))
+            .map_err(SqliteError::new)?;
+        let rows = stmt
+            .query_map([], |r| {
+                Ok((
+                    PathBuf::from(r.get::<usize, String>(0)?),
+                    r.get::<usize, i64>(1)? as u64,
+                ))
+            })
+            .map_err(SqliteError::new)?;
+
+        let mut hashes = Vec::new();
+        for row in rows {
+            hashes.push(row.map_err(SqliteError::new)?);
+        }
+        hashes
+    };
+
+    spawn_blocking(move || group_duplicates(&hashes, max_distance))
+        .await
+        .map_err(|e| SqliteError { err: e.to_string() })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn group_near_identical() {
+        let hashes = vec![
+            (PathBuf::from("a.png"), 0b1111_0000),
+            (PathBuf::from("b.png"), 0b1111_0001),
+            (PathBuf::from("c.png"), u64::MAX),
+            (PathBuf::from("d.png"), 0b0111_0000),
+        ];
+
+        let groups = group_duplicates(&hashes, 2);
+        assert_eq!(
+            groups,
+            vec![vec![
+                PathBuf::from("a.png"),
+                PathBuf::from("b.png"),
+                PathBuf::from("d.png")
+            ]]
+        );
+    }
+
+    #[test]
+    fn index_writes_in_batches() {
+        let dir = std::env::temp_dir().join("prompts-index-batches");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("pngs")).unwrap();
+        for i in 0..INDEX_BATCH + 1 {
+            image::RgbImage::new(2, 2)
+                .save(dir.join(format!("pngs/{}.png", i)))
+                .unwrap();
+        }
+
+        let pool = Pool::new(SqliteConnectionManager::file(dir.join("prompts.db"))).unwrap();
+        crate::db::run_migrations(&pool.get().unwrap()).unwrap();
+        let indexed = index_library_blocking(pool.clone(), &dir.join("pngs")).unwrap();
+        assert_eq!(indexed, INDEX_BATCH + 1);
+        let rows: usize = pool
+            .get()
+            .unwrap()
+            .query_row("SELECT COUNT(1) FROM images", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(rows, INDEX_BATCH + 1);
+
+        // nothing changed, nothing is read again
+        assert_eq!(index_library_blocking(pool, &dir.join("pngs")).unwrap(), 0);
+    }
+
+    #[test]
+    fn dhash_is_stable_for_resized_copies() {
+        // blocks of noise from a fixed seed, so neighbours differ across and down the image
+        let noise = |seed: u32| {
+            let mut state = seed;
+            let blocks: Vec<u8> = (0..9 * 8)
+                .map(|_| {
+                    state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    (state >> 24) as u8
+                })
+                .collect();
+            DynamicImage::ImageLuma8(image::ImageBuffer::from_fn(72, 64, |x, y| {
+                image::Luma([blocks[(y / 8 * 9 + x / 8) as usize]])
+            }))
+        };
+        let im = noise(1);
+        let resized = im.resize_exact(54, 48, FilterType::Nearest);
+
+        assert_ne!(dhash(&im), 0);
+        assert!((dhash(&im) ^ dhash(&resized)).count_ones() <= 2);
+        assert!((dhash(&im) ^ dhash(&noise(2))).count_ones() > DUPLICATE_MAX_DISTANCE);
+    }
+}
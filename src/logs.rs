@@ -0,0 +1,205 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// records kept for the log pane, older ones are dropped
+const CAPACITY: usize = 2000;
+
+static SINK: Mutex<Sink> = Mutex::new(Sink::new(CAPACITY));
+// whether records are also printed to stderr
+static ECHO: AtomicBool = AtomicBool::new(true);
+// bumped whenever the records change
+static VERSION: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub const ALL: [Level; 4] = [Level::Debug, Level::Info, Level::Warn, Level::Error];
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // pad so records can align the levels
+        f.pad(match self {
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub time: SystemTime,
+    pub level: Level,
+    pub message: String,
+}
+
+impl fmt::Display for Record {
+    /// "hh:mm:ss level message", the time of day in UTC
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+            % 86400;
+        write!(
+            f,
+            "{:02}:{:02}:{:02} {:<5} {}",
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60,
+            self.level,
+            self.message
+        )
+    }
+}
+
+struct Sink {
+    records: VecDeque<Record>,
+    capacity: usize,
+}
+
+impl Sink {
+    const fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    fn matching(&self, min: Level, filter: &str) -> Vec<Record> {
+        let filter = filter.trim().to_lowercase();
+        self.records
+            .iter()
+            .filter(|r| r.level >= min)
+            .filter(|r| filter.is_empty() || r.message.to_lowercase().contains(&filter))
+            .cloned()
+            .collect()
+    }
+}
+
+/// keeps `message` for the log pane and prints it to stderr like before, unless turned off
+pub fn log(level: Level, message: String) {
+    if ECHO.load(Ordering::Relaxed) {
+        eprintln!("{}: {}", level, message);
+    }
+    let record = Record {
+        time: SystemTime::now(),
+        level,
+        message,
+    };
+    if let Ok(mut sink) = SINK.lock() {
+        sink.push(record);
+    }
+    VERSION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// records at `min` or above whose message contains `filter`, ignoring case, oldest first
+pub fn records(min: Level, filter: &str) -> Vec<Record> {
+    SINK.lock()
+        .map(|sink| sink.matching(min, filter))
+        .unwrap_or_default()
+}
+
+/// changes whenever a record is kept or the records are cleared, so a copy of `records` knows
+/// when it is stale
+pub fn version() -> u64 {
+    VERSION.load(Ordering::Relaxed)
+}
+
+/// stops or resumes printing records to stderr, for interfaces drawn in the terminal
+pub fn set_echo(echo: bool) {
+    ECHO.store(echo, Ordering::Relaxed);
+}
+
+pub fn clear() {
+    if let Ok(mut sink) = SINK.lock() {
+        sink.records.clear();
+    }
+    VERSION.fetch_add(1, Ordering::Relaxed);
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::logs::log($crate::logs::Level::Debug, format!($($arg)*)) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::logs::log($crate::logs::Level::Info, format!($($arg)*)) };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::logs::log($crate::logs::Level::Warn, format!($($arg)*)) };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::logs::log($crate::logs::Level::Error, format!($($arg)*)) };
+}
+
+pub(crate) use {debug, error, info, warning};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sink_keeps_latest() {
+        let record = |level, message: &str| Record {
+            time: UNIX_EPOCH,
+            level,
+            message: message.to_string(),
+        };
+        let mut sink = Sink::new(3);
+        sink.push(record(Level::Info, "dropped"));
+        sink.push(record(Level::Debug, "watching /out"));
+        sink.push(record(Level::Error, "task 1 failed"));
+        sink.push(record(Level::Warn, "Task 2 retrying"));
+
+        let messages = |records: Vec<Record>| -> Vec<String> {
+            records.into_iter().map(|r| r.message).collect()
+        };
+        assert_eq!(
+            messages(sink.matching(Level::Debug, "")),
+            ["watching /out", "task 1 failed", "Task 2 retrying"]
+        );
+        assert_eq!(
+            messages(sink.matching(Level::Warn, "")),
+            ["task 1 failed", "Task 2 retrying"]
+        );
+        assert_eq!(
+            messages(sink.matching(Level::Debug, " task ")),
+            ["task 1 failed", "Task 2 retrying"]
+        );
+        assert_eq!(
+            record(Level::Warn, "slow").to_string(),
+            "00:00:00 warn  slow"
+        );
+    }
+
+    #[test]
+    fn version_follows_records() {
+        let before = version();
+        debug!("version check");
+        assert!(version() > before);
+    }
+}
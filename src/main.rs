@@ -1,24 +1,51 @@
-use std::{fs, sync::LazyLock};
+use std::{fs, path::PathBuf, sync::LazyLock};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use directories::ProjectDirs;
 use iced::{
-    Subscription,
-    window::{get_latest, maximize},
+    Subscription, Task,
+    window::{get_latest, get_scale_factor, maximize},
 };
 
 mod image_metadata;
-use image_metadata::extract_image_metadata;
+use image_metadata::extract_metadata;
 
+mod a1111;
+mod autocomplete;
+mod cli;
+mod comfyui;
+mod counter;
+mod crop;
 mod db;
 mod files;
+mod fuzzy;
+mod gallery;
+mod highlight;
+mod history;
+mod keymap;
+mod library;
+mod logs;
+mod metadata_cache;
 mod nai;
+mod palette;
+mod parallel;
+mod placement;
+mod prompt_diff;
+mod retention;
+mod settings;
+mod swipe;
+mod themes;
+mod thumbnails;
+mod timing;
+mod tui;
 mod ui;
 
 use crate::{
-    db::import_from_dir,
+    db::{PromptKind, import_files, import_from_dir},
+    settings::Settings,
     ui::{
-        State, event_subscribe, run_fsevent_subscription, run_image_gen_subscription, update, view,
+        Message, State, event_subscribe, run_fsevent_subscription, run_image_gen_subscription,
+        update, view,
     },
 };
 
@@ -71,29 +98,430 @@ fn main() -> iced::Result {
                     ])
                 })
                 .theme(|state| state.selected_theme.clone())
+                .scale_factor(State::scale_factor)
+                .settings(iced::Settings {
+                    // the text size only applies on startup, unlike the scale
+                    default_text_size: Settings::load()
+                        .map_or(Settings::default().text_size, |s| s.text_size)
+                        .into(),
+                    ..iced::Settings::default()
+                })
                 .run_with(|| {
                     (
                         State::default(),
-                        get_latest().and_then(|id| maximize(id, true)),
+                        Task::batch([
+                            get_latest().and_then(|id| maximize(id, true)),
+                            get_latest()
+                                .and_then(get_scale_factor)
+                                .map(Message::DisplayScale),
+                            Task::done(Message::IndexLibrary),
+                            Task::done(Message::LoadVocabulary),
+                            Task::done(Message::PlanRetention(true)),
+                            Task::done(Message::EvictThumbnails),
+                        ]),
                     )
                 })?;
         }
-        Commands::Metadata { path } => {
-            let im = image::open(path).expect("open");
-            if let Ok(map) = extract_image_metadata(im) {
-                if let Ok(ser) = serde_json::to_string_pretty(&map) {
-                    println!("{:?}", ser);
+        Commands::Metadata {
+            action: Some(MetadataCmd::Dir { path, out }),
+            ..
+        } => match cli::export_metadata(path, out) {
+            Ok((written, skipped)) => eprintln!(
+                "wrote {} images to {:?}, {} without metadata",
+                written, out, skipped
+            ),
+            Err(e) => eprintln!("export error: {}", e),
+        },
+        Commands::Metadata {
+            action: Some(MetadataCmd::Strip { path, out }),
+            ..
+        } => match cli::strip_metadata_to(path, out) {
+            Ok(written) => eprintln!("wrote {} stripped images to {:?}", written, out),
+            Err(e) => eprintln!("strip error: {}", e),
+        },
+        Commands::Metadata {
+            action: Some(MetadataCmd::Write { image, from }),
+            ..
+        } => match cli::write_metadata(image, from) {
+            Ok(()) => eprintln!("wrote the metadata of {:?} into {:?}", from, image),
+            Err(e) => eprintln!("write error: {}", e),
+        },
+        Commands::Metadata {
+            action: Some(MetadataCmd::Convert { image, to }),
+            ..
+        } => {
+            let converted = match to {
+                FormatArg::A1111 => cli::convert_to_a1111(image),
+                FormatArg::Nai => cli::convert_to_nai(image),
+            };
+            match converted {
+                Ok(()) => eprintln!("converted {:?}", image),
+                Err(e) => eprintln!("convert error: {}", e),
+            }
+        }
+        Commands::Metadata {
+            action: Some(MetadataCmd::Verify { image }),
+            ..
+        } => match image_metadata::check_integrity(image) {
+            Ok(integrity) => {
+                println!("{}", integrity);
+                if integrity.payload.is_err() {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("{:?}: {}", image, e);
+                std::process::exit(1);
+            }
+        },
+        Commands::Metadata {
+            path,
+            format,
+            field,
+            ..
+        } => {
+            let Some(path) = path else {
+                eprintln!("missing path, or `metadata dir|strip|write|convert|verify ...`");
+                std::process::exit(2);
+            };
+            let map = match image::open(path).map(|im| extract_metadata(im, path.as_ref())) {
+                Ok(Ok(map)) => serde_json::Value::Object(map),
+                Ok(Err(_)) => {
+                    eprintln!("{} has no NAI, A1111 or ComfyUI metadata", path);
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    std::process::exit(1);
+                }
+            };
+            let value = match field {
+                Some(field) => match cli::metadata_field(&map, field) {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("{} has no field {}", path, field);
+                        std::process::exit(1);
+                    }
+                },
+                None => &map,
+            };
+            let out = match (format, value) {
+                // a single text field is printed as is, so it can be used in scripts
+                (OutputArg::Pretty, serde_json::Value::String(s)) => Ok(s.clone()),
+                (OutputArg::Pretty, _) => {
+                    serde_json::to_string_pretty(value).map_err(|e| e.to_string())
+                }
+                (OutputArg::Json, _) => serde_json::to_string(value).map_err(|e| e.to_string()),
+                (OutputArg::Yaml, _) => serde_yaml::to_string(value)
+                    .map(|s| s.trim_end().to_string())
+                    .map_err(|e| e.to_string()),
+            };
+            match out {
+                Ok(out) => println!("{}", out),
+                Err(e) => {
+                    eprintln!("format error: {}", e);
+                    std::process::exit(1);
                 }
             }
         }
-        Commands::Import { action } => match action {
-            ImportCmd::Dir { path } => runtime.block_on(async {
-                match import_from_dir(path).await {
-                    Ok(_) => eprintln!("import ok"),
-                    Err(e) => eprintln!("import error: {:?}", e),
+        Commands::Search {
+            query,
+            kind,
+            tags,
+            limit,
+        } => match cli::load_prompts() {
+            Ok(mut prompts) => {
+                if let Some(kind) = kind {
+                    prompts.retain(|(k, _, _)| *k == PromptKind::from(*kind));
                 }
-            }),
+                for hit in cli::search_prompts(prompts, query, tags, *limit) {
+                    let kind = match hit.kind {
+                        PromptKind::Base => "base",
+                        PromptKind::Character => "char",
+                        PromptKind::Template => "template",
+                    };
+                    println!("{:<8} {}\t{}", kind, hit.name, hit.snippet);
+                }
+            }
+            Err(e) => eprintln!("search error: {:?}", e),
         },
+        Commands::Db { action } => match action {
+            DbCmd::Backup { file } => match cli::backup_db(&cli::db_path(), file) {
+                Ok(()) => eprintln!("backed up to {:?}", file),
+                Err(e) => eprintln!("backup error: {}", e.err),
+            },
+            DbCmd::Restore { file } => match cli::restore_db(file, &cli::db_path()) {
+                Ok(Some(kept)) => eprintln!("restored {:?}, the old database is {:?}", file, kept),
+                Ok(None) => eprintln!("restored {:?}", file),
+                Err(e) => eprintln!("restore error: {}", e.err),
+            },
+            DbCmd::Maintain { fix_orphans } => {
+                let report = rusqlite::Connection::open(cli::db_path())
+                    .map_err(db::SqliteError::new)
+                    .and_then(|conn| cli::maintain_db(&conn, *fix_orphans));
+                match report {
+                    Ok(report) => {
+                        println!("integrity: {}", report.integrity);
+                        for (table, rows, bytes) in &report.tables {
+                            match bytes {
+                                Some(bytes) => {
+                                    println!("{:<24} {:>8} rows {:>10} bytes", table, rows, bytes)
+                                }
+                                None => println!("{:<24} {:>8} rows", table, rows),
+                            }
+                        }
+                        for (template, column) in &report.orphans {
+                            println!(
+                                "template {:?}: {} refers to a missing prompt",
+                                template, column
+                            );
+                        }
+                        if *fix_orphans {
+                            println!("fixed {} references", report.fixed);
+                        }
+                    }
+                    Err(e) => eprintln!("maintenance error: {}", e.err),
+                }
+            }
+        },
+        Commands::Import { dry_run, action } => {
+            let dry_run = *dry_run;
+            let db = cli::db_path();
+            let report = runtime.block_on(async {
+                match action {
+                    ImportCmd::Dir { path } => {
+                        import_from_dir(&db, path, dry_run, cli::progress_bar).await
+                    }
+                    ImportCmd::File { path } => {
+                        import_files(&db, std::slice::from_ref(path), dry_run, |_, _| {}).await
+                    }
+                    ImportCmd::Glob { pattern } => {
+                        import_files(&db, &cli::glob(pattern), dry_run, cli::progress_bar).await
+                    }
+                }
+            });
+            match report {
+                Ok(report) => {
+                    for (path, e) in &report.failed {
+                        eprintln!("failed {:?}: {}", path, e);
+                    }
+                    eprintln!(
+                        "{} {}, {} without NAI metadata, {} failed",
+                        if dry_run { "would import" } else { "imported" },
+                        report.imported,
+                        report.skipped,
+                        report.failed.len()
+                    );
+                    for (kind, created) in [
+                        ("base prompts", report.bases),
+                        ("characters", report.characters),
+                        ("templates", report.templates),
+                    ] {
+                        eprintln!(
+                            "  {:<12} {} new, {} already saved",
+                            kind, created.new, created.existing
+                        );
+                    }
+                }
+                Err(e) => eprintln!("import error: {}", e.err),
+            }
+        }
+        Commands::Dedupe {
+            dir,
+            distance,
+            trash,
+            dry_run,
+        } => {
+            let groups = cli::find_duplicates_in(dir, *distance);
+            let mut trashed = 0;
+            for group in &groups {
+                println!("keep  {}", group[0].display());
+                for path in &group[1..] {
+                    if !*trash {
+                        println!("  dup {}", path.display());
+                    } else if *dry_run {
+                        println!("  would trash {}", path.display());
+                    } else if let Err(e) = trash::delete(path) {
+                        eprintln!("failed to trash {:?}: {}", path, e);
+                    } else {
+                        println!("  trashed {}", path.display());
+                        trashed += 1;
+                    }
+                }
+            }
+            eprintln!("{} groups, {} files trashed", groups.len(), trashed);
+        }
+        Commands::Stats { days, top } => {
+            let stats = rusqlite::Connection::open(cli::db_path())
+                .map_err(db::SqliteError::new)
+                .and_then(|conn| {
+                    cli::stats(&conn, &PROJECT_DIRS.data_dir().join("output"), *days, *top)
+                });
+            match stats {
+                Ok(stats) => {
+                    println!("generations per day");
+                    for (day, n) in &stats.per_day {
+                        println!("  {} {:>6}", day, n);
+                    }
+                    println!("most used prompts");
+                    for (prompt, n) in &stats.prompts {
+                        println!("  {:>6} {}", n, prompt.replace('\n', " "));
+                    }
+                    println!("most used tags");
+                    for (tag, n) in &stats.tags {
+                        println!("  {:>6} {}", n, tag);
+                    }
+                    if let Some(average) = stats.average {
+                        println!("average generation {}", timing::format_duration(average));
+                    }
+                    println!(
+                        "output folder {} files, {:.1} MiB",
+                        stats.files,
+                        stats.bytes as f64 / (1024.0 * 1024.0)
+                    );
+                }
+                Err(e) => eprintln!("stats error: {}", e.err),
+            }
+        }
+        Commands::Regen { image, seed, count } => {
+            let settings = Settings::load().unwrap_or_default();
+            let regen = cli::regen(
+                image,
+                matches!(seed, SeedArg::Same),
+                *count,
+                settings.output_dir,
+                std::time::Duration::from_millis(settings.cooldown_ms),
+            );
+            match runtime.block_on(regen) {
+                Ok(paths) => eprintln!("generated {} images", paths.len()),
+                Err(e) => eprintln!("regen error: {}", e),
+            }
+        }
+        Commands::Rename {
+            dir,
+            template,
+            dry_run,
+        } => match cli::plan_renames(dir, template) {
+            Ok(renames) if *dry_run => {
+                for (old, new) in &renames {
+                    println!("{} -> {}", old.display(), new.display());
+                }
+                eprintln!("would rename {} files", renames.len());
+            }
+            Ok(renames) => {
+                let mut done = Vec::with_capacity(renames.len());
+                for (old, new) in renames {
+                    match fs::rename(&old, &new) {
+                        Ok(()) => done.push((old, new)),
+                        Err(e) => eprintln!("failed to rename {:?}: {}", old, e),
+                    }
+                }
+                let moved = rusqlite::Connection::open(cli::db_path())
+                    .map_err(db::SqliteError::new)
+                    .and_then(|conn| cli::move_db_paths(&conn, &done));
+                if let Err(e) = moved {
+                    eprintln!("renamed, but the index still has the old paths: {}", e.err);
+                }
+                eprintln!("renamed {} files", done.len());
+            }
+            Err(e) => eprintln!("rename error: {}", e),
+        },
+        Commands::Config { action } => match action {
+            ConfigCmd::Init => {
+                if Settings::path().exists() {
+                    eprintln!("{:?} already exists", Settings::path());
+                } else {
+                    match Settings::default().save() {
+                        Ok(()) => eprintln!("wrote the defaults to {:?}", Settings::path()),
+                        Err(e) => eprintln!("config error: {}", e),
+                    }
+                }
+            }
+            ConfigCmd::Show => match Settings::load().map(|s| toml::to_string_pretty(&s)) {
+                Ok(Ok(s)) => {
+                    eprintln!("# {:?}", Settings::path());
+                    print!("{}", s);
+                }
+                Ok(Err(e)) => eprintln!("config error: {}", e),
+                Err(e) => eprintln!("config error: {}", e),
+            },
+            ConfigCmd::Set { key, value } => {
+                let set = Settings::load()
+                    .and_then(|s| s.with_value(key, value))
+                    .and_then(|s| s.save());
+                match set {
+                    Ok(()) => eprintln!("set {} in {:?}", key, Settings::path()),
+                    Err(e) => eprintln!("config error: {}", e),
+                }
+            }
+        },
+        Commands::Export { action } => match action {
+            ExportCmd::Gallery { dir, out } => match gallery::export_gallery(dir, out) {
+                Ok(n) => eprintln!("wrote {} images to {:?}", n, out),
+                Err(e) => eprintln!("export error: {}", e),
+            },
+        },
+        Commands::Thumbs { dir } => {
+            let cache = thumbnails::cache_dir();
+            match thumbnails::build_thumbnails(&cache, dir, cli::progress_bar) {
+                Ok(report) => {
+                    for (path, e) in &report.failed {
+                        eprintln!("failed {:?}: {}", path, e);
+                    }
+                    eprintln!(
+                        "{} thumbnails built, {} up to date, {} failed, {} evicted",
+                        report.built,
+                        report.fresh,
+                        report.failed.len(),
+                        report.evicted
+                    );
+                }
+                Err(e) => eprintln!("thumbnail error: {}", e),
+            }
+        }
+        Commands::Verify { dir } => {
+            let report = rusqlite::Connection::open(cli::db_path())
+                .map_err(db::SqliteError::new)
+                .and_then(|conn| cli::verify(&conn, dir));
+            match report {
+                Ok(report) => {
+                    for (path, e) in &report.corrupt {
+                        println!("corrupt   {}: {}", path.display(), e);
+                    }
+                    for path in &report.foreign {
+                        println!("foreign   {}", path.display());
+                    }
+                    for path in &report.missing {
+                        println!("missing   {}", path.display());
+                    }
+                    for path in &report.unindexed {
+                        println!("unindexed {}", path.display());
+                    }
+                    eprintln!(
+                        "{} checked: {} corrupt, {} foreign, {} missing, {} unindexed",
+                        report.checked,
+                        report.corrupt.len(),
+                        report.foreign.len(),
+                        report.missing.len(),
+                        report.unindexed.len()
+                    );
+                }
+                Err(e) => eprintln!("verify error: {}", e.err),
+            }
+        }
+        Commands::Tui => {
+            let tui = r2d2::Pool::new(db::manager(cli::db_path()))
+                .map_err(|e| std::io::Error::other(e.to_string()))
+                .and_then(|pool| tui::run(&runtime, pool));
+            if let Err(e) = tui {
+                eprintln!("tui error: {}", e);
+            }
+        }
+        Commands::Watch { dir } => {
+            if let Err(e) = cli::watch(dir, &cli::db_path()) {
+                eprintln!("watch error: {}", e);
+            }
+        }
     }
 
     Ok(())
@@ -108,16 +536,219 @@ struct Args {
 #[derive(Subcommand)]
 enum Commands {
     Ui,
+    /// picks saved prompts and generates from them in the terminal, for ssh or machines without
+    /// a display
+    Tui,
+    /// a png's metadata, or with `dir` that of a whole folder
+    #[command(args_conflicts_with_subcommands = true)]
     Metadata {
-        path: String,
+        path: Option<String>,
+        #[arg(long, value_enum, default_value_t = OutputArg::Pretty)]
+        format: OutputArg,
+        /// a dotted path into the metadata like `Comment.seed`, printed instead of all of it
+        #[arg(long)]
+        field: Option<String>,
+        #[command(subcommand)]
+        action: Option<MetadataCmd>,
     },
     Import {
+        /// read every file and report what would be saved, without changing the database
+        #[arg(long, global = true)]
+        dry_run: bool,
         #[command(subcommand)]
         action: ImportCmd,
     },
+    /// near-identical pngs below `dir`, grouped with the newest first
+    Dedupe {
+        dir: PathBuf,
+        /// max differing bits of the perceptual hashes
+        #[arg(long, default_value_t = library::DUPLICATE_MAX_DISTANCE)]
+        distance: u32,
+        /// move all but the newest of each group to the trash
+        #[arg(long)]
+        trash: bool,
+        /// with --trash, only list what would be moved
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// generations per day, the most used prompts and tags and the size of the output folder
+    Stats {
+        #[arg(long, default_value_t = 14)]
+        days: usize,
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// new images with the prompts, size and scale of a png's metadata
+    Regen {
+        image: PathBuf,
+        #[arg(long, value_enum, default_value_t = SeedArg::New)]
+        seed: SeedArg,
+        #[arg(long, default_value_t = 1)]
+        count: usize,
+    },
+    /// gives epoch named outputs names like 2026-10-15_1234567_1.png
+    Rename {
+        dir: PathBuf,
+        /// of {date}, {time}, {epoch}, {seed} and {n}, which counts up until the name is free
+        #[arg(long, default_value = "{date}_{seed}_{n}")]
+        template: String,
+        /// only list the new names
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// the config.toml the ui uses
+    Config {
+        #[command(subcommand)]
+        action: ConfigCmd,
+    },
+    Export {
+        #[command(subcommand)]
+        action: ExportCmd,
+    },
+    /// caches a thumbnail of every png below `dir` for the gallery and evicts the least recently
+    /// used thumbnails past the size of the cache
+    Thumbs {
+        dir: PathBuf,
+    },
+    /// checks that every png below `dir` decodes and has metadata, and that the index matches the
+    /// files on disk
+    Verify {
+        dir: PathBuf,
+    },
+    /// imports the prompts of new pngs in `dir` until stopped
+    Watch {
+        dir: PathBuf,
+    },
+    /// saved prompts by name or text, optionally only of one kind and with all of the tags
+    Search {
+        #[arg(default_value = "")]
+        query: String,
+        #[arg(long, value_enum)]
+        kind: Option<KindArg>,
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    Db {
+        #[command(subcommand)]
+        action: DbCmd,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExportCmd {
+    /// a page of every png below `dir` with its prompts and parameters, markdown if `out` ends
+    /// in .md and otherwise html with embedded thumbnails
+    Gallery {
+        dir: PathBuf,
+        #[arg(long)]
+        out: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCmd {
+    /// writes the defaults, unless there is a config already
+    Init,
+    /// the config with the defaults of missing keys filled in
+    Show,
+    /// a key like `concurrency` or `retention.action` to a toml value
+    Set { key: String, value: String },
+}
+
+#[derive(Subcommand)]
+enum DbCmd {
+    /// a consistent copy of prompts.db, also while the ui is running
+    Backup {
+        file: PathBuf,
+    },
+    /// replaces prompts.db with a backup, keeping the old one as prompts.db.bak
+    Restore {
+        file: PathBuf,
+    },
+    /// vacuum, analyze and check the database, listing the size of every table
+    Maintain {
+        /// clear characters and delete templates referring to missing prompts
+        #[arg(long)]
+        fix_orphans: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum KindArg {
+    Base,
+    Char,
+    Template,
+}
+
+impl From<KindArg> for PromptKind {
+    fn from(kind: KindArg) -> Self {
+        match kind {
+            KindArg::Base => PromptKind::Base,
+            KindArg::Char => PromptKind::Character,
+            KindArg::Template => PromptKind::Template,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum MetadataCmd {
+    /// every png below `path`, recursively, as json lines
+    Dir {
+        path: PathBuf,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// copies of a png or of every png below a folder without prompts and seeds, for sharing
+    Strip {
+        path: PathBuf,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// embeds the metadata of another png or a json file, for images an editor stripped
+    Write {
+        image: PathBuf,
+        #[arg(long)]
+        from: PathBuf,
+    },
+    /// translates the metadata of a png between NovelAI and A1111, in place
+    Convert {
+        image: PathBuf,
+        #[arg(long, value_enum)]
+        to: FormatArg,
+    },
+    /// whether the stealth payload of an image is intact and its pixels look re-encoded
+    Verify {
+        image: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SeedArg {
+    New,
+    Same,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputArg {
+    /// one line
+    Json,
+    /// indented json, text fields without quotes
+    Pretty,
+    Yaml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FormatArg {
+    A1111,
+    Nai,
 }
 
 #[derive(Subcommand)]
 enum ImportCmd {
     Dir { path: String },
+    File { path: PathBuf },
+    /// quoted so the shell leaves it alone, like "archive/**/*.png"
+    Glob { pattern: String },
 }
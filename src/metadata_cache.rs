@@ -0,0 +1,141 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde_json::{Map, Value};
+
+use crate::{
+    image_metadata::{MetadataError, read_metadata},
+    logs::debug,
+};
+
+/// what reading an image gave: its metadata, or None when it has none
+pub type Entry = Option<Map<String, Value>>;
+
+/// the mtime of `path` in seconds, as the index and the cache store it
+pub fn mtime_of(path: &Path) -> io::Result<i64> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64))
+}
+
+/// what the image at `path` gave when it was last read at `mtime`, None if it was not
+pub fn lookup(conn: &Connection, path: &Path, mtime: i64) -> Option<Entry> {
+    let json: Option<String> = conn
+        .prepare_cached("SELECT metadata FROM metadata_cache WHERE path = ?1 AND mtime = ?2")
+        .and_then(|mut stmt| {
+            stmt.query_row(params![path.to_string_lossy(), mtime], |r| r.get(0))
+                .optional()
+        })
+        .ok()??;
+    match json {
+        None => Some(None),
+        // unreadable json is read from the file again
+        Some(json) => serde_json::from_str(&json).ok().map(Some),
+    }
+}
+
+/// records what each (path, mtime) gave, replacing what was cached for the path before
+pub fn store(conn: &Connection, entries: &[(PathBuf, i64, Entry)]) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut insert = tx.prepare_cached(include_str!("../sql/i_metadata_cache.sql"))?;
+        for (path, mtime, entry) in entries {
+            let json = entry
+                .as_ref()
+                .map(|map| Value::Object(map.clone()).to_string());
+            insert.execute(params![path.to_string_lossy(), mtime, json])?;
+        }
+    }
+    tx.commit()
+}
+
+/// what `read_metadata` gave as an entry of the cache; only an image without metadata is cached
+/// as such, one that cannot be read is an error
+pub fn entry_of(read: Result<Map<String, Value>, MetadataError>) -> Result<Entry, MetadataError> {
+    match read {
+        Ok(map) => Ok(Some(map)),
+        Err(MetadataError::NoMetadata) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// like `read_metadata`, but the file is only decoded when the cache has nothing for its
+/// current mtime, and what it gives is cached for the next time
+///
+/// without a connection the file is read every time
+pub fn read_cached(
+    pool: &Pool<SqliteConnectionManager>,
+    path: &Path,
+) -> Result<Map<String, Value>, MetadataError> {
+    let Ok(conn) = pool.get() else {
+        return read_metadata(path);
+    };
+    let mtime = mtime_of(path)?;
+    if let Some(entry) = lookup(&conn, path, mtime) {
+        return entry.ok_or(MetadataError::NoMetadata);
+    }
+
+    let entry = entry_of(read_metadata(path))?;
+    // only costs the next lookup a decode
+    if let Err(e) = store(&conn, &[(path.to_path_buf(), mtime, entry.clone())]) {
+        debug!("metadata cache: {}", e);
+    }
+    entry.ok_or(MetadataError::NoMetadata)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_each_file_once_per_mtime() {
+        let dir = std::env::temp_dir().join("prompts-metadata-cache");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let pool = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        crate::db::run_migrations(&pool.get().unwrap()).unwrap();
+
+        let map = serde_json::json!({"Comment": {"prompt": "1girl", "seed": 1}});
+        let im = image::DynamicImage::ImageRgb8(image::RgbImage::new(64, 64));
+        let nai = dir.join("nai.png");
+        crate::image_metadata::embed_metadata(im.clone(), map.as_object().unwrap())
+            .unwrap()
+            .save(&nai)
+            .unwrap();
+        let plain = dir.join("plain.png");
+        im.save(&plain).unwrap();
+
+        assert_eq!(read_cached(&pool, &nai).unwrap(), *map.as_object().unwrap());
+        assert_eq!(read_cached(&pool, &plain), Err(MetadataError::NoMetadata));
+        let conn = pool.get().unwrap();
+        let mtime = mtime_of(&nai).unwrap();
+        assert_eq!(lookup(&conn, &nai, mtime), Some(map.as_object().cloned()));
+        assert_eq!(lookup(&conn, &plain, mtime_of(&plain).unwrap()), Some(None));
+
+        // the cached reading is what is returned, until the mtime changes
+        let cached = serde_json::json!({"Comment": {"prompt": "cached"}});
+        store(&conn, &[(nai.clone(), mtime, cached.as_object().cloned())]).unwrap();
+        drop(conn);
+        assert_eq!(
+            read_cached(&pool, &nai).unwrap()["Comment"]["prompt"],
+            "cached"
+        );
+        let conn = pool.get().unwrap();
+        store(&conn, &[(nai.clone(), mtime - 1, None)]).unwrap();
+        assert_eq!(lookup(&conn, &nai, mtime), None);
+        drop(conn);
+        assert_eq!(read_cached(&pool, &nai).unwrap(), *map.as_object().unwrap());
+
+        assert!(read_cached(&pool, &dir.join("missing.png")).is_err());
+    }
+}
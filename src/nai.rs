@@ -2,7 +2,7 @@ use std::{
     fmt::{self, Display},
     fs::{self},
     io::Cursor,
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
@@ -10,14 +10,81 @@ use bytes::Bytes;
 use rand::distr::{Alphanumeric, SampleString};
 use reqwest::{Client, Method, StatusCode, header::HeaderMap};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use tokio::task::spawn_blocking;
 
 use zip::{read::ZipArchive, result::ZipResult};
 
-use crate::PROJECT_DIRS;
+use crate::{
+    image_metadata::MetadataError,
+    logs::{debug, info, warning},
+};
 
 const NOVELAI_ENDPOINT: &str = "https://image.novelai.net/ai/generate-image";
-pub const NEGATIVE_PROMPT: &'static str = "lowres, artistic error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, dithering, halftone, screentone, multiple views, logo, too many watermarks, negative space, blank page, blurry, lowres, error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, logo, too many watermarks, {{{bad eyes}}}, blurry eyes, fewer, extra, missing, worst quality, watermark, unfinished, displeasing, signature, extra digits, artistic error, username, scan, bad anatomy, @_@, mismatched pupils, heart-shaped pupils, glowing eyes, low quality, {{{bad}}}, normal quality, disfigured, flower, artist signature, watermark, monochrome, black bars, cinematic bars, plaque, wall ornament, speech bubble, extra arms, extra breasts, loli, child, amputee, missing limb, 1.22::extra fingers, long fingers, missing fingers, bad hands::, extra digit, fewer digits, mutation, white border, eyes without pupils, multiple views, 1.3::disembodied penis::, x-ray, fake animal ears, animal ears, 1.1::pubic hair, female pubic hair, male pubic hair::, censored, border, 1.2::sound effects, text::";
+const SUBSCRIPTION_ENDPOINT: &str = "https://api.novelai.net/user/subscription";
+
+/// a building block of the negative prompt, toggled in the prompts pane
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NegativeGroup {
+    Quality,
+    Text,
+    Anatomy,
+    Nsfw,
+}
+
+impl NegativeGroup {
+    pub const ALL: [Self; 4] = [Self::Quality, Self::Text, Self::Anatomy, Self::Nsfw];
+
+    pub fn tags(self) -> &'static str {
+        match self {
+            Self::Quality => {
+                "lowres, artistic error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, dithering, halftone, screentone, multiple views, negative space, blank page, blurry, error, unfinished, displeasing, scan, low quality, {{{bad}}}, normal quality, disfigured, flower, monochrome, black bars, cinematic bars, plaque, wall ornament, white border, x-ray, border"
+            }
+            Self::Text => {
+                "logo, too many watermarks, watermark, signature, username, artist signature, speech bubble, 1.2::sound effects, text::"
+            }
+            Self::Anatomy => {
+                "{{{bad eyes}}}, blurry eyes, fewer, extra, missing, extra digits, bad anatomy, @_@, mismatched pupils, heart-shaped pupils, glowing eyes, extra arms, extra breasts, amputee, missing limb, 1.22::extra fingers, long fingers, missing fingers, bad hands::, extra digit, fewer digits, mutation, eyes without pupils, fake animal ears, animal ears"
+            }
+            Self::Nsfw => {
+                "loli, child, 1.3::disembodied penis::, 1.1::pubic hair, female pubic hair, male pubic hair::, censored"
+            }
+        }
+    }
+}
+
+impl Display for NegativeGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Quality => write!(f, "Quality / artifacts"),
+            Self::Text => write!(f, "Text / watermarks"),
+            Self::Anatomy => write!(f, "Anatomy"),
+            Self::Nsfw => write!(f, "NSFW filtering"),
+        }
+    }
+}
+
+impl NegativeGroup {
+    /// the groups `negative` is made of, when it is exactly what `negative_prompt` makes of them
+    pub fn of_prompt(negative: &str) -> Option<Vec<Self>> {
+        let groups: Vec<Self> = Self::ALL
+            .into_iter()
+            .filter(|g| negative.contains(g.tags()))
+            .collect();
+        (negative_prompt(&groups) == negative).then_some(groups)
+    }
+}
+
+/// the negative prompt made of the enabled `groups`, always in the order of `NegativeGroup::ALL`
+pub fn negative_prompt(groups: &[NegativeGroup]) -> String {
+    NegativeGroup::ALL
+        .iter()
+        .filter(|g| groups.contains(g))
+        .map(|g| g.tags())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
 pub struct Requester {
     client: Client,
@@ -39,26 +106,67 @@ impl Default for Requester {
 }
 
 impl Requester {
+    /// `on_saving` is called once the image is downloaded, before it is written to `output_dir`
     pub async fn generate_image(
         &self,
         req: ImageGenRequest,
+        output_dir: PathBuf,
+        on_saving: impl FnOnce(),
     ) -> Result<(Bytes, PathBuf), ImageGenerationError> {
         let (bytes, end) = self.call_service(&req).await?;
-        eprintln!("{} elapsed", end);
+        info!("{} elapsed", end);
+        on_saving();
 
         let bytes_clone = bytes.clone();
         let res = spawn_blocking(move || -> Result<PathBuf, ImageGenerationError> {
-            let output_path = save_image(bytes_clone)
+            let output_path = save_image(bytes_clone, &output_dir)
                 .map_err(|e| ImageGenerationError::ZipError(e.to_string()))?;
             Ok(output_path)
         })
         .await
         .map_err(|_e| ImageGenerationError::JoinError)??;
-        eprintln!("{:?}", res);
+        debug!("{:?}", res);
 
         Ok((bytes, res))
     }
 
+    /// the Anlas left on the account, subscription and purchased combined
+    pub async fn anlas(&self) -> Result<u64, ImageGenerationError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TrainingSteps {
+            fixed_training_steps_left: u64,
+            purchased_training_steps: u64,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Subscription {
+            training_steps_left: TrainingSteps,
+        }
+
+        let resp = self
+            .client
+            .get(SUBSCRIPTION_ENDPOINT)
+            .bearer_auth(&self.api_token)
+            .send()
+            .await
+            .map_err(|e| ImageGenerationError::SendRequest(e.to_string()))?;
+        if !resp.status().is_success() {
+            return Err(ImageGenerationError::ClientError(format!(
+                "{}: {:?}",
+                resp.status(),
+                resp.text().await
+            )));
+        }
+
+        let sub = resp
+            .json::<Subscription>()
+            .await
+            .map_err(|e| ImageGenerationError::Deserialization(e.to_string()))?;
+        let steps = sub.training_steps_left;
+        Ok(steps.fixed_training_steps_left + steps.purchased_training_steps)
+    }
+
     pub async fn call_service(
         &self,
         params: &ImageGenRequest,
@@ -88,7 +196,7 @@ impl Requester {
                 .execute(req.try_clone().unwrap())
                 .await
                 .map_err(|e| ImageGenerationError::SendRequest(e.to_string()))?;
-            eprintln!("{}", resp.status());
+            debug!("{}", resp.status());
 
             if resp.status().is_success() {
                 break resp;
@@ -100,7 +208,7 @@ impl Requester {
                 }
                 tokio::time::sleep(Duration::from_secs(wait)).await;
                 attempts -= 1;
-                eprintln!(
+                warning!(
                     "{}: {:?} ({} attempts left)",
                     resp.status(),
                     resp.text().await,
@@ -152,7 +260,7 @@ impl Display for ImageGenerationError {
     }
 }
 
-pub fn save_image(bytes: Bytes) -> ZipResult<PathBuf> {
+pub fn save_image(bytes: Bytes, output_dir: &Path) -> ZipResult<PathBuf> {
     let reader = Cursor::new(bytes);
     let mut archive = ZipArchive::new(reader)?;
     let mut file = archive.by_index(0)?;
@@ -165,7 +273,8 @@ pub fn save_image(bytes: Bytes) -> ZipResult<PathBuf> {
         .unwrap()
         .as_secs();
 
-    let mut output_path = PROJECT_DIRS.data_dir().join("output").join(now.to_string());
+    fs::create_dir_all(output_dir)?;
+    let mut output_path = output_dir.join(now.to_string());
     output_path.set_extension("png");
     fs::write(&output_path, &buf)?;
 
@@ -198,6 +307,36 @@ impl ImageGenRequest {
         self.parameters.height = shape.as_width_height().1;
     }
 
+    pub fn base_prompt(&self) -> &str {
+        &self.input
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.parameters.width, self.parameters.height)
+    }
+
+    pub fn negative(&mut self, negative: String) {
+        self.parameters.negative_prompt = negative.clone();
+        self.parameters.v4_negative_prompt.caption.base_caption = negative;
+    }
+
+    pub fn char_captions(&self) -> &[CharCaption] {
+        &self.parameters.v4_prompt.caption.char_captions
+    }
+
+    pub fn negative_prompt(&self) -> &str {
+        &self.parameters.negative_prompt
+    }
+
+    pub fn uses_coords(&self) -> bool {
+        self.parameters.use_coords
+    }
+
+    // NAI appends its quality tags to the base prompt when set
+    pub fn quality_toggle(&self) -> bool {
+        self.parameters.quality_toggle
+    }
+
     pub fn seed(&mut self, seed: u64) {
         self.parameters.seed = seed;
     }
@@ -207,7 +346,11 @@ impl ImageGenRequest {
         self.parameters.v4_prompt.caption.base_caption = prompt;
     }
 
+    /// adds `ch` to the prompt, unless it is disabled
     pub fn add_character(&mut self, ch: &Character) {
+        if !ch.enabled {
+            return;
+        }
         self.parameters.character_prompts.push(ch.clone());
         self.parameters
             .v4_prompt
@@ -232,6 +375,44 @@ impl ImageGenRequest {
         self.parameters.v4_prompt.use_coords = enable;
     }
 
+    /// the request that made an image, from its NAI metadata: the prompts with the character
+    /// positions, the size, the guidance scale and rescale, the steps and the seed
+    pub fn from_metadata(map: &Map<String, Value>) -> Result<Self, String> {
+        let meta = GenerationMetadata::from_map(map).map_err(|e| e.to_string())?;
+
+        let mut req = Self::default();
+        req.prompt(meta.prompt);
+        if let Some(negative) = meta.negative {
+            req.negative(negative);
+        }
+        for (text, center) in meta.characters {
+            req.add_character(Character::new().prompt(text).set_center(center));
+        }
+        req.use_coords(meta.use_coords);
+
+        if let Some((w, h)) = meta.size {
+            req.parameters.width = w;
+            req.parameters.height = h;
+        }
+        if let Some(scale) = meta.scale {
+            req.parameters.scale = scale;
+        }
+        if let Some(cfg_rescale) = meta.cfg_rescale {
+            req.parameters.cfg_rescale = cfg_rescale;
+        }
+        if let Some(steps) = meta.steps {
+            req.parameters.steps = steps.min(u8::MAX.into()) as u8;
+        }
+        if let Some(seed) = meta.seed {
+            req.seed(seed);
+        }
+        Ok(req)
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.parameters.seed
+    }
+
     pub fn _get_prompt(&self) -> String {
         self.input.clone()
     }
@@ -245,6 +426,89 @@ impl ImageGenRequest {
     }
 }
 
+/// what an image was generated with, from the `Comment` of the metadata
+/// `extract_image_metadata` returns and its `Source`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenerationMetadata {
+    pub model: Option<String>,
+    pub prompt: String,
+    pub negative: Option<String>,
+    // prompt and center of each character, in order
+    pub characters: Vec<(String, Point)>,
+    pub use_coords: bool,
+    pub seed: Option<u64>,
+    pub sampler: Option<String>,
+    pub steps: Option<u32>,
+    pub scale: Option<f32>,
+    pub cfg_rescale: Option<f32>,
+    // width and height
+    pub size: Option<(u32, u32)>,
+}
+
+// the part of NAI's comment that is read, images from other software leave most of it out
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Comment {
+    prompt: Option<String>,
+    uc: Option<String>,
+    seed: Option<u64>,
+    sampler: Option<String>,
+    steps: Option<u32>,
+    scale: Option<f32>,
+    cfg_rescale: Option<f32>,
+    width: Option<u32>,
+    height: Option<u32>,
+    v4_prompt: Option<CommentV4Prompt>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct CommentV4Prompt {
+    caption: Caption,
+    use_coords: bool,
+}
+
+impl GenerationMetadata {
+    pub fn character_prompts(&self) -> Vec<String> {
+        self.characters.iter().map(|(c, _)| c.clone()).collect()
+    }
+
+    pub fn from_map(map: &Map<String, Value>) -> Result<Self, MetadataError> {
+        let comment = map.get("Comment").ok_or(MetadataError::NoPrompt)?;
+        let comment: Comment = serde_json::from_value(comment.clone())?;
+        let v4 = comment.v4_prompt.unwrap_or_default();
+
+        // images from before v4 only have the prompt
+        let prompt = Some(v4.caption.base_caption)
+            .filter(|p| !p.is_empty())
+            .or(comment.prompt)
+            .ok_or(MetadataError::NoPrompt)?;
+        let characters = v4
+            .caption
+            .char_captions
+            .into_iter()
+            .map(|c| {
+                let center = c.centers.first().copied().unwrap_or_default();
+                (c.char_caption, center)
+            })
+            .collect();
+
+        Ok(Self {
+            model: map.get("Source").and_then(Value::as_str).map(String::from),
+            prompt,
+            negative: comment.uc,
+            characters,
+            use_coords: v4.use_coords,
+            seed: comment.seed,
+            sampler: comment.sampler,
+            steps: comment.steps,
+            scale: comment.scale,
+            cfg_rescale: comment.cfg_rescale,
+            size: comment.width.zip(comment.height),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct RequestParameters {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -334,7 +598,7 @@ impl Default for RequestParameters {
             legacy_uc: false,
             legacy_v3_extend: false,
             n_samples: 1,
-            negative_prompt: NEGATIVE_PROMPT.into(),
+            negative_prompt: negative_prompt(&NegativeGroup::ALL),
             noise_schedule: NoiseSchedule::default(),
             params_version: 3,
             prefer_brownian: true,
@@ -403,6 +667,20 @@ impl Character {
         self
     }
 
+    pub fn set_center(&mut self, p: Point) -> &mut Self {
+        self.center = p;
+        self
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub const fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
     pub fn get_prompt(&self) -> &str {
         &self.prompt
     }
@@ -439,7 +717,7 @@ impl Default for V4NegativePrompt {
     fn default() -> Self {
         Self {
             caption: Caption {
-                base_caption: String::from(NEGATIVE_PROMPT),
+                base_caption: negative_prompt(&NegativeGroup::ALL),
                 char_captions: vec![],
             },
             legacy_uc: false,
@@ -448,6 +726,7 @@ impl Default for V4NegativePrompt {
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct Caption {
     pub base_caption: String,
     pub char_captions: Vec<CharCaption>,
@@ -456,6 +735,7 @@ pub struct Caption {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct CharCaption {
     pub char_caption: String,
+    #[serde(default)]
     pub centers: Vec<Point>,
 }
 
@@ -471,33 +751,42 @@ impl Default for Point {
     }
 }
 
+// rows of the preset grid run down the image (y), columns across it (x)
+impl Point {
+    /// row and column of the 5x5 preset cell the point falls into
+    pub fn cell(&self) -> (usize, usize) {
+        let index = |v: f64| ((v * 5.0).floor().max(0.0) as usize).min(4);
+        (index(self.y), index(self.x))
+    }
+}
+
 impl From<Position> for Point {
     fn from(value: Position) -> Self {
         match value {
             Position::R0C0 => Point { x: 0.1, y: 0.1 },
-            Position::R0C1 => Point { x: 0.1, y: 0.3 },
-            Position::R0C2 => Point { x: 0.1, y: 0.5 },
-            Position::R0C3 => Point { x: 0.1, y: 0.7 },
-            Position::R0C4 => Point { x: 0.1, y: 0.9 },
-            Position::R1C0 => Point { x: 0.3, y: 0.1 },
+            Position::R0C1 => Point { x: 0.3, y: 0.1 },
+            Position::R0C2 => Point { x: 0.5, y: 0.1 },
+            Position::R0C3 => Point { x: 0.7, y: 0.1 },
+            Position::R0C4 => Point { x: 0.9, y: 0.1 },
+            Position::R1C0 => Point { x: 0.1, y: 0.3 },
             Position::R1C1 => Point { x: 0.3, y: 0.3 },
-            Position::R1C2 => Point { x: 0.3, y: 0.5 },
-            Position::R1C3 => Point { x: 0.3, y: 0.7 },
-            Position::R1C4 => Point { x: 0.3, y: 0.9 },
-            Position::R2C0 => Point { x: 0.5, y: 0.1 },
-            Position::R2C1 => Point { x: 0.5, y: 0.3 },
+            Position::R1C2 => Point { x: 0.5, y: 0.3 },
+            Position::R1C3 => Point { x: 0.7, y: 0.3 },
+            Position::R1C4 => Point { x: 0.9, y: 0.3 },
+            Position::R2C0 => Point { x: 0.1, y: 0.5 },
+            Position::R2C1 => Point { x: 0.3, y: 0.5 },
             Position::R2C2 => Point { x: 0.5, y: 0.5 },
-            Position::R2C3 => Point { x: 0.5, y: 0.7 },
-            Position::R2C4 => Point { x: 0.5, y: 0.9 },
-            Position::R3C0 => Point { x: 0.7, y: 0.1 },
-            Position::R3C1 => Point { x: 0.7, y: 0.3 },
-            Position::R3C2 => Point { x: 0.7, y: 0.5 },
+            Position::R2C3 => Point { x: 0.7, y: 0.5 },
+            Position::R2C4 => Point { x: 0.9, y: 0.5 },
+            Position::R3C0 => Point { x: 0.1, y: 0.7 },
+            Position::R3C1 => Point { x: 0.3, y: 0.7 },
+            Position::R3C2 => Point { x: 0.5, y: 0.7 },
             Position::R3C3 => Point { x: 0.7, y: 0.7 },
-            Position::R3C4 => Point { x: 0.7, y: 0.9 },
-            Position::R4C0 => Point { x: 0.9, y: 0.1 },
-            Position::R4C1 => Point { x: 0.9, y: 0.3 },
-            Position::R4C2 => Point { x: 0.9, y: 0.5 },
-            Position::R4C3 => Point { x: 0.9, y: 0.7 },
+            Position::R3C4 => Point { x: 0.9, y: 0.7 },
+            Position::R4C0 => Point { x: 0.1, y: 0.9 },
+            Position::R4C1 => Point { x: 0.3, y: 0.9 },
+            Position::R4C2 => Point { x: 0.5, y: 0.9 },
+            Position::R4C3 => Point { x: 0.7, y: 0.9 },
             Position::R4C4 => Point { x: 0.9, y: 0.9 },
         }
     }
@@ -533,33 +822,44 @@ pub enum Position {
     R4C4,
 }
 
+impl Position {
+    /// the presets by row and column
+    pub const GRID: [[Self; 5]; 5] = [
+        [Self::R0C0, Self::R0C1, Self::R0C2, Self::R0C3, Self::R0C4],
+        [Self::R1C0, Self::R1C1, Self::R1C2, Self::R1C3, Self::R1C4],
+        [Self::R2C0, Self::R2C1, Self::R2C2, Self::R2C3, Self::R2C4],
+        [Self::R3C0, Self::R3C1, Self::R3C2, Self::R3C3, Self::R3C4],
+        [Self::R4C0, Self::R4C1, Self::R4C2, Self::R4C3, Self::R4C4],
+    ];
+}
+
 impl From<Point> for Position {
     fn from(value: Point) -> Self {
         match value {
             Point { x: 0.1, y: 0.1 } => Self::R0C0,
-            Point { x: 0.1, y: 0.3 } => Self::R0C1,
-            Point { x: 0.1, y: 0.5 } => Self::R0C2,
-            Point { x: 0.1, y: 0.7 } => Self::R0C3,
-            Point { x: 0.1, y: 0.9 } => Self::R0C4,
-            Point { x: 0.3, y: 0.1 } => Self::R1C0,
+            Point { x: 0.3, y: 0.1 } => Self::R0C1,
+            Point { x: 0.5, y: 0.1 } => Self::R0C2,
+            Point { x: 0.7, y: 0.1 } => Self::R0C3,
+            Point { x: 0.9, y: 0.1 } => Self::R0C4,
+            Point { x: 0.1, y: 0.3 } => Self::R1C0,
             Point { x: 0.3, y: 0.3 } => Self::R1C1,
-            Point { x: 0.3, y: 0.5 } => Self::R1C2,
-            Point { x: 0.3, y: 0.7 } => Self::R1C3,
-            Point { x: 0.3, y: 0.9 } => Self::R1C4,
-            Point { x: 0.5, y: 0.1 } => Self::R2C0,
-            Point { x: 0.5, y: 0.3 } => Self::R2C1,
+            Point { x: 0.5, y: 0.3 } => Self::R1C2,
+            Point { x: 0.7, y: 0.3 } => Self::R1C3,
+            Point { x: 0.9, y: 0.3 } => Self::R1C4,
+            Point { x: 0.1, y: 0.5 } => Self::R2C0,
+            Point { x: 0.3, y: 0.5 } => Self::R2C1,
             Point { x: 0.5, y: 0.5 } => Self::R2C2,
-            Point { x: 0.5, y: 0.7 } => Self::R2C3,
-            Point { x: 0.5, y: 0.9 } => Self::R2C4,
-            Point { x: 0.7, y: 0.1 } => Self::R3C0,
-            Point { x: 0.7, y: 0.3 } => Self::R3C1,
-            Point { x: 0.7, y: 0.5 } => Self::R3C2,
+            Point { x: 0.7, y: 0.5 } => Self::R2C3,
+            Point { x: 0.9, y: 0.5 } => Self::R2C4,
+            Point { x: 0.1, y: 0.7 } => Self::R3C0,
+            Point { x: 0.3, y: 0.7 } => Self::R3C1,
+            Point { x: 0.5, y: 0.7 } => Self::R3C2,
             Point { x: 0.7, y: 0.7 } => Self::R3C3,
-            Point { x: 0.7, y: 0.9 } => Self::R3C4,
-            Point { x: 0.9, y: 0.1 } => Self::R4C0,
-            Point { x: 0.9, y: 0.3 } => Self::R4C1,
-            Point { x: 0.9, y: 0.5 } => Self::R4C2,
-            Point { x: 0.9, y: 0.7 } => Self::R4C3,
+            Point { x: 0.9, y: 0.7 } => Self::R3C4,
+            Point { x: 0.1, y: 0.9 } => Self::R4C0,
+            Point { x: 0.3, y: 0.9 } => Self::R4C1,
+            Point { x: 0.5, y: 0.9 } => Self::R4C2,
+            Point { x: 0.7, y: 0.9 } => Self::R4C3,
             Point { x: 0.9, y: 0.9 } => Self::R4C4,
             _ => unreachable!(),
         }
@@ -599,7 +899,7 @@ impl Display for Position {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ImageShape {
     #[default]
     Portrait,
@@ -613,7 +913,25 @@ pub enum ImageShape {
 }
 
 impl ImageShape {
-    fn as_width_height(&self) -> (u32, u32) {
+    pub const ALL: [Self; 8] = [
+        Self::Portrait,
+        Self::Landscape,
+        Self::Square,
+        Self::PortraitLarge,
+        Self::LandscapeLarge,
+        Self::SquareLarge,
+        Self::PortraitWallpaper,
+        Self::LandscapeWallpaper,
+    ];
+
+    /// the shape that is `width` by `height`, if any is
+    pub fn of_size(width: u32, height: u32) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|s| s.as_width_height() == (width, height))
+    }
+
+    pub fn as_width_height(&self) -> (u32, u32) {
         match self {
             Self::Portrait => (832, 1216),
             Self::Landscape => (1216, 832),
@@ -722,11 +1040,171 @@ mod test {
             req.parameters.v4_negative_prompt,
             V4NegativePrompt {
                 caption: Caption {
-                    base_caption: String::from(NEGATIVE_PROMPT),
+                    base_caption: negative_prompt(&NegativeGroup::ALL),
                     char_captions: vec![],
                 },
                 legacy_uc: false,
             }
         );
     }
+
+    #[test]
+    fn request_from_metadata() {
+        let map = serde_json::json!({
+            "Comment": {
+                "prompt": "ignored, v4 has it too",
+                "uc": "lowres",
+                "seed": 42,
+                "scale": 6.0,
+                "width": 1024,
+                "height": 1024,
+                "v4_prompt": {
+                    "caption": {
+                        "base_caption": "1girl, smile",
+                        "char_captions": [
+                            {"char_caption": "red hair", "centers": [{"x": 0.3, "y": 0.7}]}
+                        ]
+                    },
+                    "use_coords": true
+                }
+            }
+        });
+        let req = ImageGenRequest::from_metadata(map.as_object().unwrap()).unwrap();
+
+        assert_eq!(req.base_prompt(), "1girl, smile");
+        assert_eq!(req.negative_prompt(), "lowres");
+        assert_eq!(req.size(), (1024, 1024));
+        assert_eq!(req.get_seed(), 42);
+        assert!(req.uses_coords());
+        assert_eq!(req.char_captions()[0].char_caption, "red hair");
+        assert_eq!(req.char_captions()[0].centers, [Point { x: 0.3, y: 0.7 }]);
+        assert_eq!(req.parameters.scale, 6.0);
+
+        assert!(ImageGenRequest::from_metadata(&Map::new()).is_err());
+    }
+
+    #[test]
+    fn generation_metadata_from_comment() {
+        let uc = negative_prompt(&[NegativeGroup::Quality, NegativeGroup::Nsfw]);
+        let map = serde_json::json!({
+            "Source": "NovelAI Diffusion V4.5 4BDE2A90",
+            "Comment": {
+                "prompt": "1girl",
+                "uc": uc,
+                "seed": 42,
+                "sampler": "k_euler_ancestral",
+                "steps": 28,
+                "scale": 5.5,
+                "cfg_rescale": 0.2,
+                "width": 1216,
+                "height": 832,
+                "v4_prompt": {
+                    "caption": {
+                        "base_caption": "1girl, smile",
+                        "char_captions": [
+                            {"char_caption": "red hair", "centers": [{"x": 0.3, "y": 0.7}]},
+                            {"char_caption": "blue hair"}
+                        ]
+                    },
+                    "use_coords": true,
+                    "use_order": true
+                }
+            }
+        });
+        let meta = GenerationMetadata::from_map(map.as_object().unwrap()).unwrap();
+        assert_eq!(
+            meta.model.as_deref(),
+            Some("NovelAI Diffusion V4.5 4BDE2A90")
+        );
+        assert_eq!(meta.prompt, "1girl, smile");
+        assert_eq!(
+            meta.characters,
+            [
+                ("red hair".to_string(), Point { x: 0.3, y: 0.7 }),
+                ("blue hair".to_string(), Point::default())
+            ]
+        );
+        assert!(meta.use_coords);
+        assert_eq!((meta.seed, meta.steps), (Some(42), Some(28)));
+        assert_eq!((meta.scale, meta.cfg_rescale), (Some(5.5), Some(0.2)));
+        assert_eq!(meta.sampler.as_deref(), Some("k_euler_ancestral"));
+        assert_eq!(
+            meta.size.and_then(|(w, h)| ImageShape::of_size(w, h)),
+            Some(ImageShape::Landscape)
+        );
+        assert_eq!(
+            NegativeGroup::of_prompt(meta.negative.as_deref().unwrap()),
+            Some(vec![NegativeGroup::Quality, NegativeGroup::Nsfw])
+        );
+        assert_eq!(NegativeGroup::of_prompt("lowres, bad hands"), None);
+
+        // before v4 there is only the prompt
+        let map = serde_json::json!({"Comment": {"prompt": "1girl", "seed": 7}});
+        let meta = GenerationMetadata::from_map(map.as_object().unwrap()).unwrap();
+        assert_eq!((meta.prompt.as_str(), meta.seed), ("1girl", Some(7)));
+        assert!(meta.characters.is_empty() && meta.size.is_none());
+
+        let map = serde_json::json!({"Comment": {"seed": 7}});
+        assert_eq!(
+            GenerationMetadata::from_map(map.as_object().unwrap()),
+            Err(MetadataError::NoPrompt)
+        );
+    }
+
+    #[test]
+    fn negative_from_groups() {
+        assert_eq!(negative_prompt(&[]), "");
+        assert_eq!(
+            negative_prompt(&[NegativeGroup::Nsfw, NegativeGroup::Text]),
+            format!(
+                "{}, {}",
+                NegativeGroup::Text.tags(),
+                NegativeGroup::Nsfw.tags()
+            )
+        );
+
+        let mut req = ImageGenRequest::default();
+        req.negative(negative_prompt(&[NegativeGroup::Anatomy]));
+        assert_eq!(req.negative_prompt(), NegativeGroup::Anatomy.tags());
+        assert_eq!(
+            req.parameters.v4_negative_prompt.caption.base_caption,
+            NegativeGroup::Anatomy.tags()
+        );
+    }
+
+    #[test]
+    fn point_cell() {
+        for (r, row) in Position::GRID.iter().enumerate() {
+            for (c, pos) in row.iter().enumerate() {
+                assert_eq!(Point::from(*pos).cell(), (r, c));
+            }
+        }
+        assert_eq!(Point { x: 0.0, y: 1.0 }.cell(), (4, 0));
+        assert_eq!(Point { x: 0.39, y: 0.41 }.cell(), (2, 1));
+    }
+
+    #[test]
+    fn disabled_character_is_skipped() {
+        let mut req = ImageGenRequest::default();
+        let mut enabled = Character::new();
+        enabled.prompt("1girl".into());
+        let mut disabled = Character::new();
+        disabled.prompt("1boy".into()).set_enabled(false);
+
+        req.add_character(&enabled);
+        req.add_character(&disabled);
+
+        assert_eq!(req.parameters.character_prompts.len(), 1);
+        let captions = &req.parameters.v4_prompt.caption.char_captions;
+        assert_eq!(captions.len(), 1);
+        assert_eq!(captions[0].char_caption, "1girl");
+        assert_eq!(
+            req.parameters
+                .v4_negative_prompt
+                .caption
+                .char_captions
+                .len(),
+            1
+        );
+    }
 }
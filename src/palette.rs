@@ -0,0 +1,160 @@
+use std::{fmt, path::PathBuf};
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+use tokio::task::spawn_blocking;
+
+use crate::{db::SqliteError, fuzzy::fuzzy_match};
+
+/// where a palette entry comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SnippetKind {
+    Snippet,
+    Quality,
+    Location,
+}
+
+impl fmt::Display for SnippetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Snippet => write!(f, "snippet"),
+            Self::Quality => write!(f, "quality"),
+            Self::Location => write!(f, "location"),
+        }
+    }
+}
+
+/// a named piece of prompt text the palette can insert
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub kind: SnippetKind,
+    pub name: String,
+    pub text: String,
+}
+
+/// snippets matching `query` by name, best first, with the matched char indices of the name
+///
+/// an empty query lists the first `limit` snippets as loaded
+pub fn search<'a>(
+    query: &str,
+    snippets: &'a [Snippet],
+    limit: usize,
+) -> Vec<(&'a Snippet, Vec<usize>)> {
+    if query.trim().is_empty() {
+        return snippets
+            .iter()
+            .take(limit)
+            .map(|s| (s, Vec::new()))
+            .collect();
+    }
+
+    let mut ranked: Vec<_> = snippets
+        .iter()
+        .filter_map(|s| fuzzy_match(query, &s.name).map(|(score, m)| (score, s, m)))
+        .collect();
+    ranked.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then(a.1.kind.cmp(&b.1.kind))
+            .then(a.1.name.cmp(&b.1.name))
+    });
+    ranked
+        .into_iter()
+        .take(limit)
+        .map(|(_, s, m)| (s, m))
+        .collect()
+}
+
+/// the saved snippets, then the `quality` and `locations` tables created by import.rs
+///
+/// like the autocomplete vocabulary, the import tables are read from the database at
+/// `SQLITE_URL` and skipped when it or they are missing
+pub async fn load_snippets(
+    pool: Pool<SqliteConnectionManager>,
+) -> Result<Vec<Snippet>, SqliteError> {
+    spawn_blocking(move || load_snippets_blocking(pool))
+        .await
+        .map_err(|e| SqliteError { err: e.to_string() })?
+}
+
+fn load_snippets_blocking(
+    pool: Pool<SqliteConnectionManager>,
+) -> Result<Vec<Snippet>, SqliteError> {
+    let conn = pool.get().map_err(|e| SqliteError { err: e.to_string() })?;
+    let mut snippets = query(
+        &conn,
+        "SELECT name, t FROM snippets ORDER BY name",
+        SnippetKind::Snippet,
+    )?;
+
+    if let Some(path) = std::env::var_os("SQLITE_URL").map(PathBuf::from)
+        && path.exists()
+    {
+        let conn = Connection::open(path).map_err(SqliteError::new)?;
+        for (table, kind) in [
+            ("quality", SnippetKind::Quality),
+            ("locations", SnippetKind::Location),
+        ] {
+            let sql = format!("SELECT name, content FROM {} ORDER BY name", table);
+            // missing if the import was never run
+            if let Ok(rows) = query(&conn, &sql, kind) {
+                snippets.extend(rows);
+            }
+        }
+    }
+
+    Ok(snippets)
+}
+
+fn query(conn: &Connection, sql: &str, kind: SnippetKind) -> Result<Vec<Snippet>, SqliteError> {
+    let mut stmt = conn.prepare(sql).map_err(SqliteError::new)?;
+    let rows = stmt
+        .query_map([], |r| {
+            Ok(Snippet {
+                kind,
+                name: r.get(0)?,
+                text: r.get(1)?,
+            })
+        })
+        .map_err(SqliteError::new)?;
+    rows.collect::<Result<_, _>>().map_err(SqliteError::new)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn search_by_name() {
+        let snippet = |kind, name: &str| Snippet {
+            kind,
+            name: name.to_string(),
+            text: format!("{} text", name),
+        };
+        let snippets = vec![
+            snippet(SnippetKind::Snippet, "hands fix"),
+            snippet(SnippetKind::Quality, "high quality"),
+            snippet(SnippetKind::Location, "beach"),
+            snippet(SnippetKind::Snippet, "beach"),
+        ];
+        let names = |found: Vec<(&Snippet, Vec<usize>)>| -> Vec<(SnippetKind, String)> {
+            found
+                .into_iter()
+                .map(|(s, _)| (s.kind, s.name.clone()))
+                .collect()
+        };
+
+        assert_eq!(search("", &snippets, 2).len(), 2);
+        assert_eq!(
+            names(search("beach", &snippets, 10)),
+            [
+                (SnippetKind::Snippet, "beach".to_string()),
+                (SnippetKind::Location, "beach".to_string())
+            ]
+        );
+        let (found, matched) = &search("hq", &snippets, 10)[0];
+        assert_eq!(found.name, "high quality");
+        assert_eq!(matched, &[0, 5]);
+        assert!(search("forest", &snippets, 10).is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    ops::ControlFlow,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use iced::futures::{SinkExt, Stream, channel::mpsc, executor};
+
+/// one worker per core, but no more than there are items
+fn workers(items: usize) -> usize {
+    thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items)
+        .max(1)
+}
+
+/// the loop of each worker: takes the next item no worker has taken yet and hands it to `f`
+/// with its index, until the items run out or `f` breaks
+fn work<I>(items: &[I], next: &AtomicUsize, mut f: impl FnMut(usize, &I) -> ControlFlow<()>) {
+    loop {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        let Some(item) = items.get(i) else {
+            return;
+        };
+        if f(i, item).is_break() {
+            return;
+        }
+    }
+}
+
+/// `f` of every item, run on all cores, in the order of `items`
+pub fn par_map<I: Sync, T: Send>(items: &[I], f: impl Fn(&I) -> T + Sync) -> Vec<T> {
+    let next = AtomicUsize::new(0);
+
+    let mut done: Vec<(usize, T)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers(items.len()))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    work(items, &next, |i, item| {
+                        done.push((i, f(item)));
+                        ControlFlow::Continue(())
+                    });
+                    done
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("worker panicked"))
+            .collect()
+    });
+    done.sort_by_key(|(i, _)| *i);
+    done.into_iter().map(|(_, t)| t).collect()
+}
+
+/// `f` of every item, run on all cores and yielded as each is done, so in no particular order
+///
+/// finished items wait in a channel of one per worker, so at most that many are held however
+/// many items there are; dropping the stream stops the workers after the item they are on
+pub fn par_stream<I: Send + Sync + 'static, T: Send + 'static>(
+    items: Vec<I>,
+    f: impl Fn(&I) -> T + Send + Sync + 'static,
+) -> impl Stream<Item = T> {
+    let workers = workers(items.len());
+    let (tx, rx) = mpsc::channel(workers);
+    let items = Arc::new(items);
+    let next = Arc::new(AtomicUsize::new(0));
+    let f = Arc::new(f);
+
+    for _ in 0..workers {
+        let (mut tx, items, next, f) = (tx.clone(), items.clone(), next.clone(), f.clone());
+        thread::spawn(move || {
+            work(&items, &next, |_, item| {
+                // the stream was dropped
+                match executor::block_on(tx.send(f(item))) {
+                    Ok(()) => ControlFlow::Continue(()),
+                    Err(_) => ControlFlow::Break(()),
+                }
+            });
+        });
+    }
+    // the workers hold the remaining senders, so the stream ends once they are done
+    rx
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_item_once() {
+        let items: Vec<usize> = (0..100).collect();
+        assert_eq!(
+            par_map(&items, |i| i * 2),
+            (0..200).step_by(2).collect::<Vec<_>>()
+        );
+        assert!(par_map(&[] as &[usize], |i| *i).is_empty());
+
+        let mut streamed: Vec<usize> =
+            executor::block_on_stream(par_stream(items, |i| i * 2)).collect();
+        streamed.sort();
+        assert_eq!(streamed, (0..200).step_by(2).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,197 @@
+use iced::{
+    Color, Rectangle, Renderer, Size, Theme,
+    alignment::{Horizontal, Vertical},
+    mouse,
+    widget::canvas::{self, Frame, Geometry, Path, Stroke, event},
+};
+
+use crate::{nai::Point, ui::Message};
+
+/// width of the placement canvas, the height follows the image shape
+pub const CANVAS_WIDTH: f32 = 160.0;
+
+const DOT_RADIUS: f32 = 7.0;
+// presses this close to a dot pick it up
+const GRAB_RADIUS: f32 = DOT_RADIUS * 2.0;
+
+/// the image as a small rectangle with one draggable dot per character
+///
+/// dragging a dot sends `Message::SetCenter` with its new center, pressing on empty space moves
+/// the selected character there
+pub struct Placement {
+    // (index into character_prompts, center)
+    pub dots: Vec<(usize, Point)>,
+    pub selected: usize,
+}
+
+impl Placement {
+    fn dot_at(&self, pos: iced::Point, size: Size) -> Option<usize> {
+        self.dots
+            .iter()
+            .map(|&(i, center)| (i, to_canvas(center, size).distance(pos)))
+            .filter(|&(_, d)| d <= GRAB_RADIUS)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+}
+
+impl canvas::Program<Message> for Placement {
+    // the character being dragged
+    type State = Option<usize>;
+
+    fn update(
+        &self,
+        dragging: &mut Self::State,
+        event: canvas::Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> (event::Status, Option<Message>) {
+        let canvas::Event::Mouse(event) = event else {
+            return (event::Status::Ignored, None);
+        };
+
+        match event {
+            mouse::Event::ButtonPressed(mouse::Button::Left) => {
+                let Some(pos) = cursor.position_in(bounds) else {
+                    return (event::Status::Ignored, None);
+                };
+                let i = self.dot_at(pos, bounds.size()).unwrap_or(self.selected);
+                *dragging = Some(i);
+                (
+                    event::Status::Captured,
+                    Some(Message::SetCenter(i, to_center(pos, bounds.size()))),
+                )
+            }
+            mouse::Event::CursorMoved { .. } => match (*dragging, cursor.position()) {
+                (Some(i), Some(pos)) => {
+                    // keeps following the cursor outside the canvas, clamped to the edge
+                    let pos = pos - bounds.position();
+                    let pos = iced::Point::new(pos.x, pos.y);
+                    (
+                        event::Status::Captured,
+                        Some(Message::SetCenter(i, to_center(pos, bounds.size()))),
+                    )
+                }
+                _ => (event::Status::Ignored, None),
+            },
+            mouse::Event::ButtonReleased(mouse::Button::Left) if dragging.is_some() => {
+                *dragging = None;
+                (event::Status::Captured, None)
+            }
+            _ => (event::Status::Ignored, None),
+        }
+    }
+
+    fn draw(
+        &self,
+        _dragging: &Self::State,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let palette = theme.extended_palette();
+        let size = bounds.size();
+        let mut frame = Frame::new(renderer, size);
+
+        frame.fill_rectangle(iced::Point::ORIGIN, size, palette.background.weak.color);
+
+        // the 5x5 presets, as a guide
+        let guide = Stroke::default()
+            .with_width(1.0)
+            .with_color(palette.background.strong.color);
+        for n in 1..5 {
+            let t = n as f32 / 5.0;
+            frame.stroke(
+                &Path::line(
+                    iced::Point::new(size.width * t, 0.0),
+                    iced::Point::new(size.width * t, size.height),
+                ),
+                guide,
+            );
+            frame.stroke(
+                &Path::line(
+                    iced::Point::new(0.0, size.height * t),
+                    iced::Point::new(size.width, size.height * t),
+                ),
+                guide,
+            );
+        }
+
+        // the selected character is drawn last so it stays on top
+        let mut dots = self.dots.clone();
+        dots.sort_by_key(|&(i, _)| i == self.selected);
+        for (i, center) in dots {
+            let pos = to_canvas(center, size);
+            let color = if i == self.selected {
+                palette.primary.strong.color
+            } else {
+                palette.secondary.strong.color
+            };
+            frame.fill(&Path::circle(pos, DOT_RADIUS), color);
+            frame.fill_text(canvas::Text {
+                content: (i + 1).to_string(),
+                position: pos,
+                color: Color::WHITE,
+                size: 11.0.into(),
+                horizontal_alignment: Horizontal::Center,
+                vertical_alignment: Vertical::Center,
+                ..canvas::Text::default()
+            });
+        }
+
+        vec![frame.into_geometry()]
+    }
+
+    fn mouse_interaction(
+        &self,
+        dragging: &Self::State,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> mouse::Interaction {
+        if dragging.is_some() {
+            return mouse::Interaction::Grabbing;
+        }
+        match cursor.position_in(bounds) {
+            Some(pos) if self.dot_at(pos, bounds.size()).is_some() => mouse::Interaction::Grab,
+            Some(_) => mouse::Interaction::Crosshair,
+            None => mouse::Interaction::default(),
+        }
+    }
+}
+
+fn to_canvas(center: Point, size: Size) -> iced::Point {
+    iced::Point::new(center.x as f32 * size.width, center.y as f32 * size.height)
+}
+
+/// the character center for a position on the canvas, clamped to the image and rounded to
+/// hundredths so the metadata stays readable
+pub fn to_center(pos: iced::Point, size: Size) -> Point {
+    let norm = |v: f32, len: f32| ((v / len).clamp(0.0, 1.0) as f64 * 100.0).round() / 100.0;
+    Point {
+        x: norm(pos.x, size.width),
+        y: norm(pos.y, size.height),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn center_is_clamped_and_rounded() {
+        let size = Size::new(160.0, 240.0);
+        assert_eq!(
+            to_center(iced::Point::new(80.0, 60.0), size),
+            Point { x: 0.5, y: 0.25 }
+        );
+        assert_eq!(
+            to_center(iced::Point::new(-10.0, 500.0), size),
+            Point { x: 0.0, y: 1.0 }
+        );
+        assert_eq!(
+            to_center(iced::Point::new(53.0, 0.0), size),
+            Point { x: 0.33, y: 0.0 }
+        );
+    }
+}
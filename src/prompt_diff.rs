@@ -0,0 +1,79 @@
+/// a tag in the diff between two prompts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagChange<'a> {
+    Same(&'a str),
+    // only in the new prompt
+    Added(&'a str),
+    // only in the old prompt
+    Removed(&'a str),
+}
+
+pub fn tags(prompt: &str) -> Vec<&str> {
+    prompt
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// the tags of `old` and `new` in order, marking the ones only one of them has
+///
+/// based on the longest common subsequence, so a moved tag shows up as removed and added
+pub fn diff_tags<'a>(old: &'a str, new: &'a str) -> Vec<TagChange<'a>> {
+    let (old, new) = (tags(old), tags(new));
+
+    // lcs[i][j]: length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            changes.push(TagChange::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            changes.push(TagChange::Removed(old[i]));
+            i += 1;
+        } else {
+            changes.push(TagChange::Added(new[j]));
+            j += 1;
+        }
+    }
+    changes.extend(old[i..].iter().map(|t| TagChange::Removed(t)));
+    changes.extend(new[j..].iter().map(|t| TagChange::Added(t)));
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff_by_tag() {
+        use TagChange::*;
+
+        assert_eq!(
+            diff_tags("1girl, red dress, smile", "1girl,smile,\nblue dress, night"),
+            [
+                Same("1girl"),
+                Removed("red dress"),
+                Same("smile"),
+                Added("blue dress"),
+                Added("night"),
+            ]
+        );
+        assert_eq!(diff_tags("", "a"), [Added("a")]);
+        assert_eq!(diff_tags("a, b", ""), [Removed("a"), Removed("b")]);
+        assert!(diff_tags(" ,\n", "").is_empty());
+    }
+}
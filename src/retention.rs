@@ -0,0 +1,249 @@
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+
+use crate::PROJECT_DIRS;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// which outputs the cleanup removes, the [retention] table of config.toml
+///
+/// favorites are never removed; with neither limit set nothing is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    // plan a cleanup when the app starts, it is still only applied after confirming the report
+    pub on_startup: bool,
+    pub max_age_days: Option<u64>,
+    // oldest outputs are removed until the output folder is below this
+    pub max_size_gb: Option<f64>,
+    pub action: RetentionAction,
+    pub archive_dir: PathBuf,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            on_startup: false,
+            max_age_days: None,
+            max_size_gb: None,
+            action: RetentionAction::Trash,
+            archive_dir: PROJECT_DIRS.data_dir().join("archive"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionAction {
+    Trash,
+    Archive,
+}
+
+impl fmt::Display for RetentionAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Trash => write!(f, "Move to trash"),
+            Self::Archive => write!(f, "Move to archive folder"),
+        }
+    }
+}
+
+/// an output image as seen by the cleanup
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub path: PathBuf,
+    pub size: u64,
+    // seconds since the unix epoch
+    pub mtime: u64,
+}
+
+/// the dry run of a cleanup: what would be removed and how
+#[derive(Debug, Clone)]
+pub struct RetentionPlan {
+    pub outputs: Vec<Output>,
+    pub action: RetentionAction,
+    pub archive_dir: PathBuf,
+}
+
+impl RetentionPlan {
+    pub fn bytes(&self) -> u64 {
+        self.outputs.iter().map(|o| o.size).sum()
+    }
+}
+
+/// the outputs `policy` removes, oldest first
+///
+/// outputs older than max_age_days go first, then the oldest remaining non-favorites until the
+/// total size is below max_size_gb
+pub fn select(
+    mut outputs: Vec<Output>,
+    favorites: &HashSet<PathBuf>,
+    policy: &Retention,
+    now: u64,
+) -> Vec<Output> {
+    outputs.sort_by_key(|o| o.mtime);
+    let mut total: u64 = outputs.iter().map(|o| o.size).sum();
+    let max_bytes = policy.max_size_gb.map(|gb| (gb * BYTES_PER_GB) as u64);
+
+    outputs
+        .into_iter()
+        .filter(|o| !favorites.contains(&o.path))
+        .filter(|o| {
+            let too_old = policy
+                .max_age_days
+                .is_some_and(|days| now.saturating_sub(o.mtime) > days * SECS_PER_DAY);
+            let too_big = max_bytes.is_some_and(|max| total > max);
+            if too_old || too_big {
+                total -= o.size;
+                true
+            } else {
+                false
+            }
+        })
+        .collect()
+}
+
+/// plans a cleanup of the pngs directly inside `output_dir`
+pub async fn plan_retention(
+    output_dir: PathBuf,
+    favorites: HashSet<PathBuf>,
+    policy: Retention,
+) -> Result<RetentionPlan, String> {
+    spawn_blocking(move || {
+        let outputs = list_outputs(&output_dir).map_err(|e| format!("{:?}: {}", output_dir, e))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Ok(RetentionPlan {
+            outputs: select(outputs, &favorites, &policy, now),
+            action: policy.action,
+            archive_dir: policy.archive_dir,
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn list_outputs(dir: &Path) -> Result<Vec<Output>, io::Error> {
+    let mut outputs = Vec::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if !meta.is_file() || path.extension().is_none_or(|ext| ext != "png") {
+            continue;
+        }
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        outputs.push(Output {
+            path,
+            size: meta.len(),
+            mtime,
+        });
+    }
+    Ok(outputs)
+}
+
+/// carries out a plan; returns the number of removed outputs and an error per failed one
+pub async fn apply_retention(plan: RetentionPlan) -> (usize, Vec<String>) {
+    spawn_blocking(move || {
+        let mut done = 0;
+        let mut errors = Vec::new();
+        if plan.action == RetentionAction::Archive
+            && let Err(e) = fs::create_dir_all(&plan.archive_dir)
+        {
+            return (0, vec![format!("{:?}: {}", plan.archive_dir, e)]);
+        }
+
+        for output in &plan.outputs {
+            let res = match plan.action {
+                RetentionAction::Trash => trash::delete(&output.path).map_err(|e| e.to_string()),
+                RetentionAction::Archive => match output.path.file_name() {
+                    Some(name) => archive(&output.path, &plan.archive_dir.join(name)),
+                    None => Ok(()),
+                },
+            };
+            match res {
+                Ok(()) => done += 1,
+                Err(e) => errors.push(format!("{:?}: {}", output.path, e)),
+            }
+        }
+        (done, errors)
+    })
+    .await
+    .unwrap_or_else(|e| (0, vec![e.to_string()]))
+}
+
+// rename only works within a file system, otherwise the file is copied
+fn archive(from: &Path, to: &Path) -> Result<(), String> {
+    if to.exists() {
+        return Err(format!("{:?} already exists", to));
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)
+        .and_then(|_| fs::remove_file(from))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn select_by_age_and_size() {
+        let day = SECS_PER_DAY;
+        let output = |name: &str, size: u64, age_days: u64| Output {
+            path: PathBuf::from(name),
+            size,
+            mtime: 100 * day - age_days * day,
+        };
+        let outputs = vec![
+            output("new.png", 400, 1),
+            output("old.png", 100, 40),
+            output("old_favorite.png", 100, 50),
+            output("mid.png", 300, 10),
+        ];
+        let favorites = HashSet::from([PathBuf::from("old_favorite.png")]);
+        let names = |selected: Vec<Output>| -> Vec<String> {
+            selected
+                .iter()
+                .map(|o| o.path.to_string_lossy().to_string())
+                .collect()
+        };
+
+        let by_age = Retention {
+            max_age_days: Some(30),
+            ..Retention::default()
+        };
+        assert_eq!(
+            names(select(outputs.clone(), &favorites, &by_age, 100 * day)),
+            ["old.png"]
+        );
+
+        // 900 bytes in total, 500 left after removing old.png and mid.png
+        let by_size = Retention {
+            max_size_gb: Some(600.0 / BYTES_PER_GB),
+            ..Retention::default()
+        };
+        assert_eq!(
+            names(select(outputs.clone(), &favorites, &by_size, 100 * day)),
+            ["old.png", "mid.png"]
+        );
+
+        assert!(select(outputs, &favorites, &Retention::default(), 100 * day).is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+use std::{fmt, fs, io, ops::RangeInclusive, path::PathBuf};
+
+use iced::Theme;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    PROJECT_DIRS,
+    nai::{ImageShape, NegativeGroup},
+    retention::Retention,
+};
+
+/// allowed `ui_scale`, multiplies the size of everything on screen
+pub const UI_SCALE: RangeInclusive<f64> = 0.5..=3.0;
+/// allowed `text_size` in pixels, before scaling
+pub const TEXT_SIZE: RangeInclusive<u16> = 8..=32;
+
+/// everything that survives a restart, stored in config.toml in the data dir
+///
+/// missing fields take their default, so older files keep loading
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // name as shown in the theme picker
+    pub theme: String,
+    pub theme_mode: ThemeMode,
+    // used instead of `theme` when following the system appearance
+    pub light_theme: String,
+    pub dark_theme: String,
+    // requests sent to NovelAI at the same time
+    pub concurrency: usize,
+    // pause after each generated image, to stay below the rate limit
+    pub cooldown_ms: u64,
+    pub output_dir: PathBuf,
+    pub shape: ImageShape,
+    pub always_confirm: bool,
+    pub thumbnail_size: ThumbnailSize,
+    // thumbnails per row in the image history
+    pub history_columns: usize,
+    // parts of the negative prompt that are sent
+    pub negative_groups: Vec<NegativeGroup>,
+    pub retention: Retention,
+    pub ui_scale: f64,
+    // size of text without an explicit size, only applied on startup
+    pub text_size: u16,
+    // memory the images opened from the file tree may take decoded, in MiB
+    pub image_cache_mb: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            theme: Theme::CatppuccinMacchiato.to_string(),
+            theme_mode: ThemeMode::Manual,
+            light_theme: Theme::CatppuccinLatte.to_string(),
+            dark_theme: Theme::CatppuccinMacchiato.to_string(),
+            concurrency: 1,
+            cooldown_ms: 1250,
+            output_dir: PROJECT_DIRS.data_dir().join("output"),
+            shape: ImageShape::Portrait,
+            always_confirm: true,
+            thumbnail_size: ThumbnailSize::Small,
+            history_columns: 1,
+            negative_groups: NegativeGroup::ALL.to_vec(),
+            retention: Retention::default(),
+            ui_scale: 1.0,
+            text_size: 16,
+            image_cache_mb: 512,
+        }
+    }
+}
+
+impl Settings {
+    /// the settings from config.toml, or the defaults if it does not exist yet
+    pub fn load() -> Result<Self, io::Error> {
+        match fs::read_to_string(Self::path()) {
+            Ok(s) => Self::from_toml(&s),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, io::Error> {
+        let settings: Self =
+            toml::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if settings.concurrency == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "concurrency must be at least 1",
+            ));
+        }
+        if !UI_SCALE.contains(&settings.ui_scale) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "ui_scale must be between {} and {}",
+                    UI_SCALE.start(),
+                    UI_SCALE.end()
+                ),
+            ));
+        }
+        if !TEXT_SIZE.contains(&settings.text_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "text_size must be between {} and {}",
+                    TEXT_SIZE.start(),
+                    TEXT_SIZE.end()
+                ),
+            ));
+        }
+        Ok(settings)
+    }
+
+    pub fn save(&self) -> Result<(), io::Error> {
+        let s = toml::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(Self::path(), s)
+    }
+
+    /// these settings with the dotted `key` of config.toml set to `value`, which is read as a
+    /// toml value and otherwise taken as a string
+    ///
+    /// unknown keys and values the ui would refuse to load are errors
+    pub fn with_value(&self, key: &str, value: &str) -> Result<Self, io::Error> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let mut table = toml::Table::try_from(self).map_err(|e| invalid(e.to_string()))?;
+
+        let mut parts: Vec<&str> = key.split('.').collect();
+        let last = parts.pop().unwrap_or_default();
+        let mut current = &mut table;
+        for part in parts {
+            current = current
+                .get_mut(part)
+                .and_then(toml::Value::as_table_mut)
+                .ok_or_else(|| invalid(format!("unknown key {}", key)))?;
+        }
+        let slot = current
+            .get_mut(last)
+            .ok_or_else(|| invalid(format!("unknown key {}", key)))?;
+        *slot = toml::from_str::<toml::Table>(&format!("v = {}", value))
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .unwrap_or_else(|| toml::Value::String(value.to_string()));
+
+        let s = toml::to_string(&table).map_err(|e| invalid(e.to_string()))?;
+        Self::from_toml(&s)
+    }
+
+    /// `image_cache_mb` in bytes
+    pub fn image_cache_bytes(&self) -> u64 {
+        self.image_cache_mb.saturating_mul(1024 * 1024)
+    }
+
+    pub fn path() -> PathBuf {
+        PROJECT_DIRS.data_dir().join("config.toml")
+    }
+
+    /// the manually picked theme, or the default one if the name is unknown
+    pub fn theme(&self, custom: &[Theme]) -> Theme {
+        theme_named(&self.theme, custom)
+    }
+
+    /// the theme to show; `system_dark` is the system appearance, if it could be detected
+    pub fn current_theme(&self, system_dark: Option<bool>, custom: &[Theme]) -> Theme {
+        match (self.theme_mode, system_dark) {
+            (ThemeMode::System, Some(true)) => theme_named(&self.dark_theme, custom),
+            (ThemeMode::System, Some(false)) => theme_named(&self.light_theme, custom),
+            _ => self.theme(custom),
+        }
+    }
+}
+
+/// the built-in or custom theme called `name`, or the default one
+pub fn theme_named(name: &str, custom: &[Theme]) -> Theme {
+    Theme::ALL
+        .iter()
+        .chain(custom)
+        .find(|t| t.to_string() == name)
+        .cloned()
+        .unwrap_or(Theme::CatppuccinMacchiato)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeMode {
+    Manual,
+    // switch between the light and dark theme with the system appearance
+    System,
+}
+
+impl fmt::Display for ThemeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Manual => write!(f, "Manual"),
+            Self::System => write!(f, "Follow system"),
+        }
+    }
+}
+
+/// size of the image history thumbnails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [Self; 3] = [Self::Small, Self::Medium, Self::Large];
+
+    /// the longer side of a thumbnail in pixels
+    pub fn max_side(self) -> u32 {
+        match self {
+            Self::Small => 80,
+            Self::Medium => 128,
+            Self::Large => 192,
+        }
+    }
+}
+
+impl fmt::Display for ThumbnailSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Small => write!(f, "Small"),
+            Self::Medium => write!(f, "Medium"),
+            Self::Large => write!(f, "Large"),
+        }
+    }
+}
+
+/// whether the system uses a dark appearance, None if it can't be detected
+pub fn system_dark() -> Option<bool> {
+    match dark_light::detect() {
+        dark_light::Mode::Dark => Some(true),
+        dark_light::Mode::Light => Some(false),
+        dark_light::Mode::Default => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip_and_defaults() {
+        let settings = Settings {
+            theme: Theme::Nord.to_string(),
+            concurrency: 2,
+            shape: ImageShape::Landscape,
+            ..Settings::default()
+        };
+        let s = toml::to_string_pretty(&settings).unwrap();
+        assert_eq!(Settings::from_toml(&s).unwrap(), settings);
+        assert_eq!(Settings::from_toml(&s).unwrap().theme(&[]), Theme::Nord);
+
+        let partial = Settings::from_toml("cooldown_ms = 0").unwrap();
+        assert_eq!(partial.cooldown_ms, 0);
+        assert_eq!(partial.concurrency, 1);
+
+        assert!(Settings::from_toml("concurrency = 0").is_err());
+        assert!(Settings::from_toml("ui_scale = 0.1").is_err());
+        assert!(Settings::from_toml("text_size = 40").is_err());
+        assert_eq!(Settings::from_toml("ui_scale = 1.5").unwrap().ui_scale, 1.5);
+
+        let system = Settings {
+            theme_mode: ThemeMode::System,
+            ..Settings::default()
+        };
+        assert_eq!(
+            system.current_theme(Some(false), &[]),
+            Theme::CatppuccinLatte
+        );
+        assert_eq!(
+            system.current_theme(Some(true), &[]),
+            Theme::CatppuccinMacchiato
+        );
+        assert_eq!(system.current_theme(None, &[]), system.theme(&[]));
+    }
+
+    #[test]
+    fn set_by_key() {
+        let settings = Settings::default();
+        assert_eq!(
+            settings.with_value("concurrency", "3").unwrap().concurrency,
+            3
+        );
+        assert_eq!(
+            settings
+                .with_value("output_dir", "/tmp/out")
+                .unwrap()
+                .output_dir,
+            PathBuf::from("/tmp/out")
+        );
+        assert_eq!(
+            settings.with_value("shape", "Landscape").unwrap().shape,
+            ImageShape::Landscape
+        );
+        assert!(settings.with_value("concurrency", "0").is_err());
+        assert!(settings.with_value("concurrency", "many").is_err());
+        assert!(settings.with_value("backend_url", "x").is_err());
+        assert!(settings.with_value("theme.name", "x").is_err());
+    }
+}
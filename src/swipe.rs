@@ -0,0 +1,194 @@
+use iced::{
+    Color, Element, Event, Length, Point, Rectangle, Size, Theme,
+    advanced::{
+        Clipboard, Layout, Shell, Widget, image, layout, mouse, renderer,
+        widget::{Tree, tree},
+    },
+    event,
+    widget::image::Handle,
+};
+
+use crate::ui::Message;
+
+const DIVIDER_WIDTH: f32 = 2.0;
+
+/// two images on top of each other, `before` left of the split and `after` right of it
+///
+/// both are fitted into the same rectangle, so an upscale lines up with its source; pressing or
+/// dragging anywhere moves the split, which is sent as `Message::SwipeSplit`
+pub struct Swipe {
+    before: Handle,
+    after: Handle,
+    // of `after`, used for the shared rectangle
+    size: (u32, u32),
+    // 0 shows only `after`, 1 only `before`
+    split: f32,
+}
+
+impl Swipe {
+    pub fn new(before: Handle, after: Handle, size: (u32, u32), split: f32) -> Self {
+        Self {
+            before,
+            after,
+            size,
+            split,
+        }
+    }
+
+    fn split_at(&self, x: f32, bounds: Rectangle) -> Message {
+        let fit = fit(self.size, bounds);
+        Message::SwipeSplit(((x - fit.x) / fit.width).clamp(0.0, 1.0))
+    }
+}
+
+// whether the split is being dragged
+#[derive(Default)]
+struct Dragging(bool);
+
+impl<Renderer> Widget<Message, Theme, Renderer> for Swipe
+where
+    Renderer: image::Renderer<Handle = Handle>,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<Dragging>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(Dragging::default())
+    }
+
+    fn size(&self) -> Size<Length> {
+        Size::new(Length::Fill, Length::Fill)
+    }
+
+    fn layout(
+        &self,
+        _tree: &mut Tree,
+        _renderer: &Renderer,
+        limits: &layout::Limits,
+    ) -> layout::Node {
+        layout::Node::new(limits.max())
+    }
+
+    fn draw(
+        &self,
+        _tree: &Tree,
+        renderer: &mut Renderer,
+        theme: &Theme,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+    ) {
+        let bounds = layout.bounds();
+        let fit = fit(self.size, bounds);
+        let x = fit.x + fit.width * self.split;
+
+        // each image is drawn whole, the layer clips it to its side of the split
+        let left = Rectangle::new(bounds.position(), Size::new(x - bounds.x, bounds.height));
+        renderer.with_layer(left, |renderer| {
+            renderer.draw_image(image::Image::new(self.before.clone()), fit);
+        });
+        let right = Rectangle::new(
+            Point::new(x, bounds.y),
+            Size::new(bounds.x + bounds.width - x, bounds.height),
+        );
+        renderer.with_layer(right, |renderer| {
+            renderer.draw_image(image::Image::new(self.after.clone()), fit);
+        });
+
+        renderer.fill_quad(
+            renderer::Quad {
+                bounds: Rectangle::new(
+                    Point::new(x - DIVIDER_WIDTH / 2.0, fit.y),
+                    Size::new(DIVIDER_WIDTH, fit.height),
+                ),
+                ..renderer::Quad::default()
+            },
+            theme.extended_palette().primary.strong.color,
+        );
+        renderer.fill_quad(
+            renderer::Quad {
+                bounds: Rectangle::new(Point::new(x - 0.5, fit.y), Size::new(1.0, fit.height)),
+                ..renderer::Quad::default()
+            },
+            Color::WHITE,
+        );
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+        _viewport: &Rectangle,
+    ) -> event::Status {
+        let Event::Mouse(event) = event else {
+            return event::Status::Ignored;
+        };
+        let dragging = &mut tree.state.downcast_mut::<Dragging>().0;
+        let bounds = layout.bounds();
+
+        match event {
+            mouse::Event::ButtonPressed(mouse::Button::Left) => {
+                let Some(pos) = cursor.position_over(bounds) else {
+                    return event::Status::Ignored;
+                };
+                *dragging = true;
+                shell.publish(self.split_at(pos.x, bounds));
+                event::Status::Captured
+            }
+            mouse::Event::CursorMoved { position } if *dragging => {
+                shell.publish(self.split_at(position.x, bounds));
+                event::Status::Captured
+            }
+            mouse::Event::ButtonReleased(mouse::Button::Left) if *dragging => {
+                *dragging = false;
+                event::Status::Captured
+            }
+            _ => event::Status::Ignored,
+        }
+    }
+
+    fn mouse_interaction(
+        &self,
+        tree: &Tree,
+        layout: Layout<'_>,
+        cursor: mouse::Cursor,
+        _viewport: &Rectangle,
+        _renderer: &Renderer,
+    ) -> mouse::Interaction {
+        if tree.state.downcast_ref::<Dragging>().0 || cursor.is_over(layout.bounds()) {
+            mouse::Interaction::ResizingHorizontally
+        } else {
+            mouse::Interaction::default()
+        }
+    }
+}
+
+impl<'a, Renderer> From<Swipe> for Element<'a, Message, Theme, Renderer>
+where
+    Renderer: image::Renderer<Handle = Handle> + 'a,
+{
+    fn from(swipe: Swipe) -> Self {
+        Element::new(swipe)
+    }
+}
+
+/// the largest rectangle with the aspect ratio of `image` that fits centered into `bounds`
+pub fn fit(image: (u32, u32), bounds: Rectangle) -> Rectangle {
+    let (w, h) = (image.0.max(1) as f32, image.1.max(1) as f32);
+    let scale = (bounds.width / w).min(bounds.height / h);
+    let size = Size::new(w * scale, h * scale);
+    Rectangle::new(
+        Point::new(
+            bounds.x + (bounds.width - size.width) / 2.0,
+            bounds.y + (bounds.height - size.height) / 2.0,
+        ),
+        size,
+    )
+}
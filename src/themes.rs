@@ -0,0 +1,109 @@
+use std::{fs, path::Path};
+
+use iced::{Color, Theme, theme::Palette};
+use serde::Deserialize;
+
+use crate::PROJECT_DIRS;
+
+/// a theme file in data_dir/themes, colors written as "#rrggbb"
+///
+/// ```toml
+/// name = "Solarized"
+/// background = "#002b36"
+/// text = "#eee8d5"
+/// primary = "#268bd2"
+/// success = "#859900"
+/// danger = "#dc322f"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PaletteFile {
+    // defaults to the file name
+    name: Option<String>,
+    background: String,
+    text: String,
+    primary: String,
+    success: String,
+    danger: String,
+}
+
+/// every theme in data_dir/themes/*.toml, sorted by file name, and an error for each file that
+/// could not be loaded
+pub fn load_custom_themes() -> (Vec<Theme>, Vec<String>) {
+    let dir = PROJECT_DIRS.data_dir().join("themes");
+    let Ok(read_dir) = fs::read_dir(&dir) else {
+        return (Vec::new(), Vec::new());
+    };
+
+    let mut paths: Vec<_> = read_dir
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+
+    let mut themes = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        match load_theme(&path) {
+            Ok(theme) => themes.push(theme),
+            Err(e) => errors.push(format!("{:?}: {}", path, e)),
+        }
+    }
+    (themes, errors)
+}
+
+fn load_theme(path: &Path) -> Result<Theme, String> {
+    let s = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let stem = path
+        .file_stem()
+        .map_or(String::new(), |s| s.to_string_lossy().to_string());
+    parse_theme(&stem, &s)
+}
+
+/// a custom theme from the contents of a theme file; `default_name` is used if it has no name
+pub fn parse_theme(default_name: &str, s: &str) -> Result<Theme, String> {
+    let file: PaletteFile = toml::from_str(s).map_err(|e| e.to_string())?;
+
+    let color = |field: &str, value: &str| {
+        Color::parse(value).ok_or_else(|| format!("{} is not a color: {}", field, value))
+    };
+    let palette = Palette {
+        background: color("background", &file.background)?,
+        text: color("text", &file.text)?,
+        primary: color("primary", &file.primary)?,
+        success: color("success", &file.success)?,
+        danger: color("danger", &file.danger)?,
+    };
+
+    let name = file.name.unwrap_or_else(|| default_name.to_string());
+    // the name identifies the theme in config.toml
+    if Theme::ALL.iter().any(|t| t.to_string() == name) {
+        return Err(format!("{} is the name of a built-in theme", name));
+    }
+    Ok(Theme::custom(name, palette))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn palette_from_toml() {
+        let s = r##"
+            background = "#002b36"
+            text = "#eee8d5"
+            primary = "#268bd2"
+            success = "#859900"
+            danger = "#dc322f"
+        "##;
+        let theme = parse_theme("solarized", s).unwrap();
+        assert_eq!(theme.to_string(), "solarized");
+        assert_eq!(theme.palette().primary, Color::parse("#268bd2").unwrap());
+
+        let named = format!("name = \"Dark\"\n{}", s);
+        assert!(parse_theme("dark", &named).is_err());
+        assert!(parse_theme("x", &s.replace("#dc322f", "red")).is_err());
+        assert!(parse_theme("x", "background = \"#000000\"").is_err());
+    }
+}
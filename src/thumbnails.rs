@@ -0,0 +1,276 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::SystemTime,
+};
+
+use image::{
+    DynamicImage, ImageFormat, Rgb, RgbImage,
+    imageops::{self, FilterType},
+};
+
+use crate::{PROJECT_DIRS, library::collect_pngs, parallel::par_map};
+
+/// longest side of a gallery thumbnail, enough for a gallery cell on a large screen
+pub const THUMBNAIL_SIDE: u32 = 512;
+
+/// the cache is cut back to this many bytes, least recently used thumbnails first
+pub const MAX_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// what `build_thumbnails` did to the cache
+#[derive(Debug, Default)]
+pub struct ThumbnailReport {
+    pub built: usize,
+    // already cached
+    pub fresh: usize,
+    pub evicted: usize,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// the folder thumbnails are cached in, shared by the history and the gallery
+pub fn cache_dir() -> PathBuf {
+    PROJECT_DIRS.data_dir().join("cache").join("thumbs")
+}
+
+/// 64-bit FNV-1a of `bytes`, which names the thumbnails of an image wherever the file moves
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// where the thumbnail of the image with `hash` whose longer side is `side` is cached
+pub fn cache_path(cache: &Path, hash: u64, side: u32) -> PathBuf {
+    cache.join(format!("{:016x}-{}.jpg", hash, side))
+}
+
+/// the cached thumbnail of the image with `hash`, marked as just used
+pub fn cached(cache: &Path, hash: u64, side: u32) -> Option<PathBuf> {
+    let path = cache_path(cache, hash, side);
+    // the mtime orders the thumbnails for `evict`
+    File::options()
+        .write(true)
+        .open(&path)
+        .and_then(|f| f.set_modified(SystemTime::now()))
+        .ok()?;
+    Some(path)
+}
+
+/// the cached thumbnail of the encoded image `bytes`, built and cached first if there is none
+pub fn thumbnail(cache: &Path, bytes: &[u8], side: u32) -> Result<PathBuf, String> {
+    let hash = content_hash(bytes);
+    if let Some(path) = cached(cache, hash, side) {
+        return Ok(path);
+    }
+    let im = image::load_from_memory(bytes).map_err(|e| e.to_string())?;
+    store(cache, hash, side, &resize(&im, side))
+}
+
+/// caches `thumbnail` as that of the image with `hash`
+///
+/// it is written to a file of its own and renamed into place, so `cached` never finds a
+/// thumbnail that is half written, by a killed process or by another worker storing the same one
+pub fn store(cache: &Path, hash: u64, side: u32, thumbnail: &RgbImage) -> Result<PathBuf, String> {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+    let path = cache_path(cache, hash, side);
+    fs::create_dir_all(cache).map_err(|e| e.to_string())?;
+    let n = WRITES.fetch_add(1, Ordering::Relaxed);
+    let tmp = cache.join(format!("{:016x}-{}.{}-{}.tmp", hash, side, process::id(), n));
+    let written = thumbnail
+        .save_with_format(&tmp, ImageFormat::Jpeg)
+        .map_err(|e| e.to_string())
+        .and_then(|_| fs::rename(&tmp, &path).map_err(|e| e.to_string()));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written.map(|_| path)
+}
+
+/// like `thumbnail`, for the image file at `source`
+pub fn thumbnail_of_file(cache: &Path, source: &Path, side: u32) -> Result<PathBuf, String> {
+    let bytes = fs::read(source).map_err(|e| format!("{:?}: {}", source, e))?;
+    thumbnail(cache, &bytes, side)
+}
+
+/// `im` scaled so its longer side is `side`, as cached
+///
+/// large images are first halved by averaging 2x2 blocks while they stay at least twice the
+/// size, which is cheap and loses nothing, so Lanczos3 only filters a few times the pixels of
+/// the thumbnail
+pub fn resize(im: &DynamicImage, side: u32) -> RgbImage {
+    let mut rgb = im.to_rgb8();
+    while rgb.width().max(rgb.height()) >= side * 4 {
+        rgb = halve(&rgb);
+    }
+    let (w, h) = rgb.dimensions();
+    let scale = side as f64 / w.max(h) as f64;
+    let fit = |n: u32| ((n as f64 * scale).round() as u32).max(1);
+    imageops::resize(&rgb, fit(w), fit(h), FilterType::Lanczos3)
+}
+
+/// `rgb` at half its size, each pixel the mean of a 2x2 block
+fn halve(rgb: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(rgb.width() / 2, rgb.height() / 2, |x, y| {
+        // starting at 2 rounds the mean
+        let mut sum = [2u16; 3];
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let pixel = rgb.get_pixel(2 * x + dx, 2 * y + dy);
+            for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                *sum += u16::from(channel);
+            }
+        }
+        Rgb(sum.map(|sum| (sum / 4) as u8))
+    })
+}
+
+/// caches a gallery thumbnail of every png below `dir` that has none, on all cores, then evicts
+/// the least recently used thumbnails past `MAX_CACHE_BYTES`
+///
+/// `progress` is called with (done, total) as the pngs are read
+pub fn build_thumbnails(
+    cache: &Path,
+    dir: &Path,
+    progress: impl Fn(usize, usize) + Sync,
+) -> io::Result<ThumbnailReport> {
+    if !dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a folder", dir.display()),
+        ));
+    }
+    let mut pngs = Vec::new();
+    collect_pngs(dir, &mut pngs);
+    let paths: Vec<PathBuf> = pngs.into_iter().map(|(path, _)| path).collect();
+
+    let done = Mutex::new(0);
+    // Ok(true) when the thumbnail had to be built
+    let results = par_map(&paths, |path| {
+        let result = fs::read(path).map_err(|e| e.to_string()).and_then(|bytes| {
+            if cached(cache, content_hash(&bytes), THUMBNAIL_SIDE).is_some() {
+                return Ok(false);
+            }
+            thumbnail(cache, &bytes, THUMBNAIL_SIDE).map(|_| true)
+        });
+        let mut done = done.lock().unwrap();
+        *done += 1;
+        progress(*done, paths.len());
+        result
+    });
+
+    let mut report = ThumbnailReport::default();
+    for (path, result) in paths.into_iter().zip(results) {
+        match result {
+            Ok(true) => report.built += 1,
+            Ok(false) => report.fresh += 1,
+            Err(e) => report.failed.push((path, e)),
+        }
+    }
+    report.evicted = evict(cache, MAX_CACHE_BYTES)?;
+    Ok(report)
+}
+
+/// removes the least recently used thumbnails until the cache holds at most `max_bytes`;
+/// returns the number removed
+pub fn evict(cache: &Path, max_bytes: u64) -> io::Result<usize> {
+    let Ok(read_dir) = fs::read_dir(cache) else {
+        return Ok(0);
+    };
+    let mut thumbnails = vec![];
+    for entry in read_dir.flatten() {
+        // removed by an eviction running alongside
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.is_file() {
+            let used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            thumbnails.push((used, meta.len(), entry.path()));
+        }
+    }
+    thumbnails.sort();
+
+    let mut total: u64 = thumbnails.iter().map(|(_, len, _)| len).sum();
+    let mut evicted = 0;
+    for (_, len, path) in thumbnails {
+        if total <= max_bytes {
+            break;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => evicted += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        total -= len;
+    }
+    Ok(evicted)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn caches_by_content_and_evicts_the_oldest() {
+        let dir = std::env::temp_dir().join("prompts-thumbnails");
+        let cache = std::env::temp_dir().join("prompts-thumbnails-cache");
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&cache);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+
+        let im = image::RgbImage::new(1024, 512);
+        im.save(dir.join("a.png")).unwrap();
+        image::RgbImage::new(512, 1024)
+            .save(dir.join("sub/b.png"))
+            .unwrap();
+        fs::write(dir.join("broken.png"), b"not a png").unwrap();
+
+        let report = build_thumbnails(&cache, &dir, |_, _| {}).unwrap();
+        assert_eq!((report.built, report.fresh, report.evicted), (2, 0, 0));
+        assert_eq!(report.failed.len(), 1);
+
+        // a copy of an image shares its thumbnail
+        fs::copy(dir.join("a.png"), dir.join("sub/c.png")).unwrap();
+        let report = build_thumbnails(&cache, &dir, |_, _| {}).unwrap();
+        assert_eq!((report.built, report.fresh), (0, 3));
+        let bytes = fs::read(dir.join("sub/c.png")).unwrap();
+        let a = cached(&cache, content_hash(&bytes), THUMBNAIL_SIDE).unwrap();
+        assert_eq!(image::image_dimensions(&a).unwrap(), (512, 256));
+
+        // the longer side is the one asked for, whatever the size of the image
+        for (w, h) in [(4000, 3000), (64, 4096), (100, 50)] {
+            let im = DynamicImage::ImageRgb8(image::RgbImage::new(w, h));
+            let (w, h) = resize(&im, 128).dimensions();
+            assert_eq!(w.max(h), 128);
+        }
+        let checkers = image::RgbImage::from_fn(4, 2, |x, _| Rgb([(x % 2 * 255) as u8; 3]));
+        assert_eq!(halve(&checkers).into_raw(), [128; 6]);
+
+        // history thumbnails are cached apart from the gallery ones
+        let small = thumbnail(&cache, &bytes, 80).unwrap();
+        assert_eq!(image::image_dimensions(&small).unwrap(), (80, 40));
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 3);
+
+        let old = SystemTime::now() - std::time::Duration::from_secs(60);
+        File::options()
+            .write(true)
+            .open(&a)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        let rest: u64 = fs::read_dir(&cache)
+            .unwrap()
+            .flatten()
+            .filter(|e| e.path() != a)
+            .map(|e| e.metadata().unwrap().len())
+            .sum();
+        assert_eq!(evict(&cache, rest).unwrap(), 1);
+        assert!(!a.exists() && small.exists());
+        assert_eq!(evict(&cache, rest).unwrap(), 0);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{collections::VecDeque, time::Duration};
+
+// completed generations the statistics are taken over
+const WINDOW: usize = 50;
+
+/// how long the most recent successful generations took, from start to saved
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    durations: VecDeque<Duration>,
+}
+
+impl Timings {
+    pub fn record(&mut self, d: Duration) {
+        if self.durations.len() == WINDOW {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(d);
+    }
+
+    pub fn count(&self) -> usize {
+        self.durations.len()
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        let n = u32::try_from(self.durations.len())
+            .ok()
+            .filter(|n| *n > 0)?;
+        Some(self.durations.iter().sum::<Duration>() / n)
+    }
+
+    /// nearest rank percentile, `p` between 0 and 100
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let mut sorted: Vec<Duration> = self.durations.iter().copied().collect();
+        sorted.sort();
+        let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.clamp(1, sorted.len().max(1)) - 1).copied()
+    }
+
+    /// time until `remaining` tasks are done, running `concurrency` at a time
+    pub fn eta(&self, remaining: usize, concurrency: usize) -> Option<Duration> {
+        let average = self.average()?;
+        let rounds = remaining.div_ceil(concurrency.max(1));
+        Some(average * u32::try_from(rounds).unwrap_or(u32::MAX))
+    }
+}
+
+/// a duration as "45s", "3m 05s" or "1h 02m"
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn statistics() {
+        let mut timings = Timings::default();
+        assert_eq!(timings.average(), None);
+        assert_eq!(timings.percentile(50.0), None);
+        assert_eq!(timings.eta(3, 1), None);
+
+        for s in [4, 1, 3, 2, 10] {
+            timings.record(Duration::from_secs(s));
+        }
+        assert_eq!(timings.average(), Some(Duration::from_secs(4)));
+        assert_eq!(timings.percentile(50.0), Some(Duration::from_secs(3)));
+        assert_eq!(timings.percentile(90.0), Some(Duration::from_secs(10)));
+        assert_eq!(timings.percentile(0.0), Some(Duration::from_secs(1)));
+        // 5 tasks two at a time take three rounds
+        assert_eq!(timings.eta(5, 2), Some(Duration::from_secs(12)));
+
+        for _ in 0..WINDOW {
+            timings.record(Duration::from_secs(6));
+        }
+        assert_eq!(timings.count(), WINDOW);
+        assert_eq!(timings.average(), Some(Duration::from_secs(6)));
+
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m 05s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h 02m");
+    }
+}
@@ -0,0 +1,512 @@
+use std::{
+    io,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use iced::widget::shader::wgpu::naga::FastHashMap;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rand::Rng;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Gauge, List, ListState, Paragraph, Wrap},
+};
+use tokio::{runtime::Runtime, sync::mpsc};
+
+use crate::{
+    db::{PromptKind, Template, fetch_prompts, mark_used},
+    fuzzy::fuzzy_match,
+    logs::{self, Level},
+    nai::{Character, ImageGenRequest, ImageShape, Requester, negative_prompt},
+    settings::Settings,
+};
+
+// how long to wait for a key before redrawing with the progress of the queue
+const POLL: Duration = Duration::from_millis(100);
+const MAX_CHARACTERS: usize = 6;
+const MAX_COUNT: usize = 100;
+
+const HELP: &str = "tab pane  ↑↓ move  enter pick  / filter  bksp drop character  +- count  \
+s shape  g generate  q quit";
+
+#[derive(Debug, Clone, PartialEq)]
+enum JobStatus {
+    Queued,
+    Running,
+    Done(PathBuf),
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+struct Job {
+    seed: u64,
+    status: JobStatus,
+}
+
+/// sent by the generation worker as it goes through the queue
+enum Update {
+    Started(usize),
+    Finished(usize, Result<PathBuf, String>),
+}
+
+/// the prompt editor of the ui reduced to picking saved prompts
+struct App {
+    focus: PromptKind,
+    filter: String,
+    filtering: bool,
+    list: ListState,
+
+    bases: Vec<String>,
+    base_map: FastHashMap<String, String>,
+    characters: Vec<String>,
+    character_map: FastHashMap<String, String>,
+    templates: Vec<String>,
+    template_map: FastHashMap<String, Template>,
+
+    base: String,
+    chars: Vec<String>,
+    // names of the picked prompts, marked as used once generated
+    used: Vec<(PromptKind, String)>,
+    count: usize,
+    shape: ImageShape,
+    negative: String,
+    jobs: Vec<Job>,
+    message: String,
+}
+
+impl App {
+    fn new(pool: Pool<SqliteConnectionManager>, settings: &Settings) -> Result<Self, String> {
+        let (bases, base_map, characters, character_map, templates, template_map) =
+            fetch_prompts(pool).map_err(|e| e.to_string())?;
+        Ok(Self {
+            focus: PromptKind::Template,
+            filter: String::new(),
+            filtering: false,
+            list: ListState::default().with_selected(Some(0)),
+            bases,
+            base_map,
+            characters,
+            character_map,
+            templates,
+            template_map,
+            base: String::new(),
+            chars: Vec::new(),
+            used: Vec::new(),
+            count: 1,
+            shape: settings.shape,
+            negative: negative_prompt(&settings.negative_groups),
+            jobs: Vec::new(),
+            message: String::new(),
+        })
+    }
+
+    /// names of the focused pane matching the filter, best match first
+    fn visible(&self) -> Vec<&String> {
+        let names = match self.focus {
+            PromptKind::Template => &self.templates,
+            PromptKind::Base => &self.bases,
+            PromptKind::Character => &self.characters,
+        };
+        if self.filter.is_empty() {
+            return names.iter().collect();
+        }
+        let mut matches: Vec<(i32, &String)> = names
+            .iter()
+            .filter_map(|name| Some((fuzzy_match(&self.filter, name)?.0, name)))
+            .collect();
+        matches.sort_by_key(|(score, _)| -score);
+        matches.into_iter().map(|(_, name)| name).collect()
+    }
+
+    fn select(&mut self, delta: isize) {
+        let len = self.visible().len();
+        let current = self.list.selected().unwrap_or(0) as isize;
+        let index = (current + delta).clamp(0, len.saturating_sub(1) as isize);
+        self.list.select(Some(index as usize));
+    }
+
+    fn focus(&mut self, focus: PromptKind) {
+        self.focus = focus;
+        self.filter.clear();
+        self.list.select(Some(0));
+    }
+
+    /// a template replaces the base and characters, a base the base, a character is added
+    fn pick(&mut self) {
+        let Some(name) = self
+            .list
+            .selected()
+            .and_then(|i| self.visible().get(i).map(|name| name.to_string()))
+        else {
+            return;
+        };
+        match self.focus {
+            PromptKind::Template => {
+                let template = &self.template_map[&name];
+                self.base = template.base.clone();
+                self.chars = template.characters.iter().flatten().cloned().collect();
+                self.used.clear();
+            }
+            PromptKind::Base => self.base = self.base_map[&name].clone(),
+            PromptKind::Character => {
+                if self.chars.len() == MAX_CHARACTERS {
+                    self.message = format!("at most {} characters", MAX_CHARACTERS);
+                    return;
+                }
+                self.chars.push(self.character_map[&name].clone());
+            }
+        }
+        self.used.retain(|(kind, _)| *kind != self.focus);
+        self.used.push((self.focus, name));
+    }
+
+    /// the request for the picked prompts, without a seed
+    fn request(&self) -> ImageGenRequest {
+        let mut req = ImageGenRequest::default();
+        req.prompt(self.base.clone());
+        req.negative(self.negative.clone());
+        req.height_width(self.shape);
+        for prompt in &self.chars {
+            req.add_character(Character::new().prompt(prompt.clone()));
+        }
+        // like the ui, characters left at the default center are sent with positions
+        req.use_coords(!self.chars.is_empty());
+        req
+    }
+
+    /// adds `count` jobs with random seeds, returning their index and request
+    fn queue(&mut self) -> Vec<(usize, ImageGenRequest)> {
+        if self.base.trim().is_empty() {
+            self.message = "must have at least the base prompt".into();
+            return Vec::new();
+        }
+        let req = self.request();
+        let mut rng = rand::rng();
+        (0..self.count)
+            .map(|_| {
+                let seed = rng.random_range(1e8 as u64..9e9 as u64);
+                let mut req = req.clone();
+                req.seed(seed);
+                self.jobs.push(Job {
+                    seed,
+                    status: JobStatus::Queued,
+                });
+                (self.jobs.len() - 1, req)
+            })
+            .collect()
+    }
+
+    fn update(&mut self, update: Update) {
+        match update {
+            Update::Started(i) => self.jobs[i].status = JobStatus::Running,
+            Update::Finished(i, Ok(path)) => {
+                self.message = format!("saved {}", path.display());
+                self.jobs[i].status = JobStatus::Done(path);
+            }
+            Update::Finished(i, Err(e)) => {
+                self.message = format!("seed {}: {}", self.jobs[i].seed, e);
+                self.jobs[i].status = JobStatus::Failed(e);
+            }
+        }
+    }
+
+    /// jobs that are done or failed, out of all queued
+    fn progress(&self) -> (usize, usize) {
+        let finished = self
+            .jobs
+            .iter()
+            .filter(|job| matches!(job.status, JobStatus::Done(_) | JobStatus::Failed(_)))
+            .count();
+        (finished, self.jobs.len())
+    }
+}
+
+/// generates the queued requests one after another, waiting `cooldown` between them
+async fn worker(
+    mut jobs: mpsc::UnboundedReceiver<(usize, ImageGenRequest)>,
+    updates: mpsc::UnboundedSender<Update>,
+    output_dir: PathBuf,
+    cooldown: Duration,
+) {
+    let client = Requester::default();
+    while let Some((i, req)) = jobs.recv().await {
+        let _ = updates.send(Update::Started(i));
+        let result = client
+            .generate_image(req, output_dir.clone(), || {})
+            .await
+            .map(|(_, path)| path)
+            .map_err(|e| e.to_string());
+        let _ = updates.send(Update::Finished(i, result));
+        tokio::time::sleep(cooldown).await;
+    }
+}
+
+/// browses the saved prompts in the terminal and generates from them until q is pressed
+///
+/// without NOVELAI_API_KEY the prompts can still be browsed
+pub fn run(runtime: &Runtime, pool: Pool<SqliteConnectionManager>) -> io::Result<()> {
+    let settings = Settings::load().unwrap_or_default();
+    let mut app = App::new(pool.clone(), &settings).map_err(io::Error::other)?;
+
+    let (updates_tx, mut updates) = mpsc::unbounded_channel();
+    let jobs = if std::env::var("NOVELAI_API_KEY").is_ok() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        runtime.spawn(worker(
+            rx,
+            updates_tx,
+            settings.output_dir.clone(),
+            Duration::from_millis(settings.cooldown_ms),
+        ));
+        Some(tx)
+    } else {
+        app.message = "NOVELAI_API_KEY is not set, generating is disabled".into();
+        None
+    };
+
+    // log lines would be drawn over the interface, they go to the log pane instead
+    logs::set_echo(false);
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app, runtime, &pool, &jobs, &mut updates);
+    ratatui::restore();
+    logs::set_echo(true);
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    runtime: &Runtime,
+    pool: &Pool<SqliteConnectionManager>,
+    jobs: &Option<mpsc::UnboundedSender<(usize, ImageGenRequest)>>,
+    updates: &mut mpsc::UnboundedReceiver<Update>,
+) -> io::Result<()> {
+    loop {
+        while let Ok(update) = updates.try_recv() {
+            app.update(update);
+        }
+        terminal.draw(|frame| draw(frame, app))?;
+
+        if !event::poll(POLL)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if app.filtering {
+            match key.code {
+                KeyCode::Enter => app.filtering = false,
+                KeyCode::Esc => {
+                    app.filtering = false;
+                    app.filter.clear();
+                }
+                KeyCode::Backspace => {
+                    app.filter.pop();
+                }
+                KeyCode::Char(c) => app.filter.push(c),
+                _ => {}
+            }
+            app.list.select(Some(0));
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Tab => app.focus(match app.focus {
+                PromptKind::Template => PromptKind::Base,
+                PromptKind::Base => PromptKind::Character,
+                PromptKind::Character => PromptKind::Template,
+            }),
+            KeyCode::BackTab => app.focus(match app.focus {
+                PromptKind::Template => PromptKind::Character,
+                PromptKind::Base => PromptKind::Template,
+                PromptKind::Character => PromptKind::Base,
+            }),
+            KeyCode::Up | KeyCode::Char('k') => app.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => app.select(1),
+            KeyCode::Enter => app.pick(),
+            KeyCode::Char('/') => app.filtering = true,
+            KeyCode::Backspace => {
+                app.chars.pop();
+            }
+            KeyCode::Char('+') => app.count = (app.count + 1).min(MAX_COUNT),
+            KeyCode::Char('-') => app.count = app.count.saturating_sub(1).max(1),
+            KeyCode::Char('s') => {
+                let shapes = ImageShape::ALL;
+                let i = shapes.iter().position(|s| *s == app.shape).unwrap_or(0);
+                app.shape = shapes[(i + 1) % shapes.len()];
+            }
+            KeyCode::Char('g') => {
+                let Some(tx) = jobs else {
+                    app.message = "NOVELAI_API_KEY is not set".into();
+                    continue;
+                };
+                let queued = app.queue();
+                if queued.is_empty() {
+                    continue;
+                }
+                for job in queued {
+                    let _ = tx.send(job);
+                }
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs() as i64);
+                let _ = runtime.block_on(mark_used(pool.clone(), app.used.clone(), now));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [main, status] =
+        Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]).areas(frame.area());
+    let [lists, right] =
+        Layout::horizontal([Constraint::Percentage(35), Constraint::Fill(1)]).areas(main);
+    let [prompt, queue, log] = Layout::vertical([
+        Constraint::Fill(2),
+        Constraint::Fill(1),
+        Constraint::Length(6),
+    ])
+    .areas(right);
+
+    let title = match app.focus {
+        PromptKind::Template => "templates",
+        PromptKind::Base => "base prompts",
+        PromptKind::Character => "characters",
+    };
+    let title = if app.filtering || !app.filter.is_empty() {
+        format!("{} /{}", title, app.filter)
+    } else {
+        title.to_string()
+    };
+    let names: Vec<String> = app.visible().into_iter().cloned().collect();
+    let list = List::new(names)
+        .block(Block::bordered().title(title))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, lists, &mut app.list);
+
+    let mut lines = vec![
+        Line::styled("base", Style::new().fg(Color::Blue)),
+        Line::raw(&app.base),
+    ];
+    for (i, c) in app.chars.iter().enumerate() {
+        lines.push(Line::styled(
+            format!("character {}", i + 1),
+            Style::new().fg(Color::Blue),
+        ));
+        lines.push(Line::raw(c));
+    }
+    let title = format!("prompt  {}  x{}", app.shape, app.count);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title(title)),
+        prompt,
+    );
+
+    let (finished, total) = app.progress();
+    let [gauge, jobs] = Layout::vertical([Constraint::Length(1), Constraint::Fill(1)])
+        .areas(queue.inner(ratatui::layout::Margin::new(1, 1)));
+    frame.render_widget(Block::bordered().title("queue"), queue);
+    frame.render_widget(
+        Gauge::default()
+            .ratio(if total == 0 {
+                0.0
+            } else {
+                finished as f64 / total as f64
+            })
+            .label(format!("{}/{}", finished, total)),
+        gauge,
+    );
+    let rows: Vec<String> = app
+        .jobs
+        .iter()
+        .rev()
+        .map(|job| match &job.status {
+            JobStatus::Queued => format!("{:>10} queued", job.seed),
+            JobStatus::Running => format!("{:>10} generating", job.seed),
+            JobStatus::Done(path) => format!("{:>10} {}", job.seed, path.display()),
+            JobStatus::Failed(e) => format!("{:>10} failed: {}", job.seed, e),
+        })
+        .collect();
+    frame.render_widget(List::new(rows), jobs);
+
+    let records = logs::records(Level::Info, "");
+    let shown = records
+        .iter()
+        .skip(
+            records
+                .len()
+                .saturating_sub(log.height.saturating_sub(2).into()),
+        )
+        .map(|r| Line::raw(r.to_string()));
+    frame.render_widget(
+        Paragraph::new(shown.collect::<Vec<_>>()).block(Block::bordered().title("log")),
+        log,
+    );
+
+    frame.render_widget(
+        Paragraph::new(vec![Line::raw(&app.message), Line::raw(HELP)])
+            .block(Block::new().borders(ratatui::widgets::Borders::TOP)),
+        status,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pick_and_queue() {
+        let manager = SqliteConnectionManager::memory();
+        let pool = Pool::builder().max_size(1).build(manager).unwrap();
+        {
+            let conn = pool.get().unwrap();
+            crate::db::run_migrations(&conn).unwrap();
+            conn.execute_batch(
+                "INSERT INTO base(id, name, t) VALUES (1, 'beach', '1girl, beach');
+                 INSERT INTO characters(id, name, t) VALUES (1, 'alice', 'alice, smile');
+                 INSERT INTO characters(id, name, t) VALUES (2, 'bob', 'bob, hat');
+                 INSERT INTO templates(name, base, c1) VALUES ('day out', 1, 1);",
+            )
+            .unwrap();
+        }
+        let mut app = App::new(pool, &Settings::default()).unwrap();
+        assert!(app.queue().is_empty());
+
+        app.pick();
+        assert_eq!(app.base, "1girl, beach");
+        assert_eq!(app.chars, ["alice, smile"]);
+
+        app.focus(PromptKind::Character);
+        app.filter = "bo".into();
+        app.pick();
+        assert_eq!(app.chars, ["alice, smile", "bob, hat"]);
+        assert_eq!(
+            app.used,
+            [
+                (PromptKind::Template, "day out".to_string()),
+                (PromptKind::Character, "bob".to_string())
+            ]
+        );
+
+        app.count = 3;
+        let queued = app.queue();
+        assert_eq!(queued.len(), 3);
+        assert_eq!(queued[2].1.char_captions().len(), 2);
+        app.update(Update::Started(0));
+        app.update(Update::Finished(0, Ok(PathBuf::from("a.png"))));
+        app.update(Update::Finished(1, Err("busy".into())));
+        assert_eq!(app.progress(), (2, 3));
+        assert_eq!(app.jobs[2].status, JobStatus::Queued);
+    }
+}
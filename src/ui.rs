@@ -1,17 +1,20 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     fmt::{self, Display},
     io::Cursor,
-    path::PathBuf,
-    sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use bytes::Bytes;
 use iced::{
-    Alignment, Element, Event,
+    Alignment, Color, Element, Event, Font,
     Length::{self},
-    Subscription, Task, Theme, event,
+    Subscription, Task, Theme, clipboard, event,
     futures::{SinkExt, Stream, channel::mpsc::Sender},
     keyboard::{
         self,
@@ -19,40 +22,138 @@ use iced::{
     },
     stream,
     widget::{
-        self, Column, Image, PaneGrid, button, center, column, combo_box, container,
+        self, Column, Image, PaneGrid, Row, button,
+        canvas::Canvas,
+        center, checkbox, column, container,
         image::Handle,
-        mouse_area,
+        mouse_area, opaque,
         pane_grid::{self, Axis, Configuration, Direction},
-        pick_list, row, scrollable,
+        pick_list, progress_bar, row, scrollable,
         shader::wgpu::naga::{FastHashMap, FastIndexMap},
-        text,
-        text_editor::{Action, Edit},
+        slider, text,
+        text_editor::{Action, Binding, Edit, KeyPress, Motion, Status},
         text_input,
     },
     window,
 };
-use image::{GenericImageView, ImageReader};
+use image::{DynamicImage, ImageReader};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rand::{Rng, distr::Uniform, rngs::ThreadRng};
-use serde_json::{Map, Value};
+use serde_json::Value;
 use tokio::{sync::Semaphore, task::JoinHandle};
 use zip::ZipArchive;
 
 use crate::{
     PROJECT_DIRS,
+    autocomplete::{Vocabulary, current_token, load_vocabulary},
+    counter::{Level, PromptStats, TOKEN_LIMIT},
+    crop::{self, Crop, Region},
     db::{
-        PromptKind, SqliteError, Template, delete_prompt, fetch_prompts, save_prompt,
-        update_prompt, update_prompt_name,
+        PromptKind, RECENT_PROMPTS, SqliteError, Template, delete_prompt, fetch_prompts, manager,
+        mark_used, recent_prompts, run_migrations, save_prompt, update_prompt, update_prompt_name,
+    },
+    files::{
+        CreateEntryKind, EntryId, EntryKind, FileOp, FileTree, MAX_VISIBLE, is_image, watch_dirs,
+    },
+    fuzzy::rank,
+    highlight::{Highlight, WeightHighlighter, adjust_weight, weight_target},
+    history::{HistoryInfo, day_of},
+    image_metadata::{
+        Integrity, MetadataError, check_integrity, extract_image_metadata, strip_metadata,
+    },
+    keymap::{FilesAction, GlobalAction, ImageAction, Keymap},
+    library::{
+        DUPLICATE_MAX_DISTANCE, favorite_paths, find_duplicates, index_library, nai_paths_among,
+        search_images, set_favorite,
     },
-    files::{CreateEntryKind, EntryKind, FileTree, MAX_VISIBLE},
-    image_metadata::extract_image_metadata,
-    nai::{self, ImageGenRequest, ImageGenerationError, ImageShape, Point, Position, Requester},
+    logs::{self, debug, error, info, warning},
+    metadata_cache::read_cached,
+    nai::{
+        self, GenerationMetadata, ImageGenRequest, ImageGenerationError, ImageShape, NegativeGroup,
+        Point, Position, Requester, negative_prompt,
+    },
+    palette::{Snippet, load_snippets, search},
+    parallel::par_map,
+    placement::{CANVAS_WIDTH, Placement},
+    prompt_diff::{TagChange, diff_tags},
+    retention::{Retention, RetentionAction, RetentionPlan, apply_retention, plan_retention},
+    settings::{Settings, TEXT_SIZE, ThemeMode, ThumbnailSize, UI_SCALE, system_dark, theme_named},
+    swipe::Swipe,
+    themes::load_custom_themes,
+    thumbnails,
+    timing::{Timings, format_duration},
 };
 
+const GALLERY_COLUMNS: usize = 4;
+const MAX_HISTORY_COLUMNS: usize = 6;
+const HISTORY_SPACING: f32 = 4.0;
+// rows of history thumbnails built past those in view, so scrolling does not show them late
+const HISTORY_OVERSCAN: usize = 2;
+// how much of the history is built before it is first scrolled
+const HISTORY_INITIAL_HEIGHT: f32 = 2000.0;
+
+const COMPARE_ZOOM_STEP: f32 = 1.25;
+
+static COMPARE_PINNED: LazyLock<scrollable::Id> = LazyLock::new(scrollable::Id::unique);
+static COMPARE_SHOWN: LazyLock<scrollable::Id> = LazyLock::new(scrollable::Id::unique);
+static HISTORY_SCROLL: LazyLock<scrollable::Id> = LazyLock::new(scrollable::Id::unique);
+
+// on X11 / Wayland the copied image is served by this process, so the clipboard is kept alive
+// instead of being dropped after each copy
+static IMAGE_CLIPBOARD: Mutex<Option<arboard::Clipboard>> = Mutex::new(None);
+
+/// how much Ctrl+Up/Down changes a tag weight
+const WEIGHT_STEP: f32 = 0.05;
+
+const SYSTEM_THEME_POLL: Duration = Duration::from_secs(5);
+
+// digits of each character slot in the position grid
+const SLOT_COLORS: [Color; 6] = [
+    Color::from_rgb(0.88, 0.35, 0.35),
+    Color::from_rgb(0.29, 0.62, 0.88),
+    Color::from_rgb(0.35, 0.75, 0.42),
+    Color::from_rgb(0.88, 0.63, 0.19),
+    Color::from_rgb(0.69, 0.42, 0.85),
+    Color::from_rgb(0.23, 0.75, 0.75),
+];
+
+// recent seeds shown below the seed controls
+const SEED_HISTORY: usize = 10;
+const SEED_THUMBNAIL_SIDE: f32 = 48.0;
+
+// rows shown below a prompt picker
+const PROMPT_MATCHES: usize = 8;
+// rows shown in the snippet palette
+const PALETTE_MATCHES: usize = 12;
+const PALETTE_PREVIEW_CHARS: usize = 80;
+
+static PALETTE_INPUT: LazyLock<text_input::Id> = LazyLock::new(text_input::Id::unique);
+
+// newest records drawn in the log pane, the rest are only copied
+const LOG_LINES: usize = 500;
+
+// finished entries kept in the task list
+const MAX_FINISHED_TASKS: usize = 50;
+const TASK_SNIPPET_CHARS: usize = 60;
+
+const THEMES: [Theme; 10] = [
+    Theme::CatppuccinLatte,
+    Theme::CatppuccinFrappe,
+    Theme::CatppuccinMacchiato,
+    Theme::CatppuccinMocha,
+    Theme::TokyoNight,
+    Theme::TokyoNightStorm,
+    Theme::TokyoNightLight,
+    Theme::KanagawaWave,
+    Theme::KanagawaDragon,
+    Theme::KanagawaLotus,
+];
+
 pub struct State {
     task_state: TaskState,
-    task_ids: Vec<u64>,
+    // queued, running and recently finished generations, oldest first
+    tasks: Vec<TaskEntry>,
 
     pub selected_theme: Theme,
     last_key: Option<(Key, keyboard::Modifiers)>,
@@ -65,6 +166,7 @@ pub struct State {
     rng: ThreadRng,
 
     base_prompt: widget::text_editor::Content,
+    base_stats: PromptStats,
     character_prompts: [CharacterContent; 6],
     curr_char: usize,
     image_shape: ImageShape,
@@ -82,25 +184,113 @@ pub struct State {
     files: FileTree,
     files_mode: FilesMode,
     new_folder_name: String,
-
-    images: VecDeque<Vec<u8>>,
+    // the next key press in Normal mode is a type-to-jump character
+    jump_pending: bool,
+    keymap: Keymap,
+
+    vocabulary: Vocabulary,
+    // tag suggestions for the prompt editor being typed in
+    suggestions: Option<Suggestions>,
+    // the prompt editor last typed or clicked in, where the palette inserts
+    last_editor: PromptEditor,
+    palette: Option<Palette>,
+    // destructive file operation waiting on the confirmation dialog
+    pending: Option<PendingAction>,
+    always_confirm: bool,
+    // batch move/delete running in the background
+    file_ops: Option<FileOpsProgress>,
+    // a library index is running, and another was asked for meanwhile
+    indexing: bool,
+    index_again: bool,
+    search_query: String,
+    search_results: Vec<PathBuf>,
+    duplicates: Vec<DuplicateGroup>,
+    gallery: Vec<EntryId>,
+    // the cached thumbnail of each gallery image, or the image itself
+    gallery_thumbnails: Vec<Handle>,
+    gallery_index: usize,
+
+    // file tree entry being dragged with the mouse, and the drop target under the cursor
+    dragging: Option<PathBuf>,
+    drop_target: Option<DropTarget>,
+
+    // the full images stay on disk at their paths, only decoded when shown
     thumbnails: VecDeque<Handle>,
     selected_image: Option<usize>,
     image_paths: VecDeque<PathBuf>,
+    // what the history filter matches, one per history entry
+    history_info: VecDeque<HistoryInfo>,
+    history_filter: String,
+    // scroll offset and height of the history, None until it is scrolled
+    history_viewport: Option<(f32, f32)>,
+    // pixels per logical pixel of the screen, which history thumbnails are rendered for
+    display_scale: f32,
+
+    // metadata of an image shown as a collapsible tree
+    inspector: Option<Inspector>,
+
+    // as last saved to config.toml, and the open settings dialog
+    settings: Settings,
+    settings_form: Option<SettingsForm>,
+    // dry run of an output cleanup, applied once confirmed
+    retention_plan: Option<RetentionPlan>,
+    // last detected system appearance, for ThemeMode::System
+    system_dark: Option<bool>,
+    // from data_dir/themes, listed after the built-in themes
+    custom_themes: Vec<Theme>,
+
+    favorites: HashSet<PathBuf>,
+    // saved prompts and templates last picked for a generation, newest first
+    recent: Vec<(PromptKind, String)>,
+    // show what Generate would send below the editors
+    request_preview: bool,
+    // prompt of the png shown from the files pane
+    tree_prompt: Option<TreePrompt>,
+    // history and gallery show favorites only
+    favorites_only: bool,
+    // fullscreen review of a folder or of the session's history
+    review: Option<Review>,
+    // image shown borderless on the whole screen instead of the panes
+    fullscreen: Option<ShownImage>,
+    // region selection on the shown image, replacing it in the image pane
+    cropping: Option<Cropping>,
+    save_as: Option<SaveAs>,
+    // Save as also writes the metadata as .json next to the image
+    save_sidecar: bool,
+    // every shortcut, over the panes
+    help: bool,
+
+    // what the log pane shows of logs::records
+    log_level: logs::Level,
+    log_filter: String,
+    // the newest LOG_LINES matching records, formatted, as of logs::version `log_version`, and
+    // how many matched in all
+    log_lines: Vec<(logs::Level, String)>,
+    log_matched: usize,
+    log_version: Option<u64>,
+
+    // the image the image pane shows and its dimensions, read when it changes and not per frame
+    shown_size: Option<(PathBuf, Option<(u32, u32)>)>,
+    // image pinned for side-by-side comparison with the shown one
+    pinned: Option<ShownImage>,
+    comparing: bool,
+    // shared by both sides of the comparison; None fits each image to its half
+    compare_zoom: Option<f32>,
+    // where the pinned image ends in the swipe view, None shows both sides next to each other
+    swipe: Option<f32>,
 }
 
 impl Default for State {
     fn default() -> Self {
-        let manager = SqliteConnectionManager::file(PROJECT_DIRS.data_dir().join("prompts.db"));
-        let pool = r2d2::Pool::new(manager).expect("pool");
+        let pool =
+            r2d2::Pool::new(manager(PROJECT_DIRS.data_dir().join("prompts.db"))).expect("pool");
 
         {
             let conn = pool.get().unwrap();
-            conn.execute_batch(include_str!(
-                "../migrations/20250724234734_create_tables.up.sql"
-            ))
-            .expect("failed to create database tables");
+            run_migrations(&conn).expect("failed to create database tables");
         }
+        let favorites = favorite_paths(&pool.get().unwrap()).expect("favorite_paths");
+        let recent = recent_prompts(&pool.get().unwrap(), RECENT_PROMPTS).expect("recent_prompts");
 
         let (base_options, base_map, char_options, char_map, template_options, template_map) =
             fetch_prompts(pool.clone()).expect("fetch_prompts");
@@ -114,9 +304,15 @@ impl Default for State {
             CharacterContent::new(),
         ];
 
+        let mut files = FileTree::new(PROJECT_DIRS.data_dir());
+        for dir in watch_dirs() {
+            files.add_root(dir);
+        }
+
         let files_pane = Pane::new(PaneId::Files);
         let prompts_pane = Pane::new(PaneId::Prompts);
         let image_pane = Pane::new(PaneId::Image);
+        let log_pane = Pane::new(PaneId::Log);
 
         let panes = pane_grid::State::with_configuration(Configuration::Split {
             axis: Axis::Vertical,
@@ -127,20 +323,43 @@ impl Default for State {
                 a: Box::new(Configuration::Pane(files_pane)),
                 b: Box::new(Configuration::Pane(prompts_pane)),
             }),
-            b: Box::new(Configuration::Pane(image_pane)),
+            b: Box::new(Configuration::Split {
+                axis: Axis::Horizontal,
+                ratio: 0.8,
+                a: Box::new(Configuration::Pane(image_pane)),
+                b: Box::new(Configuration::Pane(log_pane)),
+            }),
+        });
+
+        let settings = Settings::load().unwrap_or_else(|e| {
+            warning!("ignoring config.toml: {}", e);
+            Settings::default()
         });
+        let (custom_themes, theme_errors) = load_custom_themes();
+        for e in &theme_errors {
+            warning!("ignoring theme {}", e);
+        }
+        let system_dark = match settings.theme_mode {
+            ThemeMode::System => system_dark(),
+            ThemeMode::Manual => None,
+        };
+        files.cache.set_max_bytes(settings.image_cache_bytes());
 
         let state = Self {
             task_state: TaskState {
                 ready: ChannelReady::NotReady,
                 status: ChannelStatus::NotReady,
+                completed: 0,
+                last_error: None,
+                anlas: None,
+                timings: Timings::default(),
             },
-            task_ids: Vec::new(),
+            tasks: Vec::new(),
 
-            selected_theme: Theme::CatppuccinMacchiato,
+            selected_theme: settings.current_theme(system_dark, &custom_themes),
             last_key: None,
 
-            message: None,
+            message: (!theme_errors.is_empty()).then(|| theme_errors.join("\n")),
 
             panes,
             focus: None,
@@ -148,33 +367,37 @@ impl Default for State {
             rng: rand::rng(),
 
             base_prompt: widget::text_editor::Content::new(),
+            base_stats: PromptStats::default(),
             character_prompts,
             curr_char: 0,
-            image_shape: ImageShape::Portrait,
+            image_shape: settings.shape,
 
             pool,
 
             base: PromptUi {
                 kind: PromptKind::Base,
-                options: combo_box::State::new(base_options),
+                options: base_options,
                 map: base_map,
                 selected: None,
+                query: String::new(),
                 rename: String::new(),
             },
 
             char: PromptUi {
                 kind: PromptKind::Character,
-                options: combo_box::State::new(char_options),
+                options: char_options,
                 map: char_map,
                 selected: None,
+                query: String::new(),
                 rename: String::new(),
             },
 
             template: PromptUi {
                 kind: PromptKind::Template,
-                options: combo_box::State::new(template_options),
+                options: template_options,
                 map: template_map,
                 selected: None,
+                query: String::new(),
                 rename: String::new(),
             },
 
@@ -183,76 +406,145 @@ impl Default for State {
             num_generate: 1.to_string(),
 
             files_mode: FilesMode::Normal,
-            files: FileTree::new(PROJECT_DIRS.data_dir()),
+            files,
             new_folder_name: String::new(),
+            jump_pending: false,
+            keymap: Keymap::load().unwrap_or_else(|e| {
+                warning!("ignoring keymap.toml: {}", e);
+                Keymap::default()
+            }),
+
+            vocabulary: Vocabulary::default(),
+            suggestions: None,
+            last_editor: PromptEditor::Base,
+            palette: None,
+            pending: None,
+            always_confirm: settings.always_confirm,
+            file_ops: None,
+            indexing: false,
+            index_again: false,
+            search_query: String::new(),
+            search_results: Vec::new(),
+            duplicates: Vec::new(),
+            gallery: Vec::new(),
+            gallery_thumbnails: Vec::new(),
+            gallery_index: 0,
+
+            dragging: None,
+            drop_target: None,
 
-            images: VecDeque::new(),
             thumbnails: VecDeque::new(),
             selected_image: None,
             image_paths: VecDeque::new(),
+            history_info: VecDeque::new(),
+            history_filter: String::new(),
+            history_viewport: None,
+            display_scale: 1.0,
+
+            inspector: None,
+            settings_form: None,
+            retention_plan: None,
+            settings,
+            system_dark,
+            custom_themes,
+
+            favorites,
+            recent,
+            request_preview: false,
+            tree_prompt: None,
+            favorites_only: false,
+            review: None,
+            fullscreen: None,
+            cropping: None,
+            save_as: None,
+            save_sidecar: false,
+            help: false,
+
+            log_level: logs::Level::Info,
+            log_filter: String::new(),
+            log_lines: Vec::new(),
+            log_matched: 0,
+            log_version: None,
+
+            shown_size: None,
+
+            pinned: None,
+            comparing: false,
+            compare_zoom: None,
+            swipe: None,
         };
         state
     }
 }
 
 impl State {
+    pub fn scale_factor(&self) -> f64 {
+        self.settings.ui_scale
+    }
+
+    /// the longer side in pixels of history thumbnails shown at the current size, so they are
+    /// neither blurred nor shrunk on screen
+    fn thumbnail_side(&self) -> u32 {
+        let side = self.settings.thumbnail_size.max_side() as f64;
+        (side * self.settings.ui_scale * self.display_scale as f64).round() as u32
+    }
+
+    fn task_mut(&mut self, id: u64) -> Option<&mut TaskEntry> {
+        self.tasks.iter_mut().find(|t| t.id == id)
+    }
+
+    /// drops the oldest finished entries beyond `MAX_FINISHED_TASKS`
+    fn prune_tasks(&mut self) {
+        let mut finished = self.tasks.iter().filter(|t| t.is_finished()).count();
+        self.tasks.retain(|t| {
+            if finished > MAX_FINISHED_TASKS && t.is_finished() {
+                finished -= 1;
+                return false;
+            }
+            true
+        });
+    }
+
     pub fn refresh_prompts(&mut self) {
         let (base_options, base_map, char_options, char_map, template_options, template_map) =
             fetch_prompts(self.pool.clone()).expect("fetch_prompts");
+        // renamed or deleted prompts leave the recent list
+        self.recent =
+            recent_prompts(&self.pool.get().unwrap(), RECENT_PROMPTS).expect("recent_prompts");
 
-        self.base.options = combo_box::State::new(base_options);
+        self.base.options = base_options;
         self.base.map = base_map;
         self.base.selected = None;
+        self.base.query.clear();
 
-        self.char.options = combo_box::State::new(char_options);
+        self.char.options = char_options;
         self.char.map = char_map;
         self.char.selected = None;
+        self.char.query.clear();
 
-        self.template.options = combo_box::State::new(template_options);
+        self.template.options = template_options;
         self.template.map = template_map;
         self.template.selected = None;
+        self.template.query.clear();
     }
 
-    /// processes generated images
-    fn insert_image(&mut self, bytes: Bytes, path: PathBuf) {
-        self.message = Some("generated image".into());
-
-        let reader = Cursor::new(bytes);
-        let mut archive = ZipArchive::new(reader).unwrap();
-        let mut file = archive.by_index(0).unwrap();
-
-        let mut buf = Vec::with_capacity(file.size() as usize);
-        std::io::copy(&mut file, &mut buf).unwrap();
-
-        let mut reader = ImageReader::new(Cursor::new(&buf));
-        reader.set_format(image::ImageFormat::Png);
-        if let Ok(im) = reader.decode() {
-            let dim = im.dimensions();
-            let resized = image::imageops::resize(
-                &im.to_rgba8(),
-                dim.0 / 16,
-                dim.1 / 16,
-                image::imageops::FilterType::Nearest,
-            );
-            let dims = resized.dimensions();
-            let thumb_handle = Handle::from_rgba(dims.0, dims.1, resized.into_raw());
-            self.thumbnails.push_front(thumb_handle);
-            self.images.push_front(buf);
-            self.image_paths.push_front(path);
-        }
+    /// adds a generated image `decode_generated` made ready to the history
+    fn insert_image(&mut self, image: DecodedImage, path: PathBuf) {
+        self.thumbnails.push_front(image.thumbnail);
+        self.history_info.push_front(image.info);
+        self.image_paths.push_front(path);
     }
 
     fn rename_prompt<V>(ui: &mut PromptUi<V>, pool: Pool<SqliteConnectionManager>) -> String {
         if let Some(old_name) = &ui.selected {
             let new_name = ui.rename.clone();
 
-            let mut new_options = ui.options.options().to_vec();
-            let pos = new_options
+            let pos = ui
+                .options
                 .iter()
                 .position(|s| s == old_name)
                 .unwrap_or_default();
-            new_options.splice(pos..pos + 1, [new_name.clone()]);
-            ui.options = combo_box::State::new(new_options);
+            ui.options.splice(pos..pos + 1, [new_name.clone()]);
 
             let old_val = ui.map.remove(old_name);
             ui.map.insert(new_name.clone(), old_val.unwrap());
@@ -282,6 +574,18 @@ pub enum Message {
 
     // pane
     FocusAdjacent(pane_grid::Direction),
+    ReloadKeymap,
+    OpenSettings,
+    EditSettings(SettingsEdit),
+    SaveSettings,
+    CloseSettings,
+    SelectTask(u64),
+    CheckSystemTheme,
+    SystemTheme(Option<bool>),
+    // redraws elapsed times while generations run
+    Tick,
+    // sent to the image generation channel
+    ApplySettings(Settings),
     Clicked(pane_grid::Pane),
     Dragged(pane_grid::DragEvent),
     Resized(pane_grid::ResizeEvent),
@@ -291,10 +595,52 @@ pub enum Message {
     // edit prompt / request parameters
     EditBasePrompt(widget::text_editor::Action),
     EditCharPrompt((usize, widget::text_editor::Action)),
+
+    // tag autocomplete
+    LoadVocabulary,
+    Vocabulary(Result<Vocabulary, SqliteError>),
+    SuggestionMove(isize),
+    SuggestionSelect(usize),
+    AcceptSuggestion,
+    DismissSuggestions,
+    AdjustWeight(PromptEditor, f32),
+
+    // snippet palette
+    OpenPalette(PromptEditor),
+    PaletteLoaded(Result<Vec<Snippet>, SqliteError>),
+    PaletteQuery(String),
+    PaletteMove(isize),
+    // index into the shown matches
+    PaletteInsert(usize),
+    ClosePalette,
+
     CharSelected(usize),
     SetPosition(Position),
+    SetCenter(usize, Point),
+    EnableCharacter(usize, bool),
+    ClearCharacter(usize),
+    // swaps the slot with the one above (-1) or below (1)
+    MoveCharacter(usize, isize),
+    ThumbnailSize(ThumbnailSize),
+    // the pixel side the thumbnails were rendered at
+    ThumbnailsRendered(u32, Vec<Option<Handle>>),
+    // scale factor of the screen the window is on, queried on startup
+    DisplayScale(f32),
+    HistoryScrolled(scrollable::Viewport),
+    // a generated image decoded for the history
+    ImageDecoded(PathBuf, Result<DecodedImage, String>),
+    // cached thumbnails of the gallery images, None where the image would not decode
+    GalleryThumbnails(Vec<PathBuf>, Vec<Option<Handle>>),
+    // cuts the thumbnail cache back to its size, on startup
+    EvictThumbnails,
+    // drops the images opened from the file tree but the shown one, and the history thumbnails
+    // held in memory
+    ClearCaches,
+    HistoryColumns(usize),
+    HistoryFilter(String),
     CopySeed,
     ClearSeed,
+    UseSeed(u64),
     ImageShape(ImageShape),
 
     // generate
@@ -307,6 +653,7 @@ pub enum Message {
     Cancel(u64),
     CancelAll,
     CreateImage((u64, ImageGenRequest)),
+    Retry(u64),
 
     // from channel
     Channel(ChannelEvent),
@@ -315,6 +662,12 @@ pub enum Message {
     BasePromptSelected(String),
     CharacterPromptSelected(String),
     TemplateSelected(String),
+    RecentPrompts(Result<Vec<(PromptKind, String)>, SqliteError>),
+    ToggleRequestPreview(bool),
+    ToggleNegativeGroup(NegativeGroup, bool),
+    PromptQuery(PromptKind, String),
+    // picks the best match
+    PromptQuerySubmit(PromptKind),
     StorePrompt,
     SavedPrompt(Result<(), SqliteError>),
     UpdatePrompt(PromptKind),
@@ -328,7 +681,64 @@ pub enum Message {
 
     // image pane
     ImageClicked(usize),
+    StartReview(ReviewSource),
+    ReviewMove(isize),
+    ReviewFavorite,
+    ReviewTrash,
+    CloseReview,
+    FavoriteSaved(Result<(), SqliteError>),
+    ToggleFavorite(PathBuf),
+    // true when planned on startup, which only happens if enabled in the settings
+    PlanRetention(bool),
+    RetentionPlanned(Result<RetentionPlan, String>),
+    ApplyRetention,
+    RetentionApplied((usize, Vec<String>)),
+    CloseRetention,
+    ToggleFavoritesOnly(bool),
+    PinImage,
+    UnpinImage,
+    ToggleCompare,
+    CompareZoom(f32),
+    CompareFit,
+    ToggleSwipe,
+    SwipeSplit(f32),
+    CompareScrolled(CompareSide, scrollable::RelativeOffset),
     MetadataFromImage(usize),
+    InspectImage(PathBuf),
+    InspectShown,
+    InspectorToggle(String),
+    InspectorCopy(String),
+    // checks the payload and pixels of the inspected image
+    VerifyImage,
+    ImageVerified(PathBuf, Result<Integrity, String>),
+    CloseInspector,
+    // the whole metadata of an image as pretty printed json
+    CopyMetadata(PathBuf),
+    MetadataCopied(Result<String, String>),
+    // prompt and characters of the png shown from the files pane
+    TreePrompt(PathBuf, Option<(String, Vec<String>)>),
+    CopyImage,
+    ImageCopied(Result<(), String>),
+    OpenFullscreen,
+    CloseFullscreen,
+    ToggleCrop,
+    CropSelect(Option<Region>),
+    ExportCrop,
+    CropExported(Result<PathBuf, String>),
+    // copy the shown image anywhere, optionally with its metadata as json next to it, or with
+    // true a copy without any metadata to share
+    OpenSaveAs(bool),
+    SaveAsSidecar(bool),
+    // the path picked in the file dialog, None when it was cancelled
+    SaveAsPicked(Option<PathBuf>),
+    SavedAs(Result<PathBuf, String>),
+    ShowHelp(bool),
+
+    // log pane
+    LogLevel(logs::Level),
+    LogFilter(String),
+    CopyLog,
+    ClearLog,
     DeleteImageHistory,
 
     // files pane
@@ -339,17 +749,133 @@ pub enum Message {
     GotoEnd,
     NavigateUp,
     SetRoot,
-    ImportPrompt(u64, String, Vec<String>),
+    SetRootPath(PathBuf),
+    JumpTo(String),
+    ToggleHidden,
+    ToggleNaturalSort,
+    ImportPrompt(GenerationMetadata),
     Delete,
     MoveBatch,
     DeleteBatch,
+    Cut,
+    Paste,
     FilesPaneMode(FilesMode),
     SelectEntry,
     CreatePath,
     CreatePathName(String),
+
+    // confirmation dialog
+    Confirmed(PendingAction),
+    ConfirmAccept,
+    ConfirmCancel,
+    ToggleAlwaysConfirm(bool),
+
+    // background file operations
+    FileOps(FileOpEvent),
+    CancelFileOps,
+
+    // library index
+    IndexLibrary,
+    Indexed(Result<usize, SqliteError>),
+    EditSearchQuery(String),
+    SubmitSearch,
+    SearchResults(Result<Vec<PathBuf>, SqliteError>),
+    RevealPath(PathBuf),
+    ToggleNaiOnly,
+    NaiPaths(Result<HashSet<PathBuf>, SqliteError>),
+    FindDuplicates,
+    Duplicates(Result<Vec<Vec<PathBuf>>, SqliteError>),
+    KeepDuplicate(usize, usize),
+    TrashDuplicates(usize),
+    CopyPath,
+    CopyFileName,
+    GalleryMove(isize),
+    GallerySelect(usize),
+
+    // drag and drop from the files pane
+    DragStart(EntryId),
+    DragHover(Option<DropTarget>),
+    DragReleased,
 }
 
 pub fn update(state: &mut State, msg: Message) -> Task<Message> {
+    // window events do not touch the prompts and arrive with every mouse move
+    let counts_stale = !matches!(msg, Message::Event(_));
+    let task = handle_message(state, msg);
+    // the log pane is only brought up to date while it is open
+    if state.log_version != Some(logs::version())
+        && state.panes.iter().any(|(_, p)| p.id == PaneId::Log)
+    {
+        refresh_log(state);
+    }
+    // read once per shown image, `view` only looks them up
+    let shown = shown_path(state);
+    if shown != state.shown_size.as_ref().map(|(path, _)| path) {
+        state.shown_size = shown.cloned().map(|path| {
+            let size = image::image_dimensions(&path).ok();
+            (path, size)
+        });
+    }
+    if counts_stale {
+        state.base_stats.update(state.base_prompt.lines());
+        for cc in &mut state.character_prompts {
+            cc.stats.update(cc.content.lines());
+        }
+    }
+    Task::batch([task, read_tree_prompt(state)])
+}
+
+/// the pngs loaded in the file tree, which the NAI filter probes when they are not indexed
+fn tree_images(state: &State) -> Vec<PathBuf> {
+    state
+        .files
+        .entries
+        .values()
+        .filter(|e| e.kind == EntryKind::File && e.path.extension().is_some_and(|ext| ext == "png"))
+        .map(|e| e.path.clone())
+        .collect()
+}
+
+/// reads the prompt of the image shown from the files pane once it changes, for the diff against
+/// the editors
+fn read_tree_prompt(state: &mut State) -> Task<Message> {
+    let Some(entry) = state.files.entries.get(state.files.selected) else {
+        return Task::none();
+    };
+    let path = &entry.path;
+    let shown = state.files.cache.contains_key(path) && is_image(path);
+    if !shown {
+        state.tree_prompt = None;
+        return Task::none();
+    }
+    if state.tree_prompt.as_ref().is_some_and(|t| t.path == *path) {
+        return Task::none();
+    }
+
+    let path = path.clone();
+    let pool = state.pool.clone();
+    state.tree_prompt = Some(TreePrompt {
+        path: path.clone(),
+        prompt: None,
+    });
+    Task::perform(
+        async move {
+            let read = path.clone();
+            let prompt = tokio::task::spawn_blocking(move || get_prompt_metadata(&pool, read))
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .map(|meta| {
+                    let characters = meta.character_prompts();
+                    (meta.prompt, characters)
+                });
+            (path, prompt)
+        },
+        |(path, prompt)| Message::TreePrompt(path, prompt),
+    )
+}
+
+fn handle_message(state: &mut State, msg: Message) -> Task<Message> {
     use Message::*;
     match msg {
         Dummy => (),
@@ -362,10 +888,224 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
             };
             return Task::done(Message::SetMessage(msg));
         }
-        SetMessage(s) => state.message = Some(s),
-        SelectedTheme(theme) => state.selected_theme = theme,
+        SetMessage(s) => {
+            info!("{}", s);
+            state.message = Some(s);
+        }
+        // picking a theme by hand stops following the system
+        SelectedTheme(theme) => {
+            let settings = Settings {
+                theme: theme.to_string(),
+                theme_mode: ThemeMode::Manual,
+                ..state.settings.clone()
+            };
+            return save_settings(state, settings);
+        }
 
         // pane
+        ReloadKeymap => {
+            let message = match Keymap::load() {
+                Ok(keymap) => {
+                    let conflicts = keymap.conflicts();
+                    state.keymap = keymap;
+                    if conflicts.is_empty() {
+                        "reloaded keymap.toml".to_string()
+                    } else {
+                        format!(
+                            "reloaded keymap.toml, {} unreachable bindings:\n{}",
+                            conflicts.len(),
+                            conflicts.join("\n")
+                        )
+                    }
+                }
+                Err(e) => format!("keymap.toml not loaded, keeping the current keys:\n{}", e),
+            };
+            return Task::done(Message::SetMessage(message));
+        }
+        // theme files are picked up without a restart
+        OpenSettings => {
+            let (custom, errors) = load_custom_themes();
+            state.custom_themes = custom;
+            state.settings_form = Some(SettingsForm::new(&state.settings, &state.custom_themes));
+            if !errors.is_empty() {
+                return Task::done(Message::SetMessage(errors.join("\n")));
+            }
+        }
+        EditSettings(edit) => {
+            if let Some(form) = &mut state.settings_form {
+                match edit {
+                    SettingsEdit::Theme(theme) => form.theme = theme,
+                    SettingsEdit::ThemeMode(mode) => form.theme_mode = mode,
+                    SettingsEdit::LightTheme(theme) => form.light_theme = theme,
+                    SettingsEdit::DarkTheme(theme) => form.dark_theme = theme,
+                    SettingsEdit::Concurrency(s) => form.concurrency = s,
+                    SettingsEdit::Cooldown(s) => form.cooldown_ms = s,
+                    SettingsEdit::OutputDir(s) => form.output_dir = s,
+                    SettingsEdit::Shape(shape) => form.shape = shape,
+                    SettingsEdit::AlwaysConfirm(b) => form.always_confirm = b,
+                    SettingsEdit::RetentionOnStartup(b) => form.retention_on_startup = b,
+                    SettingsEdit::MaxAgeDays(s) => form.max_age_days = s,
+                    SettingsEdit::MaxSizeGb(s) => form.max_size_gb = s,
+                    SettingsEdit::RetentionAction(a) => form.retention_action = a,
+                    SettingsEdit::ArchiveDir(s) => form.archive_dir = s,
+                    SettingsEdit::UiScale(s) => form.ui_scale = s,
+                    SettingsEdit::TextSize(s) => form.text_size = s,
+                    SettingsEdit::ImageCacheMb(s) => form.image_cache_mb = s,
+                }
+            }
+        }
+        SaveSettings => {
+            let Some(form) = &state.settings_form else {
+                return Task::none();
+            };
+            match form.to_settings(&state.settings) {
+                Ok(settings) => {
+                    state.image_shape = settings.shape;
+                    state.settings_form = None;
+                    let follow_system = settings.theme_mode == ThemeMode::System;
+                    let side = state.thumbnail_side();
+                    let mut tasks = vec![save_settings(state, settings)];
+                    // a new ui scale changes how many pixels the thumbnails cover
+                    if state.thumbnail_side() != side {
+                        tasks.push(render_thumbnails(state));
+                    }
+                    if follow_system {
+                        tasks.push(Task::done(Message::CheckSystemTheme));
+                    }
+                    return Task::batch(tasks);
+                }
+                Err(e) => return Task::done(Message::SetMessage(e)),
+            }
+        }
+        CloseSettings => state.settings_form = None,
+        PlanRetention(startup) => {
+            // the open dialog is used as is, so a policy can be tried before saving it
+            let settings = match &state.settings_form {
+                Some(form) if !startup => match form.to_settings(&state.settings) {
+                    Ok(settings) => settings,
+                    Err(e) => return Task::done(Message::SetMessage(e)),
+                },
+                _ => state.settings.clone(),
+            };
+            let policy = settings.retention;
+            if startup && !policy.on_startup {
+                return Task::none();
+            }
+            if policy.max_age_days.is_none() && policy.max_size_gb.is_none() {
+                return Task::done(Message::SetMessage(
+                    "set a max age or size to clean up outputs".into(),
+                ));
+            }
+            return Task::perform(
+                plan_retention(settings.output_dir, state.favorites.clone(), policy),
+                Message::RetentionPlanned,
+            );
+        }
+        RetentionPlanned(r) => match r {
+            Err(e) => return Task::done(Message::SetMessage(format!("cleanup: {}", e))),
+            Ok(plan) if plan.outputs.is_empty() => {
+                return Task::done(Message::SetMessage("cleanup: nothing to remove".into()));
+            }
+            Ok(plan) => {
+                state.settings_form = None;
+                state.retention_plan = Some(plan);
+            }
+        },
+        ApplyRetention => {
+            if let Some(plan) = state.retention_plan.take() {
+                return Task::perform(apply_retention(plan), Message::RetentionApplied);
+            }
+        }
+        RetentionApplied((done, errors)) => {
+            let mut message = format!("cleanup: removed {} outputs", done);
+            for e in errors {
+                message.push('\n');
+                message.push_str(&e);
+            }
+            return Task::done(Message::SetMessage(message));
+        }
+        CloseRetention => state.retention_plan = None,
+        ThumbnailSize(size) => {
+            if size == state.settings.thumbnail_size {
+                return Task::none();
+            }
+            let settings = Settings {
+                thumbnail_size: size,
+                ..state.settings.clone()
+            };
+            return Task::batch([save_settings(state, settings), render_thumbnails(state)]);
+        }
+        HistoryFilter(query) => state.history_filter = query,
+        HistoryColumns(columns) => {
+            let settings = Settings {
+                history_columns: columns,
+                ..state.settings.clone()
+            };
+            return save_settings(state, settings);
+        }
+        DisplayScale(scale) => {
+            if scale != state.display_scale {
+                state.display_scale = scale;
+                return render_thumbnails(state);
+            }
+        }
+        ThumbnailsRendered(side, handles) => {
+            if side != state.thumbnail_side() {
+                return Task::none();
+            }
+            // the history changed while rendering
+            if handles.len() != state.thumbnails.len() {
+                return render_thumbnails(state);
+            }
+            for (thumb, handle) in state.thumbnails.iter_mut().zip(handles) {
+                if let Some(handle) = handle {
+                    *thumb = handle;
+                }
+            }
+        }
+        ClearCaches => {
+            let (count, bytes) = (state.files.cache.len(), state.files.cache.bytes());
+            let shown = state.files.entries[state.files.selected].path.clone();
+            state.files.cache.retain(|path| path == shown);
+            let message = format!(
+                "cleared {} images, {:.0} MiB",
+                count - state.files.cache.len(),
+                (bytes - state.files.cache.bytes()) as f64 / (1024.0 * 1024.0)
+            );
+            // rendered from the thumbnail files, which are only decoded while in view
+            return Task::batch([
+                Task::done(Message::SetMessage(message)),
+                render_thumbnails(state),
+            ]);
+        }
+        EvictThumbnails => {
+            return Task::perform(
+                tokio::task::spawn_blocking(|| {
+                    thumbnails::evict(&thumbnails::cache_dir(), thumbnails::MAX_CACHE_BYTES)
+                }),
+                |r| match r {
+                    Ok(Err(e)) => Message::SetMessage(format!("thumbnail cache: {}", e)),
+                    _ => Message::Dummy,
+                },
+            );
+        }
+        GalleryThumbnails(paths, handles) => {
+            // the gallery changed while they were read
+            let entries = &state.files.entries;
+            if !state
+                .gallery
+                .iter()
+                .map(|&id| &entries[id].path)
+                .eq(paths.iter())
+            {
+                return Task::none();
+            }
+            for (thumb, handle) in state.gallery_thumbnails.iter_mut().zip(handles) {
+                if let Some(handle) = handle {
+                    *thumb = handle;
+                }
+            }
+        }
         FocusAdjacent(direction) => {
             if let Some(pane) = state.focus {
                 if let Some(adjacent) = state.panes.adjacent(pane, direction) {
@@ -387,8 +1127,112 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
         Restore => state.panes.restore(),
 
         // prompt edit
-        EditBasePrompt(action) => state.base_prompt.perform(action),
-        EditCharPrompt((i, action)) => state.character_prompts[i].content.perform(action),
+        EditBasePrompt(action) => {
+            let edited = action.is_edit();
+            state.base_prompt.perform(action);
+            state.last_editor = PromptEditor::Base;
+            update_suggestions(state, PromptEditor::Base, edited);
+        }
+        EditCharPrompt((i, action)) => {
+            let edited = action.is_edit();
+            state.character_prompts[i].content.perform(action);
+            state.last_editor = PromptEditor::Character(i);
+            update_suggestions(state, PromptEditor::Character(i), edited);
+        }
+        LoadVocabulary => {
+            return Task::perform(load_vocabulary(state.pool.clone()), Message::Vocabulary);
+        }
+        Vocabulary(result) => match result {
+            Ok(vocabulary) => state.vocabulary = vocabulary,
+            Err(e) => return Task::done(Message::SetMessage(e.err)),
+        },
+        SuggestionMove(delta) => {
+            if let Some(suggestions) = &mut state.suggestions {
+                let n = suggestions.items.len() as isize;
+                suggestions.index = (suggestions.index as isize + delta).rem_euclid(n) as usize;
+            }
+        }
+        SuggestionSelect(index) => {
+            if let Some(suggestions) = &mut state.suggestions {
+                suggestions.index = index;
+                return Task::done(Message::AcceptSuggestion);
+            }
+        }
+        AcceptSuggestion => {
+            let Some(suggestions) = state.suggestions.take() else {
+                return Task::none();
+            };
+            let Some(content) = prompt_content(state, suggestions.editor) else {
+                return Task::none();
+            };
+
+            let tag = &suggestions.items[suggestions.index];
+            for _ in 0..suggestions.token.chars().count() {
+                content.perform(Action::Edit(Edit::Backspace));
+            }
+            content.perform(Action::Edit(Edit::Paste(Arc::new(format!("{}, ", tag)))));
+            state.vocabulary.used(tag);
+        }
+        DismissSuggestions => state.suggestions = None,
+        AdjustWeight(editor, delta) => {
+            state.suggestions = None;
+            if let Some(content) = prompt_content(state, editor) {
+                adjust_weight_at_cursor(content, delta);
+            }
+        }
+        OpenPalette(editor) => {
+            state.suggestions = None;
+            state.palette = Some(Palette {
+                editor,
+                query: String::new(),
+                snippets: Vec::new(),
+                index: 0,
+            });
+            // loaded every time, the snippets table can be edited outside the app
+            return Task::batch([
+                Task::perform(load_snippets(state.pool.clone()), Message::PaletteLoaded),
+                text_input::focus(PALETTE_INPUT.clone()),
+            ]);
+        }
+        PaletteLoaded(result) => match result {
+            Ok(snippets) => {
+                if let Some(palette) = &mut state.palette {
+                    palette.snippets = snippets;
+                }
+            }
+            Err(e) => {
+                state.palette = None;
+                return Task::done(Message::SetMessage(e.err));
+            }
+        },
+        PaletteQuery(query) => {
+            if let Some(palette) = &mut state.palette {
+                palette.query = query;
+                palette.index = 0;
+            }
+        }
+        PaletteMove(delta) => {
+            if let Some(palette) = &mut state.palette {
+                let n = search(&palette.query, &palette.snippets, PALETTE_MATCHES).len() as isize;
+                if n > 0 {
+                    palette.index = (palette.index as isize + delta).rem_euclid(n) as usize;
+                }
+            }
+        }
+        PaletteInsert(index) => {
+            let Some(palette) = state.palette.take() else {
+                return Task::none();
+            };
+            let found = search(&palette.query, &palette.snippets, PALETTE_MATCHES);
+            let Some((snippet, _)) = found.get(index) else {
+                return Task::none();
+            };
+            let Some(content) = prompt_content(state, palette.editor) else {
+                return Task::none();
+            };
+            content.perform(Action::Edit(Edit::Paste(Arc::new(snippet.text.clone()))));
+        }
+        ClosePalette => state.palette = None,
         CharSelected(index) => {
             state.curr_char = index - 1;
             return Task::done(Message::SetMessage(format!(
@@ -403,10 +1247,55 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
                 state.curr_char, p
             )));
         }
+        SetCenter(i, p) => {
+            state.curr_char = i;
+            state.character_prompts[i].c.set_center(p);
+        }
+        EnableCharacter(i, enabled) => {
+            state.character_prompts[i].c.set_enabled(enabled);
+        }
+        MoveCharacter(i, delta) => {
+            let Some(j) = i
+                .checked_add_signed(delta)
+                .filter(|j| *j < state.character_prompts.len())
+            else {
+                return Task::none();
+            };
+            // the editor content moves together with its center and enabled state
+            state.character_prompts.swap(i, j);
+            if state.curr_char == i {
+                state.curr_char = j;
+            } else if state.curr_char == j {
+                state.curr_char = i;
+            }
+            let moved = |editor: PromptEditor| match editor {
+                PromptEditor::Character(k) if k == i => PromptEditor::Character(j),
+                PromptEditor::Character(k) if k == j => PromptEditor::Character(i),
+                editor => editor,
+            };
+            state.last_editor = moved(state.last_editor);
+            if let Some(palette) = &mut state.palette {
+                palette.editor = moved(palette.editor);
+            }
+            state.suggestions = None;
+        }
+        ClearCharacter(i) => {
+            let content = &mut state.character_prompts[i].content;
+            content.perform(Action::SelectAll);
+            content.perform(Action::Edit(Edit::Delete));
+            if state
+                .suggestions
+                .as_ref()
+                .is_some_and(|s| s.editor == PromptEditor::Character(i))
+            {
+                state.suggestions = None;
+            }
+        }
 
         // image generation
         ClearSeed => state.current_seed = None,
         CopySeed => state.current_seed = Some(state.previous_seed),
+        UseSeed(seed) => state.current_seed = Some(seed),
         ImageShape(shape) => {
             state.image_shape = shape;
         }
@@ -421,46 +1310,187 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
             if let ChannelReady::Ready(tx) = &mut state.task_state.ready {
                 if let Ok(num_generate) = state.num_generate.parse::<u64>() {
                     let mut tx = tx.clone();
+                    // the seed set in the prompts pane goes to the first image of the batch
+                    let chosen = state.current_seed;
                     let req = setup_request(state);
 
                     let between = Uniform::new(1e8 as u64, 9e9 as u64).unwrap();
-                    let seeds: Vec<u64> = (&mut state.rng)
-                        .sample_iter(between)
+                    let seeds: Vec<u64> = chosen
+                        .into_iter()
+                        .chain((&mut state.rng).sample_iter(between))
                         .take(num_generate as usize)
                         .collect();
+                    if let Some(last) = seeds.last() {
+                        state.previous_seed = *last;
+                    }
 
                     for i in &seeds {
-                        state.task_ids.push(*i);
+                        state.tasks.push(TaskEntry::new(*i, &req));
                     }
 
-                    return Task::perform(
+                    let used: Vec<(PromptKind, String)> = [
+                        (PromptKind::Base, &state.base.selected),
+                        (PromptKind::Character, &state.char.selected),
+                        (PromptKind::Template, &state.template.selected),
+                    ]
+                    .into_iter()
+                    .filter_map(|(kind, name)| Some((kind, name.clone()?)))
+                    .collect();
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_secs() as i64);
+
+                    return Task::batch([
+                        Task::perform(
+                            async move {
+                                for i in seeds {
+                                    let _ = tx.send(Message::CreateImage((i, req.clone()))).await;
+                                }
+                            },
+                            // generate_many(semaphore, client, req, seeds), |results| {
+                            |_r| Message::Dummy,
+                        ),
+                        Task::perform(
+                            mark_used(state.pool.clone(), used, now),
+                            Message::RecentPrompts,
+                        ),
+                    ]);
+                }
+            }
+        }
+        Retry(id) => {
+            let ChannelReady::Ready(tx) = &state.task_state.ready else {
+                return Task::done(Message::SetMessage("image channel not ready".into()));
+            };
+            let mut tx = tx.clone();
+            let Some(task) = state.task_mut(id) else {
+                return Task::none();
+            };
+            task.status = TaskStatus::Queued;
+            task.started = None;
+            task.finished = None;
+            let req = task.req.clone();
+            return Task::perform(
+                async move {
+                    let _ = tx.send(Message::CreateImage((id, req))).await;
+                },
+                |_| Message::Dummy,
+            );
+        }
+        Channel(ChannelEvent::TaskReady(mut main_tx)) => {
+            state.task_state.ready = ChannelReady::Ready(main_tx.clone());
+            state.task_state.status = ChannelStatus::Ready;
+
+            let settings = state.settings.clone();
+            return Task::perform(
+                async move {
+                    let _ = main_tx.send(Message::ApplySettings(settings)).await;
+                },
+                |_| Message::Dummy,
+            );
+        }
+        Channel(ChannelEvent::Generated(id, res)) => {
+            let (finished, task) = match res {
+                Err(e) => {
+                    state.message = Some(e.to_string());
+                    state.task_state.last_error = Some(e.to_string());
+                    (TaskStatus::Failed(e.to_string()), Task::none())
+                }
+                Ok((bytes, path)) => {
+                    state.message = Some("generated image".into());
+                    state.task_state.completed += 1;
+                    let side = state.thumbnail_side();
+                    let decoded = path.clone();
+                    let decode = Task::perform(
                         async move {
-                            for i in seeds {
-                                let _ = tx.send(Message::CreateImage((i, req.clone()))).await;
-                            }
+                            let image =
+                                tokio::task::spawn_blocking(move || decode_generated(bytes, side))
+                                    .await
+                                    .map_err(|e| e.to_string())
+                                    .and_then(|r| r);
+                            (decoded, image)
                         },
-                        // generate_many(semaphore, client, req, seeds), |results| {
-                        |_r| Message::Dummy,
+                        |(path, image)| Message::ImageDecoded(path, image),
                     );
+                    (
+                        TaskStatus::Done(path),
+                        Task::batch([decode, Task::done(Message::IndexLibrary)]),
+                    )
+                }
+            };
+            if let Some(entry) = state.task_mut(id) {
+                let done = matches!(finished, TaskStatus::Done(_));
+                entry.status = finished;
+                entry.finished = Some(Instant::now());
+                // failures return early and would skew the estimate
+                if let Some(elapsed) = entry.elapsed().filter(|_| done) {
+                    state.task_state.timings.record(elapsed);
                 }
             }
+            state.prune_tasks();
+            return task;
         }
-        Channel(ChannelEvent::TaskReady(main_tx)) => {
-            state.task_state.ready = ChannelReady::Ready(main_tx);
-            state.task_state.status = ChannelStatus::Ready;
+        HistoryScrolled(viewport) => {
+            state.history_viewport = Some((viewport.absolute_offset().y, viewport.bounds().height));
         }
-        Channel(ChannelEvent::Generated(id, res)) => match res {
-            Err(e) => state.message = Some(e.to_string()),
-            Ok((bytes, path)) => {
-                state.message = Some("generated image".into());
-                state.insert_image(bytes, path);
-                if let Some(index) = state.task_ids.iter().position(|i| *i == id) {
-                    state.task_ids.remove(index);
+        ImageDecoded(path, decoded) => match decoded {
+            Ok(image) => {
+                let side = image.side;
+                state.insert_image(image, path);
+                // the size or scale was changed while it was decoded
+                if side != state.thumbnail_side() {
+                    return render_thumbnails(state);
                 }
             }
+            Err(e) => return Task::done(Message::SetMessage(format!("{:?}: {}", path, e))),
         },
+        Channel(ChannelEvent::Started(id)) => {
+            if let Some(task) = state.task_mut(id) {
+                task.status = TaskStatus::Running;
+                task.started = Some(Instant::now());
+            }
+        }
+        Channel(ChannelEvent::Saving(id)) => {
+            if let Some(task) = state.task_mut(id) {
+                task.status = TaskStatus::Saving;
+            }
+        }
+        Channel(ChannelEvent::Anlas(anlas)) => state.task_state.anlas = Some(anlas),
         Channel(ChannelEvent::Cancelled(id)) => {
-            println!("aborted task {}", id);
+            state.tasks.retain(|t| t.id != id);
+            info!("aborted task {}", id);
+        }
+        SelectTask(id) => {
+            let path = state.tasks.iter().find_map(|t| match &t.status {
+                TaskStatus::Done(path) if t.id == id => Some(path),
+                _ => None,
+            });
+            match path.and_then(|p| state.image_paths.iter().position(|i| i == p)) {
+                Some(i) => state.selected_image = Some(i),
+                None => {
+                    return Task::done(Message::SetMessage(
+                        "the image is no longer in the history".into(),
+                    ));
+                }
+            }
+        }
+        Tick => (),
+        CheckSystemTheme => {
+            return Task::perform(
+                async {
+                    tokio::task::spawn_blocking(system_dark)
+                        .await
+                        .ok()
+                        .flatten()
+                },
+                Message::SystemTheme,
+            );
+        }
+        SystemTheme(dark) => {
+            if dark.is_some() && dark != state.system_dark {
+                state.system_dark = dark;
+                state.selected_theme = state.settings.current_theme(dark, &state.custom_themes);
+            }
         }
 
         Pause => {
@@ -488,9 +1518,7 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
             }
         }
         Cancel(id) => {
-            if let Some(index) = state.task_ids.iter().position(|i| *i == id) {
-                state.task_ids.remove(index);
-            }
+            state.tasks.retain(|t| t.id != id);
 
             if let ChannelReady::Ready(tx) = &mut state.task_state.ready {
                 let mut tx = tx.clone();
@@ -503,7 +1531,7 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
             }
         }
         CancelAll => {
-            state.task_ids.drain(..);
+            state.tasks.retain(|t| t.is_finished());
 
             if let ChannelReady::Ready(tx) = &mut state.task_state.ready {
                 let mut tx = tx.clone();
@@ -515,15 +1543,15 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
                 );
             }
         }
-        CreateImage(..) => (),
+        CreateImage(..) | ApplySettings(..) => (),
 
         // files
         ToggleExpand => {
             state.files.enter();
-            println!("{:?}", state.files.entries[state.files.selected])
+            debug!("{:?}", state.files.entries[state.files.selected])
         }
         Refresh => {
-            state.files = FileTree::new(PROJECT_DIRS.data_dir());
+            state.files.refresh();
         }
         RefreshSelected => {}
         GotoStart => {
@@ -538,29 +1566,143 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
         SetRoot => {
             state.files.cd_selected();
         }
-        ImportPrompt(seed, base, characters) => {
-            set_prompt_characters(state, base, characters);
-            state.current_seed = Some(seed);
+        ToggleHidden => {
+            state.files.toggle_hidden();
+            let message = if state.files.show_hidden {
+                "showing hidden files"
+            } else {
+                "hiding hidden files"
+            };
+            return Task::done(Message::SetMessage(message.into()));
+        }
+        ToggleNaturalSort => {
+            state.files.set_natural_sort(!state.files.natural_sort);
+            let message = if state.files.natural_sort {
+                "natural sort"
+            } else {
+                "plain sort"
+            };
+            return Task::done(Message::SetMessage(message.into()));
+        }
+        JumpTo(prefix) => {
+            if state.files.jump_to(&prefix).is_none() {
+                return Task::done(Message::SetMessage(format!(
+                    "no entry starts with {}",
+                    prefix
+                )));
+            }
+        }
+        SetRootPath(path) => {
+            if let Err(e) = state.files.cd(&path) {
+                return Task::done(Message::SetMessage(format!("cd {:?}: {}", &path, e)));
+            }
         }
+        ImportPrompt(meta) => return apply_metadata(state, meta),
         Delete => {
             let id = state.files.selected;
-            let message = match state.files.delete(id) {
-                Err(e) => e.to_string(),
-                Ok(_) => "delete ok".into(),
-            };
-            return Task::done(Message::SetMessage(message));
+            if id == state.files.root {
+                return Task::done(Message::SetMessage("cannot delete root".into()));
+            }
+            let path = state.files.entries[id].path.clone();
+            return confirm(state, PendingAction::Delete(path), state.always_confirm);
         }
         MoveBatch => {
-            if let Err(e) = state.files.batch_move() {
-                return Task::done(Message::SetMessage(e.to_string()));
-            }
-            return Task::done(Message::FilesPaneMode(FilesMode::Normal));
+            let conflicts = state.files.move_conflicts();
+            let needs_confirm = !conflicts.is_empty();
+            return confirm(state, PendingAction::MoveBatch(conflicts), needs_confirm);
         }
         DeleteBatch => {
-            if let Err(e) = state.files.batch_delete() {
-                return Task::done(Message::SetMessage(e.to_string()));
+            let count = state.files.temp.len();
+            return confirm(
+                state,
+                PendingAction::DeleteBatch(count),
+                state.always_confirm,
+            );
+        }
+        Cut => {
+            if state.files.cut() {
+                let path = &state.files.entries[state.files.selected].path;
+                return Task::done(Message::SetMessage(format!("cut {:?}", path)));
+            }
+        }
+        Paste => {
+            if state.files.temp.is_empty() {
+                return Task::done(Message::SetMessage("nothing to paste".into()));
+            }
+            return Task::done(Message::MoveBatch);
+        }
+        Confirmed(action) => match action {
+            PendingAction::Delete(path) => {
+                let Some(id) = state
+                    .files
+                    .entry_at(&path)
+                    .filter(|&id| !state.files.is_root(id))
+                else {
+                    return Task::done(Message::SetMessage(format!("{:?} is no longer in the tree", path)));
+                };
+                let message = match state.files.delete(id) {
+                    Err(e) => e.to_string(),
+                    Ok(_) => "delete ok".into(),
+                };
+                return Task::done(Message::SetMessage(message));
+            }
+            PendingAction::MoveBatch(_conflicts) => {
+                let ops = state.files.batch_move_ops();
+                return run_file_ops(state, ops);
+            }
+            PendingAction::DeleteBatch(_count) => {
+                let ops = state.files.batch_delete_ops();
+                return run_file_ops(state, ops);
+            }
+        },
+        FileOps(event) => {
+            let Some(progress) = &mut state.file_ops else {
+                return Task::none();
+            };
+
+            match event {
+                FileOpEvent::Done(op) => {
+                    progress.done += 1;
+                    state.files.apply(&op);
+                }
+                FileOpEvent::Failed(op, err) => {
+                    progress.failed += 1;
+                    return Task::done(Message::SetMessage(format!("{:?}: {}", op.path(), err)));
+                }
+                FileOpEvent::Finished => {
+                    let message = format!(
+                        "{} of {} done, {} failed",
+                        progress.done, progress.total, progress.failed
+                    );
+                    state.file_ops = None;
+                    state.files.finish_batch();
+                    return Task::batch([
+                        Task::done(Message::FilesPaneMode(FilesMode::Normal)),
+                        Task::done(Message::SetMessage(message)),
+                    ]);
+                }
             }
-            return Task::done(Message::FilesPaneMode(FilesMode::Normal));
+        }
+        CancelFileOps => {
+            if let Some(progress) = &state.file_ops {
+                progress.cancel.store(true, Ordering::Relaxed);
+            }
+        }
+        ConfirmAccept => {
+            if let Some(action) = state.pending.take() {
+                return Task::done(Message::Confirmed(action));
+            }
+        }
+        ConfirmCancel => {
+            state.pending = None;
+            return Task::done(Message::SetMessage("cancelled".into()));
+        }
+        ToggleAlwaysConfirm(b) => {
+            let settings = Settings {
+                always_confirm: b,
+                ..state.settings.clone()
+            };
+            return save_settings(state, settings);
         }
         FilesPaneMode(mode) => {
             state.files_mode = mode;
@@ -572,13 +1714,16 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
             if mode == FilesMode::Create {
                 state.files.create_flag = true;
             }
+            if mode == FilesMode::Gallery {
+                return refresh_gallery(state);
+            }
         }
         SelectEntry => {
             state.files.mark();
         }
         CreatePath => {
             let newpath = state.new_folder_name.trim_end();
-            println!("want to create: {:?}", &newpath);
+            debug!("want to create: {:?}", &newpath);
             let create_kind = if newpath.ends_with("/") {
                 CreateEntryKind::Folder(newpath.strip_suffix("/").unwrap().into())
             } else {
@@ -593,34 +1738,254 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
         }
         CreatePathName(s) => state.new_folder_name = s,
 
-        // prompt storage
-        BasePromptSelected(s) => {
-            if let Some(prompt) = state.base.map.get(&s) {
-                state.base.selected = Some(s);
-                state.base_prompt.perform(Action::SelectAll);
-                state.base_prompt.perform(Action::Edit(Edit::Delete));
-                state
-                    .base_prompt
-                    .perform(Action::Edit(Edit::Paste(Arc::new(prompt.clone()))));
+        // library index
+        IndexLibrary => {
+            // one run at a time; requests made meanwhile share a single run after it
+            if state.indexing {
+                state.index_again = true;
+                return Task::none();
             }
+            state.indexing = true;
+            let pool = state.pool.clone();
+            return Task::perform(
+                index_library(pool, PROJECT_DIRS.data_dir().to_path_buf()),
+                Message::Indexed,
+            );
         }
-        CharacterPromptSelected(s) => {
-            if let Some(prompt) = state.char.map.get(&s) {
-                state.char.selected = Some(s);
-                let i = state.curr_char;
-                state.character_prompts[i]
-                    .content
-                    .perform(Action::SelectAll);
-                state.character_prompts[i]
-                    .content
-                    .perform(Action::Edit(Edit::Delete));
-                state.character_prompts[i]
-                    .content
+        Indexed(r) => {
+            state.indexing = false;
+            let message = match r {
+                Err(e) => format!("index: {}", e.err),
+                Ok(n) => format!("indexed {} images", n),
+            };
+            let again = if std::mem::take(&mut state.index_again) {
+                Task::done(Message::IndexLibrary)
+            } else {
+                Task::none()
+            };
+
+            // pick up newly indexed images while the filter is on
+            if state.files.nai_only.is_some() {
+                let pool = state.pool.clone();
+                return Task::batch([
+                    Task::done(Message::SetMessage(message)),
+                    Task::perform(nai_paths_among(pool, tree_images(state)), Message::NaiPaths),
+                    again,
+                ]);
+            }
+            return Task::batch([Task::done(Message::SetMessage(message)), again]);
+        }
+        ToggleNaiOnly => {
+            if state.files.nai_only.is_some() {
+                state.files.set_nai_only(None);
+                return Task::done(Message::SetMessage("showing all files".into()));
+            }
+            let pool = state.pool.clone();
+            return Task::perform(nai_paths_among(pool, tree_images(state)), Message::NaiPaths);
+        }
+        NaiPaths(r) => match r {
+            Err(e) => return Task::done(Message::SetMessage(e.err)),
+            Ok(paths) => {
+                state.files.set_nai_only(Some(paths));
+                let message = Task::done(Message::SetMessage("showing NAI images only".into()));
+                if state.files_mode == FilesMode::Gallery {
+                    return Task::batch([refresh_gallery(state), message]);
+                }
+                return message;
+            }
+        },
+        EditSearchQuery(s) => state.search_query = s,
+        SubmitSearch => {
+            let pool = state.pool.clone();
+            let query = state.search_query.clone();
+            return Task::perform(search_images(pool, query), Message::SearchResults);
+        }
+        SearchResults(r) => match r {
+            Err(e) => return Task::done(Message::SetMessage(e.err)),
+            Ok(paths) => {
+                let message = format!("{} matching images", paths.len());
+                state.search_results = paths;
+                return Task::done(Message::SetMessage(message));
+            }
+        },
+        RevealPath(path) => {
+            if state.files.reveal(&path).is_none() {
+                return Task::done(Message::SetMessage(format!(
+                    "{:?} is outside the file tree",
+                    &path
+                )));
+            }
+            if !state.files.cache.contains_key(&path) {
+                state.files.enter();
+            }
+            return Task::done(Message::FilesPaneMode(FilesMode::Normal));
+        }
+        FindDuplicates => {
+            let pool = state.pool.clone();
+            return Task::perform(
+                find_duplicates(pool, DUPLICATE_MAX_DISTANCE),
+                Message::Duplicates,
+            );
+        }
+        Duplicates(r) => match r {
+            Err(e) => return Task::done(Message::SetMessage(e.err)),
+            Ok(groups) => {
+                let message = format!("{} groups of near-duplicates", groups.len());
+                state.duplicates = groups
+                    .into_iter()
+                    .map(|paths| DuplicateGroup { paths, keep: 0 })
+                    .collect();
+                state.files_mode = FilesMode::Duplicates;
+                return Task::done(Message::SetMessage(message));
+            }
+        },
+        KeepDuplicate(group, index) => {
+            if let Some(g) = state.duplicates.get_mut(group) {
+                g.keep = index;
+            }
+        }
+        TrashDuplicates(group) => {
+            if group < state.duplicates.len() {
+                let g = state.duplicates.remove(group);
+                let trash = g
+                    .paths
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _path)| *i != g.keep)
+                    .map(|(_i, path)| path);
+
+                let message = match trash::delete_all(trash) {
+                    Err(e) => format!("trash duplicates: {}", e),
+                    Ok(_) => format!("kept {:?}", &g.paths[g.keep]),
+                };
+                return Task::batch([
+                    Task::done(Message::SetMessage(message)),
+                    Task::done(Message::IndexLibrary),
+                ]);
+            }
+        }
+
+        CopyPath => {
+            let entry = &state.files.entries[state.files.selected];
+            let path = std::path::absolute(&entry.path).unwrap_or_else(|_| entry.path.clone());
+            let path = path.to_string_lossy().to_string();
+            return Task::batch([
+                clipboard::write(path.clone()),
+                Task::done(Message::SetMessage(format!("copied {}", path))),
+            ]);
+        }
+        CopyFileName => {
+            let entry = &state.files.entries[state.files.selected];
+            if let Some(name) = entry.path.file_name() {
+                let name = name.to_string_lossy().to_string();
+                return Task::batch([
+                    clipboard::write(name.clone()),
+                    Task::done(Message::SetMessage(format!("copied {}", name))),
+                ]);
+            }
+        }
+        GalleryMove(delta) => {
+            if !state.gallery.is_empty() {
+                let last = state.gallery.len() as isize - 1;
+                let index = (state.gallery_index as isize + delta).clamp(0, last);
+                return Task::done(Message::GallerySelect(index as usize));
+            }
+        }
+        GallerySelect(index) => {
+            if let Some(&id) = state.gallery.get(index) {
+                state.gallery_index = index;
+                state.files.preview(id);
+            }
+        }
+
+        DragStart(id) => {
+            if state.files.entries.contains_key(id) {
+                state.files.selected = id;
+
+                let entry = &state.files.entries[id];
+                if !state.files.is_folder(id) {
+                    state.dragging = Some(entry.path.clone());
+                }
+            }
+        }
+        DragHover(target) => state.drop_target = target,
+        DragReleased => {
+            if let (Some(path), Some(target)) = (state.dragging.take(), state.drop_target) {
+                match target {
+                    DropTarget::Prompts => {
+                        let msg = match get_prompt_metadata(&state.pool, &path) {
+                            Ok(meta) => Message::ImportPrompt(meta),
+                            Err(e) => Message::SetMessage(format!("{:?}: {}", &path, e)),
+                        };
+                        return Task::done(msg);
+                    }
+                }
+            }
+        }
+
+        // prompt storage
+        BasePromptSelected(s) => {
+            if let Some(prompt) = state.base.map.get(&s) {
+                state.base.query = s.clone();
+                state.base.selected = Some(s);
+                state.base_prompt.perform(Action::SelectAll);
+                state.base_prompt.perform(Action::Edit(Edit::Delete));
+                state
+                    .base_prompt
+                    .perform(Action::Edit(Edit::Paste(Arc::new(prompt.clone()))));
+            }
+        }
+        CharacterPromptSelected(s) => {
+            if let Some(prompt) = state.char.map.get(&s) {
+                state.char.query = s.clone();
+                state.char.selected = Some(s);
+                let i = state.curr_char;
+                state.character_prompts[i]
+                    .content
+                    .perform(Action::SelectAll);
+                state.character_prompts[i]
+                    .content
+                    .perform(Action::Edit(Edit::Delete));
+                state.character_prompts[i]
+                    .content
                     .perform(Action::Edit(Edit::Paste(Arc::new(prompt.clone()))));
             }
         }
+        ToggleRequestPreview(b) => state.request_preview = b,
+        ToggleNegativeGroup(group, enabled) => {
+            let mut groups = state.settings.negative_groups.clone();
+            groups.retain(|g| *g != group);
+            if enabled {
+                groups.push(group);
+            }
+            let settings = Settings {
+                negative_groups: groups,
+                ..state.settings.clone()
+            };
+            return save_settings(state, settings);
+        }
+        RecentPrompts(result) => match result {
+            Ok(recent) => state.recent = recent,
+            Err(e) => return Task::done(Message::SetMessage(e.err)),
+        },
+        PromptQuery(kind, s) => match kind {
+            PromptKind::Base => state.base.query = s,
+            PromptKind::Character => state.char.query = s,
+            PromptKind::Template => state.template.query = s,
+        },
+        PromptQuerySubmit(kind) => {
+            let (query, options) = match kind {
+                PromptKind::Base => (&state.base.query, &state.base.options),
+                PromptKind::Character => (&state.char.query, &state.char.options),
+                PromptKind::Template => (&state.template.query, &state.template.options),
+            };
+            if let Some((name, _)) = rank(query, options, 1).first() {
+                return Task::done(prompt_selected(kind, name.to_string()));
+            }
+        }
         TemplateSelected(s) => {
             if let Some(template) = state.template.map.get(&s) {
+                state.template.query = s.clone();
                 state.template.selected = Some(s);
 
                 state.base_prompt.perform(Action::SelectAll);
@@ -660,7 +2025,7 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
 
             let pool = state.pool.clone();
             return Task::perform(
-                async move { save_prompt(pool, vec![(now, base, characters)]).await },
+                async move { save_prompt(pool, vec![(now, base, characters)], false).await },
                 |r| Message::SavedPrompt(r),
             );
         }
@@ -722,18 +2087,21 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
             let msg = State::rename_prompt(&mut state.base, state.pool.clone());
             state.base.rename.clear();
             state.base.selected = None;
+            state.base.query.clear();
             return Task::done(Message::SetMessage(msg));
         }
         SubmitRenameCharacterPrompt => {
             let msg = State::rename_prompt(&mut state.char, state.pool.clone());
             state.char.rename.clear();
             state.char.selected = None;
+            state.char.query.clear();
             return Task::done(Message::SetMessage(msg));
         }
         SubmitRenameTemplate => {
             let msg = State::rename_prompt(&mut state.template, state.pool.clone());
             state.template.rename.clear();
             state.template.selected = None;
+            state.template.query.clear();
             return Task::done(Message::SetMessage(msg));
         }
         EditRenameBasePrompt(s) => {
@@ -748,23 +2116,425 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
 
         // image
         ImageClicked(i) => state.selected_image = Some(i),
+        StartReview(source) => {
+            let (paths, index) = match source {
+                ReviewSource::History => {
+                    let paths: Vec<PathBuf> = state.image_paths.iter().cloned().collect();
+                    (paths, state.selected_image.unwrap_or(0))
+                }
+                ReviewSource::Folder => {
+                    let selected = state.files.selected;
+                    let ids = state.files.folder_images();
+                    let index = ids.iter().position(|&id| id == selected).unwrap_or(0);
+                    let paths = ids
+                        .into_iter()
+                        .map(|id| state.files.entries[id].path.clone())
+                        .collect();
+                    (paths, index)
+                }
+            };
+            if paths.is_empty() {
+                return Task::done(Message::SetMessage("no images to review".into()));
+            }
+
+            let mut review = Review {
+                paths,
+                index,
+                info: None,
+            };
+            review.load_info(&state.pool);
+            state.review = Some(review);
+        }
+        ReviewMove(delta) => {
+            if let Some(review) = &mut state.review {
+                let n = review.paths.len() as isize;
+                review.index = (review.index as isize + delta).clamp(0, n - 1) as usize;
+                review.load_info(&state.pool);
+            }
+        }
+        ReviewFavorite => {
+            if let Some(review) = &state.review {
+                return Task::done(Message::ToggleFavorite(review.paths[review.index].clone()));
+            }
+        }
+        ToggleFavorite(path) => {
+            let favorite = !state.favorites.contains(&path);
+            if favorite {
+                state.favorites.insert(path.clone());
+            } else {
+                state.favorites.remove(&path);
+            }
+            return Task::perform(
+                set_favorite(state.pool.clone(), path, favorite),
+                Message::FavoriteSaved,
+            );
+        }
+        ToggleFavoritesOnly(b) => {
+            state.favorites_only = b;
+            if state.files_mode == FilesMode::Gallery {
+                return refresh_gallery(state);
+            }
+        }
+        FavoriteSaved(result) => {
+            if let Err(e) = result {
+                return Task::done(Message::SetMessage(e.err));
+            }
+        }
+        ReviewTrash => {
+            let Some(review) = &mut state.review else {
+                return Task::none();
+            };
+            let path = review.paths.remove(review.index);
+            if let Err(e) = trash::delete(&path) {
+                review.paths.insert(review.index, path.clone());
+                return Task::done(Message::SetMessage(format!("delete {:?}: {}", path, e)));
+            }
+
+            if review.paths.is_empty() {
+                state.review = None;
+            } else {
+                review.index = review.index.min(review.paths.len() - 1);
+                review.load_info(&state.pool);
+            }
+
+            // trashed history entries leave the thumbnails as well
+            if let Some(i) = state.image_paths.iter().position(|p| *p == path) {
+                state.thumbnails.remove(i);
+                state.history_info.remove(i);
+                state.image_paths.remove(i);
+                state.selected_image = match state.selected_image {
+                    _ if state.image_paths.is_empty() => None,
+                    Some(s) if s >= i => Some(s.saturating_sub(1)),
+                    other => other,
+                };
+            }
+            state.favorites.remove(&path);
+            return Task::perform(
+                set_favorite(state.pool.clone(), path, false),
+                Message::FavoriteSaved,
+            );
+        }
+        CloseReview => state.review = None,
+        InspectShown => {
+            if let Some(shown) = shown_image(state) {
+                return Task::done(Message::InspectImage(shown.path));
+            }
+            return Task::done(Message::SetMessage("no image to inspect".into()));
+        }
+        InspectImage(path) => {
+            let metadata = read_cached(&state.pool, &path);
+            let lost = metadata.is_err();
+            if let Err(MetadataError::Read(e)) = metadata {
+                return Task::done(Message::SetMessage(format!("{:?}: {}", path, e)));
+            }
+            state.inspector = Some(Inspector {
+                path,
+                metadata: metadata.map_or(Value::Null, Value::Object),
+                // the top level and the generation parameters start out open
+                expanded: HashSet::from([String::new(), "/Comment".to_string()]),
+                integrity: None,
+            });
+            // an image without metadata is verified right away, to say where it went
+            if lost {
+                return Task::done(Message::VerifyImage);
+            }
+        }
+        VerifyImage => {
+            let Some(inspector) = &state.inspector else {
+                return Task::none();
+            };
+            let path = inspector.path.clone();
+            return Task::perform(
+                async move {
+                    let read = path.clone();
+                    let integrity = tokio::task::spawn_blocking(move || check_integrity(&read))
+                        .await
+                        .map_err(|e| e.to_string())
+                        .and_then(|r| r.map_err(|e| e.to_string()));
+                    (path, integrity)
+                },
+                |(path, integrity)| Message::ImageVerified(path, integrity),
+            );
+        }
+        ImageVerified(path, integrity) => {
+            if let Some(inspector) = &mut state.inspector
+                && inspector.path == path
+            {
+                inspector.integrity = Some(integrity);
+            }
+        }
+        InspectorToggle(pointer) => {
+            if let Some(inspector) = &mut state.inspector
+                && !inspector.expanded.remove(&pointer)
+            {
+                inspector.expanded.insert(pointer);
+            }
+        }
+        InspectorCopy(value) => {
+            return Task::batch([
+                clipboard::write(value),
+                Task::done(Message::SetMessage("copied".into())),
+            ]);
+        }
+        CloseInspector => state.inspector = None,
+        OpenFullscreen => {
+            let Some(shown) = shown_image(state) else {
+                return Task::done(Message::SetMessage("no image to show fullscreen".into()));
+            };
+            state.fullscreen = Some(shown);
+            return window::get_latest()
+                .and_then(|id| window::change_mode(id, window::Mode::Fullscreen));
+        }
+        ToggleCrop => {
+            if state.cropping.take().is_some() {
+                return Task::none();
+            }
+            let Some(image) = shown_image(state).filter(|s| s.size.is_some()) else {
+                return Task::done(Message::SetMessage("no image to crop".into()));
+            };
+            state.cropping = Some(Cropping {
+                image,
+                region: None,
+            });
+        }
+        CropSelect(region) => {
+            if let Some(cropping) = &mut state.cropping {
+                cropping.region = region;
+            }
+        }
+        ExportCrop => {
+            let Some((path, region)) = state
+                .cropping
+                .as_ref()
+                .and_then(|c| Some((c.image.path.clone(), c.region?)))
+            else {
+                return Task::done(Message::SetMessage("drag over the image to select".into()));
+            };
+            return Task::perform(
+                async move {
+                    tokio::task::spawn_blocking(move || crop::export(&path, region))
+                        .await
+                        .map_err(|e| e.to_string())?
+                },
+                Message::CropExported,
+            );
+        }
+        CropExported(result) => {
+            let msg = match result {
+                Ok(path) => {
+                    state.cropping = None;
+                    format!("saved crop to {:?}", path)
+                }
+                Err(e) => format!("export crop: {}", e),
+            };
+            return Task::done(Message::SetMessage(msg));
+        }
+        OpenSaveAs(strip) => {
+            // the dialog of the last one is still open
+            if state.save_as.is_some() {
+                return Task::none();
+            }
+            let Some(shown) = shown_image(state) else {
+                return Task::done(Message::SetMessage("no image to save".into()));
+            };
+            let dir = directories::UserDirs::new()
+                .and_then(|d| d.picture_dir().map(Path::to_path_buf))
+                .or_else(|| directories::BaseDirs::new().map(|d| d.home_dir().to_path_buf()))
+                .unwrap_or_default();
+            let name = shown
+                .path
+                .file_name()
+                .map_or(String::new(), |n| n.to_string_lossy().to_string());
+            let title = if strip {
+                "Export without metadata"
+            } else {
+                "Save as"
+            };
+            let dialog = rfd::AsyncFileDialog::new()
+                .set_title(title)
+                .set_directory(dir)
+                .set_file_name(name);
+            state.save_as = Some(SaveAs {
+                source: shown.path,
+                sidecar: state.save_sidecar && !strip,
+                strip,
+            });
+            return Task::perform(
+                async move {
+                    dialog
+                        .save_file()
+                        .await
+                        .map(|handle| handle.path().to_path_buf())
+                },
+                Message::SaveAsPicked,
+            );
+        }
+        SaveAsSidecar(b) => state.save_sidecar = b,
+        SaveAsPicked(dest) => {
+            let (Some(save_as), Some(dest)) = (state.save_as.take(), dest) else {
+                return Task::none();
+            };
+            let SaveAs {
+                source,
+                sidecar,
+                strip,
+            } = save_as;
+            return Task::perform(
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        if strip {
+                            strip_image_to(&source, &dest)
+                        } else {
+                            save_image_as(&source, &dest, sidecar)
+                        }
+                    })
+                    .await
+                    .map_err(|e| e.to_string())?
+                },
+                Message::SavedAs,
+            );
+        }
+        SavedAs(result) => {
+            let msg = match result {
+                Ok(path) => format!("saved to {:?}", path),
+                Err(e) => format!("save as: {}", e),
+            };
+            return Task::done(Message::SetMessage(msg));
+        }
+        ShowHelp(b) => state.help = b,
+        LogLevel(level) => {
+            state.log_level = level;
+            state.log_version = None;
+        }
+        LogFilter(filter) => {
+            state.log_filter = filter;
+            state.log_version = None;
+        }
+        CopyLog => {
+            let records = logs::records(state.log_level, &state.log_filter);
+            let lines: Vec<String> = records.iter().map(|r| r.to_string()).collect();
+            return Task::batch([
+                clipboard::write(lines.join("\n")),
+                Task::done(Message::SetMessage(format!(
+                    "copied {} log lines",
+                    lines.len()
+                ))),
+            ]);
+        }
+        ClearLog => logs::clear(),
+        CloseFullscreen => {
+            if state.fullscreen.take().is_some() {
+                return window::get_latest()
+                    .and_then(|id| window::change_mode(id, window::Mode::Windowed));
+            }
+        }
+        TreePrompt(path, prompt) => {
+            // the selection may have moved on while the file was read
+            if let Some(shown) = &mut state.tree_prompt
+                && shown.path == path
+            {
+                shown.prompt = prompt;
+            }
+        }
+        CopyMetadata(path) => {
+            let pool = state.pool.clone();
+            return Task::perform(
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        let map =
+                            read_cached(&pool, &path).map_err(|e| format!("{:?}: {}", path, e))?;
+                        serde_json::to_string_pretty(&Value::Object(map)).map_err(|e| e.to_string())
+                    })
+                    .await
+                    .map_err(|e| e.to_string())?
+                },
+                Message::MetadataCopied,
+            );
+        }
+        MetadataCopied(result) => {
+            return match result {
+                Ok(json) => Task::batch([
+                    clipboard::write(json),
+                    Task::done(Message::SetMessage("copied metadata".into())),
+                ]),
+                Err(e) => Task::done(Message::SetMessage(format!("copy metadata: {}", e))),
+            };
+        }
+        CopyImage => {
+            let Some(path) = state.selected_image.and_then(|i| state.image_paths.get(i)) else {
+                return Task::done(Message::SetMessage("no generated image selected".into()));
+            };
+            let path = path.clone();
+            return Task::perform(
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        let bytes =
+                            std::fs::read(&path).map_err(|e| format!("{:?}: {}", path, e))?;
+                        copy_image(&bytes)
+                    })
+                    .await
+                    .map_err(|e| e.to_string())?
+                },
+                Message::ImageCopied,
+            );
+        }
+        ImageCopied(result) => {
+            return Task::done(Message::SetMessage(match result {
+                Ok(()) => "copied image".into(),
+                Err(e) => format!("copy image: {}", e),
+            }));
+        }
+        PinImage => {
+            let Some(shown) = shown_image(state) else {
+                return Task::done(Message::SetMessage("no image to pin".into()));
+            };
+            let message = format!("pinned {:?}", shown.path);
+            state.pinned = Some(shown);
+            state.comparing = true;
+            return Task::done(Message::SetMessage(message));
+        }
+        UnpinImage => {
+            state.pinned = None;
+            state.comparing = false;
+        }
+        ToggleCompare => {
+            if state.pinned.is_none() {
+                return Task::done(Message::SetMessage("pin an image first (p)".into()));
+            }
+            state.comparing = !state.comparing;
+        }
+        CompareZoom(factor) => {
+            let zoom = state.compare_zoom.unwrap_or(1.0) * factor;
+            state.compare_zoom = Some(zoom.clamp(0.1, 8.0));
+        }
+        CompareFit => state.compare_zoom = None,
+        ToggleSwipe => {
+            state.swipe = match state.swipe {
+                Some(_) => None,
+                None => Some(0.5),
+            };
+        }
+        SwipeSplit(split) => state.swipe = Some(split),
+        CompareScrolled(side, offset) => {
+            // keep both halves showing the same region
+            let other = match side {
+                CompareSide::Pinned => COMPARE_SHOWN.clone(),
+                CompareSide::Shown => COMPARE_PINNED.clone(),
+            };
+            return scrollable::snap_to(other, offset);
+        }
         MetadataFromImage(i) => {
-            if let Some(bytes) = state.images.get(i) {
-                let mut reader = ImageReader::new(Cursor::new(bytes));
-                reader.set_format(image::ImageFormat::Png);
-                if let Ok(im) = reader.decode() {
-                    if let Ok(metadata) = extract_image_metadata(im) {
-                        let (seed, base, characters) = get_prompt_characters(metadata);
-                        set_prompt_characters(state, base, characters);
-                        state.current_seed = Some(seed);
-                    }
+            if let Some(path) = state.image_paths.get(i) {
+                match get_prompt_metadata(&state.pool, path) {
+                    Ok(meta) => return apply_metadata(state, meta),
+                    Err(e) => return Task::done(Message::SetMessage(format!("metadata: {}", e))),
                 }
             }
         }
         DeleteImageHistory => {
             if let Some(i) = state.selected_image {
-                state.images.remove(i);
                 state.thumbnails.remove(i);
+                state.history_info.remove(i);
                 let path = state.image_paths.remove(i).unwrap();
 
                 if i > 0 {
@@ -784,184 +2554,642 @@ pub fn update(state: &mut State, msg: Message) -> Task<Message> {
     Task::none()
 }
 
-fn handle_event(state: &mut State, e: Event) -> Task<Message> {
-    match e {
-        Event::Keyboard(ref e) => match e {
-            keyboard::Event::KeyPressed { key, modifiers, .. } => {
-                state.last_key = Some((key.clone(), *modifiers));
-                if key.as_ref() == Key::Named(Named::ArrowUp) && modifiers.command() {
-                    return Task::done(Message::FocusAdjacent(Direction::Up));
-                }
-                if key.as_ref() == Key::Named(Named::ArrowDown) && modifiers.command() {
-                    return Task::done(Message::FocusAdjacent(Direction::Down));
-                }
-                if key.as_ref() == Key::Named(Named::ArrowLeft) && modifiers.command() {
-                    return Task::done(Message::FocusAdjacent(Direction::Left));
-                }
-                if key.as_ref() == Key::Named(Named::ArrowRight) && modifiers.command() {
-                    return Task::done(Message::FocusAdjacent(Direction::Right));
+/// runs `ops` one at a time on the blocking pool, reporting each result back to `update`
+///
+/// cancelling stops before the next operation; the ones already done are kept
+fn run_file_ops(state: &mut State, ops: Vec<FileOp>) -> Task<Message> {
+    if state.file_ops.is_some() {
+        return Task::done(Message::SetMessage(
+            "wait for the running file operation to finish".into(),
+        ));
+    }
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    state.file_ops = Some(FileOpsProgress {
+        done: 0,
+        failed: 0,
+        total: ops.len(),
+        cancel: Arc::clone(&cancel),
+    });
+
+    Task::run(
+        stream::channel(64, move |mut output| async move {
+            for op in ops {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
                 }
-            }
-            _ => (),
-        },
-        Event::Mouse(ref _e) => (),
-        Event::Window(ref e) => match e {
-            window::Event::FileDropped(path) => {
-                if let Some((seed, prompt, characters)) = get_prompt_metadata(path) {
-                    return Task::done(Message::ImportPrompt(seed, prompt, characters));
+
+                let event = tokio::task::spawn_blocking(move || match op.run() {
+                    Ok(_) => FileOpEvent::Done(op),
+                    Err(e) => FileOpEvent::Failed(op, e.to_string()),
+                })
+                .await;
+                if let Ok(event) = event {
+                    let _ = output.send(event).await;
                 }
             }
-            _ => (),
-        },
-        Event::Touch(_) => (),
+            let _ = output.send(FileOpEvent::Finished).await;
+        }),
+        Message::FileOps,
+    )
+}
+
+/// suggests tags for the token under the cursor after typing; moving the cursor hides them
+fn update_suggestions(state: &mut State, editor: PromptEditor, edited: bool) {
+    if !edited {
+        if state
+            .suggestions
+            .as_ref()
+            .is_some_and(|s| s.editor == editor)
+        {
+            state.suggestions = None;
+        }
+        return;
     }
 
-    if let Some(focused) = state.focus {
-        if let Some(pane) = state.panes.get(focused) {
-            match pane.id {
-                PaneId::Files => return handle_event_files(state, e),
-                PaneId::Prompts => (),
-                PaneId::Image => return handle_event_image(state, e),
-            }
+    let content = match editor {
+        PromptEditor::Base => &state.base_prompt,
+        PromptEditor::Character(i) => &state.character_prompts[i].content,
+    };
+    let (line, index) = content.cursor_position();
+    let token = content
+        .line(line)
+        .and_then(|text| current_token(&text, index).map(String::from));
+
+    state.suggestions = token.and_then(|token| {
+        let items = state.vocabulary.suggest(&token);
+        (!items.is_empty()).then_some(Suggestions {
+            editor,
+            token,
+            items,
+            index: 0,
+        })
+    });
+}
+
+fn prompt_content(
+    state: &mut State,
+    editor: PromptEditor,
+) -> Option<&mut widget::text_editor::Content> {
+    match editor {
+        PromptEditor::Base => Some(&mut state.base_prompt),
+        PromptEditor::Character(i) => state.character_prompts.get_mut(i).map(|cc| &mut cc.content),
+    }
+}
+
+/// changes the weight of the selection, or of the weight block / tag under the cursor
+fn adjust_weight_at_cursor(content: &mut widget::text_editor::Content, delta: f32) {
+    if let Some(selection) = content.selection()
+        && !selection.contains('\n')
+    {
+        let replacement = adjust_weight(&selection, delta);
+        content.perform(Action::Edit(Edit::Paste(Arc::new(replacement))));
+        return;
+    }
+
+    let (line, index) = content.cursor_position();
+    let Some(text) = content.line(line).map(|l| l.to_string()) else {
+        return;
+    };
+    let Some(range) = weight_target(&text, index) else {
+        return;
+    };
+
+    // select the target by moving to its end and extending back over it, so the paste replaces it
+    if index <= range.end {
+        for _ in text[index..range.end].chars() {
+            content.perform(Action::Move(Motion::Right));
+        }
+    } else {
+        for _ in text[range.end..index].chars() {
+            content.perform(Action::Move(Motion::Left));
         }
     }
-    Task::none()
+    for _ in text[range.clone()].chars() {
+        content.perform(Action::Select(Motion::Left));
+    }
+
+    let replacement = adjust_weight(&text[range], delta);
+    content.perform(Action::Edit(Edit::Paste(Arc::new(replacement))));
 }
 
-fn handle_event_files(state: &mut State, e: Event) -> Task<Message> {
-    match e {
-        Event::Keyboard(e) => {
-            match state.files_mode {
-                FilesMode::Normal => match e {
-                    keyboard::Event::KeyPressed { ref key, .. } => {
-                        if key.as_ref() == Key::Named(Named::Backspace) {
-                            return Task::done(Message::NavigateUp);
-                        }
-                        if key.as_ref() == Key::Character(".") {
-                            return Task::done(Message::SetRoot);
-                        }
-                        if key.as_ref() == Key::Character("i") {
-                            let id = state.files.selected;
-                            let entry = &state.files.entries[id];
-                            if entry.kind == EntryKind::File
-                                && entry
-                                    .path
-                                    .extension()
-                                    .is_some_and(|s| s.to_string_lossy().ends_with("png"))
-                            {
-                                if let Some((seed, prompt, characters)) =
-                                    get_prompt_metadata(&entry.path)
-                                {
-                                    return Task::done(Message::ImportPrompt(
-                                        seed, prompt, characters,
-                                    ));
-                                }
-                            }
-                        }
-                        if key.as_ref() == Key::Character("d") {
-                            return Task::done(Message::Delete);
-                        }
-                        if key.as_ref() == Key::Character("b") {
-                            return Task::done(Message::FilesPaneMode(FilesMode::Batch));
-                        }
-                        if key.as_ref() == Key::Character("a") {
-                            return Task::done(Message::FilesPaneMode(FilesMode::Create));
-                        }
-                    }
-                    _ => (),
-                },
-                FilesMode::Batch => match e {
-                    keyboard::Event::KeyPressed {
-                        ref key, modifiers, ..
-                    } => {
-                        if key.as_ref() == Key::Character("s") {
-                            return Task::done(Message::SelectEntry);
-                        }
-                        if key.as_ref() == Key::Character("m") {
-                            return Task::done(Message::MoveBatch);
-                        }
-                        if key.as_ref() == Key::Character("d") && modifiers.shift() {
-                            return Task::done(Message::DeleteBatch);
-                        }
-                    }
-                    _ => (),
-                },
-                _ => (),
+/// Ctrl+Up/Down adjust the weight under the cursor and the palette binding opens the palette for
+/// this editor; Tab/Enter accept, arrows pick and Escape hides the suggestions while they are
+/// shown, otherwise the default text editor bindings apply
+fn prompt_binding(
+    editor: PromptEditor,
+    suggesting: bool,
+    keymap: &Keymap,
+    key_press: KeyPress,
+) -> Option<Binding<Message>> {
+    if key_press.status == Status::Focused
+        && keymap.global(&key_press.key, key_press.modifiers) == Some(GlobalAction::Palette)
+    {
+        return Some(Binding::Custom(Message::OpenPalette(editor)));
+    }
+
+    if key_press.status == Status::Focused && key_press.modifiers.command() {
+        match key_press.key.as_ref() {
+            Key::Named(Named::ArrowUp) => {
+                return Some(Binding::Custom(Message::AdjustWeight(editor, WEIGHT_STEP)));
             }
+            Key::Named(Named::ArrowDown) => {
+                return Some(Binding::Custom(Message::AdjustWeight(editor, -WEIGHT_STEP)));
+            }
+            _ => (),
+        }
+    }
 
-            match e {
-                keyboard::Event::KeyPressed { key, modifiers, .. } => match key.as_ref() {
-                    Key::Named(Named::Escape) => {
-                        return Task::done(Message::FilesPaneMode(FilesMode::Normal));
-                    }
-                    Key::Named(Named::ArrowUp) | Key::Character("k") => {
-                        state.files.move_up();
-                    }
-                    Key::Named(Named::ArrowDown) | Key::Character("j") => {
-                        state.files.move_down();
-                    }
-                    Key::Named(Named::Enter) => {
-                        return Task::done(Message::ToggleExpand);
-                    }
-                    Key::Character("g") => {
-                        if modifiers.shift() {
-                            return Task::done(Message::GotoEnd);
-                        } else {
-                            return Task::done(Message::GotoStart);
-                        }
-                    }
-                    Key::Character("r") => {
-                        if modifiers.shift() {
-                            return Task::done(Message::Refresh);
-                        } else {
-                            return Task::done(Message::RefreshSelected);
-                        }
-                    }
+    if suggesting && key_press.status == Status::Focused {
+        match key_press.key.as_ref() {
+            Key::Named(Named::Tab | Named::Enter) => {
+                return Some(Binding::Custom(Message::AcceptSuggestion));
+            }
+            Key::Named(Named::ArrowUp) => {
+                return Some(Binding::Custom(Message::SuggestionMove(-1)));
+            }
+            Key::Named(Named::ArrowDown) => {
+                return Some(Binding::Custom(Message::SuggestionMove(1)));
+            }
+            Key::Named(Named::Escape) => return Some(Binding::Custom(Message::DismissSuggestions)),
+            _ => (),
+        }
+    }
+    Binding::from_key_press(key_press)
+}
+
+fn view_suggestions(state: &State, editor: PromptEditor) -> Option<Element<'_, Message>> {
+    let suggestions = state.suggestions.as_ref().filter(|s| s.editor == editor)?;
+
+    let col = suggestions
+        .items
+        .iter()
+        .enumerate()
+        .fold(Column::new(), |col, (i, tag)| {
+            let style = if i == suggestions.index {
+                button::primary
+            } else {
+                button::text
+            };
+            col.push(
+                button(text(tag))
+                    .style(style)
+                    .padding([0, 4])
+                    .on_press(Message::SuggestionSelect(i)),
+            )
+        });
+    Some(container(col).style(container::bordered_box).into())
+}
+
+/// saved prompts matching what was typed into a prompt picker, matched letters in bold
+fn view_prompt_matches<V>(ui: &PromptUi<V>) -> Option<Element<'_, Message>> {
+    if ui.query.trim().is_empty() || ui.selected.as_ref() == Some(&ui.query) {
+        return None;
+    }
+
+    let matches = rank(&ui.query, &ui.options, PROMPT_MATCHES);
+    if matches.is_empty() {
+        return Some(text("no matching prompt").size(12).into());
+    }
+    let col = matches
+        .into_iter()
+        .fold(Column::new(), |col, (name, matched)| {
+            col.push(
+                button(highlighted(name, &matched))
+                    .style(button::text)
+                    .padding([0, 4])
+                    .on_press(prompt_selected(ui.kind, name.to_string())),
+            )
+        });
+    Some(container(col).style(container::bordered_box).into())
+}
+
+/// `name` with the chars at `matched` in bold and underlined
+fn highlighted<'a>(name: &str, matched: &[usize]) -> Element<'a, Message> {
+    let bold = Font {
+        weight: iced::font::Weight::Bold,
+        ..Font::DEFAULT
+    };
+    let mut spans: Vec<text::Span<'a, Message>> = Vec::new();
+    for (i, c) in name.chars().enumerate() {
+        let span = text::Span::new(c.to_string());
+        spans.push(if matched.contains(&i) {
+            span.font(bold).underline(true)
+        } else {
+            span
+        });
+    }
+    widget::rich_text(spans).into()
+}
+
+/// snippets matching the query, Up/Down pick one and Enter inserts it
+fn view_palette(palette: &Palette) -> Element<'_, Message> {
+    let found = search(&palette.query, &palette.snippets, PALETTE_MATCHES);
+    let input = text_input("search snippets, quality and locations", &palette.query)
+        .id(PALETTE_INPUT.clone())
+        .on_input(Message::PaletteQuery)
+        .on_submit(Message::PaletteInsert(palette.index));
+
+    let mut list = Column::new().spacing(2);
+    if found.is_empty() {
+        list = list.push(text("nothing matches").size(12));
+    }
+    for (i, (snippet, matched)) in found.into_iter().enumerate() {
+        let preview = match snippet.text.char_indices().nth(PALETTE_PREVIEW_CHARS) {
+            Some((end, _)) => format!("{}...", &snippet.text[..end]),
+            None => snippet.text.clone(),
+        };
+        list = list.push(
+            button(column![
+                row![
+                    highlighted(&snippet.name, &matched),
+                    text(snippet.kind.to_string())
+                        .size(12)
+                        .style(text::secondary)
+                ]
+                .spacing(8)
+                .align_y(Alignment::Center),
+                text(preview.replace('\n', " ")).size(12),
+            ])
+            .width(Length::Fill)
+            .style(if i == palette.index {
+                button::primary
+            } else {
+                button::text
+            })
+            .on_press(Message::PaletteInsert(i)),
+        );
+    }
+
+    container(column![input, scrollable(list).height(Length::Fixed(400.0))].spacing(12))
+        .padding(16)
+        .width(Length::Fixed(600.0))
+        .style(container::rounded_box)
+        .into()
+}
+
+/// the saved prompts last used for generation, click to load one
+fn view_recent_prompts(state: &State) -> Option<Element<'_, Message>> {
+    if state.recent.is_empty() {
+        return None;
+    }
+    let mut recent = Row::new().push(text("Recent").size(12)).spacing(4);
+    for (kind, name) in &state.recent {
+        let kind_label = match kind {
+            PromptKind::Base => "base",
+            PromptKind::Character => "char",
+            PromptKind::Template => "template",
+        };
+        recent = recent.push(
+            button(
+                row![
+                    text(kind_label).size(10).style(text::secondary),
+                    text(name).size(12)
+                ]
+                .spacing(4)
+                .align_y(Alignment::Center),
+            )
+            .style(button::secondary)
+            .padding([0, 4])
+            .on_press(prompt_selected(*kind, name.clone())),
+        );
+    }
+    Some(recent.align_y(Alignment::Center).wrap().into())
+}
+
+fn prompt_selected(kind: PromptKind, name: String) -> Message {
+    match kind {
+        PromptKind::Base => Message::BasePromptSelected(name),
+        PromptKind::Character => Message::CharacterPromptSelected(name),
+        PromptKind::Template => Message::TemplateSelected(name),
+    }
+}
+
+/// runs `action` right away, or parks it behind the confirmation dialog if `ask` is set
+fn confirm(state: &mut State, action: PendingAction, ask: bool) -> Task<Message> {
+    if ask {
+        state.pending = Some(action);
+        Task::none()
+    } else {
+        Task::done(Message::Confirmed(action))
+    }
+}
+
+fn handle_event(state: &mut State, e: Event) -> Task<Message> {
+    // the confirmation dialog swallows keyboard input until it is answered
+    if state.pending.is_some()
+        && let Event::Keyboard(keyboard::Event::KeyPressed { key, .. }) = &e
+    {
+        return match key.as_ref() {
+            Key::Named(Named::Enter) | Key::Character("y") => Task::done(Message::ConfirmAccept),
+            Key::Named(Named::Escape) | Key::Character("n") => Task::done(Message::ConfirmCancel),
+            _ => Task::none(),
+        };
+    }
 
-                    _ => (),
+    if state.inspector.is_some()
+        && let Event::Keyboard(keyboard::Event::KeyPressed { key, .. }) = &e
+    {
+        if key.as_ref() == Key::Named(Named::Escape) {
+            return Task::done(Message::CloseInspector);
+        }
+        return Task::none();
+    }
+
+    // the palette query input has focus, only the keys it ignores get here
+    if state.palette.is_some()
+        && let Event::Keyboard(keyboard::Event::KeyPressed { key, .. }) = &e
+    {
+        return match key.as_ref() {
+            Key::Named(Named::Escape) => Task::done(Message::ClosePalette),
+            Key::Named(Named::ArrowUp) => Task::done(Message::PaletteMove(-1)),
+            Key::Named(Named::ArrowDown) => Task::done(Message::PaletteMove(1)),
+            _ => Task::none(),
+        };
+    }
+
+    if state.help
+        && let Event::Keyboard(keyboard::Event::KeyPressed { key, .. }) = &e
+    {
+        if matches!(
+            key.as_ref(),
+            Key::Named(Named::Escape) | Key::Character("?")
+        ) {
+            return Task::done(Message::ShowHelp(false));
+        }
+        return Task::none();
+    }
+
+    if state.retention_plan.is_some()
+        && let Event::Keyboard(keyboard::Event::KeyPressed { key, .. }) = &e
+    {
+        if key.as_ref() == Key::Named(Named::Escape) {
+            return Task::done(Message::CloseRetention);
+        }
+        return Task::none();
+    }
+
+    if state.settings_form.is_some()
+        && let Event::Keyboard(keyboard::Event::KeyPressed { key, .. }) = &e
+    {
+        if key.as_ref() == Key::Named(Named::Escape) {
+            return Task::done(Message::CloseSettings);
+        }
+        return Task::none();
+    }
+
+    // and fullscreen, which only closes
+    if state.fullscreen.is_some()
+        && let Event::Keyboard(keyboard::Event::KeyPressed { key, .. }) = &e
+    {
+        return match key.as_ref() {
+            Key::Named(Named::Escape) | Key::Character("q") => Task::done(Message::CloseFullscreen),
+            _ => Task::none(),
+        };
+    }
+
+    // so does review mode
+    if state.review.is_some()
+        && let Event::Keyboard(keyboard::Event::KeyPressed { key, .. }) = &e
+    {
+        return match key.as_ref() {
+            Key::Named(Named::ArrowLeft) | Key::Character("h") => {
+                Task::done(Message::ReviewMove(-1))
+            }
+            Key::Named(Named::ArrowRight | Named::Space) | Key::Character("l") => {
+                Task::done(Message::ReviewMove(1))
+            }
+            Key::Character("f") => Task::done(Message::ReviewFavorite),
+            Key::Named(Named::Delete) | Key::Character("x") => Task::done(Message::ReviewTrash),
+            Key::Named(Named::Escape) | Key::Character("q") => Task::done(Message::CloseReview),
+            _ => Task::none(),
+        };
+    }
+
+    match e {
+        Event::Keyboard(ref e) => match e {
+            keyboard::Event::KeyPressed { key, modifiers, .. } => {
+                state.last_key = Some((key.clone(), *modifiers));
+                if let Some(action) = state.keymap.global(key, *modifiers) {
+                    return Task::done(match action {
+                        GlobalAction::FocusUp => Message::FocusAdjacent(Direction::Up),
+                        GlobalAction::FocusDown => Message::FocusAdjacent(Direction::Down),
+                        GlobalAction::FocusLeft => Message::FocusAdjacent(Direction::Left),
+                        GlobalAction::FocusRight => Message::FocusAdjacent(Direction::Right),
+                        GlobalAction::ReloadKeymap => Message::ReloadKeymap,
+                        GlobalAction::Settings => Message::OpenSettings,
+                        GlobalAction::Palette => Message::OpenPalette(state.last_editor),
+                        GlobalAction::Help => Message::ShowHelp(true),
+                    });
+                }
+            }
+            _ => (),
+        },
+        Event::Mouse(ref _e) => (),
+        Event::Window(ref e) => match e {
+            window::Event::FileDropped(path) => {
+                let msg = match get_prompt_metadata(&state.pool, path) {
+                    Ok(meta) => Message::ImportPrompt(meta),
+                    Err(e) => Message::SetMessage(format!("{:?}: {}", path, e)),
+                };
+                return Task::done(msg);
+            }
+            _ => (),
+        },
+        Event::Touch(_) => (),
+    }
+
+    if let Some(focused) = state.focus {
+        if let Some(pane) = state.panes.get(focused) {
+            match pane.id {
+                PaneId::Files => return handle_event_files(state, e),
+                PaneId::Prompts => (),
+                PaneId::Image => return handle_event_image(state, e),
+                PaneId::Log => (),
+            }
+        }
+    }
+    Task::none()
+}
+
+fn handle_event_files(state: &mut State, e: Event) -> Task<Message> {
+    let Event::Keyboard(keyboard::Event::KeyPressed { key, modifiers, .. }) = e else {
+        return Task::none();
+    };
+
+    let action = match state.files_mode {
+        FilesMode::Normal => {
+            // "f" followed by any character jumps, even for bound letters
+            if state.jump_pending {
+                state.jump_pending = false;
+                if let Key::Character(c) = key.as_ref() {
+                    return Task::done(Message::JumpTo(c.to_string()));
+                }
+                return Task::none();
+            }
+            state.keymap.normal(&key, modifiers)
+        }
+        FilesMode::Batch => state.keymap.batch(&key, modifiers),
+        // the grid replaces the tree, so tree navigation below does not apply
+        FilesMode::Gallery => {
+            let cols = GALLERY_COLUMNS as isize;
+            return match key.as_ref() {
+                Key::Named(Named::ArrowLeft) | Key::Character("h") => {
+                    Task::done(Message::GalleryMove(-1))
+                }
+                Key::Named(Named::ArrowRight) | Key::Character("l") => {
+                    Task::done(Message::GalleryMove(1))
+                }
+                Key::Named(Named::ArrowUp) | Key::Character("k") => {
+                    Task::done(Message::GalleryMove(-cols))
+                }
+                Key::Named(Named::ArrowDown) | Key::Character("j") => {
+                    Task::done(Message::GalleryMove(cols))
+                }
+                Key::Named(Named::Enter) => Task::done(Message::GallerySelect(state.gallery_index)),
+                Key::Character("f") if modifiers.shift() => {
+                    Task::done(Message::ToggleFavoritesOnly(!state.favorites_only))
+                }
+                Key::Character("f") => match state.gallery.get(state.gallery_index) {
+                    Some(&id) => Task::done(Message::ToggleFavorite(
+                        state.files.entries[id].path.clone(),
+                    )),
+                    None => Task::none(),
                 },
-                _ => (),
+                Key::Named(Named::Escape) | Key::Character("v") => {
+                    Task::done(Message::FilesPaneMode(FilesMode::Normal))
+                }
+                _ => Task::none(),
+            };
+        }
+        _ => None,
+    };
+
+    // escape is not rebindable so there is always a way back to normal mode
+    if key.as_ref() == Key::Named(Named::Escape) {
+        return Task::done(Message::FilesPaneMode(FilesMode::Normal));
+    }
+
+    if let Some(action) = action.or_else(|| state.keymap.tree(&key, modifiers)) {
+        return files_action(state, action);
+    }
+
+    // any other unbound character jumps to the next entry starting with it
+    if let Key::Character(c) = key.as_ref()
+        && state.files_mode == FilesMode::Normal
+        && !modifiers.command()
+    {
+        return Task::done(Message::JumpTo(c.to_string()));
+    }
+    Task::none()
+}
+
+fn files_action(state: &mut State, action: FilesAction) -> Task<Message> {
+    use FilesAction::*;
+
+    match action {
+        Up => state.files.move_up(),
+        Down => state.files.move_down(),
+        Start => return Task::done(Message::GotoStart),
+        End => return Task::done(Message::GotoEnd),
+        Expand => return Task::done(Message::ToggleExpand),
+        Refresh => return Task::done(Message::Refresh),
+        RefreshSelected => return Task::done(Message::RefreshSelected),
+        NavigateUp => return Task::done(Message::NavigateUp),
+        SetRoot => return Task::done(Message::SetRoot),
+        Import => {
+            let id = state.files.selected;
+            let entry = &state.files.entries[id];
+            if !state.files.is_folder(id) && is_image(&entry.path) {
+                let msg = match get_prompt_metadata(&state.pool, &entry.path) {
+                    Ok(meta) => Message::ImportPrompt(meta),
+                    Err(e) => Message::SetMessage(format!("{:?}: {}", entry.path, e)),
+                };
+                return Task::done(msg);
             }
         }
-        _ => (),
+        Delete => return Task::done(Message::Delete),
+        Cut => return Task::done(Message::Cut),
+        Paste => return Task::done(Message::Paste),
+        Batch => return Task::done(Message::FilesPaneMode(FilesMode::Batch)),
+        Create => return Task::done(Message::FilesPaneMode(FilesMode::Create)),
+        Search => return Task::done(Message::FilesPaneMode(FilesMode::Search)),
+        Duplicates => return Task::done(Message::FindDuplicates),
+        Gallery => return Task::done(Message::FilesPaneMode(FilesMode::Gallery)),
+        NaiOnly => return Task::done(Message::ToggleNaiOnly),
+        Hidden => return Task::done(Message::ToggleHidden),
+        NaturalSort => return Task::done(Message::ToggleNaturalSort),
+        CopyPath => return Task::done(Message::CopyPath),
+        CopyFileName => return Task::done(Message::CopyFileName),
+        CopyMetadata => {
+            let entry = &state.files.entries[state.files.selected];
+            if entry.path.is_file() {
+                return Task::done(Message::CopyMetadata(entry.path.clone()));
+            }
+        }
+        Jump => state.jump_pending = true,
+        Reindex => return Task::done(Message::IndexLibrary),
+        Review => return Task::done(Message::StartReview(ReviewSource::Folder)),
+        Mark => return Task::done(Message::SelectEntry),
+        MoveBatch => return Task::done(Message::MoveBatch),
+        DeleteBatch => return Task::done(Message::DeleteBatch),
     }
     Task::none()
 }
 
 fn handle_event_image(state: &mut State, e: Event) -> Task<Message> {
-    match e {
-        Event::Keyboard(e) => {
-            let current_index = state.selected_image;
+    let Event::Keyboard(keyboard::Event::KeyPressed { key, modifiers, .. }) = e else {
+        return Task::none();
+    };
+    let Some(action) = state.keymap.image(&key, modifiers) else {
+        return Task::none();
+    };
 
-            match e {
-                keyboard::Event::KeyPressed { key, modifiers, .. } => {
-                    if key.as_ref() == Key::Character("d") && modifiers.shift() {
-                        return Task::done(Message::DeleteImageHistory);
-                    }
-                    if key.as_ref() == Key::Named(Named::ArrowUp) {
-                        if let Some(i) = current_index {
-                            if i > 0 {
-                                state.selected_image.replace(i - 1);
-                            }
-                        }
-                    }
-                    if key.as_ref() == Key::Named(Named::ArrowDown) {
-                        if let Some(i) = current_index {
-                            if i + 1 < state.images.len() {
-                                state.selected_image.replace(i + 1);
-                            }
-                        }
-                    }
-                }
-                _ => (),
+    match action {
+        ImageAction::Previous => return select_history(state, |pos, n| (pos + n - 1) % n),
+        ImageAction::Next => return select_history(state, |pos, n| (pos + 1) % n),
+        ImageAction::First => return select_history(state, |_, _| 0),
+        ImageAction::Last => return select_history(state, |_, n| n - 1),
+        ImageAction::DeleteHistory => return Task::done(Message::DeleteImageHistory),
+        ImageAction::Pin => return Task::done(Message::PinImage),
+        ImageAction::Unpin => return Task::done(Message::UnpinImage),
+        ImageAction::Compare => return Task::done(Message::ToggleCompare),
+        ImageAction::ZoomIn if state.comparing => {
+            return Task::done(Message::CompareZoom(COMPARE_ZOOM_STEP));
+        }
+        ImageAction::ZoomOut if state.comparing => {
+            return Task::done(Message::CompareZoom(1.0 / COMPARE_ZOOM_STEP));
+        }
+        ImageAction::ZoomFit if state.comparing => return Task::done(Message::CompareFit),
+        ImageAction::Swipe if state.comparing => return Task::done(Message::ToggleSwipe),
+        ImageAction::ZoomIn | ImageAction::ZoomOut | ImageAction::ZoomFit | ImageAction::Swipe => {}
+        ImageAction::Review => return Task::done(Message::StartReview(ReviewSource::History)),
+        ImageAction::Inspect => return Task::done(Message::InspectShown),
+        ImageAction::CopyImage => return Task::done(Message::CopyImage),
+        ImageAction::CopyMetadata => {
+            if let Some(shown) = shown_image(state) {
+                return Task::done(Message::CopyMetadata(shown.path));
+            }
+            return Task::done(Message::SetMessage("no image to copy metadata from".into()));
+        }
+        ImageAction::Favorite => {
+            if let Some(path) = state.selected_image.and_then(|i| state.image_paths.get(i)) {
+                return Task::done(Message::ToggleFavorite(path.clone()));
             }
         }
-        _ => (),
+        ImageAction::Fullscreen => return Task::done(Message::OpenFullscreen),
+        ImageAction::Crop => return Task::done(Message::ToggleCrop),
+        ImageAction::SaveAs => return Task::done(Message::OpenSaveAs(false)),
+        ImageAction::ExportStripped => return Task::done(Message::OpenSaveAs(true)),
     }
     Task::none()
 }
 
 pub fn view(state: &State) -> Element<Message> {
+    if let Some(shown) = &state.fullscreen {
+        return view_fullscreen(shown);
+    }
+
     let focus = state.focus;
     let pane_grid = PaneGrid::new(&state.panes, |id, pane, is_maximized| {
         let is_focused = focus == Some(id);
@@ -988,21 +3216,30 @@ pub fn view(state: &State) -> Element<Message> {
                 style::title_bar_active
             });
 
-        let content = match pane {
-            Pane { id: PaneId::Files } => view_files(state).into(),
+        let content: Element<Message> = match pane {
+            Pane { id: PaneId::Files } => view_files(state),
             Pane {
                 id: PaneId::Prompts,
-            } => view_prompts(state),
-            Pane { id: PaneId::Image } => view_image(state).into(),
+            } => mouse_area(view_prompts(state))
+                .on_enter(Message::DragHover(Some(DropTarget::Prompts)))
+                .on_exit(Message::DragHover(None))
+                .into(),
+            Pane { id: PaneId::Image } => view_image(state),
+            Pane { id: PaneId::Log } => view_log(state),
         };
 
-        pane_grid::Content::new(content)
-            .title_bar(title_bar)
-            .style(if is_focused {
+        // highlight the pane a dragged entry would be dropped on
+        let is_drop_target = state.dragging.is_some()
+            && matches!(pane.id, PaneId::Prompts)
+            && state.drop_target == Some(DropTarget::Prompts);
+
+        pane_grid::Content::new(content).title_bar(title_bar).style(
+            if is_focused || is_drop_target {
                 style::pane_focused
             } else {
                 style::pane_active
-            })
+            },
+        )
     })
     .width(Length::Fill)
     .height(Length::Fill)
@@ -1011,7 +3248,832 @@ pub fn view(state: &State) -> Element<Message> {
     .on_drag(Message::Dragged)
     .on_resize(10, Message::Resized);
 
-    container(pane_grid).padding(10).into()
+    let base = column![container(pane_grid).padding(10), view_status_bar(state)];
+
+    if let Some(action) = &state.pending {
+        modal(base, view_confirm(action), Message::ConfirmCancel)
+    } else if let Some(inspector) = &state.inspector {
+        modal(base, view_inspector(inspector), Message::CloseInspector)
+    } else if let Some(palette) = &state.palette {
+        modal(base, view_palette(palette), Message::ClosePalette)
+    } else if state.help {
+        modal(base, view_help(&state.keymap), Message::ShowHelp(false))
+    } else if let Some(plan) = &state.retention_plan {
+        modal(base, view_retention(plan), Message::CloseRetention)
+    } else if let Some(form) = &state.settings_form {
+        modal(base, view_settings(state, form), Message::CloseSettings)
+    } else if let Some(review) = &state.review {
+        widget::stack![base, opaque(view_review(state, review))].into()
+    } else {
+        base.into()
+    }
+}
+
+/// the seeds of the latest generated images with what they produced, newest first; clicking one
+/// uses it for the next generation
+fn view_seed_history(state: &State) -> Option<Element<'_, Message>> {
+    let mut seen = HashSet::new();
+    let recent = state
+        .history_info
+        .iter()
+        .zip(&state.thumbnails)
+        .filter_map(|(info, thumb)| Some((u64::try_from(info.seed()?).ok()?, thumb)))
+        .filter(|(seed, _)| seen.insert(*seed))
+        .take(SEED_HISTORY);
+
+    let mut seeds = Row::new().spacing(4);
+    for (seed, thumb) in recent {
+        seeds = seeds.push(
+            button(
+                column![
+                    Image::new(thumb.clone())
+                        .width(SEED_THUMBNAIL_SIDE)
+                        .height(SEED_THUMBNAIL_SIDE),
+                    text(seed).size(10),
+                ]
+                .align_x(Alignment::Center),
+            )
+            .padding(2)
+            .style(if state.current_seed == Some(seed) {
+                button::primary
+            } else {
+                button::text
+            })
+            .on_press(Message::UseSeed(seed)),
+        );
+    }
+    (!seen.is_empty()).then(|| {
+        scrollable(seeds)
+            .direction(scrollable::Direction::Horizontal(
+                scrollable::Scrollbar::default(),
+            ))
+            .into()
+    })
+}
+
+/// what Generate sends for the current editors; line breaks are shown as ↵ since they are sent
+/// as typed
+fn view_request_preview<'a>(req: &ImageGenRequest) -> Element<'a, Message> {
+    let shown = |s: &str| s.trim_end_matches('\n').replace('\n', "↵\n");
+    let section = |label: &'static str| text(label).size(12).style(text::secondary);
+
+    let mut preview = Column::new().spacing(4);
+    preview = preview.push(section("base prompt"));
+    preview = preview.push(text(shown(req.base_prompt())).size(12));
+    if req.quality_toggle() {
+        preview = preview.push(text("+ NAI quality tags (qualityToggle)").size(12));
+    }
+
+    preview = preview.push(section(if req.uses_coords() {
+        "characters (positions used)"
+    } else {
+        "characters (positions ignored, order matters)"
+    }));
+    if req.char_captions().is_empty() {
+        preview = preview.push(text("none").size(12));
+    }
+    for (i, caption) in req.char_captions().iter().enumerate() {
+        let centers = caption
+            .centers
+            .iter()
+            .map(|c| format!("({:.2}, {:.2})", c.x, c.y))
+            .collect::<Vec<_>>()
+            .join(" ");
+        preview = preview.push(
+            text(format!(
+                "{}. {}  {}",
+                i + 1,
+                centers,
+                shown(&caption.char_caption)
+            ))
+            .size(12),
+        );
+    }
+
+    preview = preview.push(section("negative prompt"));
+    preview = preview.push(text(req.negative_prompt().to_string()).size(12));
+
+    container(preview)
+        .padding(8)
+        .width(Length::Fill)
+        .style(container::bordered_box)
+        .into()
+}
+
+fn view_prompt_stats(stats: &PromptStats) -> Element<'_, Message> {
+    text(format!(
+        "{} tags, ~{} / {} tokens",
+        stats.tags(),
+        stats.tokens(),
+        TOKEN_LIMIT
+    ))
+    .size(12)
+    .style(token_style(stats.tokens()))
+    .into()
+}
+
+fn token_style(tokens: usize) -> fn(&Theme) -> text::Style {
+    match Level::of(tokens) {
+        Level::Ok => text::secondary,
+        // the palettes have no warning color
+        Level::Warning => |_theme: &Theme| text::Style {
+            color: Some(Color::from_rgb8(0xe0, 0xa0, 0x30)),
+        },
+        Level::Over => text::danger,
+    }
+}
+
+fn view_task(task: &TaskEntry) -> Element<'_, Message> {
+    let status = match &task.status {
+        TaskStatus::Queued => text("queued"),
+        TaskStatus::Running => text("running"),
+        TaskStatus::Saving => text("saving"),
+        TaskStatus::Done(_) => text("done").style(text::success),
+        TaskStatus::Failed(_) => text("failed").style(text::danger),
+    };
+    let elapsed = task
+        .elapsed()
+        .map_or(String::new(), |d| format!("{}s", d.as_secs()));
+
+    let mut entry = row![
+        status.width(Length::Fixed(60.0)),
+        text(elapsed).width(Length::Fixed(40.0)),
+        text(format!("{}x{}", task.size.0, task.size.1)).width(Length::Fixed(90.0)),
+        text(task.id).width(Length::Fixed(100.0)),
+        text(&task.snippet).width(Length::Fill),
+    ]
+    .spacing(8)
+    .align_y(Alignment::Center);
+    if !task.is_finished() {
+        entry = entry.push(button("Cancel").on_press(Message::Cancel(task.id)));
+    }
+
+    match &task.status {
+        TaskStatus::Done(_) => mouse_area(entry)
+            .on_press(Message::SelectTask(task.id))
+            .interaction(iced::mouse::Interaction::Pointer)
+            .into(),
+        TaskStatus::Failed(e) => column![
+            entry.push(button("Retry").on_press(Message::Retry(task.id))),
+            text(e).size(12).style(text::danger),
+        ]
+        .spacing(2)
+        .into(),
+        _ => entry.into(),
+    }
+}
+
+/// estimated time until the running and queued tasks are done
+fn queue_eta(state: &State) -> Option<Duration> {
+    let remaining = state
+        .tasks
+        .iter()
+        .filter(|t| {
+            matches!(
+                t.status,
+                TaskStatus::Queued | TaskStatus::Running | TaskStatus::Saving
+            )
+        })
+        .count();
+    state
+        .task_state
+        .timings
+        .eta(remaining, state.settings.concurrency)
+}
+
+/// the kept log records at the chosen level or above, newest at the bottom
+/// formats the records the log pane shows, which `view` would otherwise do every frame
+fn refresh_log(state: &mut State) {
+    state.log_version = Some(logs::version());
+    let records = logs::records(state.log_level, &state.log_filter);
+    state.log_matched = records.len();
+    let newest = records.len().saturating_sub(LOG_LINES);
+    state.log_lines = records[newest..]
+        .iter()
+        .map(|r| (r.level, r.to_string()))
+        .collect();
+}
+
+fn view_log(state: &State) -> Element<'_, Message> {
+    let controls = row![
+        pick_list(logs::Level::ALL, Some(state.log_level), Message::LogLevel).text_size(12),
+        text_input("filter", &state.log_filter)
+            .on_input(Message::LogFilter)
+            .size(12),
+        button(text("Copy").size(12))
+            .style(button::secondary)
+            .on_press(Message::CopyLog),
+        button(text("Clear").size(12))
+            .style(button::secondary)
+            .on_press(Message::ClearLog),
+    ]
+    .spacing(4)
+    .align_y(Alignment::Center);
+
+    let mut lines = Column::with_capacity(state.log_lines.len() + 1);
+    let older = state.log_matched - state.log_lines.len();
+    if older > 0 {
+        lines = lines.push(
+            text(format!("{} older lines, copy to see them", older))
+                .size(12)
+                .style(text::secondary),
+        );
+    }
+    for (level, line) in &state.log_lines {
+        let line = text(line).size(12).font(Font::MONOSPACE);
+        lines = lines.push(match level {
+            logs::Level::Debug => line.style(text::secondary),
+            logs::Level::Info => line,
+            logs::Level::Warn => line.style(text::primary),
+            logs::Level::Error => line.style(text::danger),
+        });
+    }
+
+    column![
+        controls,
+        scrollable(lines)
+            .anchor_bottom()
+            .width(Length::Fill)
+            .height(Length::Fill)
+    ]
+    .spacing(4)
+    .into()
+}
+
+fn view_status_bar(state: &State) -> Element<'_, Message> {
+    let tasks = &state.task_state;
+    let running = state
+        .tasks
+        .iter()
+        .filter(|t| matches!(t.status, TaskStatus::Running | TaskStatus::Saving))
+        .count();
+    let queued = state
+        .tasks
+        .iter()
+        .filter(|t| t.status == TaskStatus::Queued)
+        .count();
+
+    let mut bar = row![
+        text(tasks.status.to_string()),
+        text(format!(
+            "running {}  queued {}  done {}",
+            running, queued, tasks.completed
+        )),
+        text(match tasks.anlas {
+            Some(anlas) => format!("Anlas {}", anlas),
+            None => "Anlas ?".to_string(),
+        }),
+        text(match (tasks.timings.average(), queue_eta(state)) {
+            (Some(average), Some(eta)) if running + queued > 0 => format!(
+                "avg {}  ETA {}",
+                format_duration(average),
+                format_duration(eta)
+            ),
+            (Some(average), _) => format!("avg {}", format_duration(average)),
+            (None, _) => "avg ?".to_string(),
+        }),
+        text(format!(
+            "output {}",
+            state.settings.output_dir.to_string_lossy()
+        )),
+    ]
+    .spacing(24)
+    .align_y(Alignment::Center);
+    bar = bar.push_maybe(
+        tasks
+            .last_error
+            .as_ref()
+            .map(|e| text(format!("last error: {}", e)).style(text::danger)),
+    );
+
+    container(bar.push(widget::horizontal_space()))
+        .padding([4, 14])
+        .width(Length::Fill)
+        .style(container::rounded_box)
+        .into()
+}
+
+/// the built-in themes offered in the pickers followed by the custom ones
+fn theme_options(state: &State) -> Vec<Theme> {
+    THEMES.iter().chain(&state.custom_themes).cloned().collect()
+}
+
+fn view_settings<'a>(state: &State, form: &'a SettingsForm) -> Element<'a, Message> {
+    let themes = theme_options(state);
+    let field = |label: &'static str, input: Element<'static, Message>| {
+        row![text(label).width(Length::Fixed(160.0)), input]
+            .spacing(8)
+            .align_y(Alignment::Center)
+    };
+
+    let theme = pick_list(themes.clone(), Some(form.theme.clone()), |t| {
+        Message::EditSettings(SettingsEdit::Theme(t))
+    });
+    let theme_mode = pick_list(
+        [ThemeMode::Manual, ThemeMode::System],
+        Some(form.theme_mode),
+        |m| Message::EditSettings(SettingsEdit::ThemeMode(m)),
+    );
+    let light_theme = pick_list(themes.clone(), Some(form.light_theme.clone()), |t| {
+        Message::EditSettings(SettingsEdit::LightTheme(t))
+    });
+    let dark_theme = pick_list(themes, Some(form.dark_theme.clone()), |t| {
+        Message::EditSettings(SettingsEdit::DarkTheme(t))
+    });
+    let shape = pick_list(
+        [
+            ImageShape::Portrait,
+            ImageShape::Landscape,
+            ImageShape::Square,
+        ],
+        Some(form.shape),
+        |s| Message::EditSettings(SettingsEdit::Shape(s)),
+    );
+    let concurrency = text_input("1", &form.concurrency)
+        .on_input(|s| Message::EditSettings(SettingsEdit::Concurrency(s)));
+    let cooldown = text_input("1250", &form.cooldown_ms)
+        .on_input(|s| Message::EditSettings(SettingsEdit::Cooldown(s)));
+    let output_dir = text_input("output folder", &form.output_dir)
+        .on_input(|s| Message::EditSettings(SettingsEdit::OutputDir(s)));
+    let always_confirm = checkbox("", form.always_confirm)
+        .on_toggle(|b| Message::EditSettings(SettingsEdit::AlwaysConfirm(b)));
+    let retention_on_startup = checkbox("", form.retention_on_startup)
+        .on_toggle(|b| Message::EditSettings(SettingsEdit::RetentionOnStartup(b)));
+    let max_age_days = text_input("no limit", &form.max_age_days)
+        .on_input(|s| Message::EditSettings(SettingsEdit::MaxAgeDays(s)));
+    let max_size_gb = text_input("no limit", &form.max_size_gb)
+        .on_input(|s| Message::EditSettings(SettingsEdit::MaxSizeGb(s)));
+    let retention_action = pick_list(
+        [RetentionAction::Trash, RetentionAction::Archive],
+        Some(form.retention_action),
+        |a| Message::EditSettings(SettingsEdit::RetentionAction(a)),
+    );
+    let archive_dir = text_input("archive folder", &form.archive_dir)
+        .on_input(|s| Message::EditSettings(SettingsEdit::ArchiveDir(s)));
+    let ui_scale = text_input("1.0", &form.ui_scale)
+        .on_input(|s| Message::EditSettings(SettingsEdit::UiScale(s)));
+    let text_size = text_input("16", &form.text_size)
+        .on_input(|s| Message::EditSettings(SettingsEdit::TextSize(s)));
+    let image_cache = row![
+        text_input("512", &form.image_cache_mb)
+            .on_input(|s| Message::EditSettings(SettingsEdit::ImageCacheMb(s))),
+        text(format!(
+            "{} open, {:.0} MiB",
+            state.files.cache.len(),
+            state.files.cache.bytes() as f64 / (1024.0 * 1024.0)
+        ))
+        .size(12),
+        button(text("Clear caches"))
+            .style(button::secondary)
+            .on_press(Message::ClearCaches),
+    ]
+    .spacing(8)
+    .align_y(Alignment::Center);
+
+    container(
+        column![
+            text(format!("Settings ({})", Settings::path().to_string_lossy())),
+            field("Theme mode", theme_mode.into()),
+            field("Theme", theme.into()),
+            field("Light theme", light_theme.into()),
+            field("Dark theme", dark_theme.into()),
+            field("Scale", ui_scale.into()),
+            field("Text size (restart)", text_size.into()),
+            field("Image cache (MiB)", image_cache.into()),
+            field("Default shape", shape.into()),
+            field("Concurrent requests", concurrency.into()),
+            field("Cooldown (ms)", cooldown.into()),
+            field("Output folder", output_dir.into()),
+            field("Always confirm deletes", always_confirm.into()),
+            text("Cleanup of old outputs (favorites are kept)"),
+            field("Plan on startup", retention_on_startup.into()),
+            field("Older than (days)", max_age_days.into()),
+            field("Output folder above (GB)", max_size_gb.into()),
+            field("Cleanup action", retention_action.into()),
+            field("Archive folder", archive_dir.into()),
+            row![
+                button(text("Save")).on_press(Message::SaveSettings),
+                button(text("Clean up now..."))
+                    .style(button::secondary)
+                    .on_press(Message::PlanRetention(false)),
+                button(text("Cancel"))
+                    .style(button::secondary)
+                    .on_press(Message::CloseSettings),
+            ]
+            .spacing(8),
+        ]
+        .spacing(8),
+    )
+    .padding(16)
+    .width(Length::Fixed(600.0))
+    .style(container::rounded_box)
+    .into()
+}
+
+// files listed in the cleanup report, the rest is only counted
+const RETENTION_REPORT_FILES: usize = 200;
+
+fn view_retention(plan: &RetentionPlan) -> Element<'_, Message> {
+    let target = match plan.action {
+        RetentionAction::Trash => "to the trash".to_string(),
+        RetentionAction::Archive => format!("to {}", plan.archive_dir.to_string_lossy()),
+    };
+    let summary = text(format!(
+        "Dry run: {} outputs ({:.1} MB) would be moved {}",
+        plan.outputs.len(),
+        plan.bytes() as f64 / (1024.0 * 1024.0),
+        target
+    ));
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut files = plan.outputs.iter().take(RETENTION_REPORT_FILES).fold(
+        Column::new().spacing(2),
+        |col, output| {
+            col.push(
+                text(format!(
+                    "{}  {} days old  {} KB",
+                    output.path.to_string_lossy(),
+                    now.saturating_sub(output.mtime) / (24 * 60 * 60),
+                    output.size / 1024
+                ))
+                .size(12),
+            )
+        },
+    );
+    if plan.outputs.len() > RETENTION_REPORT_FILES {
+        files = files.push(
+            text(format!(
+                "and {} more",
+                plan.outputs.len() - RETENTION_REPORT_FILES
+            ))
+            .size(12),
+        );
+    }
+
+    container(
+        column![
+            summary,
+            scrollable(files).height(Length::Fixed(400.0)),
+            row![
+                button(text("Apply"))
+                    .style(button::danger)
+                    .on_press(Message::ApplyRetention),
+                button(text("Cancel"))
+                    .style(button::secondary)
+                    .on_press(Message::CloseRetention),
+            ]
+            .spacing(8),
+        ]
+        .spacing(12),
+    )
+    .padding(16)
+    .width(Length::Fixed(800.0))
+    .style(container::rounded_box)
+    .into()
+}
+
+fn view_inspector(inspector: &Inspector) -> Element<'_, Message> {
+    let mut rows = Column::new().spacing(2);
+    rows = view_json_node(
+        rows,
+        inspector,
+        "metadata",
+        &inspector.metadata,
+        String::new(),
+        0,
+    );
+
+    let title = text(
+        inspector
+            .path
+            .file_name()
+            .map_or(String::new(), |n| n.to_string_lossy().to_string()),
+    );
+    let verify = button(text("Verify")).on_press(Message::VerifyImage);
+    let close = button(text("Close")).on_press(Message::CloseInspector);
+
+    let integrity = inspector.integrity.as_ref().map(|integrity| {
+        let (report, style): (String, fn(&Theme) -> text::Style) = match integrity {
+            Ok(integrity) if integrity.payload.is_ok() => (integrity.to_string(), text::success),
+            Ok(integrity) => (integrity.to_string(), text::danger),
+            Err(e) => (e.clone(), text::danger),
+        };
+        text(report).style(style)
+    });
+
+    container(
+        column![
+            row![title, widget::horizontal_space(), verify, close]
+                .spacing(8)
+                .align_y(Alignment::Center),
+        ]
+        .push_maybe(integrity)
+        .push(scrollable(rows).height(Length::Fixed(600.0)))
+        .spacing(8),
+    )
+    .padding(16)
+    .width(Length::Fixed(800.0))
+    .style(container::rounded_box)
+    .into()
+}
+
+/// one row per node; objects and arrays can be folded and every node has a copy button
+fn view_json_node<'a>(
+    mut rows: Column<'a, Message>,
+    inspector: &'a Inspector,
+    key: &str,
+    value: &'a Value,
+    pointer: String,
+    depth: usize,
+) -> Column<'a, Message> {
+    let indent = widget::Space::with_width(Length::Fixed(depth as f32 * 16.0));
+    let copy = button(text("copy").size(12))
+        .style(button::text)
+        .padding([0, 4])
+        .on_press(Message::InspectorCopy(match value {
+            Value::String(s) => s.clone(),
+            Value::Object(_) | Value::Array(_) => {
+                serde_json::to_string_pretty(value).unwrap_or_default()
+            }
+            other => other.to_string(),
+        }));
+
+    let children: Vec<(String, &Value)> = match value {
+        Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        Value::Array(arr) => arr
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v))
+            .collect(),
+        _ => {
+            let row = row![
+                indent,
+                text(format!("{}:", key)).style(text::primary),
+                text(
+                    value
+                        .as_str()
+                        .map_or_else(|| value.to_string(), String::from)
+                ),
+                copy
+            ]
+            .spacing(6);
+            return rows.push(row);
+        }
+    };
+
+    let expanded = inspector.expanded.contains(&pointer);
+    let toggle = button(text(if expanded { "▾" } else { "▸" }))
+        .style(button::text)
+        .padding([0, 4])
+        .on_press(Message::InspectorToggle(pointer.clone()));
+    rows = rows.push(
+        row![
+            indent,
+            toggle,
+            text(format!("{} ({})", key, children.len())).style(text::primary),
+            copy
+        ]
+        .spacing(6)
+        .align_y(Alignment::Center),
+    );
+
+    if expanded {
+        for (child_key, child) in children {
+            // JSON pointer escaping
+            let child_pointer = format!(
+                "{}/{}",
+                pointer,
+                child_key.replace('~', "~0").replace('/', "~1")
+            );
+            rows = view_json_node(rows, inspector, &child_key, child, child_pointer, depth + 1);
+        }
+    }
+    rows
+}
+
+fn view_review<'a>(state: &'a State, review: &'a Review) -> Element<'a, Message> {
+    let path = &review.paths[review.index];
+
+    let mut status = format!("{}/{}", review.index + 1, review.paths.len());
+    if state.favorites.contains(path) {
+        status.push_str("  ★");
+    }
+    if let Some((seed, _)) = &review.info {
+        status.push_str(&format!("  seed {}", seed));
+    }
+    if let Some(name) = path.file_name() {
+        status.push_str(&format!("  {}", name.to_string_lossy()));
+    }
+
+    let prompt = review
+        .info
+        .as_ref()
+        .map(|(_, prompt)| text(prompt).size(14));
+    let keys = text("←/→ step   f favorite   x trash   esc close").size(12);
+
+    container(
+        column![
+            center(Image::new(Handle::from_path(path))).height(Length::Fill),
+            text(status),
+        ]
+        .push_maybe(prompt)
+        .push(keys)
+        .spacing(6)
+        .padding(12),
+    )
+    .width(Length::Fill)
+    .height(Length::Fill)
+    .style(|_theme| container::Style {
+        background: Some(Color::BLACK.into()),
+        text_color: Some(Color::WHITE),
+        ..container::Style::default()
+    })
+    .into()
+}
+
+// keys handled in place rather than through keymap.toml, listed after the keymap sections
+const FIXED_KEYS: [(&str, &[(&str, &str)]); 5] = [
+    (
+        "prompts",
+        &[
+            ("ctrl+up, ctrl+down", "adjust weight"),
+            ("tab, enter", "accept suggestion"),
+            ("up, down", "pick suggestion"),
+            ("escape", "dismiss suggestions"),
+        ],
+    ),
+    (
+        "files: gallery mode",
+        &[
+            ("arrows, h j k l", "move"),
+            ("enter", "select"),
+            ("f", "favorite"),
+            ("shift+f", "favorites only"),
+            ("escape, v", "back to normal mode"),
+        ],
+    ),
+    (
+        "files: other modes",
+        &[
+            ("escape", "back to normal mode"),
+            ("f, then a character", "jump (normal mode)"),
+        ],
+    ),
+    (
+        "review",
+        &[
+            ("left, right, h, l, space", "step"),
+            ("f", "favorite"),
+            ("x, delete", "trash"),
+            ("escape, q", "close"),
+        ],
+    ),
+    ("fullscreen", &[("escape, q", "close")]),
+];
+
+/// every shortcut by pane and mode, the rebindable ones as currently bound
+fn view_help(keymap: &Keymap) -> Element<'_, Message> {
+    let keys = |bindings: Vec<(String, String)>| {
+        let mut col = Column::new().spacing(2);
+        for (keys, action) in bindings {
+            col = col.push(
+                row![
+                    text(keys)
+                        .size(12)
+                        .font(Font::MONOSPACE)
+                        .width(Length::Fixed(180.0)),
+                    text(action).size(12)
+                ]
+                .spacing(8),
+            );
+        }
+        col
+    };
+
+    let mut sections = Column::new().spacing(12);
+    for (section, bindings) in keymap.help() {
+        sections = sections.push(column![text(section), keys(bindings)].spacing(4));
+    }
+    for (section, bindings) in FIXED_KEYS {
+        let bindings = bindings
+            .iter()
+            .map(|(k, a)| (k.to_string(), a.to_string()))
+            .collect();
+        sections = sections.push(column![text(section), keys(bindings)].spacing(4));
+    }
+
+    container(
+        column![
+            text("Keyboard shortcuts (? or escape to close)"),
+            scrollable(sections).height(Length::Fixed(500.0)),
+        ]
+        .spacing(12),
+    )
+    .padding(16)
+    .width(Length::Fixed(520.0))
+    .style(container::rounded_box)
+    .into()
+}
+
+fn view_crop(cropping: &Cropping) -> Element<'_, Message> {
+    let image = &cropping.image;
+    let selected = match cropping.region {
+        Some(r) => format!("{}x{} at {}, {}", r.width, r.height, r.x, r.y),
+        None => "drag over the image to select a region".into(),
+    };
+    column![
+        Crop::new(
+            image.handle.clone(),
+            image.size.unwrap_or((1, 1)),
+            cropping.region
+        ),
+        row![
+            text(selected).size(12),
+            button(text("Export PNG")).on_press_maybe(cropping.region.map(|_| Message::ExportCrop)),
+            button(text("Cancel"))
+                .style(button::secondary)
+                .on_press(Message::ToggleCrop),
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center),
+    ]
+    .spacing(4)
+    .into()
+}
+
+fn view_fullscreen(shown: &ShownImage) -> Element<'_, Message> {
+    mouse_area(
+        center(Image::new(shown.handle.clone()))
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .style(|_theme| container::Style {
+                background: Some(Color::BLACK.into()),
+                ..container::Style::default()
+            }),
+    )
+    .on_right_press(Message::CloseFullscreen)
+    .into()
+}
+
+fn view_confirm(action: &PendingAction) -> Element<'_, Message> {
+    let prompt = match action {
+        PendingAction::Delete(path) => format!("Delete {:?}?", path),
+        PendingAction::DeleteBatch(count) => format!("Delete {} marked entries?", count),
+        PendingAction::MoveBatch(conflicts) => conflicts
+            .iter()
+            .fold(String::from("Moving will overwrite:"), |acc, path| {
+                format!("{}\n{}", acc, path.to_string_lossy())
+            }),
+    };
+
+    container(
+        column![
+            text(prompt),
+            row![
+                button(text("Confirm (y)"))
+                    .style(button::danger)
+                    .on_press(Message::ConfirmAccept),
+                button(text("Cancel (n)"))
+                    .style(button::secondary)
+                    .on_press(Message::ConfirmCancel),
+            ]
+            .spacing(8),
+        ]
+        .spacing(12),
+    )
+    .padding(16)
+    .max_width(600)
+    .style(container::rounded_box)
+    .into()
+}
+
+/// draws `content` centered above a dimmed `base`; clicking outside of it sends `on_blur`
+fn modal<'a>(
+    base: impl Into<Element<'a, Message>>,
+    content: impl Into<Element<'a, Message>>,
+    on_blur: Message,
+) -> Element<'a, Message> {
+    widget::stack![
+        base.into(),
+        opaque(
+            mouse_area(center(opaque(content)).style(|_theme| {
+                container::Style {
+                    background: Some(
+                        Color {
+                            a: 0.8,
+                            ..Color::BLACK
+                        }
+                        .into(),
+                    ),
+                    ..container::Style::default()
+                }
+            }))
+            .on_press(on_blur)
+        )
+    ]
+    .into()
 }
 
 fn view_files(state: &State) -> Element<Message> {
@@ -1019,19 +4081,8 @@ fn view_files(state: &State) -> Element<Message> {
     let slice = &state.files.visible[state.files.view_offset..end];
 
     let theme_selector = pick_list(
-        [
-            Theme::CatppuccinLatte,
-            Theme::CatppuccinFrappe,
-            Theme::CatppuccinMacchiato,
-            Theme::CatppuccinMocha,
-            Theme::TokyoNight,
-            Theme::TokyoNightStorm,
-            Theme::TokyoNightLight,
-            Theme::KanagawaWave,
-            Theme::KanagawaDragon,
-            Theme::KanagawaLotus,
-        ],
-        Some(&state.selected_theme),
+        theme_options(state),
+        Some(state.selected_theme.clone()),
         Message::SelectedTheme,
     );
 
@@ -1041,6 +4092,10 @@ fn view_files(state: &State) -> Element<Message> {
         .fold(Column::new(), |col, (_idx, v)| {
             let entry = &state.files.entries[v.id];
             let label = match entry.kind {
+                // extra roots can live anywhere, so show where
+                EntryKind::Folder if state.files.extra_roots.contains(&v.id) => {
+                    format!("D  {}", entry.path.to_string_lossy())
+                }
                 EntryKind::Folder => {
                     format!(
                         "{}D  {}",
@@ -1048,6 +4103,17 @@ fn view_files(state: &State) -> Element<Message> {
                         entry.path.file_name().unwrap().to_string_lossy()
                     )
                 }
+                EntryKind::Symlink => {
+                    format!(
+                        "{}L  {} -> {}",
+                        "  ".repeat(v.depth),
+                        entry.path.file_name().unwrap().to_string_lossy(),
+                        entry
+                            .target
+                            .as_ref()
+                            .map_or("?".into(), |t| t.to_string_lossy())
+                    )
+                }
                 _ => {
                     format!(
                         "{}F  {}",
@@ -1068,7 +4134,7 @@ fn view_files(state: &State) -> Element<Message> {
                 text::default
             };
 
-            col.push(text(label).style(style))
+            col.push(mouse_area(text(label).style(style)).on_press(Message::DragStart(v.id)))
         })
         .padding(4)
         .spacing(2);
@@ -1083,9 +4149,67 @@ fn view_files(state: &State) -> Element<Message> {
         None
     });
 
+    if state.files_mode == FilesMode::Search {
+        col = state.search_results.iter().fold(
+            column![
+                text_input("search prompts", &state.search_query)
+                    .on_input(Message::EditSearchQuery)
+                    .on_submit(Message::SubmitSearch)
+            ]
+            .padding(4)
+            .spacing(2),
+            |col, path| {
+                let label = path
+                    .strip_prefix(PROJECT_DIRS.data_dir())
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .to_string();
+                col.push(
+                    button(text(label))
+                        .style(button::text)
+                        .padding(0)
+                        .on_press(Message::RevealPath(path.clone())),
+                )
+            },
+        );
+    }
+
+    if state.files_mode == FilesMode::Duplicates {
+        col = view_duplicates(state);
+    }
+    if state.files_mode == FilesMode::Gallery {
+        col = view_gallery(state);
+    }
+
     let mode = text(state.files_mode.to_string());
 
-    let mut all = column![theme_selector, col, mode];
+    let file_ops = state.file_ops.as_ref().map(|progress| {
+        let finished = progress.done + progress.failed;
+        row![
+            progress_bar(0.0..=progress.total as f32, finished as f32).height(Length::Fixed(8f32)),
+            text(format!("{}/{}", finished, progress.total)),
+            button(text("Cancel")).on_press(Message::CancelFileOps),
+        ]
+        .spacing(4)
+        .align_y(Alignment::Center)
+    });
+
+    let always_confirm = checkbox("Always confirm deletes", state.always_confirm)
+        .on_toggle(Message::ToggleAlwaysConfirm);
+
+    let mut all = column![
+        row![
+            theme_selector,
+            always_confirm,
+            button(text("Settings")).on_press(Message::OpenSettings)
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center),
+        view_breadcrumbs(state),
+        col,
+        mode
+    ];
+    all = all.push_maybe(file_ops);
     all = all.push_maybe(if let Some(k) = &state.last_key {
         Some(text(format!("{:?} {:?}", k.0, k.1)))
     } else {
@@ -1095,31 +4219,141 @@ fn view_files(state: &State) -> Element<Message> {
     scrollable(all.padding(2).spacing(4)).into()
 }
 
+fn view_breadcrumbs(state: &State) -> Element<'_, Message> {
+    let root = state.files.root_path();
+    let mut ancestors: Vec<&std::path::Path> = root.ancestors().collect();
+    ancestors.reverse();
+
+    ancestors
+        .into_iter()
+        .enumerate()
+        .fold(row![], |row, (i, path)| {
+            let label = path
+                .file_name()
+                .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy())
+                .to_string();
+            let row = if i > 1 { row.push(text("/")) } else { row };
+            let segment = button(text(label)).style(button::text).padding(0);
+
+            // the current root is not a link
+            row.push(if path == root {
+                segment
+            } else {
+                segment.on_press(Message::SetRootPath(path.to_path_buf()))
+            })
+        })
+        .spacing(2)
+        .wrap()
+        .into()
+}
+
+fn view_gallery(state: &State) -> Column<'_, Message> {
+    let favorites_only = checkbox("Favorites only (shift + f)", state.favorites_only)
+        .on_toggle(Message::ToggleFavoritesOnly);
+    state
+        .gallery
+        .chunks(GALLERY_COLUMNS)
+        .enumerate()
+        .fold(column![favorites_only], |col, (r, ids)| {
+            let cells = ids.iter().enumerate().fold(row![], |row, (c, &id)| {
+                let index = r * GALLERY_COLUMNS + c;
+                let style = if index == state.gallery_index {
+                    container::bordered_box
+                } else {
+                    container::rounded_box
+                };
+
+                let path = &state.files.entries[id].path;
+                let im = Image::new(state.gallery_thumbnails[index].clone()).width(Length::Fill);
+                row.push(widget::stack![
+                    mouse_area(container(im).padding(2).style(style))
+                        .on_press(Message::GallerySelect(index)),
+                    favorite_toggle(state, path)
+                ])
+            });
+
+            // pad the last row so its cells keep the same width
+            let cells = (ids.len()..GALLERY_COLUMNS)
+                .fold(cells, |row, _| row.push(widget::horizontal_space()));
+            col.push(cells.spacing(4))
+        })
+        .padding(4)
+        .spacing(4)
+}
+
+/// a star in the top right corner of a thumbnail that toggles `path` as favorite
+fn favorite_toggle<'a>(state: &State, path: &Path) -> Element<'a, Message> {
+    let star = if state.favorites.contains(path) {
+        "★"
+    } else {
+        "☆"
+    };
+    let toggle = button(text(star).size(14))
+        .padding(2)
+        .style(widget::button::text)
+        .on_press(Message::ToggleFavorite(path.to_path_buf()));
+    container(toggle)
+        .width(Length::Fill)
+        .align_x(Alignment::End)
+        .into()
+}
+
+fn view_duplicates(state: &State) -> Column<'_, Message> {
+    state
+        .duplicates
+        .iter()
+        .enumerate()
+        .fold(Column::new(), |col, (group, g)| {
+            let thumbs = g
+                .paths
+                .iter()
+                .enumerate()
+                .fold(row![], |row, (index, path)| {
+                    let style = if index == g.keep {
+                        container::bordered_box
+                    } else {
+                        container::rounded_box
+                    };
+                    let im = Image::new(Handle::from_path(path)).width(Length::Fixed(96f32));
+                    row.push(
+                        mouse_area(container(im).padding(2).style(style))
+                            .on_press(Message::KeepDuplicate(group, index)),
+                    )
+                })
+                .spacing(4)
+                .wrap();
+
+            col.push(
+                column![
+                    thumbs,
+                    button(text(format!("Keep 1, Trash {}", g.paths.len() - 1)))
+                        .on_press(Message::TrashDuplicates(group)),
+                ]
+                .spacing(2),
+            )
+        })
+        .padding(4)
+        .spacing(8)
+}
+
 fn view_prompts(state: &State) -> Element<Message> {
-    let base_select = combo_box(
-        &state.base.options,
-        "base",
-        state.base.selected.as_ref(),
-        Message::BasePromptSelected,
-    );
-    let char_select = combo_box(
-        &state.char.options,
-        "character",
-        state.char.selected.as_ref(),
-        Message::CharacterPromptSelected,
-    );
-    let template_select = combo_box(
-        &state.template.options,
-        "template",
-        state.template.selected.as_ref(),
-        Message::TemplateSelected,
-    );
+    let picker = |placeholder, query, kind| {
+        text_input(placeholder, query)
+            .on_input(move |s| Message::PromptQuery(kind, s))
+            .on_submit(Message::PromptQuerySubmit(kind))
+    };
+    let base_select = picker("base", &state.base.query, PromptKind::Base);
+    let char_select = picker("character", &state.char.query, PromptKind::Character);
+    let template_select = picker("template", &state.template.query, PromptKind::Template);
     let char_dropdown = pick_list(
         [1, 2, 3, 4, 5, 6],
         Some(state.curr_char + 1),
         Message::CharSelected,
     );
     let select_prompt = row![char_dropdown, base_select, char_select, template_select];
+    let prompt_matches = view_prompt_matches(&state.base)
+        .or_else(|| view_prompt_matches(&state.char))
+        .or_else(|| view_prompt_matches(&state.template));
 
     let base_rename = text_input("rename base_prompt", &state.base.rename)
         .on_input(Message::EditRenameBasePrompt)
@@ -1154,69 +4388,156 @@ fn view_prompts(state: &State) -> Element<Message> {
     ]
     .align_x(Alignment::Start);
 
+    let suggesting = |editor| {
+        state
+            .suggestions
+            .as_ref()
+            .is_some_and(|s| s.editor == editor)
+    };
+
     let mut text_areas = Column::with_capacity(7).spacing(10);
+    let active = suggesting(PromptEditor::Base);
     text_areas = text_areas.push(
         widget::text_editor(&state.base_prompt)
             .placeholder("base prompt")
-            .on_action(Message::EditBasePrompt),
+            .on_action(Message::EditBasePrompt)
+            .highlight_with::<WeightHighlighter>((), Highlight::to_format)
+            .key_binding(move |key_press| {
+                prompt_binding(PromptEditor::Base, active, &state.keymap, key_press)
+            }),
     );
+    text_areas = text_areas.push_maybe(view_suggestions(state, PromptEditor::Base));
+    text_areas = text_areas.push(view_prompt_stats(&state.base_stats));
     for (i, cc) in state.character_prompts.iter().enumerate() {
+        let active = suggesting(PromptEditor::Character(i));
+        text_areas = text_areas.push(
+            row![
+                checkbox(format!("Character {}", i + 1), cc.c.is_enabled())
+                    .on_toggle(move |b| Message::EnableCharacter(i, b)),
+                button(text("Clear").size(12))
+                    .style(button::secondary)
+                    .padding([0, 6])
+                    .on_press(Message::ClearCharacter(i)),
+                button(text("↑").size(12))
+                    .style(button::secondary)
+                    .padding([0, 6])
+                    .on_press_maybe((i > 0).then_some(Message::MoveCharacter(i, -1))),
+                button(text("↓").size(12))
+                    .style(button::secondary)
+                    .padding([0, 6])
+                    .on_press_maybe(
+                        (i + 1 < state.character_prompts.len())
+                            .then_some(Message::MoveCharacter(i, 1)),
+                    ),
+            ]
+            .spacing(8)
+            .align_y(Alignment::Center),
+        );
         text_areas = text_areas.push(
             widget::text_editor(&cc.content)
                 .placeholder("")
-                .on_action(move |action| Message::EditCharPrompt((i, action))),
+                .on_action(move |action| Message::EditCharPrompt((i, action)))
+                .highlight_with::<WeightHighlighter>((), Highlight::to_format)
+                .key_binding(move |key_press| {
+                    prompt_binding(PromptEditor::Character(i), active, &state.keymap, key_press)
+                }),
         );
+        text_areas = text_areas.push_maybe(view_suggestions(state, PromptEditor::Character(i)));
+        text_areas = text_areas.push(view_prompt_stats(&cc.stats));
     }
 
-    use Position::*;
-    let position_grid = column![
-        row![
-            button("*").on_press(Message::SetPosition(R0C0)),
-            button("*").on_press(Message::SetPosition(R0C1)),
-            button("*").on_press(Message::SetPosition(R0C2)),
-            button("*").on_press(Message::SetPosition(R0C3)),
-            button("*").on_press(Message::SetPosition(R0C4))
-        ]
-        .spacing(4),
-        row![
-            button("*").on_press(Message::SetPosition(R1C0)),
-            button("*").on_press(Message::SetPosition(R1C1)),
-            button("*").on_press(Message::SetPosition(R1C2)),
-            button("*").on_press(Message::SetPosition(R1C3)),
-            button("*").on_press(Message::SetPosition(R1C4))
-        ]
-        .spacing(4),
-        row![
-            button("*").on_press(Message::SetPosition(R2C0)),
-            button("*").on_press(Message::SetPosition(R2C1)),
-            button("*").on_press(Message::SetPosition(R2C2)),
-            button("*").on_press(Message::SetPosition(R2C3)),
-            button("*").on_press(Message::SetPosition(R2C4))
-        ]
-        .spacing(4),
-        row![
-            button("*").on_press(Message::SetPosition(R3C0)),
-            button("*").on_press(Message::SetPosition(R3C1)),
-            button("*").on_press(Message::SetPosition(R3C2)),
-            button("*").on_press(Message::SetPosition(R3C3)),
-            button("*").on_press(Message::SetPosition(R3C4))
-        ]
-        .spacing(4),
-        row![
-            button("*").on_press(Message::SetPosition(R4C0)),
-            button("*").on_press(Message::SetPosition(R4C1)),
-            button("*").on_press(Message::SetPosition(R4C2)),
-            button("*").on_press(Message::SetPosition(R4C3)),
-            button("*").on_press(Message::SetPosition(R4C4))
-        ]
-        .spacing(4),
-    ]
-    .spacing(4);
+    // the limit applies to the base and the enabled character prompts together
+    let total = state.base_stats.tokens()
+        + state
+            .character_prompts
+            .iter()
+            .filter(|cc| cc.c.is_enabled())
+            .map(|cc| cc.stats.tokens())
+            .sum::<usize>();
+    text_areas = text_areas
+        .push(text(format!("total ~{} / {} tokens", total, TOKEN_LIMIT)).style(token_style(total)));
+    let mut negative = Row::new().push(text("Negative").size(12)).spacing(8);
+    for group in NegativeGroup::ALL {
+        negative = negative.push(
+            checkbox(
+                group.to_string(),
+                state.settings.negative_groups.contains(&group),
+            )
+            .text_size(12)
+            .on_toggle(move |b| Message::ToggleNegativeGroup(group, b)),
+        );
+    }
+    text_areas = text_areas.push(negative.align_y(Alignment::Center).wrap());
+    // the request preview shows it as well
+    if !state.request_preview {
+        text_areas = text_areas.push(
+            text(negative_prompt(&state.settings.negative_groups))
+                .size(11)
+                .style(text::secondary),
+        );
+    }
+    text_areas = text_areas.push(
+        checkbox("Preview request", state.request_preview).on_toggle(Message::ToggleRequestPreview),
+    );
+    if state.request_preview {
+        text_areas = text_areas.push(view_request_preview(&build_request(state)));
+    }
 
-    let curr_position: Position = state.character_prompts[state.curr_char].c.center.into();
-    let position_info = row![position_grid, text(curr_position.to_string())]
-        .align_y(Alignment::Center)
-        .spacing(8);
+    // characters that will be sent, and the one being edited even if it is still empty
+    let placed: Vec<(usize, Point)> = state
+        .character_prompts
+        .iter()
+        .enumerate()
+        .filter(|(i, cc)| *i == state.curr_char || (cc.c.is_enabled() && cc.content.text() != "\n"))
+        .map(|(i, cc)| (i, cc.c.center))
+        .collect();
+
+    // each cell lists the characters placed in it, the active character's cell is highlighted
+    let active_cell = state.character_prompts[state.curr_char].c.center.cell();
+    let mut position_grid = Column::new().spacing(4);
+    for (r, positions) in Position::GRID.iter().enumerate() {
+        let mut cells = Row::new().spacing(4);
+        for (c, pos) in positions.iter().enumerate() {
+            let spans: Vec<text::Span<'_, Message>> = placed
+                .iter()
+                .filter(|(_, center)| center.cell() == (r, c))
+                .map(|(i, _)| text::Span::new((i + 1).to_string()).color(SLOT_COLORS[*i]))
+                .collect();
+            let label: Element<Message> = if spans.is_empty() {
+                text("*").into()
+            } else {
+                widget::rich_text(spans).into()
+            };
+            cells = cells.push(
+                button(center(label))
+                    .width(Length::Fixed(36.0))
+                    .style(if (r, c) == active_cell {
+                        button::primary
+                    } else {
+                        button::secondary
+                    })
+                    .on_press(Message::SetPosition(*pos)),
+            );
+        }
+        position_grid = position_grid.push(cells);
+    }
+
+    let (w, h) = state.image_shape.as_width_height();
+    let placement = Canvas::new(Placement {
+        dots: placed,
+        selected: state.curr_char,
+    })
+    .width(CANVAS_WIDTH)
+    .height(CANVAS_WIDTH * h as f32 / w as f32);
+
+    let curr_center = state.character_prompts[state.curr_char].c.center;
+    let position_info = row![
+        position_grid,
+        placement,
+        text(format!("x {:.2}\ny {:.2}", curr_center.x, curr_center.y))
+    ]
+    .align_y(Alignment::Center)
+    .spacing(8);
 
     let current_seed = text(state.current_seed.unwrap_or_default());
     let copy_seed = button(text("Use Previous Seed")).on_press(Message::CopySeed);
@@ -1224,6 +4545,9 @@ fn view_prompts(state: &State) -> Element<Message> {
     let seed = row![text("Seed: "), current_seed, copy_seed, clear_seed]
         .align_y(Alignment::Center)
         .spacing(4);
+    let seed = column![seed]
+        .push_maybe(view_seed_history(state))
+        .spacing(4);
 
     let orientation = pick_list(
         [
@@ -1251,17 +4575,42 @@ fn view_prompts(state: &State) -> Element<Message> {
     .spacing(4)
     .align_y(Alignment::Center);
 
-    let all_controls = column![num_images, generate_controls].spacing(4);
+    let timings = &state.task_state.timings;
+    let timing_stats = match (
+        timings.average(),
+        timings.percentile(50.0),
+        timings.percentile(90.0),
+    ) {
+        (Some(average), Some(p50), Some(p90)) => {
+            let mut stats = format!(
+                "last {}: avg {}  p50 {}  p90 {}",
+                timings.count(),
+                format_duration(average),
+                format_duration(p50),
+                format_duration(p90)
+            );
+            if let Some(eta) = queue_eta(state).filter(|eta| !eta.is_zero()) {
+                stats.push_str(&format!("  ETA {}", format_duration(eta)));
+            }
+            text(stats)
+        }
+        _ => text("no generation timed yet"),
+    }
+    .size(12);
+
+    let all_controls = column![num_images, generate_controls, timing_stats].spacing(4);
 
-    let mut ids = Column::with_capacity(state.task_ids.len());
-    for i in &state.task_ids {
-        ids = ids.push(row![
-            text(i),
-            button("Cancel").on_press(Message::Cancel(*i))
-        ]);
+    let mut ids = Column::with_capacity(state.tasks.len()).spacing(2);
+    for task in state.tasks.iter().rev() {
+        ids = ids.push(view_task(task));
     }
     let task_ids = scrollable(ids);
 
+    let select_prompt = column![]
+        .push_maybe(view_recent_prompts(state))
+        .push(select_prompt)
+        .push_maybe(prompt_matches);
+
     let mut content = Column::with_children([
         select_prompt.into(),
         rename.into(),
@@ -1295,31 +4644,166 @@ fn view_image(state: &State) -> Element<Message> {
             .map_or(None, |h| Some(Image::new(h).into()))
     };
 
-    let mut thumbs = Column::with_capacity(state.thumbnails.len()).align_x(Alignment::Center);
-    for (index, handle) in state.thumbnails.iter().enumerate() {
-        let style = if let Some(i) = state.selected_image {
-            if i == index {
+    let columns = state.settings.history_columns.clamp(1, MAX_HISTORY_COLUMNS);
+    let visible = visible_history(state);
+    // every row is as high as the thumbnails are long, so only the rows in view are built and
+    // the rest is empty space of the same height
+    let side = state.settings.thumbnail_size.max_side() as f32;
+    let row_height = side + HISTORY_SPACING;
+    let rows = visible.len().div_ceil(columns);
+    let (offset, height) = state
+        .history_viewport
+        .unwrap_or((0.0, HISTORY_INITIAL_HEIGHT));
+    let first = ((offset / row_height) as usize)
+        .saturating_sub(HISTORY_OVERSCAN)
+        .min(rows);
+    let last = (((offset + height) / row_height).ceil() as usize + HISTORY_OVERSCAN).min(rows);
+    let skipped = |rows: usize| {
+        widget::Space::with_height(Length::Fixed(rows as f32 * row_height - HISTORY_SPACING))
+    };
+
+    let mut thumbs = Column::with_capacity(last - first + 2)
+        .spacing(HISTORY_SPACING)
+        .align_x(Alignment::Center);
+    if first > 0 {
+        thumbs = thumbs.push(skipped(first));
+    }
+    for indices in visible[first * columns..(last * columns).min(visible.len())].chunks(columns) {
+        let mut thumb_row = Row::with_capacity(columns).spacing(HISTORY_SPACING);
+        for &index in indices {
+            let handle = &state.thumbnails[index];
+            let path = &state.image_paths[index];
+            let style = if state.selected_image == Some(index) {
                 container::bordered_box
             } else {
                 container::rounded_box
+            };
+
+            // thumbnails have more pixels than they cover on a scaled screen
+            let im = Image::new(handle)
+                .width(Length::Fixed(side))
+                .height(Length::Fixed(side));
+            let border = container(im)
+                .height(Length::Fixed(side))
+                .align_y(Alignment::Center)
+                .style(style);
+            let clickable = mouse_area(border)
+                .on_press(Message::ImageClicked(index))
+                .on_right_press(Message::MetadataFromImage(index))
+                .on_middle_press(Message::InspectImage(path.clone()));
+            thumb_row = thumb_row.push(widget::stack![clickable, favorite_toggle(state, path)]);
+        }
+        thumbs = thumbs.push(thumb_row);
+    }
+    if last < rows {
+        thumbs = thumbs.push(skipped(rows - last));
+    }
+
+    let copy =
+        button(text("Copy image")).on_press_maybe(state.selected_image.map(|_| Message::CopyImage));
+    let copy_metadata = button(text("Copy metadata")).on_press_maybe(
+        state
+            .selected_image
+            .and_then(|i| state.image_paths.get(i))
+            .map(|path| Message::CopyMetadata(path.clone())),
+    );
+    let size = state.settings.thumbnail_size;
+    let size_slider = row![
+        slider(
+            0..=ThumbnailSize::ALL.len() as u8 - 1,
+            ThumbnailSize::ALL
+                .iter()
+                .position(|s| *s == size)
+                .unwrap_or(0) as u8,
+            |i| Message::ThumbnailSize(ThumbnailSize::ALL[i as usize]),
+        )
+        .width(80),
+        text(size.to_string()).size(12),
+        pick_list(
+            (1..=MAX_HISTORY_COLUMNS).collect::<Vec<_>>(),
+            Some(columns),
+            Message::HistoryColumns
+        )
+        .text_size(12),
+        text("columns").size(12)
+    ]
+    .spacing(4)
+    .align_y(Alignment::Center);
+    let filter = text_input("seed, date, today, prompt...", &state.history_filter)
+        .on_input(Message::HistoryFilter)
+        .size(12)
+        .width(Length::Fixed(200.0));
+    let favorites_only =
+        checkbox("Favorites only", state.favorites_only).on_toggle(Message::ToggleFavoritesOnly);
+    let image_history = column![
+        row![
+            copy,
+            copy_metadata,
+            button(text("Crop")).on_press(Message::ToggleCrop),
+            button(text("Save as")).on_press(Message::OpenSaveAs(false)),
+            checkbox("with .json", state.save_sidecar).on_toggle(Message::SaveAsSidecar),
+            button(text("Export without metadata")).on_press(Message::OpenSaveAs(true)),
+        ]
+        .spacing(4),
+        size_slider,
+        filter,
+        favorites_only,
+        scrollable(thumbs)
+            .id(HISTORY_SCROLL.clone())
+            .on_scroll(Message::HistoryScrolled)
+    ]
+    .spacing(4)
+    .align_x(Alignment::Center);
+
+    if state.comparing
+        && let Some(pinned) = &state.pinned
+    {
+        let shown = shown_image(state);
+        if let Some(split) = state.swipe
+            && let Some(shown) = &shown
+        {
+            let swipe = Swipe::new(
+                pinned.handle.clone(),
+                shown.handle.clone(),
+                shown.size.unwrap_or((1, 1)),
+                split,
+            );
+            let name = |path: &PathBuf| {
+                path.file_name()
+                    .map_or(String::new(), |n| n.to_string_lossy().to_string())
+            };
+            let label = text(format!("{}  |  {}", name(&pinned.path), name(&shown.path)));
+            return row![column![label, swipe].spacing(4), image_history].into();
+        }
+        let compare = row![
+            view_compare_side(pinned, state.compare_zoom, CompareSide::Pinned),
+            match &shown {
+                Some(shown) => view_compare_side(shown, state.compare_zoom, CompareSide::Shown),
+                None => center(text("nothing to compare against")).into(),
             }
-        } else {
-            container::rounded_box
-        };
+        ]
+        .spacing(4);
+        return row![compare, image_history].into();
+    }
 
-        let im = Image::new(handle);
-        let border = container(im).style(style);
-        let clickable = mouse_area(border)
-            .on_press(Message::ImageClicked(index))
-            .on_right_press(Message::MetadataFromImage(index));
-        thumbs = thumbs.push(clickable);
+    if let Some(cropping) = &state.cropping {
+        return row![view_crop(cropping), image_history].into();
     }
 
     let final_image: Element<Message> = if let Some(image) = file_pane_image {
-        image
-    } else if !state.images.is_empty() {
+        match state.tree_prompt.as_ref().and_then(|t| t.prompt.as_ref()) {
+            Some((prompt, characters)) => column![
+                container(image).height(Length::FillPortion(3)),
+                scrollable(view_prompt_diff(state, prompt, characters))
+                    .height(Length::FillPortion(1))
+            ]
+            .spacing(4)
+            .into(),
+            _ => image,
+        }
+    } else if !state.image_paths.is_empty() {
         if let Some(i) = state.selected_image {
-            Image::new(Handle::from_bytes(state.images[i].clone())).into()
+            Image::new(Handle::from_path(&state.image_paths[i])).into()
         } else {
             text("invalid selected image").into()
         }
@@ -1327,10 +4811,332 @@ fn view_image(state: &State) -> Element<Message> {
         text("nothing to see here").into()
     };
 
-    let image_history = scrollable(thumbs);
     row![center(final_image), image_history].into()
 }
 
+/// the tags of the editors against those `prompt` and `characters` were generated with; added
+/// tags are green, removed ones red and struck through
+fn view_prompt_diff<'a>(
+    state: &State,
+    prompt: &str,
+    characters: &[String],
+) -> Element<'a, Message> {
+    let section = |label: String| text(label).size(12).style(text::secondary);
+
+    let mut col = Column::new().spacing(4);
+    col = col.push(section("changes since this image: base prompt".into()));
+    col = col.push(view_tag_diff(prompt, &state.base_prompt.text()));
+    let current: Vec<String> = state
+        .character_prompts
+        .iter()
+        .filter(|cc| cc.c.is_enabled())
+        .map(|cc| cc.content.text())
+        .filter(|t| t != "\n")
+        .collect();
+    for i in 0..characters.len().max(current.len()) {
+        col = col.push(section(format!("character {}", i + 1)));
+        col = col.push(view_tag_diff(
+            characters.get(i).map_or("", String::as_str),
+            current.get(i).map_or("", String::as_str),
+        ));
+    }
+    col.into()
+}
+
+fn view_tag_diff<'a>(old: &str, new: &str) -> Element<'a, Message> {
+    const ADDED: Color = Color::from_rgb(0.3, 0.75, 0.35);
+    const REMOVED: Color = Color::from_rgb(0.85, 0.3, 0.3);
+
+    let changes = diff_tags(old, new);
+    if changes.iter().all(|c| matches!(c, TagChange::Same(_))) {
+        return text("unchanged").size(12).style(text::secondary).into();
+    }
+    let mut spans: Vec<text::Span<'a, Message>> = Vec::with_capacity(changes.len() * 2);
+    for (i, change) in changes.into_iter().enumerate() {
+        if i > 0 {
+            spans.push(text::Span::new(", "));
+        }
+        spans.push(match change {
+            TagChange::Same(t) => text::Span::new(t.to_string()),
+            TagChange::Added(t) => text::Span::new(format!("+{}", t)).color(ADDED),
+            TagChange::Removed(t) => text::Span::new(t.to_string())
+                .color(REMOVED)
+                .strikethrough(true),
+        });
+    }
+    widget::rich_text(spans).size(12).into()
+}
+
+/// indices of the history entries the filter and favorites only let through, newest first
+fn visible_history(state: &State) -> Vec<usize> {
+    let today = day_of(SystemTime::now());
+    (0..state.thumbnails.len())
+        .filter(|&i| {
+            state.history_info[i].matches(&state.history_filter, today)
+                && (!state.favorites_only || state.favorites.contains(&state.image_paths[i]))
+        })
+        .collect()
+}
+
+/// selects the visible history entry `pick` returns from the position of the selected one and
+/// the number shown, then scrolls the thumbnails so it is in view
+///
+/// with nothing selected or the selection filtered out the position is the first entry
+fn select_history(state: &mut State, pick: impl Fn(usize, usize) -> usize) -> Task<Message> {
+    let visible = visible_history(state);
+    if visible.is_empty() {
+        return Task::none();
+    }
+    let pos = state
+        .selected_image
+        .and_then(|i| visible.iter().position(|&v| v == i))
+        .unwrap_or(0);
+    let pos = pick(pos, visible.len());
+    state.selected_image = Some(visible[pos]);
+
+    let columns = state.settings.history_columns.clamp(1, MAX_HISTORY_COLUMNS);
+    let rows = visible.len().div_ceil(columns);
+    let y = if rows > 1 {
+        (pos / columns) as f32 / (rows - 1) as f32
+    } else {
+        0.0
+    };
+    scrollable::snap_to(
+        HISTORY_SCROLL.clone(),
+        scrollable::RelativeOffset { x: 0.0, y },
+    )
+}
+
+/// images of the selected folder for the gallery, only favorites if they are filtered; they are
+/// shown whole until their cached thumbnails are ready
+fn refresh_gallery(state: &mut State) -> Task<Message> {
+    state.gallery = state.files.folder_images();
+    if state.favorites_only {
+        let (entries, favorites) = (&state.files.entries, &state.favorites);
+        state
+            .gallery
+            .retain(|&id| favorites.contains(&entries[id].path));
+    }
+    let paths: Vec<PathBuf> = state
+        .gallery
+        .iter()
+        .map(|&id| state.files.entries[id].path.clone())
+        .collect();
+    state.gallery_thumbnails = paths.iter().map(Handle::from_path).collect();
+    state.gallery_index = 0;
+
+    Task::perform(
+        async move {
+            let read = paths.clone();
+            let handles = tokio::task::spawn_blocking(move || {
+                let cache = thumbnails::cache_dir();
+                par_map(&read, |path| {
+                    thumbnails::thumbnail_of_file(&cache, path, thumbnails::THUMBNAIL_SIDE)
+                        .ok()
+                        .map(Handle::from_path)
+                })
+            })
+            .await
+            .unwrap_or_default();
+            (paths, handles)
+        },
+        |(paths, handles)| Message::GalleryThumbnails(paths, handles),
+    )
+}
+
+/// a generated image ready for the history, see `decode_generated`
+#[derive(Debug, Clone)]
+pub struct DecodedImage {
+    thumbnail: Handle,
+    // the longer side of the thumbnail in pixels
+    side: u32,
+    info: HistoryInfo,
+}
+
+/// unpacks the png NAI sends zipped and decodes it for its history thumbnail and info, on a
+/// blocking thread as it takes long enough to stall the ui
+fn decode_generated(zipped: Bytes, side: u32) -> Result<DecodedImage, String> {
+    let mut archive = ZipArchive::new(Cursor::new(zipped)).map_err(|e| e.to_string())?;
+    let mut file = archive.by_index(0).map_err(|e| e.to_string())?;
+    let mut bytes = Vec::with_capacity(file.size() as usize);
+    std::io::copy(&mut file, &mut bytes).map_err(|e| e.to_string())?;
+
+    let mut reader = ImageReader::new(Cursor::new(&bytes));
+    reader.set_format(image::ImageFormat::Png);
+    let im = reader.decode().map_err(|e| e.to_string())?;
+    let thumbnail = thumbnail(&bytes, &im, side);
+    let metadata = extract_image_metadata(im).ok();
+    Ok(DecodedImage {
+        thumbnail,
+        side,
+        info: HistoryInfo::new(metadata.as_ref(), SystemTime::now()),
+    })
+}
+
+/// a history thumbnail whose longer side is `side` pixels of the image decoded from `bytes`,
+/// from the thumbnail cache if it was seen before and cached for the next session otherwise
+fn thumbnail(bytes: &[u8], im: &DynamicImage, side: u32) -> Handle {
+    let cache = thumbnails::cache_dir();
+    let hash = thumbnails::content_hash(bytes);
+    if let Some(path) = thumbnails::cached(&cache, hash, side) {
+        return Handle::from_path(path);
+    }
+    let resized = thumbnails::resize(im, side);
+    // the history only keeps the pixels of a thumbnail that could not be cached
+    match thumbnails::store(&cache, hash, side, &resized) {
+        Ok(path) => return Handle::from_path(path),
+        Err(e) => debug!("thumbnail cache: {}", e),
+    }
+    let rgba = DynamicImage::ImageRgb8(resized).to_rgba8();
+    let (w, h) = rgba.dimensions();
+    Handle::from_rgba(w, h, rgba.into_raw())
+}
+
+/// re-renders every history thumbnail at the current size and scale in the background, from
+/// the thumbnail cache where it has them
+fn render_thumbnails(state: &State) -> Task<Message> {
+    let paths: Vec<PathBuf> = state.image_paths.iter().cloned().collect();
+    let side = state.thumbnail_side();
+    Task::perform(
+        async move {
+            tokio::task::spawn_blocking(move || {
+                let cache = thumbnails::cache_dir();
+                paths
+                    .iter()
+                    .map(|path| {
+                        thumbnails::thumbnail_of_file(&cache, path, side)
+                            .ok()
+                            .map(Handle::from_path)
+                    })
+                    .collect()
+            })
+            .await
+            .unwrap_or_default()
+        },
+        move |handles| Message::ThumbnailsRendered(side, handles),
+    )
+}
+
+/// writes `settings` to config.toml and applies them, including to the image generation channel
+fn save_settings(state: &mut State, settings: Settings) -> Task<Message> {
+    state.selected_theme = settings.current_theme(state.system_dark, &state.custom_themes);
+    state.always_confirm = settings.always_confirm;
+    state
+        .files
+        .cache
+        .set_max_bytes(settings.image_cache_bytes());
+    state.settings = settings.clone();
+
+    let saved = match settings.save() {
+        Ok(()) => Task::none(),
+        Err(e) => Task::done(Message::SetMessage(format!("save config.toml: {}", e))),
+    };
+    let ChannelReady::Ready(tx) = &state.task_state.ready else {
+        return saved;
+    };
+    let mut tx = tx.clone();
+    Task::batch([
+        saved,
+        Task::perform(
+            async move {
+                let _ = tx.send(Message::ApplySettings(settings)).await;
+            },
+            |_| Message::Dummy,
+        ),
+    ])
+}
+
+/// decodes a generated png and places it on the system clipboard as a bitmap
+fn copy_image(bytes: &[u8]) -> Result<(), String> {
+    let im = image::load_from_memory(bytes)
+        .map_err(|e| e.to_string())?
+        .to_rgba8();
+    let (width, height) = im.dimensions();
+
+    let mut clipboard = IMAGE_CLIPBOARD.lock().map_err(|e| e.to_string())?;
+    if clipboard.is_none() {
+        *clipboard = Some(arboard::Clipboard::new().map_err(|e| e.to_string())?);
+    }
+    clipboard
+        .as_mut()
+        .unwrap()
+        .set_image(arboard::ImageData {
+            width: width as usize,
+            height: height as usize,
+            bytes: im.into_raw().into(),
+        })
+        .map_err(|e| e.to_string())
+}
+
+/// the path of the image the image pane currently shows: the previewed file tree image, else the
+/// selected generated one
+fn shown_path(state: &State) -> Option<&PathBuf> {
+    let entry = &state.files.entries[state.files.selected];
+    if state.files.cache.contains_key(&entry.path) {
+        return Some(&entry.path);
+    }
+    state.image_paths.get(state.selected_image?)
+}
+
+/// the image of `shown_path`, with the dimensions `update` read for it
+fn shown_image(state: &State) -> Option<ShownImage> {
+    let path = shown_path(state)?;
+    let handle = state
+        .files
+        .cache
+        .get(path)
+        .cloned()
+        .unwrap_or_else(|| Handle::from_path(path));
+    let size = match &state.shown_size {
+        Some((shown, size)) if shown == path => *size,
+        _ => None,
+    };
+    Some(ShownImage {
+        path: path.clone(),
+        handle,
+        size,
+    })
+}
+
+fn view_compare_side<'a>(
+    image: &ShownImage,
+    zoom: Option<f32>,
+    side: CompareSide,
+) -> Element<'a, Message> {
+    let label = text(
+        image
+            .path
+            .file_name()
+            .map_or(String::new(), |n| n.to_string_lossy().to_string()),
+    );
+
+    let view: Element<'a, Message> = match (zoom, image.size) {
+        (Some(zoom), Some((w, h))) => scrollable(
+            Image::new(image.handle.clone())
+                .width(Length::Fixed(w as f32 * zoom))
+                .height(Length::Fixed(h as f32 * zoom)),
+        )
+        .id(match side {
+            CompareSide::Pinned => COMPARE_PINNED.clone(),
+            CompareSide::Shown => COMPARE_SHOWN.clone(),
+        })
+        .direction(scrollable::Direction::Both {
+            vertical: scrollable::Scrollbar::default(),
+            horizontal: scrollable::Scrollbar::default(),
+        })
+        .on_scroll(move |viewport| Message::CompareScrolled(side, viewport.relative_offset()))
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .into(),
+        _ => center(Image::new(image.handle.clone())).into(),
+    };
+
+    column![label, view]
+        .spacing(4)
+        .width(Length::FillPortion(1))
+        .into()
+}
+
 fn view_controls<'a>(pane: pane_grid::Pane, is_maximized: bool) -> Element<'a, Message> {
     let (content, message) = if is_maximized {
         ("Restore", Message::Restore)
@@ -1350,23 +5156,29 @@ fn view_controls<'a>(pane: pane_grid::Pane, is_maximized: bool) -> Element<'a, M
 }
 
 fn setup_request(state: &mut State) -> ImageGenRequest {
-    let mut req = ImageGenRequest::default();
-
-    req.prompt(state.base_prompt.text());
+    let mut req = build_request(state);
 
     let new_seed = state.rng.random_range(1e9..9e9) as u64;
     req.seed(state.current_seed.unwrap_or(new_seed));
-    state.previous_seed = new_seed;
     state.current_seed = None;
+    req
+}
 
+/// the request for the current editors, without a seed
+fn build_request(state: &State) -> ImageGenRequest {
+    let mut req = ImageGenRequest::default();
+
+    req.prompt(state.base_prompt.text());
+    req.negative(negative_prompt(&state.settings.negative_groups));
     req.height_width(state.image_shape);
 
-    for cc in &mut state.character_prompts {
+    for cc in &state.character_prompts {
         if cc.content.text() == "\n" {
             continue;
         }
-        cc.c.prompt(cc.content.text());
-        req.add_character(&cc.c);
+        let mut c = cc.c.clone();
+        c.prompt(cc.content.text());
+        req.add_character(&c);
     }
 
     if state
@@ -1388,68 +5200,134 @@ fn set_prompt_characters(state: &mut State, base: String, characters: Vec<String
         .base_prompt
         .perform(Action::Edit(Edit::Paste(Arc::new(base))));
 
-    for (i, c) in characters.iter().enumerate() {
-        state.character_prompts[i]
-            .content
-            .perform(Action::SelectAll);
-        state.character_prompts[i]
-            .content
-            .perform(Action::Edit(Edit::Delete));
-        state.character_prompts[i]
-            .content
-            .perform(Action::Edit(Edit::Paste(Arc::new(c.clone()))));
+    // there are only as many editors as NAI takes characters
+    for (cc, c) in state.character_prompts.iter_mut().zip(characters) {
+        cc.content.perform(Action::SelectAll);
+        cc.content.perform(Action::Edit(Edit::Delete));
+        cc.content.perform(Action::Edit(Edit::Paste(Arc::new(c))));
     }
 }
 
-fn get_prompt_characters(meta: Map<String, Value>) -> (u64, String, Vec<String>) {
-    let seed = meta
-        .get("Comment")
-        .unwrap()
-        .get("seed")
-        .unwrap()
-        .as_u64()
-        .unwrap();
-    let caption = meta
-        .get("Comment")
-        .unwrap()
-        .get("v4_prompt")
-        .unwrap()
-        .get("caption")
-        .unwrap();
-    let prompt = caption
-        .get("base_caption")
-        .unwrap()
-        .as_str()
-        .unwrap()
-        .to_owned();
-    let characters = caption.get("char_captions").unwrap().as_array().unwrap();
+/// fills the editors and the controls they have from the metadata of an image: the prompts,
+/// character positions, seed, shape and negative groups; the sampler, steps and scales have no
+/// controls and stay at the request defaults
+fn apply_metadata(state: &mut State, meta: GenerationMetadata) -> Task<Message> {
+    set_prompt_characters(state, meta.prompt.clone(), meta.character_prompts());
+    for (cc, (_, center)) in state.character_prompts.iter_mut().zip(&meta.characters) {
+        cc.c.set_center(*center);
+    }
+    if let Some(seed) = meta.seed {
+        state.current_seed = Some(seed);
+    }
+    if let Some(shape) = meta.size.and_then(|(w, h)| ImageShape::of_size(w, h)) {
+        state.image_shape = shape;
+    }
+
+    // a negative prompt the groups cannot make leaves them as they are
+    let groups = meta
+        .negative
+        .as_deref()
+        .filter(|n| !n.is_empty())
+        .and_then(NegativeGroup::of_prompt);
+    match groups {
+        Some(groups) if groups != state.settings.negative_groups => {
+            let settings = Settings {
+                negative_groups: groups,
+                ..state.settings.clone()
+            };
+            save_settings(state, settings)
+        }
+        _ => Task::none(),
+    }
+}
+
+/// copies `source` to `dest` as is, so the embedded metadata stays, and with `sidecar` writes
+/// that metadata pretty printed to `dest` with a json extension
+///
+/// existing files are not overwritten
+fn save_image_as(source: &Path, dest: &Path, sidecar: bool) -> Result<PathBuf, String> {
+    if dest.as_os_str().is_empty() || dest.is_dir() {
+        return Err(format!("{:?} is not a file path", dest));
+    }
+    let json = dest.with_extension("json");
+    let written = if sidecar {
+        vec![dest, &json]
+    } else {
+        vec![dest]
+    };
+    if let Some(existing) = written.into_iter().find(|p| p.exists()) {
+        return Err(format!("{:?} already exists", existing));
+    }
+    std::fs::copy(source, dest).map_err(|e| format!("{:?}: {}", dest, e))?;
+    if sidecar {
+        let map = image::open(source)
+            .map_err(|e| e.to_string())
+            .and_then(|im| extract_image_metadata(im).map_err(|e| e.to_string()))
+            .map_err(|e| format!("{:?}: {}", source, e))?;
+        let pretty =
+            serde_json::to_string_pretty(&Value::Object(map)).map_err(|e| e.to_string())?;
+        std::fs::write(&json, pretty).map_err(|e| format!("{:?}: {}", json, e))?;
+    }
+    Ok(dest.to_path_buf())
+}
 
-    let mut character_prompts = Vec::with_capacity(6);
-    for c in characters {
-        character_prompts.push(c.get("char_caption").unwrap().as_str().unwrap().to_owned());
+/// writes `source` to `dest` as a png with the alpha lsbs set and no text chunks, so neither
+/// NAI's stealth payload nor any other tool's metadata goes along; existing files are not
+/// overwritten
+fn strip_image_to(source: &Path, dest: &Path) -> Result<PathBuf, String> {
+    if dest.as_os_str().is_empty() || dest.is_dir() {
+        return Err(format!("{:?} is not a file path", dest));
     }
-    (seed, prompt, character_prompts)
+    if dest.exists() {
+        return Err(format!("{:?} already exists", dest));
+    }
+    let im = image::open(source).map_err(|e| format!("{:?}: {}", source, e))?;
+    // the png encoder writes no text chunks, so only the pixels carry over
+    strip_metadata(im)
+        .save_with_format(dest, image::ImageFormat::Png)
+        .map_err(|e| format!("{:?}: {}", dest, e))?;
+    Ok(dest.to_path_buf())
 }
 
 pub fn get_prompt_metadata<P: AsRef<std::path::Path>>(
+    pool: &Pool<SqliteConnectionManager>,
     path: P,
-) -> Option<(u64, String, Vec<String>)> {
-    if path
-        .as_ref()
-        .extension()
-        .map_or(false, |s| s.to_string_lossy() == "png")
-    {
-        if let Ok(im) = image::open(path) {
-            if let Ok(meta) = extract_image_metadata(im) {
-                return Some(get_prompt_characters(meta));
-            }
-        }
+) -> Result<GenerationMetadata, MetadataError> {
+    let path = path.as_ref();
+    if !is_image(path) {
+        return Err(MetadataError::Read(format!("{:?} is not an image", path)));
     }
-    None
+    GenerationMetadata::from_map(&read_cached(pool, path)?)
 }
 
-pub fn event_subscribe(_state: &State) -> Subscription<Message> {
-    event::listen().map(Message::Event)
+pub fn event_subscribe(state: &State) -> Subscription<Message> {
+    let running = state
+        .tasks
+        .iter()
+        .any(|t| matches!(t.status, TaskStatus::Running | TaskStatus::Saving));
+
+    Subscription::batch([
+        // there is no event for appearance changes, so the system is polled
+        if state.settings.theme_mode == ThemeMode::System {
+            iced::time::every(SYSTEM_THEME_POLL).map(|_| Message::CheckSystemTheme)
+        } else {
+            Subscription::none()
+        },
+        // the log pane has no other way to show records from the channel and watcher tasks
+        if running || state.panes.iter().any(|(_, p)| p.id == PaneId::Log) {
+            iced::time::every(Duration::from_secs(1)).map(|_| Message::Tick)
+        } else {
+            Subscription::none()
+        },
+        event::listen().map(Message::Event),
+        // widgets under the cursor may capture the release, so listen regardless of status
+        event::listen_with(|e, _status, _window| match e {
+            Event::Mouse(iced::mouse::Event::ButtonReleased(iced::mouse::Button::Left)) => {
+                Some(Message::DragReleased)
+            }
+            _ => None,
+        }),
+    ])
 }
 
 pub fn run_image_gen_subscription() -> Subscription<Message> {
@@ -1472,10 +5350,15 @@ fn channel_image_gen() -> impl Stream<Item = ChannelEvent> {
             JoinHandle<Result<(Bytes, PathBuf), ImageGenerationError>>,
         > = FastIndexMap::default();
         let client = Arc::new(Requester::default());
-        let semaphore = Arc::new(Semaphore::new(1));
+        // replaced by the settings sent once the channel is ready
+        let mut concurrency = 1;
+        let mut semaphore = Arc::new(Semaphore::new(concurrency));
+        let mut cooldown = Duration::ZERO;
+        let mut output_dir = PROJECT_DIRS.data_dir().join("output");
 
         let _ = output.send(ChannelEvent::TaskReady(main_tx)).await;
-        println!("sent TaskReady");
+        spawn_anlas_check(&client, &output);
+        debug!("sent TaskReady");
 
         loop {
             let tick = interval.tick().fuse();
@@ -1486,60 +5369,86 @@ fn channel_image_gen() -> impl Stream<Item = ChannelEvent> {
                 input = rx.select_next_some() => {
                     match input {
                         Message::Cancel(id) => {
-                            println!("rcv cancel");
+                            debug!("rcv cancel");
                             if let Some(handle) = in_flight.shift_remove(&id) {
                                 handle.abort();
                                 let _ = output.send(ChannelEvent::Cancelled(id)).await;
                             }
                         }
                         Message::CancelAll => {
-                            println!("rcv cancelall");
+                            debug!("rcv cancelall");
                             for (_seed, handle) in in_flight.drain(..) {
                                 handle.abort();
                             }
                         }
+                        Message::ApplySettings(settings) => {
+                            // running tasks keep the permits of the old semaphore
+                            if settings.concurrency != concurrency {
+                                concurrency = settings.concurrency;
+                                semaphore = Arc::new(Semaphore::new(concurrency));
+                            }
+                            cooldown = Duration::from_millis(settings.cooldown_ms);
+                            output_dir = settings.output_dir;
+                        }
                         Message::Pause => {
-                            println!("rcv pause");
+                            debug!("rcv pause");
                             paused = true;
                         }
                         Message::Resume => {
-                            println!("rcv resume");
+                            debug!("rcv resume");
                             paused = false;
 
                             while let Some((seed, mut req)) = buf.pop_front() {
-                                println!("resumed creating task {}", seed);
+                                debug!("resumed creating task {}", seed);
 
                                 let client = Arc::clone(&client);
+                                let output_dir = output_dir.clone();
+                                let mut saving = output.clone();
                                 let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
                                 let jh = tokio::spawn(async move {
                                     let _permit = permit;
                                     req.seed(seed);
 
-                                    let result = client.generate_image(req).await;
+                                    let result = client
+                                        .generate_image(req, output_dir, || {
+                                            let _ = saving.try_send(ChannelEvent::Saving(seed));
+                                        })
+                                        .await;
 
-                                    time::sleep(Duration::from_millis(1250)).await;
+                                    time::sleep(cooldown).await;
 
                                     result
                                 });
                                 in_flight.insert(seed, jh);
+                                let _ = output.send(ChannelEvent::Started(seed)).await;
                             }
                         }
                         Message::CreateImage((seed, mut req)) => {
                             if paused {
                                 buf.push_back((seed, req));
                             } else {
-                                println!("creating task {}", seed);
+                                debug!("creating task {}", seed);
 
                                 let client = Arc::clone(&client);
+                                let output_dir = output_dir.clone();
+                                let mut saving = output.clone();
                                 let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
                                 let jh = tokio::spawn(async move {
                                     let _permit = permit;
                                     req.seed(seed);
 
-                                    let result = client.generate_image(req).await;
+                                    let result = client
+                                        .generate_image(req, output_dir, || {
+                                            let _ = saving.try_send(ChannelEvent::Saving(seed));
+                                        })
+                                        .await;
+
+                                    time::sleep(cooldown).await;
+
                                     result
                                 });
                                 in_flight.insert(seed, jh);
+                                let _ = output.send(ChannelEvent::Started(seed)).await;
                             }
                         }
                         _ => (),
@@ -1553,6 +5462,9 @@ fn channel_image_gen() -> impl Stream<Item = ChannelEvent> {
                          .map(|(&id, _handle)| id)
                          .collect();
 
+                     if !done.is_empty() {
+                         spawn_anlas_check(&client, &output);
+                     }
                      for id in done {
                          if let Some(handle) = in_flight.shift_remove(&id) {
                              match handle.await {
@@ -1562,7 +5474,7 @@ fn channel_image_gen() -> impl Stream<Item = ChannelEvent> {
                                  Err(e) if e.is_cancelled() => {
                                      let _ = output.send(ChannelEvent::Cancelled(id)).await;
                                  }
-                                 Err(e) => eprintln!("task {} failed: {}", id, e),
+                                 Err(e) => error!("task {} failed: {}", id, e),
                              }
                          }
                      }
@@ -1573,6 +5485,20 @@ fn channel_image_gen() -> impl Stream<Item = ChannelEvent> {
     })
 }
 
+/// fetches the Anlas balance in the background; failures only leave the last known balance shown
+fn spawn_anlas_check(client: &Arc<Requester>, output: &Sender<ChannelEvent>) {
+    let client = Arc::clone(client);
+    let mut output = output.clone();
+    tokio::spawn(async move {
+        match client.anlas().await {
+            Ok(anlas) => {
+                let _ = output.send(ChannelEvent::Anlas(anlas)).await;
+            }
+            Err(e) => error!("anlas: {}", e),
+        }
+    });
+}
+
 pub fn run_fsevent_subscription() -> Subscription<Message> {
     Subscription::run(channel_fsevent).map(Message::FsEvent)
 }
@@ -1598,23 +5524,85 @@ fn channel_fsevent() -> impl Stream<Item = notify::Event> {
             .watch(PROJECT_DIRS.data_dir(), notify::RecursiveMode::Recursive)
             .expect("failed to watch project data_dir");
 
+        for dir in watch_dirs() {
+            if let Err(e) = watcher.watch(&dir, notify::RecursiveMode::Recursive) {
+                error!("failed to watch {:?}: {}", &dir, e);
+            }
+        }
+
         while let Some(res) = rx.next().await {
             match res {
                 Ok(e) => {
                     let _ = output.send(e).await.unwrap();
                 }
-                Err(e) => eprintln!("{}", e),
+                Err(e) => error!("watcher: {}", e),
             }
         }
     })
 }
 
+/// a generation as shown in the task list
+struct TaskEntry {
+    id: u64,
+    // start of the base prompt
+    snippet: String,
+    size: (u32, u32),
+    status: TaskStatus,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    // sent again as is on retry, the id is its seed
+    req: ImageGenRequest,
+}
+
+impl TaskEntry {
+    fn new(id: u64, req: &ImageGenRequest) -> Self {
+        let prompt = req.base_prompt();
+        let snippet = match prompt.char_indices().nth(TASK_SNIPPET_CHARS) {
+            Some((i, _)) => format!("{}...", &prompt[..i]),
+            None => prompt.to_string(),
+        };
+        Self {
+            id,
+            snippet: snippet.replace('\n', " "),
+            size: req.size(),
+            status: TaskStatus::Queued,
+            started: None,
+            finished: None,
+            req: req.clone(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.status, TaskStatus::Done(_) | TaskStatus::Failed(_))
+    }
+
+    /// time since the request was sent, frozen once it finished
+    fn elapsed(&self) -> Option<Duration> {
+        let started = self.started?;
+        Some(self.finished.unwrap_or_else(Instant::now) - started)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TaskStatus {
+    Queued,
+    Running,
+    Saving,
+    Done(PathBuf),
+    Failed(String),
+}
+
 struct TaskState {
     // sender
     ready: ChannelReady,
 
     // for ui only
     status: ChannelStatus,
+    completed: usize,
+    last_error: Option<String>,
+    // unknown until the first balance check succeeds
+    anlas: Option<u64>,
+    timings: Timings,
 }
 
 enum ChannelStatus {
@@ -1633,6 +5621,250 @@ impl Display for ChannelStatus {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReviewSource {
+    Folder,
+    History,
+}
+
+/// the settings dialog; numbers are kept as typed until saved
+struct SettingsForm {
+    theme: Theme,
+    theme_mode: ThemeMode,
+    light_theme: Theme,
+    dark_theme: Theme,
+    concurrency: String,
+    cooldown_ms: String,
+    output_dir: String,
+    shape: ImageShape,
+    always_confirm: bool,
+    retention_on_startup: bool,
+    max_age_days: String,
+    max_size_gb: String,
+    retention_action: RetentionAction,
+    archive_dir: String,
+    ui_scale: String,
+    text_size: String,
+    image_cache_mb: String,
+}
+
+impl SettingsForm {
+    fn new(settings: &Settings, custom: &[Theme]) -> Self {
+        Self {
+            theme: settings.theme(custom),
+            theme_mode: settings.theme_mode,
+            light_theme: theme_named(&settings.light_theme, custom),
+            dark_theme: theme_named(&settings.dark_theme, custom),
+            concurrency: settings.concurrency.to_string(),
+            cooldown_ms: settings.cooldown_ms.to_string(),
+            output_dir: settings.output_dir.to_string_lossy().to_string(),
+            shape: settings.shape,
+            always_confirm: settings.always_confirm,
+            retention_on_startup: settings.retention.on_startup,
+            max_age_days: settings
+                .retention
+                .max_age_days
+                .map_or(String::new(), |d| d.to_string()),
+            max_size_gb: settings
+                .retention
+                .max_size_gb
+                .map_or(String::new(), |gb| gb.to_string()),
+            retention_action: settings.retention.action,
+            archive_dir: settings.retention.archive_dir.to_string_lossy().to_string(),
+            ui_scale: settings.ui_scale.to_string(),
+            text_size: settings.text_size.to_string(),
+            image_cache_mb: settings.image_cache_mb.to_string(),
+        }
+    }
+
+    /// the form applied on top of `current`, which keeps the settings changed outside the dialog
+    fn to_settings(&self, current: &Settings) -> Result<Settings, String> {
+        let concurrency = self
+            .concurrency
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or("concurrent requests must be a number above 0")?;
+        let cooldown_ms = self
+            .cooldown_ms
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| "cooldown must be a number of milliseconds")?;
+        if self.output_dir.trim().is_empty() {
+            return Err("output folder must not be empty".into());
+        }
+        // empty means no limit
+        let max_age_days = match self.max_age_days.trim() {
+            "" => None,
+            s => Some(
+                s.parse::<u64>()
+                    .map_err(|_| "max age must be a number of days")?,
+            ),
+        };
+        let max_size_gb = match self.max_size_gb.trim() {
+            "" => None,
+            s => Some(
+                s.parse::<f64>()
+                    .ok()
+                    .filter(|&gb| gb > 0.0)
+                    .ok_or("max size must be a number of GB above 0")?,
+            ),
+        };
+        if self.retention_action == RetentionAction::Archive && self.archive_dir.trim().is_empty() {
+            return Err("archive folder must not be empty".into());
+        }
+        let ui_scale = self
+            .ui_scale
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|s| UI_SCALE.contains(s))
+            .ok_or(format!(
+                "scale must be a number from {} to {}",
+                UI_SCALE.start(),
+                UI_SCALE.end()
+            ))?;
+        let text_size = self
+            .text_size
+            .trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|s| TEXT_SIZE.contains(s))
+            .ok_or(format!(
+                "text size must be a number from {} to {}",
+                TEXT_SIZE.start(),
+                TEXT_SIZE.end()
+            ))?;
+        let image_cache_mb = self
+            .image_cache_mb
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| "image cache must be a number of MiB")?;
+
+        Ok(Settings {
+            theme: self.theme.to_string(),
+            theme_mode: self.theme_mode,
+            light_theme: self.light_theme.to_string(),
+            dark_theme: self.dark_theme.to_string(),
+            concurrency,
+            cooldown_ms,
+            output_dir: PathBuf::from(self.output_dir.trim()),
+            shape: self.shape,
+            always_confirm: self.always_confirm,
+            retention: Retention {
+                on_startup: self.retention_on_startup,
+                max_age_days,
+                max_size_gb,
+                action: self.retention_action,
+                archive_dir: PathBuf::from(self.archive_dir.trim()),
+            },
+            ui_scale,
+            text_size,
+            image_cache_mb,
+            ..current.clone()
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SettingsEdit {
+    Theme(Theme),
+    ThemeMode(ThemeMode),
+    LightTheme(Theme),
+    DarkTheme(Theme),
+    Concurrency(String),
+    Cooldown(String),
+    OutputDir(String),
+    Shape(ImageShape),
+    AlwaysConfirm(bool),
+    RetentionOnStartup(bool),
+    MaxAgeDays(String),
+    MaxSizeGb(String),
+    RetentionAction(RetentionAction),
+    ArchiveDir(String),
+    UiScale(String),
+    TextSize(String),
+    ImageCacheMb(String),
+}
+
+struct Inspector {
+    path: PathBuf,
+    // null when the image has no metadata
+    metadata: Value,
+    // JSON pointers of the open objects and arrays
+    expanded: HashSet<String>,
+    integrity: Option<Result<Integrity, String>>,
+}
+
+struct Review {
+    paths: Vec<PathBuf>,
+    index: usize,
+    // seed and base prompt of the current image
+    info: Option<(u64, String)>,
+}
+
+impl Review {
+    fn load_info(&mut self, pool: &Pool<SqliteConnectionManager>) {
+        self.info = get_prompt_metadata(pool, &self.paths[self.index])
+            .ok()
+            .map(|meta| (meta.seed.unwrap_or_default(), meta.prompt));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareSide {
+    Pinned,
+    Shown,
+}
+
+// the image a Save as dialog is open for
+struct SaveAs {
+    source: PathBuf,
+    sidecar: bool,
+    // exports a copy without metadata instead, see `strip_image_to`
+    strip: bool,
+}
+
+struct Cropping {
+    image: ShownImage,
+    region: Option<Region>,
+}
+
+struct TreePrompt {
+    path: PathBuf,
+    // embedded prompt and characters, None until read or if the png has none
+    prompt: Option<(String, Vec<String>)>,
+}
+
+struct ShownImage {
+    path: PathBuf,
+    handle: Handle,
+    size: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromptEditor {
+    Base,
+    Character(usize),
+}
+
+struct Palette {
+    editor: PromptEditor,
+    query: String,
+    snippets: Vec<Snippet>,
+    // selected row of the shown matches
+    index: usize,
+}
+
+struct Suggestions {
+    editor: PromptEditor,
+    // the partial tag before the cursor, replaced on accept
+    token: String,
+    items: Vec<String>,
+    index: usize,
+}
+
 enum ChannelReady {
     NotReady,
     Ready(Sender<Message>),
@@ -1642,24 +5874,70 @@ enum ChannelReady {
 pub enum ChannelEvent {
     Generated(u64, Result<(Bytes, PathBuf), ImageGenerationError>),
     Cancelled(u64),
+    Started(u64),
+    // downloaded, being written to the output folder
+    Saving(u64),
+    Anlas(u64),
     TaskReady(Sender<Message>),
 }
 
 #[derive(Debug, Clone)]
 struct PromptUi<V> {
     kind: PromptKind,
-    options: combo_box::State<String>,
+    options: Vec<String>,
     map: FastHashMap<String, V>,
     selected: Option<String>,
+    // typed into the picker, the selected name once one is picked
+    query: String,
     rename: String,
 }
 
+struct FileOpsProgress {
+    done: usize,
+    failed: usize,
+    total: usize,
+    cancel: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Clone)]
+pub enum FileOpEvent {
+    Done(FileOp),
+    Failed(FileOp, String),
+    Finished,
+}
+
+/// a destructive file operation that is waiting on the confirmation dialog
+#[derive(Debug, Clone)]
+pub enum PendingAction {
+    // by path, the tree may be rebuilt with new ids before it is confirmed
+    Delete(PathBuf),
+    DeleteBatch(usize),
+    // paths that would be overwritten
+    MoveBatch(Vec<PathBuf>),
+}
+
+/// where a file tree entry can be dropped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropTarget {
+    // import the image's metadata into the prompt editors
+    Prompts,
+}
+
+/// near-identical images found by perceptual hash; `keep` indexes the one to hold on to
+struct DuplicateGroup {
+    paths: Vec<PathBuf>,
+    keep: usize,
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub enum FilesMode {
     #[default]
     Normal,
     Batch,
     Create,
+    Search,
+    Duplicates,
+    Gallery,
 }
 
 impl Display for FilesMode {
@@ -1669,6 +5947,9 @@ impl Display for FilesMode {
             Normal => write!(f, "Normal"),
             Batch => write!(f, "Batch"),
             Create => write!(f, "CreateFolder"),
+            Search => write!(f, "Search"),
+            Duplicates => write!(f, "Duplicates"),
+            Gallery => write!(f, "Gallery"),
         }
     }
 }
@@ -1676,6 +5957,7 @@ impl Display for FilesMode {
 struct CharacterContent {
     c: nai::Character,
     content: widget::text_editor::Content,
+    stats: PromptStats,
 }
 
 impl CharacterContent {
@@ -1683,14 +5965,17 @@ impl CharacterContent {
         Self {
             c: nai::Character::new(),
             content: widget::text_editor::Content::new(),
+            stats: PromptStats::default(),
         }
     }
 }
 
+#[derive(PartialEq)]
 enum PaneId {
     Files,
     Prompts,
     Image,
+    Log,
 }
 
 impl fmt::Display for PaneId {
@@ -1699,6 +5984,7 @@ impl fmt::Display for PaneId {
             Self::Files => write!(f, "files"),
             Self::Prompts => write!(f, "prompts"),
             Self::Image => write!(f, "image"),
+            Self::Log => write!(f, "log"),
         }
     }
 }